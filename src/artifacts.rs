@@ -0,0 +1,468 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Finding and post-processing the binaries cargo builds
+
+use crate::json::{self, Json};
+use crate::manifest::{arg_value, has_flag};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Name of the output directory for the build profile selected
+/// by the cargo arguments, e.g. `--release` builds into `release`
+pub fn profile_dir(params: &[String]) -> String {
+    let profile = match arg_value(params, "--profile") {
+        Some(profile) => profile,
+        None if has_flag(params, "--release") || has_flag(params, "-r") => String::from("release"),
+        None => String::from("dev"),
+    };
+
+    // The built-in profiles don't all build into directories of their name
+    match profile.as_str() {
+        "dev" | "test" => String::from("debug"),
+        "bench" => String::from("release"),
+        _ => profile,
+    }
+}
+
+/// The `compiler-artifact` messages in cargo's `--message-format=json`
+/// output, in the order it built them
+fn artifact_messages(messages: &[u8]) -> Vec<Json> {
+    String::from_utf8_lossy(messages)
+        .lines()
+        .filter_map(|line| json::parse(line).ok())
+        .filter(|message| message.get("reason").and_then(Json::as_str) == Some("compiler-artifact"))
+        .collect()
+}
+
+/// Leave the build messages of packages other than those with the given
+/// manifests out of cargo's JSON messages, keeping the rest as they were
+pub fn only_packages(messages: &[u8], manifests: &[PathBuf]) -> Vec<u8> {
+    let mut kept = vec![];
+    for line in messages.split_inclusive(|b| *b == b'\n') {
+        let other = json::parse(String::from_utf8_lossy(line).trim())
+            .ok()
+            .filter(|message| {
+                message.get("reason").and_then(Json::as_str) == Some("compiler-artifact")
+            })
+            .and_then(|message| {
+                message
+                    .get("manifest_path")
+                    .and_then(Json::as_str)
+                    .map(|path| !manifests.iter().any(|manifest| manifest == Path::new(path)))
+            })
+            .unwrap_or(false);
+        if !other {
+            kept.extend_from_slice(line);
+        }
+    }
+    kept
+}
+
+/// The executables cargo reports building
+pub fn executables(messages: &[u8]) -> Vec<PathBuf> {
+    artifact_messages(messages)
+        .iter()
+        .filter_map(|message| message.get("executable").and_then(Json::as_str))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// The executables and dynamic libraries (cdylibs) cargo reports building
+pub fn outputs(messages: &[u8]) -> Vec<PathBuf> {
+    let mut outputs = vec![];
+    for message in artifact_messages(messages) {
+        if let Some(executable) = message.get("executable").and_then(Json::as_str) {
+            outputs.push(PathBuf::from(executable));
+            continue;
+        }
+        let cdylib = message
+            .get("target")
+            .and_then(|target| target.get("kind"))
+            .and_then(Json::as_array)
+            .is_some_and(|kinds| kinds.iter().any(|kind| kind.as_str() == Some("cdylib")));
+        if !cdylib {
+            continue;
+        }
+        // Alongside the library itself may be an import library or rlib
+        let libraries = message
+            .get("filenames")
+            .and_then(Json::as_array)
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(Json::as_str)
+            .filter(|name| {
+                [".so", ".dylib", ".dll"]
+                    .iter()
+                    .any(|ext| name.ends_with(ext))
+            });
+        outputs.extend(libraries.map(PathBuf::from));
+    }
+    outputs
+}
+
+/// Whether the file at the path is an ELF object
+pub fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .map(|_| magic == *b"\x7fELF")
+        .unwrap_or(false)
+}
+
+/// An ELF file's architecture, from its header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElfArch {
+    /// `e_machine`
+    machine: u16,
+    /// Whether it's ELFCLASS64 rather than ELFCLASS32
+    wide: bool,
+}
+
+const EM_386: u16 = 3;
+const EM_MIPS: u16 = 8;
+const EM_PPC: u16 = 20;
+const EM_PPC64: u16 = 21;
+const EM_ARM: u16 = 40;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const EM_RISCV: u16 = 243;
+
+impl ElfArch {
+    /// What binaries for a target triple should be built for, if it's
+    /// an architecture we know
+    pub fn for_triple(triple: &str) -> Option<ElfArch> {
+        let arch = triple.split('-').next().unwrap_or("");
+        let (machine, wide) = match arch {
+            "x86_64" => (EM_X86_64, true),
+            "i386" | "i586" | "i686" => (EM_386, false),
+            "aarch64" | "aarch64_be" => (EM_AARCH64, true),
+            "riscv64" | "riscv64gc" | "riscv64imac" => (EM_RISCV, true),
+            "mips" | "mipsel" => (EM_MIPS, false),
+            "mips64" | "mips64el" => (EM_MIPS, true),
+            "powerpc" => (EM_PPC, false),
+            "powerpc64" | "powerpc64le" => (EM_PPC64, true),
+            arch if arch.starts_with("arm") || arch.starts_with("thumb") => (EM_ARM, false),
+            arch if arch.starts_with("riscv32") => (EM_RISCV, false),
+            _ => return None,
+        };
+        Some(ElfArch { machine, wide })
+    }
+
+    /// Read the architecture from the first 20 bytes of an ELF file,
+    /// or `None` if it isn't one
+    pub fn read(path: &Path) -> Result<Option<ElfArch>, String> {
+        let mut header = [0u8; 20];
+        let read = File::open(path).and_then(|mut file| file.read_exact(&mut header));
+        match read {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        }
+        if header[..4] != *b"\x7fELF" {
+            return Ok(None);
+        }
+        // EI_DATA says whether the rest of the header is big-endian
+        let machine = [header[18], header[19]];
+        let machine = if header[5] == 2 {
+            u16::from_be_bytes(machine)
+        } else {
+            u16::from_le_bytes(machine)
+        };
+        Ok(Some(ElfArch {
+            machine,
+            wide: header[4] == 2,
+        }))
+    }
+}
+
+impl fmt::Display for ElfArch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match (self.machine, self.wide) {
+            (EM_386, _) => "x86",
+            (EM_X86_64, _) => "x86-64",
+            (EM_ARM, _) => "arm",
+            (EM_AARCH64, _) => "aarch64",
+            (EM_RISCV, true) => "riscv64",
+            (EM_RISCV, false) => "riscv32",
+            (EM_MIPS, true) => "mips64",
+            (EM_MIPS, false) => "mips",
+            (EM_PPC, _) => "powerpc",
+            (EM_PPC64, _) => "powerpc64",
+            (machine, wide) => {
+                return write!(
+                    f,
+                    "ELF machine {} ({}-bit)",
+                    machine,
+                    if wide { 64 } else { 32 }
+                )
+            }
+        };
+        f.write_str(name)
+    }
+}
+
+/// The ELF executables and shared libraries in a profile output
+/// directory, skipping rlibs, static libraries and build metadata
+pub fn find_binaries(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut binaries: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_elf(path))
+        .collect();
+    binaries.sort();
+    Ok(binaries)
+}
+
+/// The size of a file in bytes
+pub fn file_size(path: &Path) -> Result<u64, String> {
+    fs::metadata(path)
+        .map(|meta| meta.len())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Strip a binary in place with the given strip program,
+/// returning its size before and after
+pub fn strip(strip: &Path, binary: &Path) -> Result<(u64, u64), String> {
+    let before = file_size(binary)?;
+    let status = Command::new(strip)
+        .arg(binary)
+        .status()
+        .map_err(|e| format!("failed to run {}: {}", strip.display(), e))?;
+    if !status.success() {
+        return Err(format!(
+            "{} {} failed ({})",
+            strip.display(),
+            binary.display(),
+            status
+        ));
+    }
+    Ok((before, file_size(binary)?))
+}
+
+/// The size a binary would be stripped, stripping a copy of it
+/// so that the binary itself keeps its symbols
+pub fn stripped_size(strip: &Path, binary: &Path) -> Result<u64, String> {
+    let name = binary
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let copy = env::temp_dir().join(format!("cargo-kubos-{}-{}", std::process::id(), name));
+    let output = Command::new(strip)
+        .arg("-o")
+        .arg(&copy)
+        .arg(binary)
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", strip.display(), e))?;
+    let size = if output.status.success() {
+        file_size(&copy)
+    } else {
+        Err(format!(
+            "{} -o {} {} failed ({}): {}",
+            strip.display(),
+            copy.display(),
+            binary.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    };
+    let _ = fs::remove_file(&copy);
+    size
+}
+
+/// Copy an executable into the directory, creating it if needed, with the
+/// suffix put before any extension. What's there already is replaced by
+/// renaming a complete copy over it, so readers never see half a binary.
+pub fn stage(binary: &Path, dir: &Path, suffix: &str) -> Result<PathBuf, String> {
+    let stem = binary
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("{} has no file name", binary.display()))?;
+    let name = match binary.extension() {
+        Some(ext) => format!("{}{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}{}", stem, suffix),
+    };
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    let staged = dir.join(&name);
+    let temp = dir.join(format!(".{}.tmp-{}", name, std::process::id()));
+    if let Err(e) = fs::copy(binary, &temp) {
+        let _ = fs::remove_file(&temp);
+        return Err(format!(
+            "copying {} to {}: {}",
+            binary.display(),
+            temp.display(),
+            e
+        ));
+    }
+    fs::rename(&temp, &staged).map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        format!("{}: {}", staged.display(), e)
+    })?;
+    Ok(staged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn profiles_name_their_output_directories() {
+        let dir = |args: &[&str]| profile_dir(&strings(args));
+        assert_eq!(dir(&["build"]), "debug");
+        assert_eq!(dir(&["build", "--release"]), "release");
+        assert_eq!(dir(&["build", "-r"]), "release");
+        assert_eq!(dir(&["build", "--profile", "dev"]), "debug");
+        assert_eq!(dir(&["build", "--profile=test"]), "debug");
+        assert_eq!(dir(&["build", "--profile", "bench"]), "release");
+        assert_eq!(dir(&["build", "--profile", "flight"]), "flight");
+        // The profile named beats --release, as it does for cargo
+        assert_eq!(
+            dir(&["build", "--release", "--profile", "flight"]),
+            "flight"
+        );
+    }
+
+    #[test]
+    fn outputs_are_executables_and_dynamic_libraries() {
+        let messages = br#"{"reason":"compiler-artifact","target":{"kind":["lib"]},"filenames":["/t/libcore.rlib"],"executable":null}
+{"reason":"compiler-artifact","target":{"kind":["cdylib","rlib"]},"filenames":["/t/libhal.so","/t/libhal.rlib"],"executable":null}
+{"reason":"build-script-executed","package_id":"hal 0.1.0"}
+not json
+{"reason":"compiler-artifact","target":{"kind":["bin"]},"filenames":["/t/telemetry"],"executable":"/t/telemetry"}
+"#;
+        assert_eq!(
+            outputs(messages),
+            [PathBuf::from("/t/libhal.so"), PathBuf::from("/t/telemetry")]
+        );
+        assert_eq!(executables(messages), [PathBuf::from("/t/telemetry")]);
+    }
+
+    /// A directory of its own for a test's files
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join("cargo-kubos-artifacts").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The start of an ELF header, of the class and byte order given
+    fn elf_header(wide: bool, big_endian: bool, machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF".to_vec();
+        header.push(if wide { 2 } else { 1 });
+        header.push(if big_endian { 2 } else { 1 });
+        header.extend_from_slice(&[1, 0]);
+        header.extend_from_slice(&[0; 8]);
+        // e_type, ET_EXEC
+        header.extend_from_slice(&[2, 0]);
+        if big_endian {
+            header.extend_from_slice(&machine.to_be_bytes());
+        } else {
+            header.extend_from_slice(&machine.to_le_bytes());
+        }
+        // The rest of the header, which isn't read
+        header.extend_from_slice(&[0; 32]);
+        header
+    }
+
+    fn read(dir: &Path, name: &str, contents: &[u8]) -> Result<Option<ElfArch>, String> {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        ElfArch::read(&path)
+    }
+
+    #[test]
+    fn headers_are_read_in_their_class_and_byte_order() {
+        let dir = test_dir("headers_are_read_in_their_class_and_byte_order");
+        let arm = read(&dir, "arm", &elf_header(false, false, EM_ARM)).unwrap();
+        assert_eq!(arm, ElfArch::for_triple("arm-unknown-linux-gnueabihf"));
+        let x86_64 = read(&dir, "x86_64", &elf_header(true, false, EM_X86_64)).unwrap();
+        assert_eq!(x86_64, ElfArch::for_triple("x86_64-unknown-linux-gnu"));
+        let mips = read(&dir, "mips", &elf_header(false, true, EM_MIPS)).unwrap();
+        assert_eq!(mips, ElfArch::for_triple("mips-unknown-linux-gnu"));
+        let ppc64 = read(&dir, "ppc64", &elf_header(true, true, EM_PPC64)).unwrap();
+        assert_eq!(ppc64, ElfArch::for_triple("powerpc64-unknown-linux-gnu"));
+    }
+
+    #[test]
+    fn class_tells_32_from_64_bit() {
+        let dir = test_dir("class_tells_32_from_64_bit");
+        let riscv32 = read(&dir, "riscv32", &elf_header(false, false, EM_RISCV)).unwrap();
+        let riscv64 = read(&dir, "riscv64", &elf_header(true, false, EM_RISCV)).unwrap();
+        assert_eq!(riscv32, ElfArch::for_triple("riscv32imac-unknown-none-elf"));
+        assert_eq!(riscv64, ElfArch::for_triple("riscv64gc-unknown-linux-gnu"));
+        assert_ne!(riscv32, riscv64);
+        assert_eq!(riscv32.unwrap().to_string(), "riscv32");
+        assert_eq!(riscv64.unwrap().to_string(), "riscv64");
+    }
+
+    #[test]
+    fn other_files_are_not_elf() {
+        let dir = test_dir("other_files_are_not_elf");
+        assert_eq!(read(&dir, "empty", b""), Ok(None));
+        assert_eq!(
+            read(&dir, "script", b"#!/bin/sh\necho hello world\n"),
+            Ok(None)
+        );
+        // Too short to say what it's for
+        let header = elf_header(false, false, EM_ARM);
+        assert_eq!(read(&dir, "truncated", &header[..19]), Ok(None));
+        assert!(!is_elf(&dir.join("script")));
+        assert!(is_elf(&dir.join("truncated")));
+
+        let missing = dir.join("missing");
+        assert!(ElfArch::read(&missing)
+            .unwrap_err()
+            .starts_with(&missing.display().to_string()));
+    }
+
+    #[test]
+    fn triples_name_their_architecture() {
+        let name = |triple: &str| ElfArch::for_triple(triple).map(|arch| arch.to_string());
+        assert_eq!(name("arm-unknown-linux-gnueabihf").as_deref(), Some("arm"));
+        assert_eq!(
+            name("armv5te-unknown-linux-gnueabi").as_deref(),
+            Some("arm")
+        );
+        assert_eq!(name("thumbv7em-none-eabihf").as_deref(), Some("arm"));
+        assert_eq!(name("i686-unknown-linux-gnu").as_deref(), Some("x86"));
+        assert_eq!(
+            name("aarch64-unknown-linux-gnu").as_deref(),
+            Some("aarch64")
+        );
+        assert_eq!(
+            name("mips64el-unknown-linux-gnuabi64").as_deref(),
+            Some("mips64")
+        );
+        assert_eq!(name("wasm32-unknown-unknown"), None);
+        assert_eq!(
+            ElfArch {
+                machine: 999,
+                wide: true
+            }
+            .to_string(),
+            "ELF machine 999 (64-bit)"
+        );
+    }
+}
@@ -0,0 +1,824 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Running cargo for each selected target, and what follows a build
+//! which succeeded: checking and stripping the binaries, the post-build
+//! hooks, and staging and printing what was built.
+
+use crate::artifacts::{
+    executables, file_size, find_binaries, only_packages, outputs, profile_dir, stage,
+    strip as strip_binary, ElfArch,
+};
+use crate::error::{self, Error};
+use crate::json::json_string;
+use crate::manifest::{self, target_dir};
+use crate::preflight::unavailable_reason;
+use crate::targets::Target;
+use crate::toolchain::{
+    find_in_path, host_triple, is_host_triple, rust_sysroot, target_linker, toolchain_binutils,
+};
+use crate::{
+    cargo_params, color, deploy, events, hooks, log, prepare_build, process, shell_quote, yotta,
+    BuildOptions,
+};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// How to tag the output of a build among several others
+struct OutputTag {
+    tag: String,
+    /// ANSI color code
+    color: u8,
+}
+
+/// Colors for the output tags of multi-target builds, used in turn
+const TAG_COLORS: &[u8] = &[36, 35, 33, 32, 34];
+
+impl OutputTag {
+    /// Tag a target with its shortest name
+    fn new(target: &Target, index: usize) -> Self {
+        let tag = target
+            .aliases
+            .iter()
+            .chain(std::iter::once(&target.name))
+            .min_by_key(|name| name.len())
+            .cloned()
+            .unwrap_or_default();
+        OutputTag {
+            tag,
+            color: TAG_COLORS[index % TAG_COLORS.len()],
+        }
+    }
+}
+
+/// What to do with cargo's output
+enum Output<'a> {
+    /// Pass it straight through
+    Inherit,
+    /// Tag each line with the target being built
+    Tagged(&'a OutputTag),
+    /// Collect cargo's JSON messages from stdout
+    Messages(&'a mut Vec<u8>),
+}
+
+/// The program and command which run cargo for the target, in the
+/// build container if there is one, ready to be started
+fn build_command(
+    target: &Target,
+    command: Vec<String>,
+    extra_params: Vec<String>,
+    options: &BuildOptions,
+) -> Result<(String, Command), Error> {
+    let (params, mut build_env) = prepare_build(target, command, extra_params, options);
+    build_env.set("CARGO_TERM_COLOR", color::cargo());
+    let (program, mut command) = match options.container {
+        Some(ref container) => (
+            container.engine(),
+            container
+                .command(target, &params, &build_env, &options.cache)
+                .map_err(Error::Environment)?,
+        ),
+        None => {
+            let mut command = Command::new("cargo");
+            build_env.apply(&mut command);
+            command.args(&params);
+            (String::from("cargo"), command)
+        }
+    };
+    command.args(&options.raw_args);
+    Ok((program, command))
+}
+
+/// Build the target's yotta modules, then run cargo, returning how it exited
+/// or `None` if it was killed for taking longer than the timeout
+fn cargo_command(
+    target: &Target,
+    command: Vec<String>,
+    extra_params: Vec<String>,
+    options: &BuildOptions,
+    output: Output,
+    runner: &dyn process::Runner,
+) -> Result<Option<ExitStatus>, Error> {
+    // Several packages may wrap the same module, which only needs building once
+    let mut built: Vec<&Path> = vec![];
+    for module in &options.yotta {
+        if built.contains(&module.dir.as_path()) {
+            continue;
+        }
+        built.push(&module.dir);
+        yotta::build(module, &target.name).map_err(Error::Failed)?;
+    }
+    let (program, mut command) = build_command(target, command, extra_params, options)?;
+    if log::enabled(log::Level::Debug) {
+        let argv: Vec<String> = command
+            .get_args()
+            .map(|arg| shell_quote(&arg.to_string_lossy()))
+            .collect();
+        log!(Debug, "running {} {}", program, argv.join(" "));
+    }
+    command
+        .stdin(Stdio::inherit())
+        .stdout(events::child_stdout())
+        .stderr(Stdio::inherit());
+    let (capture, messages) = match output {
+        Output::Inherit => (process::Capture::Nothing, None),
+        Output::Tagged(tag) => (
+            process::Capture::Prefixed {
+                tag: &tag.tag,
+                color: tag.color,
+            },
+            None,
+        ),
+        Output::Messages(messages) => (process::Capture::Messages, Some(messages)),
+    };
+    let start = Instant::now();
+    let status = runner
+        .run(&mut command, options.timeout, capture)
+        .map(|(status, captured)| {
+            if let Some(messages) = messages {
+                match options.packages {
+                    Some(ref manifests) => messages.extend(only_packages(&captured, manifests)),
+                    None => messages.extend(captured),
+                }
+            }
+            status
+        });
+    let status = match status {
+        Ok(status) => status,
+        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+            error!(
+                "timed out building {} after {}, killed cargo",
+                target.name,
+                process::format_duration(start.elapsed())
+            );
+            return Ok(None);
+        }
+        Err(source) => return Err(Error::Spawn { program, source }),
+    };
+    if let Some(description) = process::describe_abnormal(&program, &status) {
+        error!("{}", description);
+    }
+    Ok(Some(status))
+}
+
+/// Steps to run once a target has been built successfully, given cargo's
+/// JSON messages if they were collected. On failure, prints the error
+/// and returns the exit code to fail with.
+pub fn post_build(
+    target: &Target,
+    params: &[String],
+    options: &BuildOptions,
+    messages: Option<&[u8]>,
+) -> Result<(), i32> {
+    let building = params.first().map(String::as_str) == Some("build");
+    if !building {
+        return Ok(());
+    }
+
+    let built = built_artifacts(target, params, messages);
+    if options.verify_arch {
+        verify_arch(target, &built).map_err(|e| {
+            error!("{}", e);
+            1
+        })?;
+    }
+    if options.strip && !is_host_triple(&target.triple) {
+        strip_binaries(target, params, options).map_err(|e| {
+            error!("{}", e);
+            1
+        })?;
+    }
+    if !options.no_hooks {
+        run_hooks(target, params, &built)?;
+    }
+    Ok(())
+}
+
+/// The executables and libraries built for the target, as cargo reported
+/// them if its messages were collected, or else the binaries found in
+/// the output directory
+fn built_artifacts(target: &Target, params: &[String], messages: Option<&[u8]>) -> Vec<PathBuf> {
+    match messages {
+        Some(messages) => outputs(messages),
+        None => target_dir(params)
+            .map(|dir| dir.join(&target.triple).join(profile_dir(params)))
+            .and_then(|dir| find_binaries(&dir).ok())
+            .unwrap_or_default(),
+    }
+}
+
+/// Check that each ELF artifact was built for the target's architecture,
+/// which it won't be if the host's linker was used by mistake
+fn verify_arch(target: &Target, artifacts: &[PathBuf]) -> Result<(), String> {
+    let expected = match ElfArch::for_triple(&target.triple) {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    for artifact in artifacts {
+        match ElfArch::read(artifact)? {
+            Some(arch) if arch != expected => {
+                return Err(format!(
+                    "built artifact {} is {} but target {} is {}; the host's \
+                     linker may have been used, check with `cargo kubos doctor` \
+                     or pass --no-verify-arch if this is expected",
+                    artifact.display(),
+                    arch,
+                    target.name,
+                    expected
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Run the crate's post-build hooks, telling them what was built
+fn run_hooks(target: &Target, params: &[String], artifacts: &[PathBuf]) -> Result<(), i32> {
+    let hooks = hooks::load(params).map_err(|e| {
+        error!("{}", e);
+        1
+    })?;
+    if hooks.post_build.is_empty() {
+        return Ok(());
+    }
+
+    let profile = profile_dir(params);
+    let build = hooks::Build {
+        target,
+        profile: &profile,
+        artifacts,
+    };
+    hooks::run_post_build(&hooks, &build).map_err(|(e, code)| {
+        error!("{}", e);
+        code
+    })
+}
+
+/// Find the strip for the target: the host's for a host target, or
+/// else the cross toolchain's, refusing to fall back to the host's
+pub fn strip_program(target: &Target, options: &BuildOptions) -> Result<PathBuf, String> {
+    if is_host_triple(&target.triple) {
+        return find_in_path("strip").ok_or_else(|| String::from("strip not found on PATH"));
+    }
+    let linker = target_linker(target, options.discover, &options.cache).map_err(|e| {
+        format!(
+            "can't strip the {} binaries without a cross toolchain: {}",
+            target.name, e
+        )
+    })?;
+    toolchain_binutils(&linker)
+        .into_iter()
+        .find(|(var, _)| *var == "STRIP")
+        .map(|(_, path)| path)
+        .ok_or_else(|| {
+            format!(
+                "no cross strip found alongside {} for target {}, \
+                 refusing to strip with the host's",
+                linker, target.name
+            )
+        })
+}
+
+/// Strip the target's cross binaries
+fn strip_binaries(
+    target: &Target,
+    params: &[String],
+    options: &BuildOptions,
+) -> Result<(), String> {
+    let strip = strip_program(target, options)?;
+    let dir = target_dir(params)
+        .ok_or_else(|| String::from("could not find the cargo target directory"))?
+        .join(&target.triple)
+        .join(profile_dir(params));
+    for binary in find_binaries(&dir)? {
+        let (before, after) = strip_binary(&strip, &binary)?;
+        info!(
+            "stripped {}: {} -> {} bytes",
+            binary.display(),
+            before,
+            after
+        );
+    }
+    Ok(())
+}
+
+/// Copy the target's executables into their own directory under
+/// --out-dir, named with the --artifact-suffix, returning each
+/// copy made and its size
+fn stage_artifacts(
+    target: &Target,
+    params: &[String],
+    executables: &[PathBuf],
+    out_dir: &Path,
+    suffix: &str,
+) -> Result<Vec<(PathBuf, u64)>, String> {
+    let suffix = artifact_suffix(suffix, params, target)?;
+    let dir = out_dir.join(&target.name);
+    executables
+        .iter()
+        .map(|binary| {
+            let staged = stage(binary, &dir, &suffix)?;
+            Ok((staged.clone(), file_size(&staged)?))
+        })
+        .collect()
+}
+
+/// Expand `{name}`, `{version}` and `{target}` in an --artifact-suffix
+fn artifact_suffix(template: &str, params: &[String], target: &Target) -> Result<String, String> {
+    if !template.contains('{') {
+        return Ok(String::from(template));
+    }
+    let package = manifest::package(params)?;
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unterminated {{...}} in --artifact-suffix '{}'", template))?;
+        match &rest[start + 1..start + end] {
+            "name" => out.push_str(&package.name),
+            "version" => out.push_str(&package.version),
+            "target" => out.push_str(&target.name),
+            name => {
+                return Err(format!(
+                    "unknown placeholder {{{}}} in --artifact-suffix '{}', \
+                     expected {{name}}, {{version}} or {{target}}",
+                    name, template
+                ))
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// List everything copied into --out-dir
+fn print_staged(staged: &[(PathBuf, u64)]) {
+    for (path, size) in staged {
+        events::Event::new("staged")
+            .string("path", &path.to_string_lossy())
+            .number("size", size)
+            .emit();
+    }
+    if staged.is_empty() {
+        info!("nothing was staged");
+        return;
+    }
+    let lines: Vec<String> = staged
+        .iter()
+        .map(|(path, size)| format!("    {} ({} bytes)", path.display(), size))
+        .collect();
+    info!("staged:\n{}", lines.join("\n"));
+}
+
+/// Print the paths of the executables and libraries built for each
+/// target, one per line or as JSON, and nothing else
+fn print_artifacts(artifacts: &[(&Target, Vec<PathBuf>)], json: bool) {
+    // Each was reported with an artifact event as it was built
+    if events::json() {
+        return;
+    }
+    if !json {
+        for (_, paths) in artifacts {
+            for path in paths {
+                println!("{}", path.display());
+            }
+        }
+        return;
+    }
+    let entries: Vec<String> = artifacts
+        .iter()
+        .flat_map(|(target, paths)| {
+            paths.iter().map(move |path| {
+                format!(
+                    "{{\"target\":{},\"triple\":{},\"path\":{}}}",
+                    json_string(&target.name),
+                    json_string(&target.triple),
+                    json_string(&path.to_string_lossy())
+                )
+            })
+        })
+        .collect();
+    println!("[{}]", entries.join(","));
+}
+
+/// How building one of several targets went
+enum Outcome {
+    /// cargo ran to completion, successfully or not
+    Built(ExitStatus),
+    /// cargo succeeded but a post-build step failed, with this exit code
+    PostBuildFailed(i32),
+    /// cargo was killed for running past --timeout
+    TimedOut,
+    /// The target was skipped, for the given reason
+    Skipped(&'static str),
+}
+
+impl Outcome {
+    /// The result and detail for the summary
+    fn describe(&self) -> (&'static str, String) {
+        match self {
+            Outcome::Built(status) if status.success() => ("passed", String::new()),
+            Outcome::Built(status) => ("failed", status.to_string()),
+            Outcome::PostBuildFailed(_) => ("failed", String::from("post-build")),
+            Outcome::TimedOut => ("failed", String::from("timed out")),
+            Outcome::Skipped(reason) => ("skipped", String::from(*reason)),
+        }
+    }
+
+    /// How bad the result is, for picking the worst of several. An
+    /// interrupt is the worst, as the user asked to stop, then cargo being
+    /// killed, timing out, a program which couldn't be run, cargo failing,
+    /// and lastly a post-build step failing.
+    fn severity(&self) -> u8 {
+        match self {
+            Outcome::Built(status) if status.success() => 0,
+            Outcome::Built(status) if process::interrupted(status) => 6,
+            Outcome::Built(status) if status.code().is_none() => 5,
+            Outcome::Built(_) => 2,
+            Outcome::TimedOut => 4,
+            Outcome::PostBuildFailed(process::SPAWN_FAILURE_CODE) => 3,
+            Outcome::PostBuildFailed(_) => 1,
+            Outcome::Skipped(_) => 0,
+        }
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Built(status) if status.success() => 0,
+            Outcome::Built(status) => process::exit_code(status),
+            Outcome::PostBuildFailed(code) => *code,
+            Outcome::TimedOut => process::TIMEOUT_CODE,
+            Outcome::Skipped(_) => 0,
+        }
+    }
+}
+
+/// Report a build starting, when writing events
+fn report_start(target: &Target, command: &[String]) {
+    events::Event::new("build-start")
+        .string("target", &target.name)
+        .string("triple", &target.triple)
+        .strings("command", command)
+        .emit();
+}
+
+/// Report what a build built and how it went, when writing events
+fn report_finish(target: &Target, outcome: &Outcome, elapsed: Duration, built: &[PathBuf]) {
+    for path in built {
+        events::Event::new("artifact")
+            .string("target", &target.name)
+            .string("triple", &target.triple)
+            .string("path", &path.to_string_lossy())
+            .emit();
+    }
+    let (status, detail) = outcome.describe();
+    events::Event::new("build-finish")
+        .string("target", &target.name)
+        .string("triple", &target.triple)
+        .string("status", status)
+        .string("detail", &detail)
+        .number("exit_code", outcome.exit_code())
+        .number("duration", format!("{:.3}", elapsed.as_secs_f64()))
+        .emit();
+}
+
+/// Build the target and pick out the one executable built, or
+/// return the exit code to fail with
+pub fn build_binary(
+    target: &Target,
+    command: Vec<String>,
+    extra_params: Vec<String>,
+    options: &BuildOptions,
+) -> Result<PathBuf, i32> {
+    let params = cargo_params(&command, &extra_params);
+    let messages = build_messages(target, command, extra_params, options)?;
+    post_build(target, &params, options, Some(&messages))?;
+
+    deploy::select_binary(&executables(&messages)).map_err(|e| {
+        error!("{}", e);
+        deploy::ARTIFACT_FAILURE_CODE
+    })
+}
+
+/// Build the target and return cargo's JSON messages, or
+/// the exit code to fail with
+pub fn build_messages(
+    target: &Target,
+    mut command: Vec<String>,
+    extra_params: Vec<String>,
+    options: &BuildOptions,
+) -> Result<Vec<u8>, i32> {
+    report_start(target, &command);
+    let start = Instant::now();
+    command.push(String::from("--message-format=json-render-diagnostics"));
+    let mut messages = vec![];
+    let status = cargo_command(
+        target,
+        command,
+        extra_params,
+        options,
+        Output::Messages(&mut messages),
+        &process::System,
+    );
+    let outcome = match status {
+        Ok(Some(status)) => Outcome::Built(status),
+        Ok(None) => Outcome::TimedOut,
+        Err(e) => {
+            error!("{}", e);
+            events::Event::new("error")
+                .string("message", &e.to_string())
+                .number("exit_code", e.exit_code())
+                .emit();
+            return Err(e.exit_code());
+        }
+    };
+    let built = match outcome {
+        Outcome::Built(status) if status.success() => outputs(&messages),
+        _ => vec![],
+    };
+    report_finish(target, &outcome, start.elapsed(), &built);
+    match outcome {
+        Outcome::Built(status) if status.success() => Ok(messages),
+        Outcome::Built(status) => {
+            error!("building {} failed ({})", target.name, status);
+            Err(process::exit_code(&status))
+        }
+        _ => Err(process::TIMEOUT_CODE),
+    }
+}
+
+/// What's done with each target's build besides running cargo
+pub struct Settings {
+    /// Whether cargo's JSON messages are read to know what was built
+    pub collect: bool,
+    /// Whether the user asked for cargo's JSON messages themselves
+    pub json_messages: bool,
+    /// Where to copy the executables built, with --out-dir
+    pub out_dir: Option<PathBuf>,
+    /// The --artifact-suffix template for the copies' names
+    pub suffix: String,
+    pub print_artifact: bool,
+    /// Whether the artifacts are printed as JSON
+    pub json: bool,
+    /// Whether each target's output is tagged with its name
+    pub prefix: bool,
+    pub fail_fast: bool,
+    /// Whether targets which can't be built here are skipped, for `-t all`
+    pub skip_unavailable: bool,
+}
+
+/// Build for a single target, exiting as cargo did
+pub fn one(
+    target: &Target,
+    command: Vec<String>,
+    extra_params: Vec<String>,
+    options: &BuildOptions,
+    settings: &Settings,
+) -> Result<(), Error> {
+    let params = cargo_params(&command, &extra_params);
+    let mut messages = vec![];
+    let output = if settings.collect {
+        Output::Messages(&mut messages)
+    } else {
+        Output::Inherit
+    };
+    report_start(target, &command);
+    let start = Instant::now();
+    let status = match cargo_command(
+        target,
+        command,
+        extra_params,
+        options,
+        output,
+        &process::System,
+    )? {
+        Some(status) => status,
+        None => {
+            report_finish(target, &Outcome::TimedOut, start.elapsed(), &[]);
+            return Err(Error::Exit(process::TIMEOUT_CODE));
+        }
+    };
+
+    // Attempt to exit in a way which
+    // honors the subprocess exit code
+    if !status.success() {
+        report_finish(target, &Outcome::Built(status), start.elapsed(), &[]);
+        return error::child("cargo", status);
+    }
+    let messages = if settings.collect {
+        Some(messages.as_slice())
+    } else {
+        None
+    };
+    if let Err(code) = post_build(target, &params, options, messages) {
+        let outcome = Outcome::PostBuildFailed(code);
+        report_finish(target, &outcome, start.elapsed(), &[]);
+        return Err(Error::Exit(code));
+    }
+    report_finish(
+        target,
+        &Outcome::Built(status),
+        start.elapsed(),
+        &outputs(messages.unwrap_or_default()),
+    );
+    if let Some(ref out_dir) = settings.out_dir {
+        let executables = executables(messages.unwrap_or_default());
+        match stage_artifacts(target, &params, &executables, out_dir, &settings.suffix) {
+            Ok(staged) => print_staged(&staged),
+            Err(e) => return Err(Error::Failed(e)),
+        }
+    }
+    if settings.print_artifact {
+        print_artifacts(
+            &[(target, outputs(messages.unwrap_or_default()))],
+            settings.json,
+        );
+    }
+    Ok(())
+}
+
+/// Build for each target in turn, summarising how each went and exiting
+/// with the worst result of them
+pub fn all(
+    targets: &[Target],
+    command: &[String],
+    extra_params: &[String],
+    options: &BuildOptions,
+    settings: &Settings,
+) -> Result<(), Error> {
+    let (sysroot, host) = if settings.skip_unavailable {
+        (rust_sysroot(), host_triple())
+    } else {
+        (None, None)
+    };
+
+    let mut results: Vec<(&Target, Outcome)> = vec![];
+    let mut artifacts: Vec<(&Target, Vec<PathBuf>)> = vec![];
+    let mut staged_paths: Vec<(PathBuf, u64)> = vec![];
+    for (index, target) in targets.iter().enumerate() {
+        if settings.skip_unavailable {
+            if let Some(reason) = unavailable_reason(
+                target,
+                sysroot.as_ref(),
+                host.as_ref(),
+                options.discover,
+                &options.cache,
+            ) {
+                info!("skipping {} ({})", target.name, reason);
+                let outcome = Outcome::Skipped(reason);
+                report_finish(target, &outcome, Duration::ZERO, &[]);
+                results.push((target, outcome));
+                continue;
+            }
+        }
+        let tag = if settings.prefix {
+            Some(OutputTag::new(target, index))
+        } else {
+            None
+        };
+        let mut messages = vec![];
+        let output = if settings.collect {
+            Output::Messages(&mut messages)
+        } else {
+            tag.as_ref().map_or(Output::Inherit, Output::Tagged)
+        };
+        report_start(target, command);
+        let start = Instant::now();
+        let outcome = match cargo_command(
+            target,
+            command.to_vec(),
+            extra_params.to_vec(),
+            options,
+            output,
+            &process::System,
+        )? {
+            Some(status) if status.success() => {
+                let params = cargo_params(command, extra_params);
+                let collected = if settings.collect {
+                    Some(messages.as_slice())
+                } else {
+                    None
+                };
+                let staged = match (
+                    post_build(target, &params, options, collected),
+                    &settings.out_dir,
+                ) {
+                    (Err(code), _) => Err(code),
+                    (Ok(()), Some(out_dir)) => stage_artifacts(
+                        target,
+                        &params,
+                        &executables(&messages),
+                        out_dir,
+                        &settings.suffix,
+                    )
+                    .map_err(|e| {
+                        error!("{}", e);
+                        1
+                    }),
+                    (Ok(()), None) => Ok(vec![]),
+                };
+                match staged {
+                    Ok(paths) => {
+                        staged_paths.extend(paths);
+                        artifacts.push((target, outputs(&messages)));
+                        Outcome::Built(status)
+                    }
+                    Err(code) => Outcome::PostBuildFailed(code),
+                }
+            }
+            Some(status) => Outcome::Built(status),
+            None => Outcome::TimedOut,
+        };
+        let built = match outcome {
+            Outcome::Built(status) if status.success() => outputs(&messages),
+            _ => vec![],
+        };
+        report_finish(target, &outcome, start.elapsed(), &built);
+        let failed = outcome.exit_code() != 0;
+        // Don't carry on with the other targets after Ctrl-C
+        let interrupted = match outcome {
+            Outcome::Built(ref status) => process::interrupted(status),
+            _ => false,
+        };
+        results.push((target, outcome));
+
+        if interrupted {
+            break;
+        }
+        if failed && settings.fail_fast && index + 1 < targets.len() {
+            info!(
+                "not building the remaining targets after {} failed",
+                target.name
+            );
+            break;
+        }
+    }
+
+    if settings.json_messages {
+        // Alongside cargo's own JSON messages, distinguished by the reason
+        let entries: Vec<String> = results
+            .iter()
+            .map(|(target, outcome)| {
+                let (result, detail) = outcome.describe();
+                format!(
+                    "{{\"target\":{},\"triple\":{},\"result\":{},\"detail\":{}}}",
+                    json_string(&target.name),
+                    json_string(&target.triple),
+                    json_string(result),
+                    json_string(&detail)
+                )
+            })
+            .collect();
+        println!(
+            "{{\"reason\":\"kubos-build-summary\",\"targets\":[{}]}}",
+            entries.join(",")
+        );
+    } else {
+        let summary: Vec<String> = results
+            .iter()
+            .map(|(target, outcome)| match outcome.describe() {
+                (result, ref detail) if detail.is_empty() => format!("{}: {}", target.name, result),
+                (result, detail) => format!("{}: {} ({})", target.name, result, detail),
+            })
+            .collect();
+        info!("{}", summary.join(", "));
+    }
+
+    if settings.out_dir.is_some() {
+        print_staged(&staged_paths);
+    }
+    if settings.print_artifact {
+        print_artifacts(&artifacts, settings.json);
+    }
+
+    // Exit with the worst result of all the builds, the first of those
+    // as bad. The codes alone don't say which is worse: a cargo which
+    // exits with 200 would beat one killed by SIGKILL, exiting with 137.
+    let worst = results.iter().map(|(_, outcome)| outcome).fold(
+        None,
+        |worst: Option<&Outcome>, outcome| match worst {
+            Some(worst) if worst.severity() >= outcome.severity() => Some(worst),
+            _ => Some(outcome),
+        },
+    );
+    error::status(worst.map_or(0, Outcome::exit_code))
+}
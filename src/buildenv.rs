@@ -0,0 +1,68 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::env;
+use std::process::Command;
+
+/// The environment variables cargo-kubos adds to the cargo child process
+#[derive(Debug, Default)]
+pub struct BuildEnv {
+    vars: Vec<(String, String)>,
+}
+
+impl BuildEnv {
+    /// An environment adding nothing yet
+    pub fn new() -> Self {
+        BuildEnv::default()
+    }
+
+    /// Set a variable, replacing any inherited or earlier value
+    pub fn set<V: Into<String>>(&mut self, key: &str, value: V) {
+        let value = value.into();
+        match self.vars.iter_mut().find(|(k, _)| k == key) {
+            Some(var) => var.1 = value,
+            None => self.vars.push((String::from(key), value)),
+        }
+    }
+
+    /// Set a variable unless it's already set, either in the
+    /// environment cargo-kubos was run with or by an earlier call
+    pub fn set_default<V: Into<String>>(&mut self, key: &str, value: V) {
+        if env::var_os(key).is_none() && self.get(key).is_none() {
+            self.set(key, value);
+        }
+    }
+
+    /// The value a variable has been given, if any
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every variable which has been set, in the order they were first set
+    pub fn vars(&self) -> &[(String, String)] {
+        &self.vars
+    }
+
+    /// Add the variables to a command's environment
+    pub fn apply(&self, command: &mut Command) {
+        for (key, value) in &self.vars {
+            command.env(key, value);
+        }
+    }
+}
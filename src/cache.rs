@@ -0,0 +1,175 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! What resolving each target's build needs from the cargo configs and the
+//! toolchains, kept once found for the rest of the invocation. Nothing is
+//! read or run until a target needs it.
+
+use crate::compat::Probe;
+use crate::config::{config_paths, read_config, ConfigError, ConfigFile};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Finds and reads the cargo configs cargo would
+pub trait ConfigLoader {
+    /// The config files to read, nearest first, or if there are none,
+    /// every path one was looked for at
+    fn paths(&self) -> Result<Vec<PathBuf>, Vec<PathBuf>>;
+
+    /// Read and parse one of them
+    fn load(&self, path: &Path) -> Result<ConfigFile, ConfigError>;
+
+    /// Those given with `--cargo-config`, which cargo must be told of too
+    fn given(&self) -> &[PathBuf] {
+        &[]
+    }
+}
+
+/// Finds the configs in the config directories, after any given with
+/// `--cargo-config`, and reads them from disk
+#[derive(Default)]
+pub struct DiskLoader {
+    given: Vec<PathBuf>,
+}
+
+impl DiskLoader {
+    /// A loader which reads the given configs ahead of those found,
+    /// taking precedence over them
+    pub fn new(given: Vec<PathBuf>) -> DiskLoader {
+        DiskLoader { given }
+    }
+}
+
+impl ConfigLoader for DiskLoader {
+    fn paths(&self) -> Result<Vec<PathBuf>, Vec<PathBuf>> {
+        config_paths(&self.given)
+    }
+
+    fn load(&self, path: &Path) -> Result<ConfigFile, ConfigError> {
+        read_config(path.to_path_buf())
+    }
+
+    fn given(&self) -> &[PathBuf] {
+        &self.given
+    }
+}
+
+/// The parsed cargo configs, and what each linker said when probed,
+/// shared by every target an invocation builds
+pub struct ResolutionCache {
+    loader: Box<dyn ConfigLoader + Send + Sync>,
+    paths: OnceLock<Result<Vec<PathBuf>, Vec<PathBuf>>>,
+    /// Configs which have been read, by path. Those which couldn't be
+    /// aren't kept, as that ends the invocation wherever it matters.
+    configs: Mutex<HashMap<PathBuf, Arc<ConfigFile>>>,
+    /// Whether each linker, by path, ran `--version`
+    runs: Mutex<HashMap<PathBuf, bool>>,
+    /// Each linker's versions and sysroot, by program
+    probes: Mutex<HashMap<String, Probe>>,
+}
+
+impl Default for ResolutionCache {
+    fn default() -> Self {
+        ResolutionCache::with_loader(DiskLoader::default())
+    }
+}
+
+impl ResolutionCache {
+    /// A cache of the configs on disk
+    pub fn new() -> ResolutionCache {
+        ResolutionCache::default()
+    }
+
+    /// A cache of the configs on disk, with those given with
+    /// `--cargo-config` taking precedence
+    pub fn with_given_configs(paths: Vec<PathBuf>) -> ResolutionCache {
+        ResolutionCache::with_loader(DiskLoader::new(paths))
+    }
+
+    /// A cache of the configs the loader finds
+    pub fn with_loader<L: ConfigLoader + Send + Sync + 'static>(loader: L) -> ResolutionCache {
+        ResolutionCache {
+            loader: Box::new(loader),
+            paths: OnceLock::new(),
+            configs: Mutex::new(HashMap::new()),
+            runs: Mutex::new(HashMap::new()),
+            probes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Every cargo config file to read, nearest first, or if there
+    /// are none, every path one was looked for at
+    pub fn config_paths(&self) -> Result<Vec<PathBuf>, Vec<PathBuf>> {
+        self.paths.get_or_init(|| self.loader.paths()).clone()
+    }
+
+    /// The configs given with `--cargo-config`
+    pub fn given_configs(&self) -> &[PathBuf] {
+        self.loader.given()
+    }
+
+    /// The config at the path, read the first time it's asked for
+    pub fn config(&self, path: &Path) -> Result<Arc<ConfigFile>, ConfigError> {
+        if let Some(config) = self.configs.lock().unwrap().get(path) {
+            return Ok(Arc::clone(config));
+        }
+        let config = Arc::new(self.loader.load(path)?);
+        self.configs
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), Arc::clone(&config));
+        Ok(config)
+    }
+
+    /// The configs at the paths, in the same order
+    pub fn configs(&self, paths: &[PathBuf]) -> Result<Vec<Arc<ConfigFile>>, ConfigError> {
+        paths.iter().map(|path| self.config(path)).collect()
+    }
+
+    /// Every cargo config, nearest first, of which there may be none
+    pub fn discover_configs(&self) -> Result<Vec<Arc<ConfigFile>>, ConfigError> {
+        self.configs(&self.config_paths().unwrap_or_default())
+    }
+
+    /// Whether the linker at the path ran `--version`, running it with
+    /// `run` the first time it's asked about
+    pub fn linker_runs<F: FnOnce() -> bool>(&self, program: &Path, run: F) -> bool {
+        if let Some(works) = self.runs.lock().unwrap().get(program) {
+            return *works;
+        }
+        let works = run();
+        self.runs
+            .lock()
+            .unwrap()
+            .insert(program.to_path_buf(), works);
+        works
+    }
+
+    /// What the linker program said of its versions, asking it with
+    /// `probe` the first time
+    pub fn toolchain<F: FnOnce() -> Probe>(&self, program: &str, probe: F) -> Probe {
+        if let Some(probe) = self.probes.lock().unwrap().get(program) {
+            return probe.clone();
+        }
+        let probe = probe();
+        self.probes
+            .lock()
+            .unwrap()
+            .insert(String::from(program), probe.clone());
+        probe
+    }
+}
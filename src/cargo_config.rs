@@ -0,0 +1,372 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The parts of a cargo config cargo-kubos reads, typed, and how
+//! they combine across the config files cargo would read
+
+use crate::config::{base_dir, resolve_path, ConfigFile, EnvEntry, Linker, LinkerSource};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// `[build]`, `[target.<triple>]` and `[env]` of one cargo config.
+/// Everything else in the file is ignored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CargoConfig {
+    pub build: BuildConfig,
+    /// Keyed by triple, or by `cfg(...)` expression
+    pub target: BTreeMap<String, TargetConfig>,
+    pub env: BTreeMap<String, EnvValue>,
+}
+
+/// `[build]`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BuildConfig {
+    pub rustflags: Option<StringList>,
+}
+
+/// `[target.<triple>]`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TargetConfig {
+    pub linker: Option<LinkerValue>,
+    pub runner: Option<StringList>,
+    pub rustflags: Option<StringList>,
+}
+
+/// A list of words given either as one space-separated string or as an array
+#[derive(Clone, Debug, PartialEq)]
+pub enum StringList {
+    String(String),
+    List(Vec<String>),
+}
+
+impl StringList {
+    pub fn words(&self) -> Vec<String> {
+        match self {
+            StringList::String(words) => words.split_whitespace().map(String::from).collect(),
+            StringList::List(words) => words.clone(),
+        }
+    }
+}
+
+/// A linker given as a path, or as a `{ path = "...", args = [...] }` table
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkerValue {
+    Path(String),
+    Table { path: String, args: Vec<String> },
+}
+
+/// A variable in `[env]`, given as its value or as a
+/// `{ value = "...", force = true, relative = true }` table
+#[derive(Clone, Debug, PartialEq)]
+pub enum EnvValue {
+    Plain(String),
+    Table {
+        value: String,
+        force: bool,
+        relative: bool,
+    },
+}
+
+/// Deserialize a table into a struct of the same keys, ignoring any others
+macro_rules! config_table {
+    ($name:ident, $expecting:expr, { $($key:expr => $field:ident),* $(,)? }) => {
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct TableVisitor;
+
+                impl<'de> Visitor<'de> for TableVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str($expecting)
+                    }
+
+                    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<$name, A::Error> {
+                        let mut table = $name::default();
+                        while let Some(key) = map.next_key::<String>()? {
+                            match key.as_str() {
+                                $($key => table.$field = map.next_value()?,)*
+                                _ => {
+                                    map.next_value::<IgnoredAny>()?;
+                                }
+                            }
+                        }
+                        Ok(table)
+                    }
+                }
+
+                deserializer.deserialize_map(TableVisitor)
+            }
+        }
+    };
+}
+
+config_table!(CargoConfig, "a cargo config", {
+    "build" => build,
+    "target" => target,
+    "env" => env,
+});
+
+config_table!(BuildConfig, "a [build] table", {
+    "rustflags" => rustflags,
+});
+
+config_table!(TargetConfig, "a [target] table", {
+    "linker" => linker,
+    "runner" => runner,
+    "rustflags" => rustflags,
+});
+
+/// The table form of a linker, before its path is known to be there
+#[derive(Default)]
+struct LinkerTable {
+    path: Option<String>,
+    args: Vec<String>,
+}
+
+config_table!(LinkerTable, "a linker table", {
+    "path" => path,
+    "args" => args,
+});
+
+/// The table form of an `[env]` variable, before its value is known to be there
+#[derive(Default)]
+struct EnvTable {
+    value: Option<String>,
+    force: bool,
+    relative: bool,
+}
+
+config_table!(EnvTable, "an env table", {
+    "value" => value,
+    "force" => force,
+    "relative" => relative,
+});
+
+impl<'de> Deserialize<'de> for StringList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StringListVisitor;
+
+        impl<'de> Visitor<'de> for StringListVisitor {
+            type Value = StringList;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or array of strings")
+            }
+
+            fn visit_str<E: de::Error>(self, words: &str) -> Result<StringList, E> {
+                Ok(StringList::String(String::from(words)))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<StringList, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(StringList::List)
+            }
+        }
+
+        deserializer.deserialize_any(StringListVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for LinkerValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LinkerVisitor;
+
+        impl<'de> Visitor<'de> for LinkerVisitor {
+            type Value = LinkerValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a linker path or a table with a path")
+            }
+
+            fn visit_str<E: de::Error>(self, path: &str) -> Result<LinkerValue, E> {
+                Ok(LinkerValue::Path(String::from(path)))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<LinkerValue, A::Error> {
+                let table = LinkerTable::deserialize(MapAccessDeserializer::new(map))?;
+                let path = table.path.ok_or_else(|| de::Error::missing_field("path"))?;
+                Ok(LinkerValue::Table {
+                    path,
+                    args: table.args,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(LinkerVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for EnvValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EnvVisitor;
+
+        impl<'de> Visitor<'de> for EnvVisitor {
+            type Value = EnvValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or a table with a string value")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<EnvValue, E> {
+                Ok(EnvValue::Plain(String::from(value)))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<EnvValue, A::Error> {
+                let table = EnvTable::deserialize(MapAccessDeserializer::new(map))?;
+                let value = table
+                    .value
+                    .ok_or_else(|| de::Error::missing_field("value"))?;
+                Ok(EnvValue::Table {
+                    value,
+                    force: table.force,
+                    relative: table.relative,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(EnvVisitor)
+    }
+}
+
+/// What a `[target.<triple>]` comes to across every config
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MergedTarget {
+    /// From the nearest config which sets it
+    pub linker: Option<Linker>,
+    /// From the nearest config which sets it, with the program resolved
+    pub runner: Option<String>,
+    /// Joined from every config, with nearer configs' flags placed later
+    pub rustflags: Option<Vec<String>>,
+}
+
+/// The cargo configs cargo would read, combined as cargo combines them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MergedConfig {
+    /// `[build].rustflags` joined from every config, with nearer configs'
+    /// flags placed later
+    pub build_rustflags: Option<Vec<String>>,
+    pub targets: BTreeMap<String, MergedTarget>,
+    /// `[env]`, with the nearest config taking precedence for each variable
+    pub env: Vec<EnvEntry>,
+    /// The configs merged, nearest first
+    pub paths: Vec<PathBuf>,
+}
+
+impl MergedConfig {
+    /// Merge configs given nearest first, resolving the relative
+    /// paths in each against the project it belongs to
+    pub fn new<'a, I>(configs: I) -> MergedConfig
+    where
+        I: IntoIterator<Item = &'a ConfigFile>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        let mut merged = MergedConfig::default();
+        // Working from the farthest config, nearer ones replace what it set
+        for config in configs.into_iter().rev() {
+            merged.add(&config.cargo, &config.path);
+        }
+        merged
+    }
+
+    fn add(&mut self, config: &CargoConfig, path: &Path) {
+        self.paths.insert(0, path.to_path_buf());
+        if let Some(flags) = &config.build.rustflags {
+            self.build_rustflags
+                .get_or_insert_with(Vec::new)
+                .extend(flags.words());
+        }
+
+        for (triple, target) in &config.target {
+            let merged = self.targets.entry(triple.clone()).or_default();
+            if let Some(linker) = &target.linker {
+                merged.linker = Some(linker.resolve(path));
+            }
+            if let Some(runner) = &target.runner {
+                let mut words = runner.words().into_iter();
+                merged.runner = words.next().map(|program| {
+                    let mut runner = resolve_path(path, &program);
+                    for word in words {
+                        runner.push(' ');
+                        runner.push_str(&word);
+                    }
+                    runner
+                });
+            }
+            if let Some(flags) = &target.rustflags {
+                merged
+                    .rustflags
+                    .get_or_insert_with(Vec::new)
+                    .extend(flags.words());
+            }
+        }
+
+        for (key, value) in &config.env {
+            let entry = value.resolve(key, path);
+            match self.env.iter_mut().find(|e| e.key == *key) {
+                Some(existing) => *existing = entry,
+                None => self.env.push(entry),
+            }
+        }
+    }
+
+    pub fn target(&self, triple: &str) -> Option<&MergedTarget> {
+        self.targets.get(triple)
+    }
+}
+
+impl LinkerValue {
+    /// The linker, with its path resolved against the config at `path`
+    pub fn resolve(&self, path: &Path) -> Linker {
+        let source = LinkerSource::Config(path.to_path_buf());
+        match self {
+            LinkerValue::Path(linker) => Linker::new(&resolve_path(path, linker), source),
+            LinkerValue::Table { path: linker, args } => Linker {
+                path: resolve_path(path, linker),
+                args: args.clone(),
+                source,
+            },
+        }
+    }
+}
+
+impl EnvValue {
+    /// The variable, with a relative value resolved against the config at `path`
+    pub fn resolve(&self, key: &str, path: &Path) -> EnvEntry {
+        let (value, force) = match self {
+            EnvValue::Plain(value) => (value.clone(), false),
+            EnvValue::Table {
+                value,
+                force,
+                relative,
+            } => {
+                let value = if *relative {
+                    base_dir(path).join(value).to_string_lossy().into_owned()
+                } else {
+                    value.clone()
+                };
+                (value, *force)
+            }
+        };
+        EnvEntry {
+            key: String::from(key),
+            value,
+            force,
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Just enough CBOR (RFC 7049) to speak the file-transfer-service protocol,
+//! whose messages are arrays of integers, strings, byte strings and booleans
+
+/// A CBOR value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cbor {
+    Unsigned(u64),
+    /// A negative integer, stored as -1 - n like the encoding
+    Negative(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Bool(bool),
+    Null,
+}
+
+impl Cbor {
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Cbor::Unsigned(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Cbor::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Encode a value in the shortest form, as serde_cbor's packed encoding does
+pub fn encode(value: &Cbor) -> Vec<u8> {
+    let mut out = vec![];
+    write(&mut out, value);
+    out
+}
+
+fn write(out: &mut Vec<u8>, value: &Cbor) {
+    match value {
+        Cbor::Unsigned(n) => write_head(out, 0, *n),
+        Cbor::Negative(n) => write_head(out, 1, *n),
+        Cbor::Bytes(bytes) => {
+            write_head(out, 2, bytes.len() as u64);
+            out.extend_from_slice(bytes);
+        }
+        Cbor::Text(text) => {
+            write_head(out, 3, text.len() as u64);
+            out.extend_from_slice(text.as_bytes());
+        }
+        Cbor::Array(items) => {
+            write_head(out, 4, items.len() as u64);
+            for item in items {
+                write(out, item);
+            }
+        }
+        Cbor::Bool(false) => out.push(0xf4),
+        Cbor::Bool(true) => out.push(0xf5),
+        Cbor::Null => out.push(0xf6),
+    }
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= u64::from(u8::MAX) {
+        out.push(major | 24);
+        out.push(n as u8);
+    } else if n <= u64::from(u16::MAX) {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u64::from(u32::MAX) {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+/// Decode one complete value
+pub fn decode(data: &[u8]) -> Result<Cbor, String> {
+    let mut rest = data;
+    let value = read(&mut rest)?;
+    if !rest.is_empty() {
+        return Err(format!("{} bytes left over after CBOR value", rest.len()));
+    }
+    Ok(value)
+}
+
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if rest.len() < len {
+        return Err(String::from("truncated CBOR value"));
+    }
+    let (taken, left) = rest.split_at(len);
+    *rest = left;
+    Ok(taken)
+}
+
+fn read(rest: &mut &[u8]) -> Result<Cbor, String> {
+    let head = take(rest, 1)?[0];
+    let (major, info) = (head >> 5, head & 0x1f);
+    if major == 7 {
+        return match info {
+            20 => Ok(Cbor::Bool(false)),
+            21 => Ok(Cbor::Bool(true)),
+            22 | 23 => Ok(Cbor::Null),
+            _ => Err(format!("unsupported CBOR simple value {}", info)),
+        };
+    }
+
+    let n = match info {
+        0..=23 => u64::from(info),
+        24 => u64::from(take(rest, 1)?[0]),
+        25 => take(rest, 2)?.iter().fold(0, |n, b| n << 8 | u64::from(*b)),
+        26 => take(rest, 4)?.iter().fold(0, |n, b| n << 8 | u64::from(*b)),
+        27 => take(rest, 8)?.iter().fold(0, |n, b| n << 8 | u64::from(*b)),
+        _ => {
+            return Err(String::from(
+                "indefinite-length CBOR values aren't supported",
+            ))
+        }
+    };
+    match major {
+        0 => Ok(Cbor::Unsigned(n)),
+        1 => Ok(Cbor::Negative(n)),
+        2 => Ok(Cbor::Bytes(take(rest, n as usize)?.to_vec())),
+        3 => String::from_utf8(take(rest, n as usize)?.to_vec())
+            .map(Cbor::Text)
+            .map_err(|_| String::from("CBOR text isn't valid UTF-8")),
+        4 => {
+            // Each item takes at least a byte, which bounds a bogus length
+            if n as usize > rest.len() {
+                return Err(String::from("truncated CBOR array"));
+            }
+            (0..n)
+                .map(|_| read(rest))
+                .collect::<Result<_, _>>()
+                .map(Cbor::Array)
+        }
+        _ => Err(format!("unsupported CBOR major type {}", major)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Examples from RFC 7049's appendix A
+    fn examples() -> Vec<(Cbor, &'static [u8])> {
+        vec![
+            (Cbor::Unsigned(0), &[0x00]),
+            (Cbor::Unsigned(23), &[0x17]),
+            (Cbor::Unsigned(24), &[0x18, 0x18]),
+            (Cbor::Unsigned(1000), &[0x19, 0x03, 0xe8]),
+            (Cbor::Unsigned(1_000_000), &[0x1a, 0x00, 0x0f, 0x42, 0x40]),
+            (
+                Cbor::Unsigned(1_000_000_000_000),
+                &[0x1b, 0x00, 0x00, 0x00, 0xe8, 0xd4, 0xa5, 0x10, 0x00],
+            ),
+            (Cbor::Negative(0), &[0x20]),
+            (Cbor::Negative(99), &[0x38, 0x63]),
+            (Cbor::Bool(false), &[0xf4]),
+            (Cbor::Bool(true), &[0xf5]),
+            (Cbor::Null, &[0xf6]),
+            (Cbor::Bytes(vec![1, 2, 3, 4]), &[0x44, 1, 2, 3, 4]),
+            (Cbor::Text(String::new()), &[0x60]),
+            (
+                Cbor::Text(String::from("IETF")),
+                &[0x64, 0x49, 0x45, 0x54, 0x46],
+            ),
+            (
+                Cbor::Array(vec![
+                    Cbor::Unsigned(1),
+                    Cbor::Array(vec![Cbor::Unsigned(2), Cbor::Unsigned(3)]),
+                    Cbor::Array(vec![Cbor::Unsigned(4), Cbor::Unsigned(5)]),
+                ]),
+                &[0x83, 0x01, 0x82, 0x02, 0x03, 0x82, 0x04, 0x05],
+            ),
+        ]
+    }
+
+    #[test]
+    fn encodes_the_rfc_examples() {
+        for (value, bytes) in examples() {
+            assert_eq!(encode(&value), bytes, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn decodes_the_rfc_examples() {
+        for (value, bytes) in examples() {
+            assert_eq!(decode(bytes), Ok(value));
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let message = Cbor::Array(vec![
+            Cbor::Unsigned(u64::MAX),
+            Cbor::Text(String::from("export")),
+            Cbor::Bytes(vec![0xff; 300]),
+            Cbor::Unsigned(0o644),
+        ]);
+        assert_eq!(decode(&encode(&message)), Ok(message));
+    }
+
+    #[test]
+    fn malformed_values_are_refused() {
+        assert_eq!(decode(&[]), Err(String::from("truncated CBOR value")));
+        assert_eq!(
+            decode(&[0x19, 0x03]),
+            Err(String::from("truncated CBOR value"))
+        );
+        assert_eq!(
+            decode(&[0x9a, 0xff, 0xff, 0xff, 0xff]),
+            Err(String::from("truncated CBOR array"))
+        );
+        assert_eq!(
+            decode(&[0x5f]),
+            Err(String::from(
+                "indefinite-length CBOR values aren't supported"
+            ))
+        );
+        assert_eq!(
+            decode(&[0x62, 0xc3, 0x28]),
+            Err(String::from("CBOR text isn't valid UTF-8"))
+        );
+        assert_eq!(
+            decode(&[0x01, 0x02]),
+            Err(String::from("1 bytes left over after CBOR value"))
+        );
+    }
+}
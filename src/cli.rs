@@ -0,0 +1,641 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The command line, parsed into what cargo-kubos was asked to do.
+//! It parses the same whether cargo ran cargo-kubos, adding `kubos`
+//! after the program's name, or it was run directly. The options each
+//! command takes are defined here too, for the help and completions.
+
+use crate::error::Error;
+use crate::{
+    color, events, log, BUILDING, BUILD_COMMANDS, CARGO_COMMANDS, CARGO_SCOPE, KUBOS_COMMANDS,
+};
+use getopts::{Matches, Options};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// A parsed and checked command line
+#[derive(Debug)]
+pub struct Cli {
+    /// The options before any `--`, for those only one command looks at
+    pub matches: Matches,
+    /// The arguments before any `--`, less the program's name
+    pub args: Vec<String>,
+    /// The arguments after `--`, if it was given
+    pub passthrough: Option<Vec<String>>,
+    /// Arguments after `--` given to cargo as they are, from
+    /// the first which isn't valid UTF-8
+    pub raw_args: Vec<OsString>,
+    pub color: Option<color::Choice>,
+    /// How many times `--quiet` was given
+    pub quiet: usize,
+    /// How many times `-v` was given
+    pub verbose: usize,
+    pub log_level: Option<log::Level>,
+    pub output_format: Option<events::Format>,
+    /// The configs given with `--cargo-config`
+    pub cargo_configs: Vec<PathBuf>,
+    /// The rustup toolchain a leading `+toolchain` selects
+    pub plus_toolchain: Option<String>,
+    /// The first positional argument, whichever command it names
+    pub subcommand: Option<String>,
+    /// A cargo command given as the first argument rather than with -c
+    pub positional_command: Option<String>,
+    /// Whether that's a command cargo-kubos doesn't know, forwarded to cargo
+    pub forwarded: bool,
+    /// The command whose options apply, with `None` for all of them
+    pub scope: Option<&'static str>,
+    /// A command which builds and then does something with what it built
+    pub build_command: Option<String>,
+    /// The positional arguments left over, and those after `--`,
+    /// which are for cargo unless they're for the program
+    pub user_params: Vec<String>,
+    /// The program's arguments, for run-remote and debug
+    pub program_args: Vec<String>,
+}
+
+impl Cli {
+    /// Parse the command line, the program's name first. Only the arguments
+    /// after `--` need be valid UTF-8, those of them from the first which
+    /// isn't going to cargo untouched after all the others.
+    pub fn parse<I: IntoIterator<Item = OsString>>(args_os: I) -> Result<Cli, Error> {
+        let mut args: Vec<String> = vec![];
+        let mut raw_args: Vec<OsString> = vec![];
+        for (i, arg) in args_os.into_iter().enumerate() {
+            if !raw_args.is_empty() {
+                raw_args.push(arg);
+                continue;
+            }
+            match arg.into_string() {
+                Ok(arg) => args.push(arg),
+                Err(arg) if i == 0 => args.push(arg.to_string_lossy().into_owned()),
+                Err(arg) if args[1..].iter().any(|arg| arg == "--") => raw_args.push(arg),
+                Err(arg) => {
+                    return Err(Error::Usage(format!(
+                        "argument '{}' isn't valid UTF-8, which only those after -- may not be",
+                        arg.to_string_lossy()
+                    )));
+                }
+            }
+        }
+        if args.is_empty() {
+            args.push(String::from("cargo-kubos"));
+        }
+
+        // Everything after `--` is passed to cargo untouched, so only
+        // parse the options before it
+        let (own_args, passthrough) = match args[1..].iter().position(|arg| arg == "--") {
+            Some(split) => (
+                args[1..split + 1].to_vec(),
+                Some(args[split + 2..].to_vec()),
+            ),
+            None => (args[1..].to_vec(), None),
+        };
+        let matches = options(None)
+            .parse(&own_args)
+            .map_err(|f| Error::Usage(format!("{}, see `cargo kubos --help`", f)))?;
+
+        let color = match matches.opt_str("color") {
+            Some(choice) => Some(color::Choice::parse(&choice).map_err(Error::Usage)?),
+            None => None,
+        };
+        let quiet = matches.opt_count("quiet");
+        if quiet > 0 && matches.opt_present("verbose") {
+            return Err(Error::Usage(String::from(
+                "--quiet and --verbose can't be used together",
+            )));
+        }
+        let log_level = match matches.opt_str("log-level") {
+            Some(level) => Some(log::Level::parse(&level).map_err(Error::Usage)?),
+            None => None,
+        };
+        let output_format = match matches.opt_str("output-format") {
+            Some(format) => Some(events::Format::parse(&format).map_err(Error::Usage)?),
+            None => None,
+        };
+        let cargo_configs: Vec<PathBuf> = matches
+            .opt_strs("cargo-config")
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+
+        // cargo runs us as `cargo-kubos kubos ...`, so drop that first token
+        let mut positional = matches.free.clone();
+        if positional.first().map(String::as_str) == Some("kubos") {
+            positional.remove(0);
+        }
+
+        // A leading `+toolchain` selects the rustup toolchain, like it does for cargo
+        let plus_toolchain = match positional.first() {
+            Some(arg) if arg.starts_with('+') => Some(positional.remove(0)[1..].to_owned()),
+            _ => None,
+        };
+        let subcommand = positional.first().cloned();
+
+        // Accept the cargo command as the first argument, like plain cargo.
+        // Without -c, anything which isn't one of our own commands is taken
+        // to be a cargo command too, so that `cargo kubos nextest run` works.
+        let positional_command = match subcommand {
+            Some(ref command) if CARGO_COMMANDS.contains(&command.as_str()) => {
+                positional.remove(0);
+                Some(command.clone())
+            }
+            Some(ref command)
+                if !matches.opt_present("c")
+                    && !KUBOS_COMMANDS.contains(&command.as_str())
+                    && !BUILD_COMMANDS.contains(&command.as_str()) =>
+            {
+                positional.remove(0);
+                Some(command.clone())
+            }
+            _ => None,
+        };
+        // The same goes for the command given with -c, like -c "nextest run"
+        let forwarded = match (&positional_command, matches.opt_str("c")) {
+            (Some(command), _) => !CARGO_COMMANDS.contains(&command.as_str()),
+            (None, Some(command)) => command
+                .split_whitespace()
+                .next()
+                .is_some_and(|name| !CARGO_COMMANDS.contains(&name)),
+            (None, None) => false,
+        };
+
+        // Each command only takes its own options, except with -c or no
+        // command at all, which take them all as they always have
+        let scope = match subcommand.as_deref() {
+            _ if positional_command.is_some() => Some(CARGO_SCOPE),
+            Some(command)
+                if KUBOS_COMMANDS.contains(&command) || BUILD_COMMANDS.contains(&command) =>
+            {
+                KUBOS_COMMANDS
+                    .iter()
+                    .chain(BUILD_COMMANDS)
+                    .find(|name| **name == command)
+                    .copied()
+            }
+            _ => None,
+        };
+        if let (Some(scope), Some(ref command)) = (scope, &subcommand) {
+            if let Err(f) = options(Some(scope)).parse(&own_args) {
+                return Err(Error::Usage(format!(
+                    "{} for `cargo kubos {}`, see `cargo kubos {} --help`",
+                    f, command, command
+                )));
+            }
+        }
+
+        // Commands which build and then deploy what they built
+        let build_command = match subcommand {
+            Some(ref command) if BUILD_COMMANDS.contains(&command.as_str()) => {
+                positional.remove(0);
+                Some(command.clone())
+            }
+            _ => None,
+        };
+
+        // Collect extra parameters. For run-remote and debug, those after --
+        // are for the program rather than cargo, as with cargo run.
+        let mut user_params = positional;
+        let mut program_args = vec![];
+        let rest = passthrough.iter().flatten().cloned();
+        if matches!(build_command.as_deref(), Some("run-remote") | Some("debug")) {
+            if !raw_args.is_empty() {
+                return Err(Error::Usage(String::from(
+                    "the program's arguments are run over ssh by a shell, so must be valid UTF-8",
+                )));
+            }
+            program_args.extend(rest);
+        } else {
+            user_params.extend(rest);
+        }
+
+        Ok(Cli {
+            verbose: matches.opt_count("v"),
+            matches,
+            args: own_args,
+            passthrough,
+            raw_args,
+            color,
+            quiet,
+            log_level,
+            output_format,
+            cargo_configs,
+            plus_toolchain,
+            subcommand,
+            positional_command,
+            forwarded,
+            scope,
+            build_command,
+            user_params,
+            program_args,
+        })
+    }
+}
+
+/// The options a command accepts, or with no command every option.
+/// Cargo's own commands, and any others forwarded to cargo, share the
+/// options of [`CARGO_SCOPE`].
+pub fn options(command: Option<&str>) -> Options {
+    let accepts = |commands: &[&str]| command.is_none_or(|c| commands.contains(&c));
+    let mut opts = Options::new();
+
+    // Every command
+    opts.optflag("h", "help", "Displays help");
+    opts.optflagmulti(
+        "v",
+        "verbose",
+        "Use verbose output, -vv also makes cargo verbose",
+    );
+    opts.optflagmulti(
+        "q",
+        "quiet",
+        "Leave out cargo-kubos' own output and make cargo quiet, -qq leaves out warnings too",
+    );
+    opts.optopt(
+        "",
+        "color",
+        "Color output, for cargo too: auto (default), always or never",
+        "WHEN",
+    );
+    opts.optopt(
+        "",
+        "log-level",
+        "Log how cargo-kubos decides what to do: info, debug or trace \
+         (default RUST_LOG, else off)",
+        "LEVEL",
+    );
+    opts.optmulti(
+        "",
+        "cargo-config",
+        "Read this cargo config before any others, passing it to cargo too, \
+         may be given multiple times",
+        "PATH",
+    );
+    opts.optopt(
+        "",
+        "output-format",
+        "How cargo-kubos reports what it does: human (default), or json for \
+         newline-delimited JSON events on stdout, with all else on stderr",
+        "FMT",
+    );
+    // Commands which work with targets
+    if !matches!(
+        command,
+        Some("verify-manifest") | Some("completions") | Some("help") | Some("generate-man")
+    ) {
+        opts.optmulti(
+            "t",
+            "target",
+            "sets (Kubos) target, may be given multiple times",
+            "NAME",
+        );
+    }
+    // Commands which run cargo or check the toolchains it would use
+    if accepts(BUILDING) || accepts(&["doctor"]) {
+        opts.optopt(
+            "",
+            "toolchain",
+            "rustup toolchain to build with, like cargo's +TOOLCHAIN",
+            "NAME",
+        );
+        opts.optflag(
+            "",
+            "no-toolchain-discovery",
+            "Don't look for a cross gcc in the Kubos SDK or on PATH when none is configured",
+        );
+    }
+    if accepts(BUILDING) || accepts(&["init-target"]) {
+        opts.optflag("n", "dry-run", "Print what would be done without doing it");
+    }
+    // Commands which run cargo
+    if accepts(BUILDING) {
+        opts.optopt(
+            "T",
+            "target-triple",
+            "sets a raw Rust target triple, bypassing the Kubos target mapping",
+            "TRIPLE",
+        );
+        opts.optflag(
+            "",
+            "non-interactive",
+            "Never ask which target to build for, building for x86-linux-native \
+             when none is given or configured",
+        );
+        opts.optflag("", "release", "Build with the release profile");
+        opts.optopt("", "profile", "Build with the given cargo profile", "NAME");
+        opts.optmulti(
+            "",
+            "features",
+            "Cargo features to enable, comma or space separated, may be given multiple times",
+            "LIST",
+        );
+        opts.optflag("", "all-features", "Enable all the crate's features");
+        opts.optmulti(
+            "p",
+            "package",
+            "Build only this workspace package, may be given multiple times",
+            "NAME",
+        );
+        opts.optflag("", "workspace", "Build every package in the workspace");
+        opts.optopt(
+            "",
+            "manifest-path",
+            "Build the crate with this Cargo.toml, finding its project config there too",
+            "PATH",
+        );
+        opts.optmulti(
+            "",
+            "exclude",
+            "Leave a package out of --workspace, may be given multiple times",
+            "NAME",
+        );
+        opts.optflag(
+            "",
+            "no-default-features",
+            "Don't enable the crate's default features",
+        );
+        opts.optopt(
+            "",
+            "message-format",
+            "Cargo's output format, e.g. json for tools",
+            "FMT",
+        );
+        opts.optflag(
+            "",
+            "install-target",
+            "Installs the Rust target with rustup if it is missing",
+        );
+        opts.optflag(
+            "",
+            "strict-linker",
+            "Fails instead of warning when no linker is found for a cross target",
+        );
+        opts.optopt(
+            "",
+            "sysroot",
+            "Target sysroot for pkg-config and C compilers (default: from the cross gcc)",
+            "PATH",
+        );
+        opts.optflag(
+            "",
+            "no-sysroot-flags",
+            "Don't pass --sysroot to C compilers, for toolchains with a built-in sysroot",
+        );
+        opts.optflag(
+            "",
+            "bindgen-args",
+            "Pass the target and sysroot to bindgen's clang for cross targets",
+        );
+        opts.optflag(
+            "",
+            "legacy-cc",
+            "Also set the global CC and CXX to the cross compiler",
+        );
+        opts.optflag(
+            "",
+            "no-pkg-config-cross",
+            "Don't set PKG_CONFIG_ALLOW_CROSS for cross targets",
+        );
+        opts.optopt(
+            "",
+            "openssl-dir",
+            "OpenSSL install prefix for the target (default: from the sysroot)",
+            "PATH",
+        );
+        opts.optflag(
+            "",
+            "skip-preflight",
+            "Don't check that the linker runs before building",
+        );
+        opts.optflag(
+            "",
+            "strict-versions",
+            "Fail rather than warn when the toolchain doesn't meet the crate's \
+             min-toolchain-version or max-glibc-version",
+        );
+        opts.optflag(
+            "",
+            "sdk-vm",
+            "Build in the Kubos SDK's Vagrant box when there's no toolchain here",
+        );
+        opts.optflag(
+            "",
+            "container",
+            "Run cargo in the target's toolchain image with docker or podman",
+        );
+        opts.optflag(
+            "",
+            "strip",
+            "Strip cross binaries with the toolchain's strip after building",
+        );
+        opts.optflag(
+            "",
+            "skip-yotta",
+            "Don't build the yotta modules wrapped by crates in the dependency graph",
+        );
+        opts.optflag(
+            "",
+            "force-sync",
+            "Switch the yotta module to the target being built even if it has another",
+        );
+        opts.optflag(
+            "",
+            "no-verify-arch",
+            "Don't check that the binaries built are for the target's architecture",
+        );
+        opts.optflag(
+            "",
+            "no-hooks",
+            "Don't run the post-build hooks from the crate manifest",
+        );
+        opts.optopt(
+            "",
+            "timeout",
+            "Kill cargo if it runs for longer than this, for each target",
+            "SECS",
+        );
+        opts.optflag(
+            "",
+            "fail-fast",
+            "Don't build the remaining targets after one fails",
+        );
+        opts.optflag(
+            "",
+            "no-prefix",
+            "Don't tag each line of output with its target in multi-target builds",
+        );
+    }
+    if accepts(BUILDING) || accepts(&["sync-yotta-target"]) {
+        opts.optopt(
+            "",
+            "yotta-module",
+            "yotta module whose search paths to give build scripts \
+             (default: the crate's module.json)",
+            "PATH",
+        );
+    }
+    if accepts(BUILD_COMMANDS) {
+        opts.optopt(
+            "",
+            "deploy-profile",
+            "Deploy with the settings under [package.metadata.kubos.deploy.NAME]",
+            "NAME",
+        );
+    }
+    // Running cargo's own commands
+    if accepts(&[CARGO_SCOPE]) {
+        opts.optopt(
+            "c",
+            "command",
+            "cargo command to run (deprecated, give the command first instead)",
+            "COMMAND",
+        );
+        opts.optflag(
+            "",
+            "no-qemu",
+            "Don't run cross tests with qemu when no runner is configured",
+        );
+        opts.optflag(
+            "",
+            "print-artifact",
+            "Print the path of each executable and library built, or with --json as JSON",
+        );
+        opts.optopt(
+            "",
+            "out-dir",
+            "Copy the executables built into PATH/<kubos-target>/",
+            "PATH",
+        );
+        opts.optopt(
+            "",
+            "artifact-suffix",
+            "Add this to the names of the executables copied to --out-dir, \
+             with {name}, {version} and {target} expanded",
+            "SUFFIX",
+        );
+    }
+    if accepts(&["generate-man"]) {
+        opts.optopt(
+            "",
+            "out",
+            "Write the man page into DIR rather than printing it",
+            "DIR",
+        );
+    }
+    if command.is_none() || accepts(&[CARGO_SCOPE]) {
+        opts.optflag("", "json", "Use JSON output for --list-targets");
+    }
+    if command.is_none() {
+        opts.optflag("", "list-targets", "Lists the supported targets");
+        opts.optflag(
+            "V",
+            "version",
+            "Print the version, and with --verbose the built-in targets",
+        );
+    }
+    // Deploying
+    if accepts(&["flash", "run-remote", "debug"]) {
+        opts.optopt(
+            "",
+            "host",
+            "Board to deploy to, as [user@]host (default: from the deploy config)",
+            "HOST",
+        );
+        opts.optopt(
+            "",
+            "dest",
+            "Directory on the board to flash to (default: /home/system/usr/bin)",
+            "PATH",
+        );
+    }
+    if accepts(&["flash"]) {
+        opts.optopt(
+            "",
+            "file-service",
+            "Make flash upload through the file-transfer-service at this address",
+            "HOST:PORT",
+        );
+        opts.optflag(
+            "",
+            "manifest",
+            "Also write an OTA manifest of the flashed binary and copy it to the board",
+        );
+    }
+    if accepts(&["run-remote"]) {
+        opts.optmulti(
+            "",
+            "env",
+            "Set a variable for the program run by run-remote",
+            "KEY=VAL",
+        );
+    }
+    if accepts(&["package"]) {
+        opts.optopt(
+            "",
+            "format",
+            "Archive for package to write: tar.gz (default) or ipk",
+            "FORMAT",
+        );
+    }
+    if accepts(&["size"]) {
+        opts.optopt(
+            "",
+            "max-size",
+            "Make size fail if any stripped binary is bigger than this",
+            "BYTES",
+        );
+    }
+    // Writing configuration
+    if accepts(&["init-target", "setup"]) {
+        opts.optopt(
+            "",
+            "linker",
+            "Linker to write for init-target and setup (default: discovered)",
+            "PATH",
+        );
+        opts.optflag(
+            "",
+            "force",
+            "Replace an existing init-target entry or setup's generated files",
+        );
+    }
+    if accepts(&["init-target"]) {
+        opts.optflag(
+            "",
+            "global",
+            "Write init-target config to $CARGO_HOME/config.toml",
+        );
+        opts.optflag(
+            "",
+            "local",
+            "Write init-target config to .cargo/config.toml (default)",
+        );
+    }
+    if accepts(&["setup"]) {
+        opts.optopt(
+            "",
+            "ci",
+            "Also generate a CI job with setup, for github or gitlab",
+            "SYSTEM",
+        );
+        opts.optflag(
+            "",
+            "stdout",
+            "Print what setup generates instead of writing it",
+        );
+    }
+    opts
+}
@@ -0,0 +1,159 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Whether cargo-kubos, and the cargo it runs, color their output, and
+//! the [`error!`], [`warning!`] and [`info!`] macros which print its own
+//! diagnostics: errors and warnings in red and yellow when colored, and
+//! fewer of them with `--quiet`
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// ANSI color code for errors
+pub const RED: u8 = 31;
+
+/// ANSI color code for warnings
+pub const YELLOW: u8 = 33;
+
+/// When to color output, as given to `--color` or in `CARGO_TERM_COLOR`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Choice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Choice {
+    pub fn parse(value: &str) -> Result<Choice, String> {
+        match value {
+            "auto" => Ok(Choice::Auto),
+            "always" => Ok(Choice::Always),
+            "never" => Ok(Choice::Never),
+            _ => Err(format!(
+                "invalid color choice '{}', expected auto, always or never",
+                value
+            )),
+        }
+    }
+}
+
+static CHOICE: OnceLock<Choice> = OnceLock::new();
+
+/// Set the choice from `--color`, defaulting to cargo's own
+/// `CARGO_TERM_COLOR` so the two agree unless told otherwise.
+/// Only the first call has any effect.
+pub fn init(choice: Option<Choice>) {
+    CHOICE.get_or_init(|| choice.unwrap_or_else(from_env));
+}
+
+fn from_env() -> Choice {
+    env::var("CARGO_TERM_COLOR")
+        .ok()
+        .and_then(|value| Choice::parse(&value).ok())
+        .unwrap_or(Choice::Auto)
+}
+
+/// Whether to color a stream, which with `auto` is whether it's a
+/// terminal and `NO_COLOR` isn't set
+fn enabled(terminal: bool) -> bool {
+    match *CHOICE.get_or_init(from_env) {
+        Choice::Always => true,
+        Choice::Never => false,
+        Choice::Auto => {
+            terminal
+                && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && env::var_os("TERM").is_none_or(|term| term != "dumb")
+        }
+    }
+}
+
+/// Whether to color what goes to cargo-kubos' stderr
+pub fn stderr() -> bool {
+    enabled(io::stderr().is_terminal())
+}
+
+/// Whether to color what goes to cargo-kubos' stdout
+pub fn stdout() -> bool {
+    enabled(io::stdout().is_terminal())
+}
+
+/// `CARGO_TERM_COLOR` for the cargo cargo-kubos runs. Its output may be
+/// piped through cargo-kubos, so cargo is told what was decided for
+/// the stderr they share rather than left to work it out itself.
+pub fn cargo() -> &'static str {
+    if stderr() {
+        "always"
+    } else {
+        "never"
+    }
+}
+
+static QUIET: AtomicUsize = AtomicUsize::new(0);
+
+/// Set how many times `--quiet` was given: once leaves out [`info!`]
+/// lines, and twice warnings too. Errors are always printed.
+pub fn set_quiet(level: usize) {
+    QUIET.store(level, Ordering::Relaxed);
+}
+
+pub fn quiet() -> usize {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// The text in bold and the color, if stderr is colored
+pub fn paint(text: &str, color: u8) -> String {
+    if stderr() {
+        format!("\x1b[1;{}m{}\x1b[0m", color, text)
+    } else {
+        String::from(text)
+    }
+}
+
+/// Print an error to stderr, as `Error - ` and the message
+macro_rules! error {
+    ($($arg:tt)*) => {
+        eprintln!(
+            "{} - {}",
+            $crate::color::paint("Error", $crate::color::RED),
+            format_args!($($arg)*)
+        )
+    };
+}
+
+/// Print a warning to stderr, as `warning: ` and the message, unless -qq
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if $crate::color::quiet() < 2 {
+            eprintln!(
+                "{}: {}",
+                $crate::color::paint("warning", $crate::color::YELLOW),
+                format_args!($($arg)*)
+            )
+        }
+    };
+}
+
+/// Print what cargo-kubos is doing to stderr, as `cargo-kubos: `
+/// and the message, unless --quiet
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::color::quiet() == 0 {
+            eprintln!("cargo-kubos: {}", format_args!($($arg)*))
+        }
+    };
+}
@@ -0,0 +1,190 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Checking the cross toolchain's versions against the constraints a crate
+//! declares in `[package.metadata.kubos]`, like `min-toolchain-version`
+
+use crate::cache::ResolutionCache;
+use crate::config::Linker;
+use crate::manifest::{find_manifest, kubos_metadata, read_manifest};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use toml::Value;
+
+/// Versions reported by a cross gcc
+#[derive(Clone, Debug, Default)]
+pub struct Probe {
+    /// From `-dumpfullversion`, or `-dumpversion` for older gcc
+    pub gcc_version: Option<String>,
+    /// From `-print-sysroot`
+    pub sysroot: Option<String>,
+    /// The glibc in the sysroot, from its `features.h`
+    pub glibc_version: Option<String>,
+}
+
+/// Ask the linker for its version and sysroot, or recall what it
+/// said before, so that multi-target builds only run each compiler once
+pub fn probe(linker: &Linker, cache: &ResolutionCache) -> Probe {
+    let program = linker.program();
+    cache.toolchain(program, || ask_linker(program))
+}
+
+/// Ask the linker program for its version and sysroot
+fn ask_linker(program: &str) -> Probe {
+    let ask = |arg: &str| {
+        let output = Command::new(program)
+            .arg(arg)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        let answer = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        if output.status.success() && !answer.is_empty() {
+            Some(answer)
+        } else {
+            None
+        }
+    };
+    // Before gcc 7, -dumpfullversion didn't exist and -dumpversion was full
+    let gcc_version = ask("-dumpfullversion").or_else(|| ask("-dumpversion"));
+    let sysroot = ask("-print-sysroot");
+    let glibc_version = sysroot.as_deref().and_then(|s| glibc_version(Path::new(s)));
+    Probe {
+        gcc_version,
+        sysroot,
+        glibc_version,
+    }
+}
+
+/// Read the glibc version from `features.h` in a sysroot
+fn glibc_version(sysroot: &Path) -> Option<String> {
+    let features = fs::read_to_string(sysroot.join("usr/include/features.h")).ok()?;
+    let define = |name: &str| {
+        features.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("#define"), Some(n), Some(value)) if n == name => value.parse::<u32>().ok(),
+                _ => None,
+            }
+        })
+    };
+    Some(format!(
+        "{}.{}",
+        define("__GLIBC__")?,
+        define("__GLIBC_MINOR__")?
+    ))
+}
+
+/// Version constraints from `[package.metadata.kubos]`
+#[derive(Default)]
+pub struct Constraints {
+    /// The oldest cross gcc the crate builds with, `min-toolchain-version`
+    pub min_toolchain: Option<String>,
+    /// The board's glibc, `max-glibc-version`, which the sysroot's mustn't
+    /// be newer than or binaries may need symbols the board lacks
+    pub max_glibc: Option<String>,
+}
+
+impl Constraints {
+    /// Read the constraints from the crate manifest, if there is one
+    pub fn load(params: &[String]) -> Result<Constraints, String> {
+        let path = match find_manifest(params) {
+            Some(path) => path,
+            None => return Ok(Constraints::default()),
+        };
+        let manifest = read_manifest(&path)?;
+        let version = |key: &str| match kubos_metadata(&manifest, key) {
+            Some(Value::String(version)) if parse(version).is_some() => Ok(Some(version.clone())),
+            Some(_) => Err(format!(
+                "{}: package.metadata.kubos.{} must be a version like \"6.3\"",
+                path.display(),
+                key
+            )),
+            None => Ok(None),
+        };
+        Ok(Constraints {
+            min_toolchain: version("min-toolchain-version")?,
+            max_glibc: version("max-glibc-version")?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_toolchain.is_none() && self.max_glibc.is_none()
+    }
+
+    /// How the probed versions break the constraints, if they do.
+    /// Versions which couldn't be probed are reported too.
+    pub fn violations(&self, probe: &Probe) -> Vec<String> {
+        let mut violations = vec![];
+        if let Some(ref min) = self.min_toolchain {
+            match probe.gcc_version {
+                Some(ref version) if compare(version, min) == Some(Ordering::Less) => violations
+                    .push(format!(
+                        "gcc {} is older than the min-toolchain-version {}",
+                        version, min
+                    )),
+                Some(_) => {}
+                None => violations.push(format!(
+                    "couldn't tell the gcc version to check against min-toolchain-version {}",
+                    min
+                )),
+            }
+        }
+        if let Some(ref max) = self.max_glibc {
+            match probe.glibc_version {
+                Some(ref version) if compare(version, max) == Some(Ordering::Greater) => violations
+                    .push(format!(
+                        "the sysroot's glibc {} is newer than the max-glibc-version {}",
+                        version, max
+                    )),
+                Some(_) => {}
+                None => violations.push(format!(
+                    "couldn't find the sysroot's glibc to check against max-glibc-version {}",
+                    max
+                )),
+            }
+        }
+        violations
+    }
+}
+
+/// The numeric parts of a dotted version, ignoring any suffix like `-rc1`
+fn parse(version: &str) -> Option<Vec<u32>> {
+    let numeric = version
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()?;
+    numeric
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u32>>>()
+        .filter(|parts| !parts.is_empty())
+}
+
+/// Compare dotted versions, with missing parts counting as 0
+fn compare(a: &str, b: &str) -> Option<Ordering> {
+    let (a, b) = (parse(a)?, parse(b)?);
+    let len = a.len().max(b.len());
+    let part = |v: &[u32], i: usize| v.get(i).copied().unwrap_or(0);
+    Some(
+        (0..len)
+            .map(|i| part(&a, i).cmp(&part(&b, i)))
+            .find(|order| *order != Ordering::Equal)
+            .unwrap_or(Ordering::Equal),
+    )
+}
@@ -0,0 +1,527 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos completions`, which prints a completion script for bash,
+//! zsh or fish. The options come from the same getopts definitions the
+//! commands parse with, and the targets and deploy profiles are those
+//! known when the script is generated.
+
+use crate::man::HIDDEN_COMMANDS;
+use crate::targets::TargetRegistry;
+use crate::{
+    cli, deploy, shell_quote, ALL_TARGETS, BUILD_COMMANDS, CARGO_COMMANDS, KUBOS_COMMANDS,
+};
+use getopts::{Fail, Options};
+use std::iter;
+
+/// The shells there are completion scripts for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Names of the shells, as given to `cargo kubos completions`
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+impl Shell {
+    pub fn parse(name: &str) -> Result<Shell, String> {
+        match name {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!(
+                "unknown shell '{}', expected one of: {}",
+                name,
+                SHELLS.join(", ")
+            )),
+        }
+    }
+}
+
+/// An option as the completion scripts see it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opt {
+    pub short: Option<String>,
+    pub long: Option<String>,
+    pub takes_value: bool,
+    /// What its value is called in the help, like `NAME`
+    pub hint: Option<String>,
+    /// Its help text, unwrapped
+    pub description: String,
+}
+
+impl Opt {
+    /// How the option can be written on the command line
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![];
+        if let Some(ref short) = self.short {
+            names.push(format!("-{}", short));
+        }
+        if let Some(ref long) = self.long {
+            names.push(format!("--{}", long));
+        }
+        names
+    }
+}
+
+/// The options in a getopts definition. getopts doesn't list them, so the
+/// names, value hint and help text are read from its usage rows, and
+/// whether each takes a value is found by seeing whether parsing it alone
+/// fails for want of one.
+pub fn options(opts: &Options) -> Vec<Opt> {
+    let mut rows = vec![];
+    opts.usage_with_format(|items| {
+        rows.extend(items);
+        String::new()
+    });
+    rows.iter()
+        .filter_map(|row| {
+            // The names and hint are separated from the help text by at
+            // least two spaces, or the help starts on the next line
+            let row = row.trim_start();
+            let (first, rest) = row.split_once('\n').unwrap_or((row, ""));
+            let (left, help) = first.split_once("  ").unwrap_or((first, ""));
+            let mut opt = Opt {
+                short: None,
+                long: None,
+                takes_value: false,
+                hint: None,
+                description: help
+                    .split_whitespace()
+                    .chain(rest.split_whitespace())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            };
+            for word in left.split_whitespace() {
+                let name = word.trim_end_matches(',');
+                if let Some(long) = name.strip_prefix("--") {
+                    opt.long = Some(String::from(long));
+                } else if let Some(short) = name.strip_prefix('-') {
+                    opt.short = Some(String::from(short));
+                } else {
+                    opt.hint = Some(String::from(word));
+                }
+            }
+            let name = opt.names().pop()?;
+            match opts.parse(&[name]) {
+                Ok(_) => {}
+                Err(Fail::ArgumentMissing(_)) => opt.takes_value = true,
+                Err(_) => return None,
+            }
+            Some(opt)
+        })
+        .collect()
+}
+
+/// Everything the completion scripts complete
+pub struct Spec {
+    /// Commands offered before one has been given
+    pub commands: Vec<&'static str>,
+    /// Options before any command
+    pub top: Vec<Opt>,
+    /// cargo-kubos' own commands, with their options
+    pub own: Vec<(&'static str, Vec<Opt>)>,
+    /// Options of every other command, which is run by cargo
+    pub cargo: Vec<Opt>,
+    /// Values for `-t`
+    pub targets: Vec<String>,
+    /// Values for `--deploy-profile`
+    pub profiles: Vec<String>,
+}
+
+/// Name the scripts use for the options of commands cargo runs
+const CARGO_SCOPE: &str = "cargo";
+
+/// Name the fish script uses for the options before any command
+const TOP_SCOPE: &str = "top";
+
+impl Spec {
+    /// Each scope's name, as the bash and zsh scripts have it, with its options
+    fn scopes(&self) -> Vec<(&str, &[Opt])> {
+        let mut scopes = vec![("", &self.top[..])];
+        scopes.extend(self.own.iter().map(|(name, opts)| (*name, &opts[..])));
+        scopes.push((CARGO_SCOPE, &self.cargo[..]));
+        scopes
+    }
+
+    /// Every option which takes a value, in any scope
+    fn valued(&self) -> Vec<String> {
+        let mut valued: Vec<String> = vec![];
+        for (_, opts) in self.scopes() {
+            for name in opts.iter().filter(|o| o.takes_value).flat_map(Opt::names) {
+                if !valued.contains(&name) {
+                    valued.push(name);
+                }
+            }
+        }
+        valued
+    }
+
+    fn own_names(&self) -> Vec<&str> {
+        self.own.iter().map(|(name, _)| *name).collect()
+    }
+}
+
+fn words(opts: &[Opt]) -> String {
+    opts.iter()
+        .flat_map(Opt::names)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn quoted(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|value| shell_quote(value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The completion script for a shell
+pub fn render(shell: Shell, spec: &Spec) -> String {
+    match shell {
+        Shell::Bash => bash(spec),
+        Shell::Zsh => zsh(spec),
+        Shell::Fish => fish(spec),
+    }
+}
+
+fn bash(spec: &Spec) -> String {
+    let mut cases = String::new();
+    for (name, opts) in spec.scopes() {
+        let pattern = match name {
+            "" => String::from("\"\""),
+            CARGO_SCOPE => String::from("*"),
+            name => String::from(name),
+        };
+        cases.push_str(&format!(
+            "        {}) opts=\"{}\" ;;\n",
+            pattern,
+            words(opts)
+        ));
+    }
+    format!(
+        r#"# bash completion for cargo-kubos, from `cargo kubos completions bash`
+
+_cargo_kubos() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    local targets=({targets}) profiles=({profiles})
+    local valued=" {valued} "
+    local i=1 word command="" opts
+
+    # Arguments after `--` are cargo's own
+    for word in "${{COMP_WORDS[@]:1:COMP_CWORD-1}}"; do
+        [[ "$word" == -- ]] && return
+    done
+
+    # Find the command, skipping options, their values and +toolchain
+    [[ "${{COMP_WORDS[1]}}" == kubos ]] && (( COMP_CWORD > 1 )) && i=2
+    for (( ; i < COMP_CWORD; i++ )); do
+        word="${{COMP_WORDS[i]}}"
+        case "$word" in
+            +*) ;;
+            -*) [[ "$valued" == *" $word "* ]] && (( i++ )) ;;
+            *) command="$word"; break ;;
+        esac
+    done
+
+    case "$prev" in
+        -t|--target) COMPREPLY=($(compgen -W "${{targets[*]}}" -- "$cur")); return ;;
+        --deploy-profile) COMPREPLY=($(compgen -W "${{profiles[*]}}" -- "$cur")); return ;;
+    esac
+    [[ "$valued" == *" $prev "* ]] && return
+
+    case "$command" in
+{cases}    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "$opts" -- "$cur"))
+    elif [[ -z "$command" ]]; then
+        COMPREPLY=($(compgen -W "{commands}" -- "$cur"))
+    elif [[ "$command" == completions ]]; then
+        COMPREPLY=($(compgen -W "{shells}" -- "$cur"))
+    fi
+}}
+complete -o default -F _cargo_kubos cargo-kubos
+
+# `cargo kubos` is completed by cargo's completion, so wrap that and take
+# over from it after `kubos`
+if ! complete -p cargo &>/dev/null && declare -F _completion_loader &>/dev/null; then
+    _completion_loader cargo
+fi
+if [[ "$(complete -p cargo 2>/dev/null)" =~ -F\ ([^ ]+) ]] &&
+    [[ "${{BASH_REMATCH[1]}}" != _cargo_kubos_cargo ]]; then
+    _cargo_kubos_wrapped="${{BASH_REMATCH[1]}}"
+fi
+_cargo_kubos_cargo() {{
+    if [[ "${{COMP_WORDS[1]}}" == kubos ]] && (( COMP_CWORD > 1 )); then
+        _cargo_kubos
+    elif [[ -n "$_cargo_kubos_wrapped" ]]; then
+        "$_cargo_kubos_wrapped" "$@"
+    fi
+}}
+complete -o default -F _cargo_kubos_cargo cargo
+"#,
+        targets = quoted(&spec.targets),
+        profiles = quoted(&spec.profiles),
+        valued = spec.valued().join(" "),
+        cases = cases,
+        commands = spec.commands.join(" "),
+        shells = SHELLS.join(" "),
+    )
+}
+
+fn zsh(spec: &Spec) -> String {
+    let mut cases = String::new();
+    for (name, opts) in spec.scopes() {
+        let pattern = match name {
+            "" => String::from("''"),
+            CARGO_SCOPE => String::from("*"),
+            name => String::from(name),
+        };
+        cases.push_str(&format!(
+            "        ({}) opts=({}) ;;\n",
+            pattern,
+            words(opts)
+        ));
+    }
+    format!(
+        r#"#compdef cargo-kubos
+# zsh completion for cargo-kubos, from `cargo kubos completions zsh`
+
+_cargo-kubos() {{
+    local -a targets profiles valued opts
+    targets=({targets})
+    profiles=({profiles})
+    valued=({valued})
+    local i=2 word command=
+
+    # Arguments after `--` are cargo's own
+    if (( ${{${{words[2,CURRENT-1]}}[(Ie)--]}} )); then
+        _files
+        return
+    fi
+
+    # Find the command, skipping options, their values and +toolchain
+    [[ $words[2] == kubos ]] && (( CURRENT > 2 )) && i=3
+    while (( i < CURRENT )); do
+        word=$words[i]
+        case $word in
+            (+*) ;;
+            (-*) (( ${{valued[(Ie)$word]}} )) && (( i++ )) ;;
+            (*) command=$word; break ;;
+        esac
+        (( i++ ))
+    done
+
+    case $words[CURRENT-1] in
+        (-t|--target) compadd -a targets; return ;;
+        (--deploy-profile) compadd -a profiles; return ;;
+    esac
+    if (( ${{valued[(Ie)$words[CURRENT-1]]}} )); then
+        _files
+        return
+    fi
+
+    case $command in
+{cases}    esac
+    if [[ $PREFIX == -* ]]; then
+        compadd -a opts
+    elif [[ -z $command ]]; then
+        compadd {commands}
+    elif [[ $command == completions ]]; then
+        compadd {shells}
+    else
+        _files
+    fi
+}}
+
+if [[ $zsh_eval_context[-1] == loadautofunc ]]; then
+    _cargo-kubos "$@"
+else
+    compdef _cargo-kubos cargo-kubos
+fi
+"#,
+        targets = quoted(&spec.targets),
+        profiles = quoted(&spec.profiles),
+        valued = spec.valued().join(" "),
+        cases = cases,
+        commands = spec.commands.join(" "),
+        shells = SHELLS.join(" "),
+    )
+}
+
+/// Quote a value for fish, whose single quotes escape `\` and `'`
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish(spec: &Spec) -> String {
+    let echo = |values: &[String]| -> String {
+        values
+            .iter()
+            .map(|value| format!("    echo {}\n", fish_quote(value)))
+            .collect()
+    };
+
+    // Each option once, with the scopes it's in
+    let mut opts: Vec<(&Opt, Vec<&str>)> = vec![];
+    for (name, scope_opts) in spec.scopes() {
+        let name = if name.is_empty() { TOP_SCOPE } else { name };
+        for opt in scope_opts {
+            match opts.iter_mut().find(|(o, _)| *o == opt) {
+                Some((_, scopes)) => scopes.push(name),
+                None => opts.push((opt, vec![name])),
+            }
+        }
+    }
+    let mut lines = String::new();
+    for (opt, scopes) in opts {
+        let mut line = format!(
+            "    complete -c $cmd -n '__cargo_kubos_in {}'",
+            scopes.join(" ")
+        );
+        if let Some(ref short) = opt.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        if let Some(ref long) = opt.long {
+            line.push_str(&format!(" -l {}", long));
+        }
+        match opt.long.as_deref() {
+            Some("target") => line.push_str(" -x -a '(__cargo_kubos_targets)'"),
+            Some("deploy-profile") => line.push_str(" -x -a '(__cargo_kubos_profiles)'"),
+            _ if opt.takes_value => line.push_str(" -r"),
+            _ => {}
+        }
+        lines.push_str(&line);
+        lines.push('\n');
+    }
+
+    format!(
+        r#"# fish completion for cargo-kubos, from `cargo kubos completions fish`
+
+# Which options apply: {top} before any command, cargo-kubos' own commands
+# by name and {cargo} for those cargo runs. Nothing applies after `--`.
+function __cargo_kubos_scope
+    set -l words (commandline -opc)
+    if test "$words[1]" = cargo
+        test "$words[2]" = kubos; or return 1
+        set -e words[1]
+    end
+    set -e words[1]
+    if test "$words[1]" = kubos
+        set -e words[1]
+    end
+    contains -- -- $words; and return 1
+    set -l skip 0
+    for word in $words
+        if test $skip = 1
+            set skip 0
+            continue
+        end
+        switch $word
+            case '+*'
+            case '-*'
+                contains -- $word {valued}; and set skip 1
+            case {own}
+                echo $word
+                return
+            case '*'
+                echo {cargo}
+                return
+        end
+    end
+    echo {top}
+end
+
+function __cargo_kubos_in
+    set -l scope (__cargo_kubos_scope); or return 1
+    contains -- $scope $argv
+end
+
+function __cargo_kubos_targets
+{targets}end
+
+function __cargo_kubos_profiles
+{profiles}end
+
+complete -c cargo -n __fish_use_subcommand -f -a kubos
+for cmd in cargo-kubos cargo
+    complete -c $cmd -n '__cargo_kubos_in {top}' -f -a '{commands}'
+    complete -c $cmd -n '__cargo_kubos_in completions' -f -a '{shells}'
+{lines}end
+"#,
+        top = TOP_SCOPE,
+        cargo = CARGO_SCOPE,
+        valued = spec.valued().join(" "),
+        own = spec.own_names().join(" "),
+        targets = echo(&spec.targets),
+        profiles = echo(&spec.profiles),
+        commands = spec.commands.join(" "),
+        shells = SHELLS.join(" "),
+        lines = lines,
+    )
+}
+
+/// Run `cargo kubos completions SHELL`, returning the exit code
+pub fn command(positional: &[String]) -> i32 {
+    let shell = match positional {
+        [_, shell] => match Shell::parse(shell) {
+            Ok(shell) => shell,
+            Err(e) => {
+                error!("{}", e);
+                return 2;
+            }
+        },
+        _ => {
+            error!(
+                "completions needs the shell to complete for, one of: {}",
+                SHELLS.join(", ")
+            );
+            return 2;
+        }
+    };
+
+    // Broken target files or manifests shouldn't stop the script being
+    // generated, it just completes fewer values
+    let mut targets = vec![String::from(ALL_TARGETS)];
+    let known = TargetRegistry::load().unwrap_or_else(|_| TargetRegistry::builtin());
+    for target in Vec::from(known) {
+        for name in iter::once(target.name).chain(target.aliases) {
+            if !targets.contains(&name) {
+                targets.push(name);
+            }
+        }
+    }
+
+    let own = KUBOS_COMMANDS
+        .iter()
+        .chain(BUILD_COMMANDS)
+        .filter(|command| !HIDDEN_COMMANDS.contains(command));
+    let spec = Spec {
+        commands: CARGO_COMMANDS.iter().chain(own.clone()).cloned().collect(),
+        top: options(&cli::options(None)),
+        own: own
+            .map(|command| (*command, options(&cli::options(Some(command)))))
+            .collect(),
+        cargo: options(&cli::options(Some(CARGO_SCOPE))),
+        targets,
+        profiles: deploy::profile_names(&[]),
+    };
+    print!("{}", render(shell, &spec));
+    0
+}
@@ -0,0 +1,713 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::cache::ResolutionCache;
+use crate::cargo_config::{CargoConfig, EnvValue, MergedConfig};
+use crate::targets::edit_distance;
+use crate::toolchain::find_executable;
+use std::collections::BTreeMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use toml::Value;
+
+/// Config file names within a cargo config directory, in order of precedence
+const CONFIG_FILES: &[&str] = &["config.toml", "config"];
+
+/// Directories which have already been warned about having both config files
+static BOTH_CONFIGS_WARNED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Why a cargo config couldn't be read
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file couldn't be read
+    Read {
+        /// The config's path
+        path: PathBuf,
+        /// Why reading failed
+        source: io::Error,
+    },
+    /// The file isn't valid TOML, or has the wrong type for a key cargo-kubos reads
+    Parse {
+        /// The config's path
+        path: PathBuf,
+        /// Where and why parsing failed
+        source: toml::de::Error,
+    },
+    /// A `[kubos]` key has a value cargo-kubos can't use
+    Invalid {
+        /// The config's path
+        path: PathBuf,
+        /// The key, like `kubos.target."kubos-linux-beaglebone-gcc".sysroot`
+        key: String,
+        /// What's wrong with it, like `must be a string`
+        problem: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Parse { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Invalid { path, key, problem } => {
+                write!(f, "{}: {} {}", path.display(), key, problem)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConfigError::Read { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            ConfigError::Invalid { .. } => None,
+        }
+    }
+}
+
+/// Most config errors end up as part of a message
+impl From<ConfigError> for String {
+    fn from(e: ConfigError) -> String {
+        e.to_string()
+    }
+}
+
+/// A parsed cargo config file
+#[derive(Debug)]
+pub struct ConfigFile {
+    /// Where the config was read from
+    pub path: PathBuf,
+    /// Parsed contents
+    pub value: Value,
+    /// The parts cargo-kubos reads as cargo does
+    pub cargo: CargoConfig,
+}
+
+impl ConfigFile {
+    /// Look up `[kubos.target."<name>"].<key>` in this config
+    pub fn kubos_target_value(&self, name: &str, key: &str) -> Option<&Value> {
+        self.value.get("kubos")?.get("target")?.get(name)?.get(key)
+    }
+
+    /// The directory relative paths in this config are relative to. Like
+    /// cargo, this is the directory containing the `.cargo` directory, or
+    /// for a config given elsewhere, the directory the config is in.
+    pub fn base_dir(&self) -> &Path {
+        base_dir(&self.path)
+    }
+}
+
+/// The directory relative paths in the config at `path` are relative to
+pub fn base_dir(path: &Path) -> &Path {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    if dir.file_name().is_some_and(|name| name == ".cargo") {
+        dir.parent().unwrap_or_else(|| Path::new(""))
+    } else {
+        dir
+    }
+}
+
+/// Make a path from the config at `path` absolute, leaving
+/// bare program names to be looked up on PATH
+pub fn resolve_path(path: &Path, value: &str) -> String {
+    let value_path = Path::new(value);
+    if value_path.has_root() || !value.contains(std::path::is_separator) {
+        return String::from(value);
+    }
+    base_dir(path)
+        .join(value_path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Where a linker was found
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkerSource {
+    /// A `CARGO_TARGET_<TRIPLE>_LINKER` variable
+    Env(String),
+    /// A cargo config file
+    Config(PathBuf),
+    /// The Kubos target mapping
+    TargetMapping,
+    /// The gcc named by a `CROSS_COMPILE` prefix
+    CrossCompile,
+    /// A toolchain in the Kubos SDK
+    Sdk,
+    /// A toolchain in the toolchain paths of the user's defaults file
+    Defaults(PathBuf),
+    /// A cross gcc found on PATH
+    Path,
+}
+
+impl fmt::Display for LinkerSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkerSource::Env(var) => write!(f, "${}", var),
+            LinkerSource::Config(path) => write!(f, "{}", path.display()),
+            LinkerSource::TargetMapping => write!(f, "the Kubos target mapping"),
+            LinkerSource::CrossCompile => write!(f, "$CROSS_COMPILE"),
+            LinkerSource::Sdk => write!(f, "the Kubos SDK"),
+            LinkerSource::Defaults(path) => write!(f, "the toolchain paths of {}", path.display()),
+            LinkerSource::Path => write!(f, "PATH"),
+        }
+    }
+}
+
+/// A linker and any extra arguments it needs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Linker {
+    /// Linker program, used for `CC`/`CXX`
+    pub path: String,
+    /// Extra arguments for both compiling and linking
+    pub args: Vec<String>,
+    /// Where the linker was found
+    pub source: LinkerSource,
+}
+
+impl Linker {
+    /// A linker which needs no extra arguments
+    pub fn new(path: &str, source: LinkerSource) -> Self {
+        Linker {
+            path: String::from(path),
+            args: vec![],
+            source,
+        }
+    }
+
+    /// The program to run, without any arguments embedded in the path
+    /// such as `gcc -m32`
+    pub fn program(&self) -> &str {
+        // Windows toolchains are often installed under `C:\Program Files`
+        if find_executable(Path::new(&self.path)).is_some() {
+            return &self.path;
+        }
+        self.path.split_whitespace().next().unwrap_or(&self.path)
+    }
+}
+
+impl fmt::Display for Linker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why no linker could be resolved for a triple
+#[derive(Debug)]
+pub enum LinkerError {
+    /// A cargo config which might have set one couldn't be read
+    Config(ConfigError),
+    /// Neither the environment nor any cargo config sets one
+    NotSet {
+        /// The triple
+        triple: String,
+        /// The cargo configs looked in, or if there are none,
+        /// every path one was looked for at
+        searched: Vec<PathBuf>,
+        /// Whether there were any cargo configs to look in
+        found_config: bool,
+        /// A triple a typo away which does have a linker set,
+        /// and so is most likely meant for this one
+        closest: Option<String>,
+    },
+}
+
+impl fmt::Display for LinkerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkerError::Config(e) => write!(f, "{}", e),
+            LinkerError::NotSet {
+                triple,
+                searched,
+                found_config,
+                closest,
+            } => f.write_str(&linker_not_set(triple, searched, *found_config, closest)),
+        }
+    }
+}
+
+impl error::Error for LinkerError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LinkerError::Config(e) => Some(e),
+            LinkerError::NotSet { .. } => None,
+        }
+    }
+}
+
+impl From<ConfigError> for LinkerError {
+    fn from(e: ConfigError) -> Self {
+        LinkerError::Config(e)
+    }
+}
+
+/// Say where a triple's linker was looked for and not found
+pub(crate) fn linker_not_set(
+    triple: &str,
+    searched: &[PathBuf],
+    found_config: bool,
+    closest: &Option<String>,
+) -> String {
+    let paths: Vec<String> = searched
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    if !found_config {
+        return format!("no cargo config found, tried {}", paths.join(", "));
+    }
+    let mut message = format!(
+        "`[target.{}].linker` is not set in {}",
+        triple,
+        paths.join(", ")
+    );
+    if let Some(closest) = closest {
+        message.push_str(&format!(", though `[target.{}].linker` is", closest));
+    }
+    message
+}
+
+/// Find the cargo config file in `dir`, trying `config.toml` before
+/// the legacy `config`. Returns every path which was tried on failure.
+pub fn config_file(dir: &Path) -> Result<PathBuf, Vec<PathBuf>> {
+    let candidates: Vec<PathBuf> = CONFIG_FILES.iter().map(|name| dir.join(name)).collect();
+    let found: Vec<&PathBuf> = candidates
+        .iter()
+        .filter(|path| {
+            let exists = path.is_file();
+            log!(
+                Trace,
+                "cargo config {}: {}",
+                path.display(),
+                if exists { "found" } else { "not found" }
+            );
+            exists
+        })
+        .collect();
+
+    match found.as_slice() {
+        [] => Err(candidates),
+        [path] => Ok(path.to_path_buf()),
+        [path, ignored, ..] => {
+            let mut warned = BOTH_CONFIGS_WARNED.lock().unwrap();
+            if !warned.iter().any(|d| d == dir) {
+                warned.push(dir.to_path_buf());
+                warning!(
+                    "both `{}` and `{}` exist, using `{}`",
+                    path.display(),
+                    ignored.display(),
+                    path.display()
+                );
+            }
+            Ok(path.to_path_buf())
+        }
+    }
+}
+
+/// Cargo's home directory: `$CARGO_HOME` if set, otherwise `.cargo`
+/// in the user's home directory like cargo itself
+pub fn cargo_home() -> Option<PathBuf> {
+    if let Some(cargo_home) = env::var_os("CARGO_HOME").filter(|h| !h.is_empty()) {
+        return Some(PathBuf::from(cargo_home));
+    }
+    home_dir().map(|home| home.join(".cargo"))
+}
+
+/// The current user's home directory
+pub fn home_dir() -> Option<PathBuf> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    env::var_os(var)
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Config directories cargo would consult, nearest first: `.cargo`
+/// in the current directory and each of its parents, then `$CARGO_HOME`
+pub fn config_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = env::current_dir()
+        .map(|cwd| cwd.ancestors().map(|dir| dir.join(".cargo")).collect())
+        .unwrap_or_default();
+
+    if let Some(cargo_home) = cargo_home() {
+        if !dirs.contains(&cargo_home) {
+            dirs.push(cargo_home);
+        }
+    }
+
+    dirs
+}
+
+/// Every cargo config file to read, nearest first: those given with
+/// `--cargo-config`, then one from each of the config directories.
+/// Returns every path which was tried if there are none.
+pub fn config_paths(given: &[PathBuf]) -> Result<Vec<PathBuf>, Vec<PathBuf>> {
+    let mut tried = vec![];
+    let mut paths = given.to_vec();
+
+    for dir in config_dirs() {
+        match config_file(&dir) {
+            Ok(path) => paths.push(path),
+            Err(candidates) => tried.extend(candidates),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err(tried);
+    }
+    Ok(paths)
+}
+
+/// Read and parse the cargo config at the path
+pub fn read_config(path: PathBuf) -> Result<ConfigFile, ConfigError> {
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(source) => return Err(ConfigError::Read { path, source }),
+    };
+    let parsed = data
+        .parse::<Value>()
+        .and_then(|value| Ok((value, toml::from_str::<CargoConfig>(&data)?)));
+    match parsed {
+        Ok((value, cargo)) => Ok(ConfigFile { path, value, cargo }),
+        Err(source) => Err(ConfigError::Parse { path, source }),
+    }
+}
+
+/// Merge the cargo configs cargo would read, if there are any
+pub fn merged_configs(cache: &ResolutionCache) -> Result<MergedConfig, ConfigError> {
+    Ok(MergedConfig::new(
+        cache.discover_configs()?.iter().map(|c| &**c),
+    ))
+}
+
+/// Name of the environment variable cargo reads `[target.<triple>].<key>` from,
+/// e.g. `CARGO_TARGET_ARM_UNKNOWN_LINUX_GNUEABIHF_LINKER`
+pub fn target_env_var(triple: &str, key: &str) -> String {
+    format!("CARGO_TARGET_{}_{}", triple, key)
+        .to_uppercase()
+        .replace(['-', '.'], "_")
+}
+
+/// A variable from the `[env]` section of a cargo config
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvEntry {
+    pub key: String,
+    pub value: String,
+    /// Whether the value overrides one already in the environment
+    pub force: bool,
+}
+
+/// Collect the `[env]` variables from the cargo configs, with the nearest
+/// config taking precedence for each variable
+pub fn config_env(cache: &ResolutionCache) -> Result<Vec<EnvEntry>, ConfigError> {
+    Ok(merged_configs(cache)?.env)
+}
+
+/// Collect the `[kubos.target."<name>".env]` variables for a Kubos target
+/// from the cargo configs, with the nearest config taking precedence
+pub fn kubos_target_env(cache: &ResolutionCache, name: &str) -> Result<Vec<EnvEntry>, ConfigError> {
+    let configs = cache.discover_configs()?;
+    let mut entries: Vec<EnvEntry> = vec![];
+
+    for config in &configs {
+        let table = match config.kubos_target_value(name, "env") {
+            Some(table) => table,
+            None => continue,
+        };
+        let table: BTreeMap<String, EnvValue> =
+            table.clone().try_into().map_err(|e| ConfigError::Invalid {
+                path: config.path.clone(),
+                key: format!("kubos.target.\"{}\".env", name),
+                problem: format!("is invalid: {}", e),
+            })?;
+        for (key, value) in &table {
+            if !entries.iter().any(|e| e.key == *key) {
+                entries.push(value.resolve(key, &config.path));
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Resolve a path under `[kubos.target."<name>"]` from the nearest cargo
+/// config which sets it. Relative paths are relative to the config's project.
+pub fn kubos_target_path(
+    cache: &ResolutionCache,
+    name: &str,
+    key: &str,
+) -> Result<Option<String>, ConfigError> {
+    let configs = cache.discover_configs()?;
+    let (config, value) = match configs
+        .iter()
+        .find_map(|config| config.kubos_target_value(name, key).map(|v| (config, v)))
+    {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+
+    let value = value
+        .as_str()
+        .ok_or_else(|| invalid(config, &format!("target.\"{}\".{}", name, key), "a string"))?;
+    Ok(Some(
+        config.base_dir().join(value).to_string_lossy().into_owned(),
+    ))
+}
+
+/// Read a string under `[kubos.target."<name>"]` from
+/// the nearest cargo config which sets it
+pub fn kubos_target_string(
+    cache: &ResolutionCache,
+    name: &str,
+    key: &str,
+) -> Result<Option<String>, ConfigError> {
+    let configs = cache.discover_configs()?;
+    match configs
+        .iter()
+        .find_map(|config| config.kubos_target_value(name, key).map(|v| (config, v)))
+    {
+        Some((config, value)) => value
+            .as_str()
+            .map(|s| Some(String::from(s)))
+            .ok_or_else(|| invalid(config, &format!("target.\"{}\".{}", name, key), "a string")),
+        None => Ok(None),
+    }
+}
+
+/// Read a boolean under `[kubos]` from the nearest cargo config which sets it
+pub fn kubos_setting(cache: &ResolutionCache, key: &str) -> Result<bool, ConfigError> {
+    let configs = cache.discover_configs()?;
+    match configs
+        .iter()
+        .find_map(|config| config.value.get("kubos")?.get(key).map(|v| (config, v)))
+    {
+        Some((config, value)) => value
+            .as_bool()
+            .ok_or_else(|| invalid(config, key, "true or false")),
+        None => Ok(false),
+    }
+}
+
+/// Read a boolean under `[kubos.target."<name>"]` from
+/// the nearest cargo config which sets it
+pub fn kubos_target_flag(
+    cache: &ResolutionCache,
+    name: &str,
+    key: &str,
+) -> Result<bool, ConfigError> {
+    let configs = cache.discover_configs()?;
+    match configs
+        .iter()
+        .find_map(|config| config.kubos_target_value(name, key).map(|v| (config, v)))
+    {
+        Some((config, value)) => value.as_bool().ok_or_else(|| {
+            invalid(
+                config,
+                &format!("target.\"{}\".{}", name, key),
+                "true or false",
+            )
+        }),
+        None => Ok(false),
+    }
+}
+
+/// The error for a `[kubos]` key in the config which isn't what it must be
+fn invalid(config: &ConfigFile, key: &str, expected: &str) -> ConfigError {
+    ConfigError::Invalid {
+        path: config.path.clone(),
+        key: format!("kubos.{}", key),
+        problem: format!("must be {}", expected),
+    }
+}
+
+/// Split a flags environment variable, if it's set
+fn env_flags(var: &str, separator: Option<char>) -> Option<Vec<String>> {
+    let flags = env::var(var).ok()?;
+    Some(match separator {
+        Some(separator) => flags
+            .split(separator)
+            .filter(|flag| !flag.is_empty())
+            .map(String::from)
+            .collect(),
+        None => flags.split_whitespace().map(String::from).collect(),
+    })
+}
+
+/// Resolve the rustflags cargo would use for the target, following
+/// cargo's precedence: `CARGO_ENCODED_RUSTFLAGS`, then `RUSTFLAGS`, then
+/// every `[target.<triple>].rustflags`, then `[build].rustflags`.
+/// Only the first of those sources which is set is used.
+pub fn resolve_rustflags(
+    cache: &ResolutionCache,
+    target: &str,
+) -> Result<Vec<String>, ConfigError> {
+    if let Some(flags) = env_flags("CARGO_ENCODED_RUSTFLAGS", Some('\x1f')) {
+        return Ok(flags);
+    }
+    if let Some(flags) = env_flags("RUSTFLAGS", None) {
+        return Ok(flags);
+    }
+
+    let merged = merged_configs(cache)?;
+
+    // Target flags from every config are joined, with nearer configs'
+    // flags placed later, as are those from the environment
+    let mut target_flags = merged.target(target).and_then(|t| t.rustflags.clone());
+    if let Some(flags) = env_flags(&target_env_var(target, "rustflags"), None) {
+        target_flags.get_or_insert_with(Vec::new).extend(flags);
+    }
+    if let Some(flags) = target_flags {
+        return Ok(flags);
+    }
+
+    if let Some(flags) = env_flags("CARGO_BUILD_RUSTFLAGS", None) {
+        return Ok(flags);
+    }
+    Ok(merged.build_rustflags.unwrap_or_default())
+}
+
+/// Resolve `[target.<triple>].runner` from the environment or the cargo configs.
+/// Returns `None` if no runner is configured.
+pub fn config_runner(cache: &ResolutionCache, target: &str) -> Result<Option<String>, ConfigError> {
+    if let Ok(runner) = env::var(target_env_var(target, "runner")) {
+        if !runner.is_empty() {
+            return Ok(Some(runner));
+        }
+    }
+
+    Ok(merged_configs(cache)?
+        .target(target)
+        .and_then(|t| t.runner.clone()))
+}
+
+/// Resolve `[target.<triple>].linker` from the environment or the
+/// cargo configs cargo would read, with the nearest taking precedence
+pub fn config_linker(cache: &ResolutionCache, target: &str) -> Result<Linker, LinkerError> {
+    let var = target_env_var(target, "linker");
+    if let Ok(linker) = env::var(&var) {
+        if !linker.is_empty() {
+            return Ok(Linker::new(&linker, LinkerSource::Env(var)));
+        }
+    }
+
+    // Without a config, there's only where one was looked for to say
+    let paths = match cache.config_paths() {
+        Ok(paths) => paths,
+        Err(tried) => {
+            return Err(LinkerError::NotSet {
+                triple: String::from(target),
+                searched: tried,
+                found_config: false,
+                closest: None,
+            })
+        }
+    };
+    let merged = MergedConfig::new(cache.configs(&paths)?.iter().map(|c| &**c));
+    if let Some(linker) = merged.target(target).and_then(|t| t.linker.clone()) {
+        return Ok(linker);
+    }
+
+    let max_distance = (target.chars().count() / 6).max(1);
+    let closest = merged
+        .targets
+        .iter()
+        .filter(|(_, t)| t.linker.is_some())
+        .map(|(triple, _)| (edit_distance(triple, target), triple))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, triple)| triple.clone());
+    Err(LinkerError::NotSet {
+        triple: String::from(target),
+        searched: merged.paths,
+        found_config: true,
+        closest,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+    /// An empty `.cargo` directory for a test
+    fn cargo_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir()
+            .join("cargo-kubos-config")
+            .join(name)
+            .join(".cargo");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write a config setting the linker for `TRIPLE`
+    fn write_linker(path: &Path, linker: &str) {
+        fs::write(
+            path,
+            format!("[target.{}]\nlinker = \"{}\"\n", TRIPLE, linker),
+        )
+        .unwrap();
+    }
+
+    /// The linker the config found in `dir` sets for `TRIPLE`
+    fn linker(dir: &Path) -> String {
+        let config = read_config(config_file(dir).unwrap()).unwrap();
+        let merged = MergedConfig::new(std::iter::once(&config));
+        merged.target(TRIPLE).unwrap().linker.clone().unwrap().path
+    }
+
+    #[test]
+    fn legacy_config_alone_is_found() {
+        let dir = cargo_dir("legacy_config_alone_is_found");
+        write_linker(&dir.join("config"), "legacy-gcc");
+        assert_eq!(config_file(&dir), Ok(dir.join("config")));
+        assert_eq!(linker(&dir), "legacy-gcc");
+    }
+
+    #[test]
+    fn config_toml_alone_is_found() {
+        let dir = cargo_dir("config_toml_alone_is_found");
+        write_linker(&dir.join("config.toml"), "toml-gcc");
+        assert_eq!(config_file(&dir), Ok(dir.join("config.toml")));
+        assert_eq!(linker(&dir), "toml-gcc");
+    }
+
+    #[test]
+    fn config_toml_wins_over_legacy_config() {
+        let dir = cargo_dir("config_toml_wins_over_legacy_config");
+        write_linker(&dir.join("config"), "legacy-gcc");
+        write_linker(&dir.join("config.toml"), "toml-gcc");
+        assert_eq!(config_file(&dir), Ok(dir.join("config.toml")));
+        assert_eq!(linker(&dir), "toml-gcc");
+    }
+
+    #[test]
+    fn no_config_lists_both_names() {
+        let dir = cargo_dir("no_config_lists_both_names");
+        assert_eq!(
+            config_file(&dir),
+            Err(vec![dir.join("config.toml"), dir.join("config")])
+        );
+    }
+}
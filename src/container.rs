@@ -0,0 +1,221 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Running cargo inside a toolchain image with Docker or Podman, for
+//! `--container`, so cross builds don't need the toolchains installed
+
+use crate::buildenv::BuildEnv;
+use crate::cache::ResolutionCache;
+use crate::config::{cargo_home, kubos_target_string};
+use crate::find_in_path;
+use crate::manifest::{target_dir, workspace_dir};
+use crate::targets::Target;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Images with the Kubos toolchains, used for targets whose
+/// `[kubos.target."<name>"]` doesn't set an `image`
+const DEFAULT_IMAGES: &[(&str, &str)] = &[
+    ("x86-linux-native", "kubos/kubos-dev:latest"),
+    ("kubos-linux-beaglebone-gcc", "kubos/kubos-dev:latest"),
+    ("kubos-linux-pumpkin-mbm2-gcc", "kubos/kubos-dev:latest"),
+    ("kubos-linux-isis-gcc", "kubos/kubos-dev:latest"),
+];
+
+/// Where the host's cargo registry and git checkouts are mounted,
+/// which becomes `CARGO_HOME` inside the container
+const CONTAINER_CARGO_HOME: &str = "/cargo";
+
+/// A container engine and what to mount into its containers
+#[derive(Clone, Debug)]
+pub struct Container {
+    /// The docker or podman program
+    engine: PathBuf,
+    podman: bool,
+    /// The workspace, mounted at the same path so that paths in cargo's
+    /// messages and output are the same inside and out
+    project: PathBuf,
+    /// The directory cargo builds into, if outside the workspace
+    target_dir: Option<PathBuf>,
+}
+
+impl Container {
+    /// Find docker, or failing that podman, and the workspace to mount
+    pub fn new(params: &[String]) -> Result<Container, String> {
+        let (engine, podman) = match find_in_path("docker") {
+            Some(docker) => (docker, false),
+            None => match find_in_path("podman") {
+                Some(podman) => (podman, true),
+                None => {
+                    return Err(String::from(
+                        "--container needs docker or podman, and neither is on PATH",
+                    ))
+                }
+            },
+        };
+        let cwd = env::current_dir().map_err(|e| format!("current directory: {}", e))?;
+        let project = workspace_dir(params).unwrap_or_else(|| cwd.clone());
+        let project = cwd.join(project);
+        let project = project
+            .canonicalize()
+            .map_err(|e| format!("{}: {}", project.display(), e))?;
+        let target_dir = target_dir(params)
+            .map(|dir| cwd.join(dir))
+            .filter(|dir| !dir.starts_with(&project));
+        Ok(Container {
+            engine,
+            podman,
+            project,
+            target_dir,
+        })
+    }
+
+    /// The engine's program name, for messages
+    pub fn engine(&self) -> String {
+        self.engine
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// The arguments to the engine which run cargo with the given
+    /// parameters in the target's image. Only the `CARGO_KUBOS_*`
+    /// variables and cargo's color choice are passed in, since the
+    /// rest name host toolchains.
+    pub fn args(
+        &self,
+        target: &Target,
+        params: &[String],
+        build_env: &BuildEnv,
+        cache: &ResolutionCache,
+    ) -> Result<Vec<String>, String> {
+        let image = image(target, cache)?;
+        let project = self.project.to_string_lossy().into_owned();
+        // The working directory is mounted if it's in the workspace
+        let workdir = env::current_dir()
+            .ok()
+            .filter(|cwd| cwd.starts_with(&self.project))
+            .map(|cwd| cwd.to_string_lossy().into_owned())
+            .unwrap_or_else(|| project.clone());
+
+        // --init gives cargo a PID 1 which passes on the Ctrl-C the
+        // engine forwards, and reaps what cargo leaves behind
+        let mut args: Vec<String> = ["run", "--rm", "-i", "--init"]
+            .iter()
+            .map(|arg| String::from(*arg))
+            .collect();
+        args.extend(self.user());
+        args.push(String::from("-v"));
+        args.push(format!("{}:{}", project, project));
+        if let Some(ref dir) = self.target_dir {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+            let dir = dir.to_string_lossy();
+            args.push(String::from("-v"));
+            args.push(format!("{}:{}", dir, dir));
+        }
+        if let Some(home) = cargo_home() {
+            for cache in ["registry", "git"] {
+                let dir = home.join(cache);
+                // Left to the engine, the directory would be created as root
+                fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+                args.push(String::from("-v"));
+                args.push(format!(
+                    "{}:{}/{}",
+                    dir.to_string_lossy(),
+                    CONTAINER_CARGO_HOME,
+                    cache
+                ));
+            }
+            args.push(String::from("-e"));
+            args.push(format!("CARGO_HOME={}", CONTAINER_CARGO_HOME));
+        }
+        for (key, value) in build_env.vars() {
+            if key.starts_with("CARGO_KUBOS_") || key == "CARGO_TERM_COLOR" {
+                args.push(String::from("-e"));
+                args.push(format!("{}={}", key, value));
+            }
+        }
+        args.push(String::from("-w"));
+        args.push(workdir);
+        args.push(image);
+        args.push(String::from("cargo"));
+        args.extend(params.iter().cloned());
+        Ok(args)
+    }
+
+    /// The command running cargo in the target's image
+    pub fn command(
+        &self,
+        target: &Target,
+        params: &[String],
+        build_env: &BuildEnv,
+        cache: &ResolutionCache,
+    ) -> Result<Command, String> {
+        let mut command = Command::new(&self.engine);
+        command.args(self.args(target, params, build_env, cache)?);
+        Ok(command)
+    }
+
+    /// Arguments running the container as the current user, so that what
+    /// cargo writes to the mounts isn't owned by root
+    fn user(&self) -> Vec<String> {
+        if self.podman {
+            // Rootless podman maps the user to root unless told otherwise
+            return vec![String::from("--userns=keep-id")];
+        }
+        match ids() {
+            Some((uid, gid)) => vec![String::from("--user"), format!("{}:{}", uid, gid)],
+            None => vec![],
+        }
+    }
+}
+
+/// The image to build a target in: `[kubos.target."<name>"] image`,
+/// or the default for a built-in target
+pub fn image(target: &Target, cache: &ResolutionCache) -> Result<String, String> {
+    if let Some(image) = kubos_target_string(cache, &target.name, "image")? {
+        return Ok(image);
+    }
+    DEFAULT_IMAGES
+        .iter()
+        .find(|(name, _)| *name == target.name)
+        .map(|(_, image)| String::from(*image))
+        .ok_or_else(|| {
+            format!(
+                "there's no default container image for target {}; set one with\n\n    \
+                 [kubos.target.\"{}\"]\n    \
+                 image = \"...\"\n",
+                target.name, target.name
+            )
+        })
+}
+
+#[cfg(unix)]
+fn ids() -> Option<(u32, u32)> {
+    extern "C" {
+        fn getuid() -> u32;
+        fn getgid() -> u32;
+    }
+    // Neither can fail
+    unsafe { Some((getuid(), getgid())) }
+}
+
+#[cfg(not(unix))]
+fn ids() -> Option<(u32, u32)> {
+    None
+}
@@ -0,0 +1,230 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The user's own defaults, from `~/.config/cargo-kubos/config.toml`
+//! (or `$XDG_CONFIG_HOME/cargo-kubos/config.toml`, or wherever
+//! `CARGO_KUBOS_CONFIG` points), for the settings which otherwise have to
+//! be typed every time. Everything else takes precedence over them: the
+//! command line, environment variables, the project config and the crate
+//! manifest. Keys it doesn't know are warned about and ignored, so that
+//! a file written for a newer cargo-kubos still works.
+//!
+//! ```toml
+//! default-target = "bbb"
+//! toolchain-paths = ["~/toolchains/bbb/usr/bin"]
+//!
+//! # Extra cargo arguments for each command
+//! [default-flags]
+//! package = ["--release"]
+//!
+//! # As under [package.metadata.kubos.deploy], except target
+//! [deploy]
+//! host = "kubos@10.0.2.20"
+//! ```
+
+use crate::config::home_dir;
+use crate::deploy::DEPLOY_KEYS;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use toml::Value;
+
+/// Environment variable naming the defaults file to use instead
+pub const CONFIG_ENV_VAR: &str = "CARGO_KUBOS_CONFIG";
+
+/// Settings from the user's defaults file
+#[derive(Debug, Default)]
+pub struct UserDefaults {
+    /// File the settings were read from
+    pub path: PathBuf,
+    /// Kubos target to build for when nothing else names one
+    pub target: Option<String>,
+    /// Extra cargo arguments for each command, by command name
+    pub flags: Vec<(String, Vec<String>)>,
+    /// The `[deploy]` table, checked to only have deploy settings
+    pub deploy: Option<Value>,
+    /// Directories to look for cross toolchains in, after the Kubos SDK
+    pub toolchain_paths: Vec<PathBuf>,
+}
+
+static DEFAULTS: OnceLock<Option<UserDefaults>> = OnceLock::new();
+
+/// Where the defaults file is read from, if there's anywhere
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_ENV_VAR).filter(|path| !path.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))?;
+    Some(config_home.join("cargo-kubos").join("config.toml"))
+}
+
+/// Read the defaults file, once, if there is one. A file named by
+/// `CARGO_KUBOS_CONFIG` has to exist.
+pub fn load() -> Result<Option<&'static UserDefaults>, String> {
+    if let Some(defaults) = DEFAULTS.get() {
+        return Ok(defaults.as_ref());
+    }
+    let defaults = match config_path() {
+        Some(path) if path.is_file() => {
+            log!(Trace, "defaults file {}: found", path.display());
+            Some(read_defaults(path)?)
+        }
+        Some(path) if env::var_os(CONFIG_ENV_VAR).is_some_and(|var| !var.is_empty()) => {
+            return Err(format!(
+                "{} is set to {}, which doesn't exist",
+                CONFIG_ENV_VAR,
+                path.display()
+            ));
+        }
+        Some(path) => {
+            log!(Trace, "defaults file {}: not found", path.display());
+            None
+        }
+        None => None,
+    };
+    Ok(DEFAULTS.get_or_init(|| defaults).as_ref())
+}
+
+/// The defaults, if they've been loaded and there are any
+pub fn get() -> Option<&'static UserDefaults> {
+    DEFAULTS.get().and_then(Option::as_ref)
+}
+
+fn read_defaults(path: PathBuf) -> Result<UserDefaults, String> {
+    let data = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let cfg = data
+        .parse::<Value>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let table = cfg
+        .as_table()
+        .ok_or_else(|| format!("{}: expected a table", path.display()))?;
+
+    let mut defaults = UserDefaults {
+        path: path.clone(),
+        ..UserDefaults::default()
+    };
+    for (key, value) in table {
+        match key.as_str() {
+            "default-target" => {
+                defaults.target = Some(
+                    value
+                        .as_str()
+                        .map(String::from)
+                        .ok_or_else(|| format!("{}: {} must be a string", path.display(), key))?,
+                )
+            }
+            "toolchain-paths" => {
+                defaults.toolchain_paths = strings(&path, key, value)?
+                    .iter()
+                    .map(|dir| expand_home(dir))
+                    .collect()
+            }
+            "default-flags" => {
+                let commands = value
+                    .as_table()
+                    .ok_or_else(|| format!("{}: {} must be a table", path.display(), key))?;
+                for (command, flags) in commands {
+                    let key = format!("{}.{}", key, command);
+                    defaults
+                        .flags
+                        .push((command.clone(), strings(&path, &key, flags)?));
+                }
+            }
+            "deploy" => defaults.deploy = Some(deploy_table(&path, value)?),
+            _ => warning!("{}: ignoring unknown key `{}`", path.display(), key),
+        }
+    }
+    Ok(defaults)
+}
+
+fn strings(path: &Path, key: &str, value: &Value) -> Result<Vec<String>, String> {
+    value
+        .as_array()
+        .and_then(|items| {
+            items
+                .iter()
+                .map(|item| item.as_str().map(String::from))
+                .collect::<Option<Vec<String>>>()
+        })
+        .ok_or_else(|| format!("{}: {} must be an array of strings", path.display(), key))
+}
+
+/// The deploy settings, without any key which isn't one. The target
+/// is left out too, as it would take precedence over `KUBOS_TARGET`
+/// and the project's own target in the deploy settings.
+fn deploy_table(path: &Path, value: &Value) -> Result<Value, String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| format!("{}: deploy must be a table", path.display()))?;
+    let mut deploy = table.clone();
+    for key in table.keys() {
+        if key == "target" {
+            warning!(
+                "{}: ignoring deploy.target, use default-target instead",
+                path.display()
+            );
+        } else if !DEPLOY_KEYS.contains(&key.as_str()) {
+            warning!("{}: ignoring unknown key `deploy.{}`", path.display(), key);
+        } else {
+            continue;
+        }
+        deploy.remove(key);
+    }
+    Ok(Value::Table(deploy))
+}
+
+/// Expand a leading `~/` to the user's home directory
+fn expand_home(dir: &str) -> PathBuf {
+    match (dir.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(dir),
+    }
+}
+
+impl UserDefaults {
+    /// The extra cargo arguments for a command
+    pub fn flags_for(&self, command: &str) -> &[String] {
+        self.flags
+            .iter()
+            .find(|(name, _)| name == command)
+            .map(|(_, flags)| flags.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// What the file sets, one setting per line, for `cargo kubos doctor`
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![];
+        if let Some(ref target) = self.target {
+            lines.push(format!("default target {}", target));
+        }
+        for (command, flags) in &self.flags {
+            lines.push(format!("{} flags: {}", command, flags.join(" ")));
+        }
+        if let Some(deploy) = self.deploy.as_ref().and_then(Value::as_table) {
+            for (key, value) in deploy {
+                lines.push(format!("deploy {} = {}", key, value));
+            }
+        }
+        for dir in &self.toolchain_paths {
+            lines.push(format!("toolchain path {}", dir.display()));
+        }
+        lines
+    }
+}
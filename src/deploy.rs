@@ -0,0 +1,803 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Getting built binaries onto a board, configured by
+//! `[package.metadata.kubos.deploy]` in the crate manifest, for
+//! `cargo kubos flash`, `run-remote` and `debug`
+
+use crate::build::build_binary;
+use crate::file_service::{self, FileService};
+use crate::manifest::{
+    self, arg_value, find_manifest, has_flag, kubos_metadata, package, read_manifest, target_dir,
+    Package,
+};
+use crate::preflight::explicit_sysroot;
+use crate::targets::Target;
+use crate::toolchain::{
+    find_in_path, is_host_triple, linker_sysroot, resolve_program, target_linker, toolchain_tool,
+};
+use crate::{cargo_params, defaults, events, ota, package, process, shell_quote, BuildOptions};
+use getopts::Matches;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Duration;
+use toml::Value;
+
+/// Exit code used when the build succeeded but the binary
+/// to deploy couldn't be picked out of what cargo built
+pub const ARTIFACT_FAILURE_CODE: i32 = 3;
+
+/// Exit code used when the binary couldn't be copied to the board
+pub const TRANSFER_FAILURE_CODE: i32 = 4;
+
+/// Where Kubos Linux keeps user binaries
+const DEFAULT_DEST: &str = "/home/system/usr/bin";
+
+/// Where binaries are copied to be run once by run-remote and debug
+const RUN_DIR: &str = "/tmp";
+
+/// Port for gdbserver to listen on when none is configured
+const DEFAULT_GDB_PORT: u16 = 2345;
+
+/// How long to wait for gdbserver to start listening
+const GDBSERVER_WAIT: Duration = Duration::from_secs(10);
+
+/// Settings from `[package.metadata.kubos.deploy]`, or from one of the
+/// named profiles under it like `[package.metadata.kubos.deploy.flatsat]`,
+/// which override those at the top level
+#[derive(Debug, Default)]
+pub struct DeployConfig {
+    /// Board to deploy to, optionally as `user@host`
+    pub host: Option<String>,
+    /// User to log in as, if the host doesn't name one
+    pub user: Option<String>,
+    /// Directory on the board to copy binaries into
+    pub dest: Option<String>,
+    /// SSH port
+    pub port: Option<u16>,
+    /// Kubos target to build for when none is given
+    pub target: Option<String>,
+    /// Local gdb to debug with
+    pub gdb: Option<String>,
+    /// Port for gdbserver on the board
+    pub gdb_port: Option<u16>,
+    /// Extra commands for gdb, run after connecting
+    pub gdb_commands: Vec<String>,
+    /// `host:port` of a file-transfer-service for flash to upload through
+    pub file_service: Option<String>,
+    /// Bytes per file-transfer-service chunk
+    pub chunk_size: Option<usize>,
+    /// Times the file-transfer-service upload may go without progress
+    pub retries: Option<u32>,
+    /// The profile the settings came from, if any
+    pub profile: Option<String>,
+}
+
+/// The keys of a deploy settings table
+pub const DEPLOY_KEYS: &[&str] = &[
+    "host",
+    "user",
+    "dest",
+    "port",
+    "target",
+    "gdb",
+    "gdb-port",
+    "gdb-commands",
+    "file-service",
+    "chunk-size",
+    "retries",
+];
+
+/// Deploy settings tables, most specific first: those in the manifest,
+/// then the user's defaults
+struct Settings<'a> {
+    tables: Vec<(&'a Value, &'a Path, String)>,
+}
+
+impl<'a> Settings<'a> {
+    /// A setting, with the file and section it's in for errors
+    fn get(&self, key: &str) -> Option<(&'a Value, String)> {
+        self.tables.iter().find_map(|(table, path, section)| {
+            table
+                .get(key)
+                .map(|value| (value, format!("{}: {}", path.display(), section)))
+        })
+    }
+
+    fn string(&self, key: &str) -> Result<Option<String>, String> {
+        match self.get(key) {
+            Some((Value::String(value), _)) => Ok(Some(value.clone())),
+            Some((_, section)) => Err(format!("{}.{} must be a string", section, key)),
+            None => Ok(None),
+        }
+    }
+
+    fn strings(&self, key: &str) -> Result<Vec<String>, String> {
+        match self.get(key) {
+            Some((value, section)) => value
+                .as_array()
+                .and_then(|items| {
+                    items
+                        .iter()
+                        .map(|item| item.as_str().map(String::from))
+                        .collect::<Option<Vec<String>>>()
+                })
+                .ok_or_else(|| format!("{}.{} must be an array of strings", section, key)),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn port(&self, key: &str) -> Result<Option<u16>, String> {
+        match self.get(key) {
+            Some((Value::Integer(port), _)) if *port > 0 && *port <= i64::from(u16::MAX) => {
+                Ok(Some(*port as u16))
+            }
+            Some((_, section)) => Err(format!("{}.{} must be a port number", section, key)),
+            None => Ok(None),
+        }
+    }
+
+    fn count(&self, key: &str) -> Result<Option<u64>, String> {
+        match self.get(key) {
+            Some((Value::Integer(n), _)) if *n >= 0 => Ok(Some(*n as u64)),
+            Some((_, section)) => Err(format!(
+                "{}.{} must be a non-negative integer",
+                section, key
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+impl DeployConfig {
+    /// Read the deploy settings from the crate manifest, if there is one,
+    /// using the given profile's settings over the top-level ones and
+    /// those over the user's defaults
+    pub fn load(params: &[String], profile: Option<&str>) -> Result<DeployConfig, String> {
+        let path = match find_manifest(params) {
+            Some(path) => path,
+            None => match profile {
+                Some(profile) => {
+                    return Err(format!(
+                        "deploy profile '{}' was requested but there's no Cargo.toml",
+                        profile
+                    ))
+                }
+                None => return Ok(DeployConfig::default()),
+            },
+        };
+        let package = package(params)?;
+        let deploy = kubos_metadata(&package.manifest, "deploy");
+
+        let section = "package.metadata.kubos.deploy";
+        let mut tables = vec![];
+        if let Some(profile) = profile {
+            match deploy.and_then(|d| d.get(profile)) {
+                Some(table) if table.is_table() => {
+                    tables.push((table, path.as_path(), format!("{}.{}", section, profile)))
+                }
+                _ => {
+                    let available = profiles(deploy);
+                    return Err(if available.is_empty() {
+                        format!(
+                            "unknown deploy profile '{}', {} doesn't define any under [{}]",
+                            profile,
+                            path.display(),
+                            section
+                        )
+                    } else {
+                        format!(
+                            "unknown deploy profile '{}', available profiles: {}",
+                            profile,
+                            available.join(", ")
+                        )
+                    });
+                }
+            }
+        }
+        if let Some(deploy) = deploy {
+            tables.push((deploy, path.as_path(), String::from(section)));
+        }
+        if let Some(defaults) = defaults::get() {
+            if let Some(ref deploy) = defaults.deploy {
+                tables.push((deploy, defaults.path.as_path(), String::from("deploy")));
+            }
+        }
+        let settings = Settings { tables };
+
+        Ok(DeployConfig {
+            host: settings.string("host")?,
+            user: settings.string("user")?,
+            dest: match settings.string("dest")? {
+                Some(dest) => Some(expand(&dest, &package)?),
+                None => None,
+            },
+            port: settings.port("port")?,
+            target: settings.string("target")?,
+            gdb: settings.string("gdb")?,
+            gdb_port: settings.port("gdb-port")?,
+            gdb_commands: settings.strings("gdb-commands")?,
+            file_service: settings.string("file-service")?,
+            chunk_size: match settings.count("chunk-size")? {
+                Some(0) => {
+                    let section = settings
+                        .get("chunk-size")
+                        .map(|(_, section)| section)
+                        .unwrap_or_default();
+                    return Err(format!("{}.chunk-size must be at least 1", section));
+                }
+                size => size.map(|size| size as usize),
+            },
+            retries: settings
+                .count("retries")?
+                .map(|n| n.min(u64::from(u32::MAX)) as u32),
+            profile: profile.map(String::from),
+        })
+    }
+}
+
+/// Names of the deploy profiles in the crate manifest, or none
+/// if there's no manifest or it can't be read
+pub fn profile_names(params: &[String]) -> Vec<String> {
+    match find_manifest(params).map(|path| read_manifest(&path)) {
+        Some(Ok(manifest)) => profiles(kubos_metadata(&manifest, "deploy")),
+        _ => vec![],
+    }
+}
+
+/// Names of the profiles in the deploy table
+fn profiles(deploy: Option<&Value>) -> Vec<String> {
+    match deploy.and_then(Value::as_table) {
+        Some(table) => table
+            .iter()
+            .filter(|(_, value)| value.is_table())
+            .map(|(name, _)| name.clone())
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Substitute `${CARGO_PKG_NAME}` and `${CARGO_PKG_VERSION}` in a setting
+fn expand(value: &str, package: &Package) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unterminated ${{...}} in '{}'", value))?;
+        match &rest[start + 2..start + end] {
+            "CARGO_PKG_NAME" => out.push_str(&package.name),
+            "CARGO_PKG_VERSION" => out.push_str(&package.version),
+            name => {
+                return Err(format!(
+                    "unknown variable ${{{}}} in '{}', expected CARGO_PKG_NAME \
+                     or CARGO_PKG_VERSION",
+                    name, value
+                ))
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// A fully resolved place to deploy to
+pub struct Remote {
+    /// `user@host`, or just the host to log in as the current user
+    pub host: String,
+    pub port: Option<u16>,
+    pub dest: String,
+}
+
+impl Remote {
+    /// Combine the config with the --host and --dest flags, which win
+    pub fn new(
+        config: &DeployConfig,
+        host: Option<String>,
+        dest: Option<String>,
+    ) -> Result<Remote, String> {
+        let host = host.or_else(|| config.host.clone()).ok_or_else(|| {
+            String::from(
+                "no board to deploy to, pass --host or set host \
+                 under [package.metadata.kubos.deploy] in Cargo.toml",
+            )
+        })?;
+        let host = match config.user {
+            Some(ref user) if !host.contains('@') => format!("{}@{}", user, host),
+            _ => host,
+        };
+        Ok(Remote {
+            host,
+            port: config.port,
+            dest: dest
+                .or_else(|| config.dest.clone())
+                .unwrap_or_else(|| String::from(DEFAULT_DEST)),
+        })
+    }
+}
+
+/// Pick the one executable to deploy out of those cargo built
+pub fn select_binary(executables: &[PathBuf]) -> Result<PathBuf, String> {
+    match executables {
+        [] => Err(String::from("cargo didn't build an executable to deploy")),
+        [binary] => Ok(binary.clone()),
+        _ => {
+            let names: Vec<String> = executables
+                .iter()
+                .map(|path| file_name(path).to_owned())
+                .collect();
+            Err(format!(
+                "cargo built several executables ({}), pick one with --bin",
+                names.join(", ")
+            ))
+        }
+    }
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(|n| n.to_str()).unwrap_or("")
+}
+
+/// How flash gets files onto the board
+pub enum Transfer {
+    /// scp, over the same connection as ssh
+    Scp,
+    /// The Kubos file-transfer-service, as used over the real uplink
+    FileService(FileService),
+}
+
+/// Copy the binary into the destination directory on the board, keeping
+/// its mode so it stays executable. Returns its path there.
+pub fn copy(binary: &Path, remote: &Remote, transfer: &Transfer) -> Result<String, String> {
+    let remote_path = format!(
+        "{}/{}",
+        remote.dest.trim_end_matches('/'),
+        file_name(binary)
+    );
+    match transfer {
+        Transfer::Scp => copy_to(binary, remote, &remote_path)?,
+        Transfer::FileService(service) => service.upload(binary, &remote_path)?,
+    }
+    Ok(remote_path)
+}
+
+fn copy_to(binary: &Path, remote: &Remote, remote_path: &str) -> Result<(), String> {
+    let mut command = Command::new("scp");
+    command.arg("-p").stdout(events::child_stdout());
+    if let Some(port) = remote.port {
+        command.arg("-P").arg(port.to_string());
+    }
+    command
+        .arg(binary)
+        .arg(format!("{}:{}", remote.host, remote_path));
+    log!(Debug, "running {:?}", command);
+
+    let status = command.status().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => String::from("scp not found on PATH"),
+        _ => format!("failed to run scp: {}", e),
+    })?;
+    if !status.success() {
+        return Err(format!(
+            "copying {} to {}:{} failed ({})",
+            binary.display(),
+            remote.host,
+            remote_path,
+            status
+        ));
+    }
+    Ok(())
+}
+
+/// Copy the binary to a scratch path on the board and run it there over
+/// ssh with the given environment and arguments, returning its status.
+///
+/// When we have a terminal, ssh is given one too, so that Ctrl-C reaches
+/// the remote process rather than just closing the connection.
+pub fn run(
+    binary: &Path,
+    remote: &Remote,
+    env: &[String],
+    args: &[String],
+) -> Result<ExitStatus, String> {
+    let remote_path = run_path(binary);
+    copy_to(binary, remote, &remote_path)?;
+
+    let mut words: Vec<String> = vec![];
+    if !env.is_empty() {
+        words.push(String::from("env"));
+        words.extend(env.iter().map(|var| shell_quote(var)));
+    }
+    words.push(shell_quote(&remote_path));
+    words.extend(args.iter().map(|arg| shell_quote(arg)));
+    // Clean up afterwards but keep the program's exit code
+    let script = format!(
+        "{}; status=$?; rm -f {}; exit $status",
+        words.join(" "),
+        shell_quote(&remote_path)
+    );
+
+    let mut command = ssh(remote, &script, io::stdin().is_terminal());
+    process::run(&mut command, None).map_err(|e| process::spawn_error("ssh", &e))
+}
+
+/// Where run-remote and debug copy a binary to on the board
+fn run_path(binary: &Path) -> String {
+    format!("{}/cargo-kubos-{}", RUN_DIR, file_name(binary))
+}
+
+/// An ssh command to run the script on the board
+fn ssh(remote: &Remote, script: &str, tty: bool) -> Command {
+    let mut command = Command::new("ssh");
+    command.stdout(events::child_stdout());
+    if tty {
+        command.arg("-t");
+    }
+    if let Some(port) = remote.port {
+        command.arg("-p").arg(port.to_string());
+    }
+    command.arg(&remote.host).arg(script);
+    log!(Debug, "running {:?}", command);
+    command
+}
+
+/// How to debug a binary on the board
+pub struct Debug<'a> {
+    /// Local gdb for the target
+    pub gdb: PathBuf,
+    /// Sysroot of the target, for gdb to find shared libraries in
+    pub sysroot: Option<&'a str>,
+    pub port: u16,
+    /// Extra gdb commands, run after connecting
+    pub commands: &'a [String],
+}
+
+impl DeployConfig {
+    /// Port for gdbserver on the board
+    pub fn gdbserver_port(&self) -> u16 {
+        self.gdb_port.unwrap_or(DEFAULT_GDB_PORT)
+    }
+}
+
+/// Copy the binary to the board, start it under gdbserver over ssh and
+/// connect the local gdb to it, returning gdb's status. gdbserver and the
+/// copy of the binary are cleaned up once gdb exits, however it exits.
+pub fn debug(
+    binary: &Path,
+    remote: &Remote,
+    debug: &Debug,
+    args: &[String],
+) -> Result<ExitStatus, String> {
+    let remote_path = run_path(binary);
+    copy_to(binary, remote, &remote_path)?;
+    let pid_file = format!("{}.gdbserver.pid", remote_path);
+
+    let mut words = vec![
+        String::from("gdbserver"),
+        format!(":{}", debug.port),
+        shell_quote(&remote_path),
+    ];
+    words.extend(args.iter().map(|arg| shell_quote(arg)));
+    let script = format!(
+        "{} & echo $! > {}; wait",
+        words.join(" "),
+        shell_quote(&pid_file)
+    );
+    let mut server = process::spawn_background(ssh(remote, &script, false).stdin(Stdio::null()))
+        .map_err(|e| process::spawn_error("ssh", &e))?;
+
+    let result = connect_gdb(binary, remote, debug);
+
+    let cleanup = format!(
+        "kill $(cat {pid}) 2>/dev/null; rm -f {pid} {path}",
+        pid = shell_quote(&pid_file),
+        path = shell_quote(&remote_path)
+    );
+    let stopped = ssh(remote, &cleanup, false).stdin(Stdio::null()).status();
+    process::stop_background(&mut server);
+    if !matches!(stopped, Ok(ref status) if status.success()) {
+        warning!(
+            "could not stop gdbserver on {}, it may still be running",
+            remote.host
+        );
+    }
+    result
+}
+
+fn connect_gdb(binary: &Path, remote: &Remote, debug: &Debug) -> Result<ExitStatus, String> {
+    let host = remote.host.rsplit('@').next().unwrap_or(&remote.host);
+
+    // gdb retries while gdbserver starts up, for up to the connect timeout
+    let mut script = format!("set tcp connect-timeout {}\n", GDBSERVER_WAIT.as_secs());
+    if let Some(sysroot) = debug.sysroot {
+        script.push_str(&format!("set sysroot {}\n", sysroot));
+    }
+    script.push_str(&format!("file {}\n", binary.display()));
+    script.push_str(&format!("target remote {}:{}\n", host, debug.port));
+    for command in debug.commands {
+        script.push_str(command);
+        script.push('\n');
+    }
+    let script_path = env::temp_dir().join(format!("cargo-kubos-{}.gdb", std::process::id()));
+    fs::write(&script_path, script).map_err(|e| format!("{}: {}", script_path.display(), e))?;
+
+    let mut command = Command::new(&debug.gdb);
+    command.arg("-x").arg(&script_path);
+    log!(Debug, "running {:?}", command);
+    let status = process::run_interactive(&mut command)
+        .map_err(|e| process::spawn_error(&debug.gdb.to_string_lossy(), &e));
+    let _ = fs::remove_file(&script_path);
+    status
+}
+
+/// A cargo-kubos command which builds a binary and then deploys it
+pub struct Deploy {
+    /// flash, run-remote, debug or package
+    pub command: String,
+    /// Arguments for run-remote and debug to run the program with
+    pub program_args: Vec<String>,
+    pub config: DeployConfig,
+}
+
+/// Run `cargo kubos flash`, `run-remote`, `debug` or `package`, building the
+/// target and deploying or packaging the executable, returning the
+/// exit code
+pub fn command(
+    deploy: &Deploy,
+    matches: &Matches,
+    selected: &[Target],
+    mut command: Vec<String>,
+    extra_params: Vec<String>,
+    options: &BuildOptions,
+) -> i32 {
+    let target = match selected {
+        [target] => target,
+        _ => {
+            error!("{} needs exactly one target", deploy.command);
+            return 2;
+        }
+    };
+    let env = matches.opt_strs("env");
+    if let Some(bad) = env.iter().find(|var| !var.contains('=')) {
+        error!("invalid --env '{}', expected KEY=VAL", bad);
+        return 2;
+    }
+
+    if deploy.command == "package" {
+        // Packages are for release, unless another profile is asked for
+        let given = cargo_params(&command, &extra_params);
+        if !has_flag(&given, "--release")
+            && !has_flag(&given, "-r")
+            && arg_value(&given, "--profile").is_none()
+        {
+            command.push(String::from("--release"));
+        }
+        let format = match package::Format::parse(
+            &matches
+                .opt_str("format")
+                .unwrap_or_else(|| String::from("tar.gz")),
+        ) {
+            Ok(format) => format,
+            Err(e) => {
+                error!("{}", e);
+                return 2;
+            }
+        };
+        let params = cargo_params(&command, &extra_params);
+        let binary = match build_binary(target, command, extra_params, options) {
+            Ok(binary) => binary,
+            Err(code) => return code,
+        };
+        return match package::command(target, &params, &binary, format, options) {
+            Ok(archive) => {
+                info!("packaged {}", archive.display());
+                0
+            }
+            Err(e) => {
+                error!("{}", e);
+                1
+            }
+        };
+    }
+
+    let file_service = matches
+        .opt_str("file-service")
+        .or_else(|| deploy.config.file_service.clone());
+    if matches.opt_present("file-service") && deploy.command != "flash" {
+        error!("--file-service is only used by flash");
+        return 2;
+    }
+    let file_service = if deploy.command == "flash" {
+        file_service
+    } else {
+        None
+    };
+    // The file service is enough to know where to flash to
+    let host = matches.opt_str("host").or_else(|| match file_service {
+        Some(ref endpoint) if deploy.config.host.is_none() => endpoint
+            .rsplit_once(':')
+            .map(|(host, _)| String::from(host)),
+        _ => None,
+    });
+    let remote = match Remote::new(&deploy.config, host, matches.opt_str("dest")) {
+        Ok(remote) => remote,
+        Err(e) => {
+            error!("{}", e);
+            return 2;
+        }
+    };
+    if deploy.command == "debug" {
+        return debug_binary(deploy, target, &remote, command, extra_params, options);
+    }
+    let params = cargo_params(&command, &extra_params);
+    let binary = match build_binary(target, command, extra_params, options) {
+        Ok(binary) => binary,
+        Err(code) => return code,
+    };
+
+    if deploy.command == "run-remote" {
+        return match run(&binary, &remote, &env, &deploy.program_args) {
+            Ok(status) => process::exit_code(&status),
+            Err(e) => {
+                error!("{}", e);
+                TRANSFER_FAILURE_CODE
+            }
+        };
+    }
+    let manifest = if matches.opt_present("manifest") {
+        match binary_manifest(target, &params, &binary) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                error!("{}", e);
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
+    let transfer = match file_service {
+        Some(endpoint) => Transfer::FileService(FileService {
+            endpoint,
+            chunk_size: deploy
+                .config
+                .chunk_size
+                .unwrap_or(file_service::DEFAULT_CHUNK_SIZE),
+            retries: deploy
+                .config
+                .retries
+                .unwrap_or(file_service::DEFAULT_RETRIES),
+            state_dir: target_dir(&params)
+                .unwrap_or_else(|| PathBuf::from("target"))
+                .join("kubos")
+                .join("transfers"),
+        }),
+        None => Transfer::Scp,
+    };
+    for file in std::iter::once(&binary).chain(manifest.as_ref()) {
+        match copy(file, &remote, &transfer) {
+            Ok(path) => info!("flashed {} to {}:{}", file.display(), remote.host, path),
+            Err(e) => {
+                error!("{}", e);
+                return TRANSFER_FAILURE_CODE;
+            }
+        }
+    }
+    0
+}
+
+/// Write `<binary>.manifest.toml` beside the binary
+fn binary_manifest(target: &Target, params: &[String], binary: &Path) -> Result<PathBuf, String> {
+    let package = manifest::package(params)?;
+    let dir = binary.parent().unwrap_or_else(|| Path::new("."));
+    let name = binary
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let manifest = dir.join(format!("{}.manifest.toml", name));
+    let info = ota::BuildInfo {
+        name: &package.name,
+        version: &package.version,
+        kubos_target: &target.name,
+    };
+    ota::write(dir, &[name], &info, &manifest)?;
+    Ok(manifest)
+}
+
+/// Run `cargo kubos debug`, building the target with its debug info and
+/// debugging it on the board with gdbserver and the toolchain's gdb
+fn debug_binary(
+    deploy: &Deploy,
+    target: &Target,
+    remote: &Remote,
+    command: Vec<String>,
+    extra_params: Vec<String>,
+    options: &BuildOptions,
+) -> i32 {
+    let gdb = match debugger(target, &deploy.config, options) {
+        Ok(gdb) => gdb,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+    let sysroot = explicit_sysroot(target, options)
+        .unwrap_or_else(|e| {
+            warning!("{}", e);
+            None
+        })
+        .or_else(|| {
+            if is_host_triple(&target.triple) {
+                return None;
+            }
+            target_linker(target, options.discover, &options.cache)
+                .ok()
+                .and_then(|linker| linker_sysroot(&linker, &options.cache))
+        });
+
+    // gdb needs the symbols stripping would remove
+    let options = BuildOptions {
+        strip: false,
+        ..options.clone()
+    };
+    let binary = match build_binary(target, command, extra_params, &options) {
+        Ok(binary) => binary,
+        Err(code) => return code,
+    };
+
+    let settings = Debug {
+        gdb,
+        sysroot: sysroot.as_deref(),
+        port: deploy.config.gdbserver_port(),
+        commands: &deploy.config.gdb_commands,
+    };
+    match debug(&binary, remote, &settings, &deploy.program_args) {
+        Ok(status) => process::exit_code(&status),
+        Err(e) => {
+            error!("{}", e);
+            TRANSFER_FAILURE_CODE
+        }
+    }
+}
+
+/// Find the gdb to debug the target with: the configured one, the cross
+/// toolchain's, or gdb-multiarch, or the host's gdb for a host target
+fn debugger(
+    target: &Target,
+    config: &DeployConfig,
+    options: &BuildOptions,
+) -> Result<PathBuf, String> {
+    if let Some(ref gdb) = config.gdb {
+        return resolve_program(gdb).ok_or_else(|| format!("gdb {} not found", gdb));
+    }
+    if is_host_triple(&target.triple) {
+        return find_in_path("gdb").ok_or_else(|| String::from("gdb not found on PATH"));
+    }
+    target_linker(target, options.discover, &options.cache)
+        .ok()
+        .and_then(|linker| toolchain_tool(&linker, "gdb"))
+        .or_else(|| find_in_path("gdb-multiarch"))
+        .ok_or_else(|| {
+            format!(
+                "no gdb found for {} alongside its linker or as gdb-multiarch on PATH, \
+                 set gdb under [package.metadata.kubos.deploy]",
+                target.name
+            )
+        })
+}
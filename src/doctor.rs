@@ -0,0 +1,414 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos doctor`, which validates the cross-compilation
+//! environment for one or more targets
+
+use crate::cache::ResolutionCache;
+use crate::compat::{self, Constraints};
+use crate::defaults::{config_path, UserDefaults};
+use crate::events;
+use crate::targets::{target_converter, Target, TargetRegistry};
+use crate::toolchain::{
+    is_executable, is_host_triple, resolve_program, rust_sysroot, rustup_installed_targets,
+    target_linker,
+};
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// The outcome of a single check
+struct Check {
+    status: Status,
+    message: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(message: String) -> Self {
+        Check {
+            status: Status::Pass,
+            message,
+            hint: None,
+        }
+    }
+
+    fn warn(message: String, hint: String) -> Self {
+        Check {
+            status: Status::Warn,
+            message,
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(message: String, hint: String) -> Self {
+        Check {
+            status: Status::Fail,
+            message,
+            hint: Some(hint),
+        }
+    }
+
+    /// Print the check, or report it as part of the section
+    fn print(&self, section: &str) {
+        let label = match self.status {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        if events::json() {
+            events::Event::new("check")
+                .string("section", section)
+                .string("status", &label.to_lowercase())
+                .string("message", &self.message)
+                .opt_string("hint", self.hint.as_deref())
+                .emit();
+            return;
+        }
+        println!("  [{}] {}", label, self.message);
+        if let Some(ref hint) = self.hint {
+            println!("         hint: {}", hint);
+        }
+    }
+}
+
+/// Print a section's heading, after a blank line unless it's the first
+fn heading(title: &str, first: bool) {
+    if events::json() {
+        return;
+    }
+    if !first {
+        println!();
+    }
+    println!("{}", title);
+}
+
+/// Run the checks for the requested targets, or every known target if none
+/// were requested. Returns the exit code: non-zero if any check failed.
+pub fn run(
+    targets: &TargetRegistry,
+    requested: &[String],
+    discover: bool,
+    defaults: &Result<Option<&UserDefaults>, String>,
+    cache: &ResolutionCache,
+) -> i32 {
+    let mut failed = false;
+
+    heading("user defaults", true);
+    let defaults_checks = check_defaults(defaults);
+    for check in &defaults_checks {
+        check.print("user defaults");
+    }
+    failed |= defaults_checks.iter().any(|c| c.status == Status::Fail);
+
+    heading("cargo config", false);
+    let config_checks = check_configs(cache);
+    for check in &config_checks {
+        check.print("cargo config");
+    }
+    failed |= config_checks.iter().any(|c| c.status == Status::Fail);
+
+    let constraints = match Constraints::load(&[]) {
+        Ok(constraints) => constraints,
+        Err(e) => {
+            heading("crate manifest", false);
+            Check::fail(String::from("version constraints do not parse"), e)
+                .print("crate manifest");
+            failed = true;
+            Constraints::default()
+        }
+    };
+
+    let selected: Vec<(String, Result<&Target, String>)> = if requested.is_empty() {
+        targets.iter().map(|t| (t.name.clone(), Ok(t))).collect()
+    } else {
+        requested
+            .iter()
+            .map(|name| {
+                let target = target_converter(targets, name).map_err(|e| e.to_string());
+                (name.clone(), target)
+            })
+            .collect()
+    };
+
+    let installed = rustup_installed_targets();
+    let sysroot = rust_sysroot();
+    let mut summary = vec![];
+
+    for (name, target) in selected {
+        let (label, checks) = match target {
+            Ok(target) => {
+                heading(&format!("{} ({})", target.name, target.triple), false);
+                let checks = check_target(
+                    target,
+                    installed.as_ref(),
+                    sysroot.as_ref(),
+                    &constraints,
+                    discover,
+                    cache,
+                );
+                (target.name.clone(), checks)
+            }
+            Err(e) => {
+                heading(&name, false);
+                let check = Check::fail(
+                    String::from("target mapping does not resolve"),
+                    e.lines().next().unwrap_or("").to_owned(),
+                );
+                (name, vec![check])
+            }
+        };
+
+        for check in &checks {
+            check.print(&label);
+        }
+        let failures = checks.iter().filter(|c| c.status == Status::Fail).count();
+        let warnings = checks.iter().filter(|c| c.status == Status::Warn).count();
+        failed |= failures > 0;
+        events::Event::new("check-summary")
+            .string("target", &label)
+            .number("failed", failures)
+            .number("warnings", warnings)
+            .emit();
+        summary.push(format!(
+            "{}: {} failed, {} warnings",
+            label, failures, warnings
+        ));
+    }
+
+    heading("Summary:", false);
+    if !events::json() {
+        for line in summary {
+            println!("  {}", line);
+        }
+    }
+
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Say which defaults file was loaded and what it sets
+fn check_defaults(defaults: &Result<Option<&UserDefaults>, String>) -> Vec<Check> {
+    match defaults {
+        Ok(Some(defaults)) => {
+            let mut checks = vec![Check::pass(format!("{} loaded", defaults.path.display()))];
+            checks.extend(defaults.describe().into_iter().map(Check::pass));
+            checks
+        }
+        Ok(None) => vec![Check::pass(match config_path() {
+            Some(path) => format!("no defaults file at {}", path.display()),
+            None => String::from("no defaults file, there's no home directory"),
+        })],
+        Err(e) => vec![Check::fail(
+            String::from("defaults file does not load"),
+            e.clone(),
+        )],
+    }
+}
+
+/// Check that every cargo config which exists can be parsed
+fn check_configs(cache: &ResolutionCache) -> Vec<Check> {
+    let mut checks = vec![];
+    for path in cache.config_paths().unwrap_or_default() {
+        let display = path.display().to_string();
+        match cache.config(&path) {
+            Ok(_) => checks.push(Check::pass(format!("{} parses", display))),
+            Err(e) => checks.push(Check::fail(
+                format!("{} does not parse", display),
+                e.to_string(),
+            )),
+        }
+    }
+
+    if checks.is_empty() {
+        checks.push(Check::warn(
+            String::from("no cargo config found"),
+            String::from("create $CARGO_HOME/config.toml with a [target.<triple>] linker entry"),
+        ));
+    }
+    checks
+}
+
+fn check_target(
+    target: &Target,
+    installed: Option<&Vec<String>>,
+    sysroot: Option<&PathBuf>,
+    constraints: &Constraints,
+    discover: bool,
+    cache: &ResolutionCache,
+) -> Vec<Check> {
+    let mut checks = vec![Check::pass(format!(
+        "target mapping resolves to {}",
+        target.triple
+    ))];
+
+    checks.push(check_rust_target(target, installed, sysroot));
+
+    let cross = !is_host_triple(&target.triple);
+    checks.push(check_linker(target, cross, discover, cache));
+    if cross {
+        checks.extend(check_versions(target, constraints, discover, cache));
+        checks.push(check_pkg_config(target));
+    }
+
+    checks
+}
+
+/// Check that the Rust standard library for the target is installed
+fn check_rust_target(
+    target: &Target,
+    installed: Option<&Vec<String>>,
+    sysroot: Option<&PathBuf>,
+) -> Check {
+    let is_installed = match (installed, sysroot) {
+        (Some(installed), _) => installed.contains(&target.triple),
+        (None, Some(sysroot)) => sysroot
+            .join("lib")
+            .join("rustlib")
+            .join(&target.triple)
+            .is_dir(),
+        (None, None) => {
+            return Check::warn(
+                String::from("could not determine the installed Rust targets"),
+                String::from("make sure rustc is on PATH"),
+            )
+        }
+    };
+
+    if is_installed {
+        Check::pass(String::from("Rust target is installed"))
+    } else {
+        Check::fail(
+            String::from("Rust target is not installed"),
+            format!("run `rustup target add {}`", target.triple),
+        )
+    }
+}
+
+/// Check that the linker exists, is executable and runs
+fn check_linker(target: &Target, cross: bool, discover: bool, cache: &ResolutionCache) -> Check {
+    let linker = match target_linker(target, discover, cache) {
+        Ok(linker) => linker,
+        Err(_) if !cross => {
+            return Check::pass(String::from("no linker configured, using host toolchain"))
+        }
+        Err(e) => {
+            return Check::fail(
+                format!("no linker found: {}", e),
+                format!(
+                    "add `linker = \"/path/to/gcc\"` under [target.{}] in your cargo config",
+                    target.triple
+                ),
+            )
+        }
+    };
+
+    let program = match resolve_program(linker.program()) {
+        Some(program) => program,
+        None => {
+            return Check::fail(
+                format!("linker {} does not exist", linker.program()),
+                String::from("install the toolchain or fix the linker path in your cargo config"),
+            )
+        }
+    };
+    if !is_executable(&program) {
+        return Check::fail(
+            format!("linker {} is not executable", program.display()),
+            format!("run `chmod +x {}`", program.display()),
+        );
+    }
+
+    match Command::new(&program).arg("--version").output() {
+        Ok(ref output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout);
+            Check::pass(format!(
+                "linker {} ({}) from {}",
+                program.display(),
+                version.lines().next().unwrap_or("unknown version").trim(),
+                linker.source
+            ))
+        }
+        _ => Check::warn(
+            format!("linker {} does not report a --version", program.display()),
+            String::from("make sure the linker is a working gcc or clang"),
+        ),
+    }
+}
+
+/// Report the toolchain's gcc and glibc versions, and whether they meet
+/// the crate's `min-toolchain-version` and `max-glibc-version`
+fn check_versions(
+    target: &Target,
+    constraints: &Constraints,
+    discover: bool,
+    cache: &ResolutionCache,
+) -> Vec<Check> {
+    let linker = match target_linker(target, discover, cache) {
+        Ok(linker) => linker,
+        Err(_) => return vec![],
+    };
+    let probe = compat::probe(&linker, cache);
+    let mut checks = vec![];
+    if let Some(ref version) = probe.gcc_version {
+        checks.push(Check::pass(format!(
+            "toolchain is gcc {}{}",
+            version,
+            match probe.glibc_version {
+                Some(ref glibc) => format!(" with glibc {}", glibc),
+                None => String::new(),
+            }
+        )));
+    }
+    checks.extend(constraints.violations(&probe).into_iter().map(|violation| {
+        Check::warn(
+            violation,
+            String::from(
+                "use a toolchain which meets the versions in [package.metadata.kubos], \
+                 or builds with --strict-versions will fail",
+            ),
+        )
+    }));
+    checks
+}
+
+/// Check whether pkg-config has been pointed at a target sysroot
+fn check_pkg_config(target: &Target) -> Check {
+    let suffixed = format!("PKG_CONFIG_SYSROOT_DIR_{}", target.triple.replace('-', "_"));
+    for var in &[suffixed.as_str(), "PKG_CONFIG_SYSROOT_DIR"] {
+        if let Ok(dir) = env::var(var) {
+            return Check::pass(format!("{} is set to {}", var, dir));
+        }
+    }
+
+    Check::warn(
+        String::from("no pkg-config sysroot is set"),
+        format!(
+            "export {} so -sys crates don't link against host libraries",
+            suffixed
+        ),
+    )
+}
@@ -0,0 +1,246 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The ways cargo-kubos can fail and the exit code each fails with, so
+//! that wrapper scripts can tell a mistake in how it was run from a broken
+//! environment, a failed build or a bug in cargo-kubos itself:
+//!
+//! | Code | Meaning                                                       |
+//! |------|---------------------------------------------------------------|
+//! | 0    | Success                                                       |
+//! | 1    | A step of cargo-kubos' own failed, like reading the hooks     |
+//! | 2    | The command line was wrong, like an unknown option or target  |
+//! | 3    | The build succeeded but there was no binary to deploy         |
+//! | 4    | The binary couldn't be copied to the board                    |
+//! | 5    | The environment or config is broken, like a missing toolchain |
+//! | 70   | cargo-kubos hit a bug                                         |
+//! | 124  | cargo ran past `--timeout`                                    |
+//! | 127  | A program, like cargo itself, couldn't be started             |
+//!
+//! Otherwise the exit code is cargo's, a post-build hook's, or the program
+//! run on the board's.
+
+use crate::config::{self, ConfigError, LinkerError};
+use crate::targets::TargetError;
+use crate::{process, sdk};
+use std::panic::{self, PanicHookInfo};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::{env, error, fmt, io};
+
+/// Exit code for a step of cargo-kubos' own failing
+pub const FAILURE_CODE: i32 = 1;
+
+/// Exit code for a mistake in the command line
+pub const USAGE_CODE: i32 = 2;
+
+/// Exit code for a broken environment or configuration
+pub const ENVIRONMENT_CODE: i32 = 5;
+
+/// Exit code for a bug in cargo-kubos, when it panics.
+/// This is sysexits' EX_SOFTWARE, as cargo's own 101 means the build failed.
+pub const INTERNAL_CODE: i32 = 70;
+
+/// Why cargo-kubos is giving up
+#[derive(Debug)]
+pub enum Error {
+    /// The command line was wrong
+    Usage(String),
+    /// A target couldn't be resolved
+    Target(TargetError),
+    /// The environment or config is broken, like a missing toolchain
+    /// or an unreadable config file
+    Environment(String),
+    /// A cargo config couldn't be read or parsed
+    Config(ConfigError),
+    /// No linker is configured or could be found for a cross target
+    LinkerNotFound {
+        /// The target's triple
+        triple: String,
+        /// The cargo configs looked in, or if there are none,
+        /// every path one was looked for at
+        searched: Vec<PathBuf>,
+        /// Whether there were any cargo configs to look in
+        found_config: bool,
+        /// A triple a typo away which does have a linker set
+        closest: Option<String>,
+    },
+    /// A program couldn't be started
+    Spawn {
+        /// The program, as it was looked for
+        program: String,
+        /// Why starting it failed
+        source: io::Error,
+    },
+    /// A program cargo-kubos ran failed, having said why itself
+    Child {
+        /// The program
+        program: String,
+        /// How it exited, which cargo-kubos exits with too
+        status: ExitStatus,
+    },
+    /// A step of cargo-kubos' own failed
+    Failed(String),
+    /// A failure which has already been reported, exiting with this code.
+    /// This is how a child's exit code is passed through.
+    Exit(i32),
+}
+
+impl Error {
+    /// The code cargo-kubos exits with for the error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => USAGE_CODE,
+            Error::Target(e) => e.exit_code(),
+            Error::Environment(_) | Error::Config(_) | Error::LinkerNotFound { .. } => {
+                ENVIRONMENT_CODE
+            }
+            Error::Spawn { .. } => process::SPAWN_FAILURE_CODE,
+            Error::Child { status, .. } => process::exit_code(status),
+            Error::Failed(_) => FAILURE_CODE,
+            Error::Exit(code) => *code,
+        }
+    }
+
+    /// Whether there's anything left to say about it
+    pub fn reported(&self) -> bool {
+        matches!(self, Error::Exit(_) | Error::Child { .. })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(msg) | Error::Environment(msg) | Error::Failed(msg) => f.write_str(msg),
+            Error::Target(e) => write!(f, "{}", e),
+            Error::Config(e) => write!(f, "{}", e),
+            Error::LinkerNotFound {
+                triple,
+                searched,
+                found_config,
+                closest,
+            } => {
+                let reason = config::linker_not_set(triple, searched, *found_config, closest);
+                write!(
+                    f,
+                    "no linker found for target {}: {}\n\
+                     Add one to your cargo config, e.g.:\n\n    \
+                     [target.{}]\n    \
+                     linker = \"/path/to/gcc\"\n",
+                    triple, reason, triple
+                )?;
+                if !sdk::in_sdk() {
+                    write!(f, "\n{}\n", sdk::guidance())?;
+                }
+                Ok(())
+            }
+            Error::Spawn { program, source } => f.write_str(&process::spawn_error(program, source)),
+            Error::Child { program, status } => write!(f, "{} failed ({})", program, status),
+            Error::Exit(code) => write!(f, "exited with code {}", code),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Target(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::Spawn { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<TargetError> for Error {
+    fn from(e: TargetError) -> Self {
+        Error::Target(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<LinkerError> for Error {
+    fn from(e: LinkerError) -> Self {
+        match e {
+            LinkerError::Config(e) => Error::Config(e),
+            LinkerError::NotSet {
+                triple,
+                searched,
+                found_config,
+                closest,
+            } => Error::LinkerNotFound {
+                triple,
+                searched,
+                found_config,
+                closest,
+            },
+        }
+    }
+}
+
+/// The result of a command which reports its own errors and returns
+/// the exit code, as a success or an already-reported failure
+pub fn status(code: i32) -> Result<(), Error> {
+    match code {
+        0 => Ok(()),
+        code => Err(Error::Exit(code)),
+    }
+}
+
+/// The result of running a program which reports its own failures,
+/// exiting with the program's exit code if it failed
+pub fn child(program: &str, status: ExitStatus) -> Result<(), Error> {
+    match process::exit_code(&status) {
+        0 => Ok(()),
+        _ => Err(Error::Child {
+            program: String::from(program),
+            status,
+        }),
+    }
+}
+
+/// Report panics as a single line and exit with [`INTERNAL_CODE`],
+/// leaving the usual message and backtrace for when `RUST_BACKTRACE` is set
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info: &PanicHookInfo| {
+        if env::var_os("RUST_BACKTRACE").is_some_and(|value| value != "0") {
+            default_hook(info);
+        } else {
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("panicked");
+            let location = info
+                .location()
+                .map(|l| format!(" at {}:{}", l.file(), l.line()))
+                .unwrap_or_default();
+            error!(
+                "internal error in cargo-kubos{}: {} (this is a bug, \
+                 set RUST_BACKTRACE=1 for a backtrace)",
+                location, message
+            );
+        }
+        std::process::exit(INTERNAL_CODE);
+    }));
+}
@@ -0,0 +1,133 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `--output-format json`, under which cargo-kubos reports what it does as
+//! newline-delimited JSON events on stdout, for build dashboards and other
+//! tools. Nothing else goes to stdout meanwhile: what cargo and the
+//! programs cargo-kubos runs print is sent to stderr instead.
+
+use crate::json::json_string;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Version of the events' schema, given in every event. It goes up when
+/// a field is removed or changes meaning, but not when one is added.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How cargo-kubos reports what it does, as given to `--output-format`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Result<Format, String> {
+        match value {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "invalid output format '{}', expected human or json",
+                value
+            )),
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+/// Whether events are being written, rather than text for people
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Where a child should write its stdout: ours, or with events
+/// being written, stderr so that it doesn't get among them
+pub fn child_stdout() -> Stdio {
+    if json() {
+        Stdio::from(io::stderr())
+    } else {
+        Stdio::inherit()
+    }
+}
+
+/// An event, built up field by field and then written with [`Event::emit`]
+pub struct Event {
+    line: String,
+}
+
+impl Event {
+    pub fn new(name: &str) -> Event {
+        Event {
+            line: format!(
+                "{{\"event\":{},\"version\":{}",
+                json_string(name),
+                SCHEMA_VERSION
+            ),
+        }
+    }
+
+    fn field(mut self, key: &str, value: &str) -> Event {
+        self.line
+            .push_str(&format!(",{}:{}", json_string(key), value));
+        self
+    }
+
+    pub fn string(self, key: &str, value: &str) -> Event {
+        self.field(key, &json_string(value))
+    }
+
+    /// A string, or null without one
+    pub fn opt_string(self, key: &str, value: Option<&str>) -> Event {
+        match value {
+            Some(value) => self.string(key, value),
+            None => self.field(key, "null"),
+        }
+    }
+
+    pub fn strings(self, key: &str, values: &[String]) -> Event {
+        let values: Vec<String> = values.iter().map(|value| json_string(value)).collect();
+        self.field(key, &format!("[{}]", values.join(",")))
+    }
+
+    pub fn number<N: Display>(self, key: &str, value: N) -> Event {
+        self.field(key, &value.to_string())
+    }
+
+    /// A number, or null without one
+    pub fn opt_number<N: Display>(self, key: &str, value: Option<N>) -> Event {
+        match value {
+            Some(value) => self.number(key, value),
+            None => self.field(key, "null"),
+        }
+    }
+
+    /// Write the event as a line of its own, if events are being written
+    pub fn emit(self) {
+        if !json() {
+            return;
+        }
+        let mut stdout = io::stdout().lock();
+        let _ = writeln!(stdout, "{}}}", self.line);
+        let _ = stdout.flush();
+    }
+}
@@ -0,0 +1,609 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Uploading through the Kubos file-transfer-service, which is how files
+//! reach the spacecraft over the real uplink.
+//!
+//! The service speaks CBOR arrays over UDP, naming files by a 16-byte
+//! BLAKE2b hash of their contents:
+//!
+//! - metadata: `[channel, hash, num_chunks]`
+//! - export: `[channel, "export", hash, path, mode]`
+//! - chunk: `[channel, hash, index, data, crc32]`
+//! - ack: `[channel, hash, true, num_chunks]`
+//! - nak: `[channel, hash, false, start, end, ...]`, ranges of chunks
+//!   still missing, each end exclusive
+//! - result: `[channel, true]` or `[channel, false, error]`
+//!
+//! The service keeps the chunks it has received under the file's hash,
+//! so a transfer which fails part way resumes with only the missing ones.
+//! We record the last ranges it reported missing in
+//! `target/kubos/transfers/<hash>.toml` so a rerun starts from there.
+
+use crate::blake2b;
+use crate::cbor::{self, Cbor};
+use crate::ota::file_mode;
+use std::fs;
+use std::io;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use toml::Value;
+
+/// Chunk size when none is configured, the service's own default
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// How many times in a row to go without any progress before giving up
+pub const DEFAULT_RETRIES: u32 = 5;
+
+/// Bytes in the hash naming a file
+const HASH_SIZE: usize = 16;
+
+/// How long to wait for the service to answer before trying again
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest message we expect back, a NAK listing many ranges
+const MAX_REPLY: usize = 64 * 1024;
+
+/// A file-transfer-service to upload through
+pub struct FileService {
+    /// `host:port` of the service
+    pub endpoint: String,
+    pub chunk_size: usize,
+    pub retries: u32,
+    /// Where to keep the state of unfinished transfers
+    pub state_dir: PathBuf,
+}
+
+/// What the service has of an unfinished transfer
+struct Progress {
+    chunks: u64,
+    /// Ranges of chunks not yet received, each end exclusive
+    missing: Vec<(u64, u64)>,
+}
+
+impl Progress {
+    fn acked(&self) -> u64 {
+        self.chunks
+            - self
+                .missing
+                .iter()
+                .map(|(start, end)| end - start)
+                .sum::<u64>()
+    }
+}
+
+impl FileService {
+    /// Upload the file to the given path on the spacecraft, resuming
+    /// a transfer of the same file to the same place if one failed before
+    pub fn upload(&self, local: &Path, remote_path: &str) -> Result<(), String> {
+        let data = fs::read(local).map_err(|e| format!("{}: {}", local.display(), e))?;
+        let mode = fs::metadata(local)
+            .map(|meta| file_mode(&meta))
+            .unwrap_or(0o644);
+        let hash = blake2b::digest(&data, HASH_SIZE);
+        let chunks: Vec<&[u8]> = data.chunks(self.chunk_size).collect();
+        let total = chunks.len() as u64;
+        let name = local
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let state_path = self.state_dir.join(format!("{}.toml", hash));
+        let mut progress = match self.load_state(&state_path, remote_path, total) {
+            Some(progress) => {
+                info!(
+                    "resuming upload of {}, {}/{} chunks already acked",
+                    name,
+                    progress.acked(),
+                    total
+                );
+                progress
+            }
+            None => Progress {
+                chunks: total,
+                missing: if total == 0 { vec![] } else { vec![(0, total)] },
+            },
+        };
+
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.connect(&self.endpoint).map(|_| socket))
+            .map_err(|e| format!("can't reach the file service at {}: {}", self.endpoint, e))?;
+        let channel = channel_id();
+        let send = |message: Vec<Cbor>| {
+            let mut fields = vec![Cbor::Unsigned(channel)];
+            fields.extend(message);
+            socket.send(&cbor::encode(&Cbor::Array(fields))).map(|_| ())
+        };
+        let fail = |progress: &Progress, message: String| {
+            self.save_state(&state_path, remote_path, progress);
+            Err(format!(
+                "{}, {}/{} chunks acked; run the same command again to resume",
+                message,
+                progress.acked(),
+                total
+            ))
+        };
+
+        let mut acked = false;
+        let mut attempts = 0;
+        loop {
+            let sent = if acked {
+                // Only the final result is outstanding, so ask again for it
+                send(export(&hash, remote_path, mode))
+            } else {
+                send_chunks(&send, &hash, &chunks, &progress.missing)
+                    .and_then(|_| send(export(&hash, remote_path, mode)))
+            };
+            let reply = match sent.and_then(|_| receive(&socket, channel, REPLY_TIMEOUT)) {
+                Ok(reply) => reply,
+                // The service not answering, or not running for now
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::ConnectionRefused =>
+                {
+                    attempts += 1;
+                    if attempts > self.retries {
+                        return fail(
+                            &progress,
+                            format!(
+                                "no reply from the file service at {} after {} retries",
+                                self.endpoint, self.retries
+                            ),
+                        );
+                    }
+                    log!(
+                        Debug,
+                        "no reply from {} ({}), retrying ({}/{})",
+                        self.endpoint,
+                        e,
+                        attempts,
+                        self.retries
+                    );
+                    continue;
+                }
+                Err(e) => return fail(&progress, format!("talking to {}: {}", self.endpoint, e)),
+            };
+
+            match reply.as_slice() {
+                [Cbor::Bool(true), ..] => {
+                    if !acked {
+                        report(&name, total, total);
+                    }
+                    let _ = fs::remove_file(&state_path);
+                    return Ok(());
+                }
+                [Cbor::Bool(false), rest @ ..] => {
+                    let error = rest
+                        .first()
+                        .and_then(Cbor::as_str)
+                        .unwrap_or("no reason given");
+                    return fail(
+                        &progress,
+                        format!("the file service failed the upload: {}", error),
+                    );
+                }
+                [Cbor::Text(h), Cbor::Bool(true), ..] if *h == hash => {
+                    if !acked {
+                        acked = true;
+                        attempts = 0;
+                        progress.missing.clear();
+                        report(&name, total, total);
+                    }
+                }
+                [Cbor::Text(h), Cbor::Bool(false), ranges @ ..] if *h == hash => {
+                    let missing = match parse_ranges(ranges, total) {
+                        Some(missing) => missing,
+                        None => {
+                            return fail(
+                                &progress,
+                                String::from("the file service sent a malformed NAK"),
+                            )
+                        }
+                    };
+                    let before = progress.acked();
+                    progress.missing = missing;
+                    if progress.acked() > before {
+                        attempts = 0;
+                    } else {
+                        attempts += 1;
+                        if attempts > self.retries {
+                            return fail(
+                                &progress,
+                                format!("no progress after {} retries", self.retries),
+                            );
+                        }
+                    }
+                    report(&name, progress.acked(), total);
+                    self.save_state(&state_path, remote_path, &progress);
+                }
+                _ => {
+                    log!(Debug, "ignoring unexpected reply {:?}", reply);
+                }
+            }
+        }
+    }
+
+    /// Read the state of an earlier transfer of this file, if it was
+    /// going to the same place in chunks of the same size
+    fn load_state(&self, path: &Path, remote_path: &str, chunks: u64) -> Option<Progress> {
+        let state = fs::read_to_string(path).ok()?.parse::<Value>().ok()?;
+        let same = state.get("endpoint").and_then(Value::as_str) == Some(&self.endpoint)
+            && state.get("path").and_then(Value::as_str) == Some(remote_path)
+            && state.get("chunk-size").and_then(Value::as_integer) == Some(self.chunk_size as i64)
+            && state.get("chunks").and_then(Value::as_integer) == Some(chunks as i64);
+        if !same {
+            return None;
+        }
+        let missing = state
+            .get("missing")?
+            .as_array()?
+            .iter()
+            .map(|range| {
+                let range = range.as_array()?;
+                match (range.first()?.as_integer()?, range.get(1)?.as_integer()?) {
+                    (start, end) if 0 <= start && start < end && end as u64 <= chunks => {
+                        Some((start as u64, end as u64))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Progress { chunks, missing })
+    }
+
+    /// Record what's still missing, so a rerun resumes from there. Failing
+    /// to only means a rerun sends everything again, so isn't an error.
+    fn save_state(&self, path: &Path, remote_path: &str, progress: &Progress) {
+        let missing: Vec<String> = progress
+            .missing
+            .iter()
+            .map(|(start, end)| format!("[{}, {}]", start, end))
+            .collect();
+        let contents = format!(
+            "endpoint = {}\npath = {}\nchunk-size = {}\nchunks = {}\nmissing = [{}]\n",
+            Value::String(self.endpoint.clone()),
+            Value::String(remote_path.to_owned()),
+            self.chunk_size,
+            progress.chunks,
+            missing.join(", ")
+        );
+        if let Err(e) = fs::create_dir_all(&self.state_dir).and_then(|_| fs::write(path, contents))
+        {
+            warning!(
+                "couldn't save the transfer state to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Send the metadata and the chunks in the given ranges
+fn send_chunks<F>(send: &F, hash: &str, chunks: &[&[u8]], ranges: &[(u64, u64)]) -> io::Result<()>
+where
+    F: Fn(Vec<Cbor>) -> io::Result<()>,
+{
+    send(vec![
+        Cbor::Text(hash.to_owned()),
+        Cbor::Unsigned(chunks.len() as u64),
+    ])?;
+    for (start, end) in ranges {
+        for index in *start..*end {
+            let chunk = chunks[index as usize];
+            send(vec![
+                Cbor::Text(hash.to_owned()),
+                Cbor::Unsigned(index),
+                Cbor::Bytes(chunk.to_vec()),
+                Cbor::Unsigned(u64::from(crc32(chunk))),
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+fn export(hash: &str, remote_path: &str, mode: u32) -> Vec<Cbor> {
+    vec![
+        Cbor::Text(String::from("export")),
+        Cbor::Text(hash.to_owned()),
+        Cbor::Text(remote_path.to_owned()),
+        Cbor::Unsigned(u64::from(mode)),
+    ]
+}
+
+/// Wait up to `timeout` for the next message on our channel, returning
+/// its fields after the channel ID. Anything else arriving on the socket
+/// is ignored, but doesn't make us wait any longer.
+fn receive(socket: &UdpSocket, channel: u64, timeout: Duration) -> io::Result<Vec<Cbor>> {
+    let deadline = Instant::now() + timeout;
+    let mut buffer = vec![0u8; MAX_REPLY];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "nothing on our channel",
+            ));
+        }
+        socket.set_read_timeout(Some(left))?;
+        let len = socket.recv(&mut buffer)?;
+        if let Ok(Cbor::Array(mut fields)) = cbor::decode(&buffer[..len]) {
+            if !fields.is_empty() && fields[0].as_u64() == Some(channel) {
+                fields.remove(0);
+                return Ok(fields);
+            }
+        }
+    }
+}
+
+/// The missing ranges listed in a NAK, if they make sense
+fn parse_ranges(fields: &[Cbor], chunks: u64) -> Option<Vec<(u64, u64)>> {
+    if !fields.len().is_multiple_of(2) {
+        return None;
+    }
+    fields
+        .chunks(2)
+        .map(|pair| match (pair[0].as_u64()?, pair[1].as_u64()?) {
+            (start, end) if start < end && end <= chunks => Some((start, end)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn report(name: &str, acked: u64, total: u64) {
+    info!("uploading {}: {}/{} chunks acked", name, acked, total);
+}
+
+/// A channel ID for this transfer, unlikely to clash with another's
+fn channel_id() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or(0);
+    u64::from(nanos ^ process::id().rotate_left(16))
+}
+
+/// CRC-32 (IEEE), checked by the service against each chunk it receives
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::thread::{self, JoinHandle};
+
+    /// What a fake service says to each export request, in turn
+    enum Answer {
+        Nak(Vec<u64>),
+        Ack,
+        Done,
+        Fail(&'static str),
+    }
+
+    /// A file service on a loopback socket which answers each export as
+    /// scripted, returning the chunk indexes it was sent before each
+    fn service(answers: Vec<Answer>) -> (String, JoinHandle<Vec<Vec<u64>>>) {
+        service_at("127.0.0.1:0", answers)
+    }
+
+    fn service_at(address: &str, answers: Vec<Answer>) -> (String, JoinHandle<Vec<Vec<u64>>>) {
+        let socket = UdpSocket::bind(address).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let endpoint = socket.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let mut rounds = vec![];
+            let mut chunks = vec![];
+            let mut buffer = vec![0u8; MAX_REPLY];
+            for answer in answers {
+                let (channel, hash, from) = loop {
+                    let (len, from) = socket.recv_from(&mut buffer).unwrap();
+                    let fields = match cbor::decode(&buffer[..len]).unwrap() {
+                        Cbor::Array(fields) => fields,
+                        other => panic!("not an array: {:?}", other),
+                    };
+                    match fields.as_slice() {
+                        [Cbor::Unsigned(channel), Cbor::Text(export), Cbor::Text(hash), ..]
+                            if export == "export" =>
+                        {
+                            break (*channel, hash.clone(), from)
+                        }
+                        [_, _, Cbor::Unsigned(index), Cbor::Bytes(data), Cbor::Unsigned(crc)] => {
+                            assert_eq!(u64::from(crc32(data)), *crc);
+                            chunks.push(*index);
+                        }
+                        _ => {}
+                    }
+                };
+                rounds.push(std::mem::take(&mut chunks));
+                let mut reply = vec![Cbor::Unsigned(channel)];
+                match answer {
+                    Answer::Nak(ranges) => {
+                        reply.extend(vec![Cbor::Text(hash), Cbor::Bool(false)]);
+                        reply.extend(ranges.into_iter().map(Cbor::Unsigned));
+                    }
+                    Answer::Ack => {
+                        reply.extend(vec![Cbor::Text(hash), Cbor::Bool(true), Cbor::Unsigned(3)])
+                    }
+                    Answer::Done => reply.push(Cbor::Bool(true)),
+                    Answer::Fail(error) => {
+                        reply.extend(vec![Cbor::Bool(false), Cbor::Text(error.into())])
+                    }
+                }
+                socket
+                    .send_to(&cbor::encode(&Cbor::Array(reply)), from)
+                    .unwrap();
+            }
+            rounds
+        });
+        (endpoint, handle)
+    }
+
+    /// A file of three 4-byte chunks to upload, in a directory of its own
+    fn upload_dir(name: &str) -> (PathBuf, PathBuf) {
+        let dir = env::temp_dir().join("cargo-kubos-file-service").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("telemetry");
+        fs::write(&file, b"abcdefghijkl").unwrap();
+        (dir, file)
+    }
+
+    fn file_service(endpoint: String, dir: &Path) -> FileService {
+        FileService {
+            endpoint,
+            chunk_size: 4,
+            retries: 0,
+            state_dir: dir.join("transfers"),
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn nak_ranges_must_fit_the_file() {
+        let ranges = |numbers: &[u64]| {
+            let fields: Vec<Cbor> = numbers.iter().copied().map(Cbor::Unsigned).collect();
+            parse_ranges(&fields, 10)
+        };
+        assert_eq!(ranges(&[]), Some(vec![]));
+        assert_eq!(ranges(&[0, 2, 5, 10]), Some(vec![(0, 2), (5, 10)]));
+        assert_eq!(ranges(&[0]), None);
+        assert_eq!(ranges(&[3, 3]), None);
+        assert_eq!(ranges(&[4, 2]), None);
+        assert_eq!(ranges(&[8, 11]), None);
+        assert_eq!(
+            parse_ranges(&[Cbor::Bool(true), Cbor::Unsigned(1)], 10),
+            None
+        );
+    }
+
+    #[test]
+    fn state_is_kept_only_for_the_same_transfer() {
+        let (dir, _) = upload_dir("state_is_kept_only_for_the_same_transfer");
+        let service = file_service(String::from("127.0.0.1:8008"), &dir);
+        let path = service.state_dir.join("state.toml");
+        let progress = Progress {
+            chunks: 10,
+            missing: vec![(2, 4), (7, 10)],
+        };
+        service.save_state(&path, "/home/system/usr/bin/telemetry", &progress);
+
+        let loaded = service
+            .load_state(&path, "/home/system/usr/bin/telemetry", 10)
+            .unwrap();
+        assert_eq!(loaded.missing, progress.missing);
+        assert_eq!(loaded.acked(), 5);
+        assert!(service
+            .load_state(&path, "/home/system/telemetry", 10)
+            .is_none());
+        assert!(service
+            .load_state(&path, "/home/system/usr/bin/telemetry", 8)
+            .is_none());
+        let elsewhere = file_service(String::from("127.0.0.1:8009"), &dir);
+        assert!(elsewhere
+            .load_state(&path, "/home/system/usr/bin/telemetry", 10)
+            .is_none());
+    }
+
+    #[test]
+    fn nak_resends_only_the_missing_chunks() {
+        let (dir, file) = upload_dir("nak_resends_only_the_missing_chunks");
+        let (endpoint, handle) = service(vec![Answer::Nak(vec![1, 2]), Answer::Ack, Answer::Done]);
+        let service = file_service(endpoint, &dir);
+        service
+            .upload(&file, "/home/system/usr/bin/telemetry")
+            .unwrap();
+        assert_eq!(handle.join().unwrap(), [vec![0, 1, 2], vec![1], vec![]]);
+        // Nothing is left to resume
+        assert!(fs::read_dir(&service.state_dir).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn failed_upload_resumes_where_it_stopped() {
+        let (dir, file) = upload_dir("failed_upload_resumes_where_it_stopped");
+        let (endpoint, handle) = service(vec![Answer::Nak(vec![2, 3]), Answer::Fail("disk full")]);
+        let service = file_service(endpoint, &dir);
+        let error = service
+            .upload(&file, "/home/system/usr/bin/telemetry")
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "the file service failed the upload: disk full, 2/3 chunks acked; \
+             run the same command again to resume"
+        );
+        assert_eq!(handle.join().unwrap(), [vec![0, 1, 2], vec![2]]);
+
+        // The same file to the same place, through a service at the same
+        // address, starts from what was still missing
+        let (endpoint, handle) = service_at(&service.endpoint, vec![Answer::Ack, Answer::Done]);
+        let service = FileService {
+            endpoint,
+            ..service
+        };
+        service
+            .upload(&file, "/home/system/usr/bin/telemetry")
+            .unwrap();
+        assert_eq!(handle.join().unwrap(), [vec![2], vec![]]);
+    }
+
+    #[test]
+    fn stray_datagrams_dont_hold_off_the_timeout() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stray = UdpSocket::bind("127.0.0.1:0").unwrap();
+        stray.connect(socket.local_addr().unwrap()).unwrap();
+        let start = Instant::now();
+        let sender = thread::spawn(move || {
+            // Another channel's messages, more often than the timeout
+            let message = cbor::encode(&Cbor::Array(vec![Cbor::Unsigned(2), Cbor::Bool(true)]));
+            while start.elapsed() < Duration::from_millis(1500) {
+                let _ = stray.send(&message);
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let error = receive(&socket, 1, Duration::from_millis(300)).unwrap_err();
+        assert!(
+            matches!(
+                error.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ),
+            "{}",
+            error
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        sender.join().unwrap();
+    }
+}
@@ -0,0 +1,385 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The usage and the help for each command, from the command table and
+//! the getopts definitions the command line is parsed with.
+
+use crate::cache::ResolutionCache;
+use crate::error::Error;
+use crate::targets::{builtin_table_digest, TargetRegistry};
+use crate::toolchain::{is_host_triple, target_linker};
+use crate::{cli, CARGO_COMMANDS, CARGO_SCOPE};
+use getopts::Options;
+
+/// The usage and a description of each command, for --help
+pub const COMMAND_HELP: &[(&str, &str, &str)] = &[
+    (
+        CARGO_SCOPE,
+        "<cargo command> -t target [options] -- [cargo options]",
+        "Run a cargo command, like build or test, for the targets. Commands \
+         cargo-kubos doesn't know, like nextest, are given the target with \
+         CARGO_BUILD_TARGET rather than --target.",
+    ),
+    (
+        "flash",
+        "-t target [--host user@host] [--dest PATH] [--file-service HOST:PORT]",
+        "Build the executable and copy it to the board.",
+    ),
+    (
+        "run-remote",
+        "-t target [--env KEY=VAL] -- [program args]",
+        "Build the executable, copy it to the board and run it there.",
+    ),
+    (
+        "debug",
+        "-t target -- [program args]",
+        "Build the executable and debug it on the board with gdbserver.",
+    ),
+    (
+        "package",
+        "-t target [--format tar.gz|ipk]",
+        "Build the executable and package it for the board.",
+    ),
+    (
+        "size",
+        "-t target [-t target...] [--max-size BYTES]",
+        "Build and report how big each stripped binary is.",
+    ),
+    (
+        "doctor",
+        "[-t target]",
+        "Check the cross-compilation environment for each target.",
+    ),
+    (
+        "init-target",
+        "-t target [--linker PATH] [--global|--local]",
+        "Write the cargo config for a target's linker.",
+    ),
+    (
+        "setup",
+        "-t target [--ci github|gitlab] [--stdout] [--force]",
+        "Generate the cargo config, a kubos-env.sh and optionally a CI job for a target.",
+    ),
+    (
+        "sync-yotta-target",
+        "-t target [--yotta-module PATH]",
+        "Make the crate's yotta module build for the target.",
+    ),
+    (
+        "verify-manifest",
+        "PATH",
+        "Check an artifact directory against its OTA manifest.",
+    ),
+    (
+        "completions",
+        "bash|zsh|fish",
+        "Print a shell completion script, completing -t with the targets and \
+         --deploy-profile with the profiles known when it's generated.",
+    ),
+    (
+        "help",
+        "[command]",
+        "Show the usage and options of cargo-kubos, or of one of its commands.",
+    ),
+];
+
+/// Width the help is wrapped to, as it's read over serial consoles
+const HELP_WIDTH: usize = 80;
+
+/// What cargo-kubos is, to open the help
+pub const HELP_INTRO: &str = "cargo-kubos is a helper utility for running Cargo commands with a \
+     Kubos target attached. It is used when building/running/testing crates which either \
+     contain a yotta module or depend on one.";
+
+/// The usage of cargo-kubos as a whole
+const HELP_USAGE: &str = "[command] -t target [options] -- [cargo options]";
+
+pub const HELP_EXAMPLES: &[&str] = &[
+    "cargo kubos build -t x86-linux-native -- -vv",
+    "cargo kubos test -t bb -- --lib --features foo",
+    "cargo kubos +nightly build -t bb -- -Z build-std",
+    "cargo kubos clippy -T armv7-unknown-linux-gnueabihf",
+];
+
+/// How the command and arguments are put together
+pub const HELP_COMMAND_NOTE: &str = "The command defaults to build. Arguments in the command go \
+     before --target, and those after -- go last. Run `cargo kubos help <command>` for the \
+     usage and options of each. `-c COMMAND` is still accepted in place of the command, as \
+     in `cargo kubos -c \"test --lib\" -t bb`, but is deprecated.";
+
+/// Where user defaults are read from
+pub const HELP_DEFAULTS_NOTE: &str = "Defaults for the target, each command's cargo flags, \
+     deploying and where to find toolchains can be set in ~/.config/cargo-kubos/config.toml, \
+     or the file CARGO_KUBOS_CONFIG names.";
+
+/// What's set in the environment of cargo, and so of build scripts
+pub const BUILD_SCRIPT_ENV: &[(&str, &str)] = &[
+    ("CARGO_KUBOS_TARGET", "the Kubos target name"),
+    ("CARGO_KUBOS_TRIPLE", "the Rust target triple"),
+    (
+        "CARGO_KUBOS_PROFILE",
+        "the profile directory, like debug or release",
+    ),
+    (
+        "CARGO_KUBOS_LINKER",
+        "the cross gcc, as an absolute path when found",
+    ),
+    (
+        "CARGO_KUBOS_TOOLCHAIN_PREFIX",
+        "prefix of the toolchain's tools, like arm-linux-gnueabihf-",
+    ),
+    ("CARGO_KUBOS_SYSROOT", "the target sysroot, when known"),
+];
+
+/// What cargo-kubos' exit codes mean
+pub const EXIT_STATUS: &[(&str, &str)] = &[
+    ("0", "success"),
+    (
+        "1",
+        "a step of cargo-kubos' own failed, like reading the post-build hooks",
+    ),
+    (
+        "2",
+        "the command line was wrong, like an unknown option or target",
+    ),
+    ("3", "the build succeeded but there was no binary to deploy"),
+    ("4", "the binary couldn't be copied to the board"),
+    (
+        "5",
+        "the environment or config is broken, like a missing toolchain",
+    ),
+    ("70", "cargo-kubos hit a bug"),
+    ("124", "cargo ran past --timeout"),
+    ("127", "a program, like cargo itself, couldn't be started"),
+];
+
+/// The exit codes which aren't cargo-kubos' own
+pub const EXIT_STATUS_NOTE: &str =
+    "otherwise it's the exit code of cargo, a post-build hook, or the program run on the board. \
+     Building several targets, it's that of the worst build: interrupted, then cargo killed, \
+     timed out, a program not starting, cargo failing and lastly a post-build step failing.";
+
+/// Break text into lines of at most `width` characters, at spaces
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Lay out rows as an indented two-column table, wrapping the right
+/// column. A left column wider than `max_left` goes on a line of its own.
+fn help_columns(rows: &[(String, String)], max_left: usize) -> String {
+    const INDENT: usize = 4;
+    let left_width = rows
+        .iter()
+        .map(|(left, _)| left.chars().count())
+        .filter(|width| *width <= max_left)
+        .max()
+        .unwrap_or(0);
+    let margin = " ".repeat(INDENT + left_width + 2);
+    let mut out = String::new();
+    for (left, right) in rows {
+        let lines = wrap(right, HELP_WIDTH - margin.len());
+        let width = left.chars().count();
+        out.push_str(&" ".repeat(INDENT));
+        out.push_str(left);
+        let mut lines = lines.iter();
+        if width <= left_width {
+            if let Some(first) = lines.next() {
+                out.push_str(&" ".repeat(left_width - width + 2));
+                out.push_str(first);
+            }
+        }
+        for line in lines {
+            out.push('\n');
+            out.push_str(&margin);
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// The known targets, marked with whether a linker for each can be
+/// found on this machine. Neither broken target files nor a broken
+/// cargo config keeps the help from showing.
+fn help_targets() -> String {
+    let targets = TargetRegistry::load().unwrap_or_else(|_| TargetRegistry::builtin());
+    let cache = ResolutionCache::new();
+    let rows: Vec<(String, String)> = targets
+        .iter()
+        .map(|target| {
+            let linked =
+                is_host_triple(&target.triple) || target_linker(target, true, &cache).is_ok();
+            let mut name = format!("{} {}", if linked { "✓" } else { "✗" }, target.name);
+            if !target.aliases.is_empty() {
+                name.push_str(&format!(" ({})", target.aliases.join(", ")));
+            }
+            (name, target.triple.clone())
+        })
+        .collect();
+    format!(
+        "\nTargets (✓ has a linker on this machine, ✗ needs one set up):\n{}",
+        help_columns(&rows, 40)
+    )
+}
+
+/// The first sentence of a command's description
+fn summary(description: &str) -> &str {
+    match description.find(". ") {
+        Some(end) => &description[..=end],
+        None => description,
+    }
+}
+
+/// A command as it's run, given its usage from [`COMMAND_HELP`]
+pub fn command_line(name: &str, usage: &str) -> String {
+    if name == CARGO_SCOPE {
+        format!("cargo kubos [+toolchain] {}", usage)
+    } else {
+        format!("cargo kubos {} {}", name, usage)
+    }
+}
+
+/// Displays usage message, on stderr if it's accompanying an error
+/// so it can't be mistaken for the output of cargo. With a command,
+/// only its own usage and options are shown.
+pub fn print_usage(opts: Options, command: Option<&str>, error: bool) {
+    let usage_line = |name: &str, usage: &str| {
+        format!(
+            "Usage: {}",
+            wrap(&command_line(name, usage), HELP_WIDTH - 7).join("\n       ")
+        )
+    };
+    let paragraph = |text: &str| wrap(text, HELP_WIDTH).join("\n");
+    let rows = |rows: &[(&str, &str)]| {
+        let rows: Vec<(String, String)> = rows
+            .iter()
+            .map(|(left, right)| (String::from(*left), String::from(*right)))
+            .collect();
+        help_columns(&rows, 30)
+    };
+    let brief = match command.and_then(|c| COMMAND_HELP.iter().find(|(name, _, _)| *name == c)) {
+        Some((name, usage, description)) => {
+            format!("{}\n\n{}", usage_line(name, usage), paragraph(description))
+        }
+        None => {
+            let commands: Vec<(String, String)> = COMMAND_HELP
+                .iter()
+                .map(|(name, _, description)| {
+                    let name = if *name == CARGO_SCOPE {
+                        "<cargo command>"
+                    } else {
+                        name
+                    };
+                    (String::from(name), String::from(summary(description)))
+                })
+                .collect();
+            format!(
+                "{}\n\n{}\n\nCommands:\n{}\nFor example:\n{}\n{}\n\n{}\n\n\
+                 Build scripts can read these from the environment:\n{}\n\
+                 Exit status:\n{}{}",
+                paragraph(HELP_INTRO),
+                usage_line(CARGO_SCOPE, HELP_USAGE),
+                help_columns(&commands, 30),
+                HELP_EXAMPLES
+                    .iter()
+                    .map(|example| format!("    {}\n", example))
+                    .collect::<String>(),
+                paragraph(HELP_COMMAND_NOTE),
+                paragraph(HELP_DEFAULTS_NOTE),
+                rows(BUILD_SCRIPT_ENV),
+                rows(EXIT_STATUS),
+                paragraph(EXIT_STATUS_NOTE),
+            )
+        }
+    };
+    let mut usage = opts.usage(&brief);
+    if !matches!(
+        command,
+        Some("verify-manifest") | Some("completions") | Some("help") | Some("generate-man")
+    ) {
+        usage.push_str(&help_targets());
+    }
+    if error {
+        eprint!("{}", usage);
+    } else {
+        print!("{}", usage);
+    }
+}
+
+/// Run `cargo kubos help [command]`
+pub fn command(args: &[String]) -> Result<(), Error> {
+    let scope = match args {
+        [] => None,
+        [command] if CARGO_COMMANDS.contains(&command.as_str()) => Some(CARGO_SCOPE),
+        [command] => match COMMAND_HELP.iter().find(|(name, _, _)| name == command) {
+            Some((name, _, _)) => Some(*name),
+            None => {
+                return Err(Error::Usage(format!(
+                    "no such command '{}', run `cargo kubos help` for the list",
+                    command
+                )))
+            }
+        },
+        _ => return Err(Error::Usage(String::from("help takes at most one command"))),
+    };
+    print_usage(cli::options(scope), scope, false);
+    Ok(())
+}
+
+/// Print `cargo kubos --version`, with the built-in target table if verbose
+pub fn print_version(verbose: bool) {
+    let version = env!("CARGO_PKG_VERSION");
+    match option_env!("CARGO_KUBOS_COMMIT") {
+        Some(commit) => println!("cargo-kubos {} ({})", version, commit),
+        None => println!("cargo-kubos {}", version),
+    }
+    let builtin = TargetRegistry::builtin();
+    println!(
+        "built-in targets: {} (table {})",
+        builtin.len(),
+        builtin_table_digest()
+    );
+    if !verbose {
+        return;
+    }
+    for target in builtin.iter() {
+        println!("\n{}", target.name);
+        println!("    triple:  {}", target.triple);
+        if !target.aliases.is_empty() {
+            println!("    aliases: {}", target.aliases.join(", "));
+        }
+        if !target.gcc.is_empty() {
+            println!("    gcc:     {}", target.gcc.join(", "));
+        }
+        if !target.cflags.is_empty() {
+            println!("    cflags:  {}", target.cflags.join(" "));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Project commands run after a build, configured with
+//! `[package.metadata.kubos.hooks]` in the crate manifest
+
+use crate::manifest::{find_manifest, kubos_metadata, read_manifest};
+use crate::targets::Target;
+use crate::{events, log, process, shell_quote, split_words};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Commands to run, in order, after a successful build
+pub struct Hooks {
+    /// Each hook, as written in the manifest
+    pub post_build: Vec<String>,
+    /// The crate's directory, which hooks are run in
+    pub dir: PathBuf,
+}
+
+/// Read the hooks from the crate manifest, if there is one
+pub fn load(params: &[String]) -> Result<Hooks, String> {
+    let path = match find_manifest(params) {
+        Some(path) => path,
+        None => {
+            return Ok(Hooks {
+                post_build: vec![],
+                dir: PathBuf::from("."),
+            })
+        }
+    };
+    let manifest = read_manifest(&path)?;
+    let post_build = match kubos_metadata(&manifest, "hooks").and_then(|h| h.get("post-build")) {
+        Some(value) => value
+            .as_array()
+            .and_then(|items| {
+                items
+                    .iter()
+                    .map(|item| item.as_str().map(String::from))
+                    .collect::<Option<Vec<String>>>()
+            })
+            .ok_or_else(|| {
+                format!(
+                    "{}: package.metadata.kubos.hooks.post-build must be an array of strings",
+                    path.display()
+                )
+            })?,
+        None => vec![],
+    };
+    // Made absolute, as the hooks are run there: a relative --manifest-path
+    // would otherwise name them relative to the crate twice over
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let dir = env::current_dir()
+        .map(|cwd| cwd.join(dir))
+        .unwrap_or_else(|_| dir.to_path_buf());
+    Ok(Hooks { post_build, dir })
+}
+
+/// What a finished build made, for the hooks' environment
+pub struct Build<'a> {
+    pub target: &'a Target,
+    /// The profile directory built into, like `debug` or `release`
+    pub profile: &'a str,
+    pub artifacts: &'a [PathBuf],
+}
+
+/// Run the post-build hooks in order, stopping at the first to fail.
+/// On failure, returns the error and the exit code to fail with.
+pub fn run_post_build(hooks: &Hooks, build: &Build) -> Result<(), (String, i32)> {
+    let artifacts = env::join_paths(build.artifacts).unwrap_or_default();
+    for hook in &hooks.post_build {
+        let words =
+            split_words(hook).map_err(|e| (format!("post-build hook '{}': {}", hook, e), 1))?;
+        let (program, args) = match words.split_first() {
+            Some(split) => split,
+            None => continue,
+        };
+        // Scripts in the crate are named relative to it, like cargo's build.rs
+        let program = if program.contains(std::path::is_separator) {
+            hooks.dir.join(program)
+        } else {
+            PathBuf::from(program)
+        };
+
+        let mut command = Command::new(&program);
+        command
+            .args(args)
+            .current_dir(&hooks.dir)
+            .env("CARGO_KUBOS_TARGET", &build.target.name)
+            .env("CARGO_KUBOS_TRIPLE", &build.target.triple)
+            .env("CARGO_KUBOS_PROFILE", build.profile)
+            .env("CARGO_KUBOS_ARTIFACTS", &artifacts)
+            .stdout(events::child_stdout());
+        info!("running post-build hook {}", hook);
+        if log::enabled(log::Level::Debug) {
+            let argv: Vec<String> = std::iter::once(program.to_string_lossy().into_owned())
+                .chain(args.iter().cloned())
+                .map(|arg| shell_quote(&arg))
+                .collect();
+            log!(Debug, "running {}", argv.join(" "));
+        }
+
+        let status = process::run(&mut command, None).map_err(|e| {
+            (
+                format!(
+                    "post-build hook '{}': {}",
+                    hook,
+                    process::spawn_error(&program.to_string_lossy(), &e)
+                ),
+                process::SPAWN_FAILURE_CODE,
+            )
+        })?;
+        if !status.success() {
+            return Err((
+                format!("post-build hook '{}' failed ({})", hook, status),
+                process::exit_code(&status),
+            ));
+        }
+    }
+    Ok(())
+}
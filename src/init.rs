@@ -0,0 +1,217 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos init-target`, which writes the cargo config
+//! stanza needed to cross-compile for a board
+
+use crate::config::{cargo_home, config_file};
+use crate::targets::{target_converter, Target, TargetRegistry};
+use crate::toolchain::{find_gcc, sdk_toolchain};
+use getopts::Matches;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// Where the config stanza should be written
+pub enum Scope {
+    /// `$CARGO_HOME/config.toml`, or `~/.cargo/config.toml` if unset
+    Global,
+    /// `.cargo/config.toml` in the current directory
+    Local,
+}
+
+/// Options for `init-target`
+pub struct InitOptions {
+    pub linker: Option<String>,
+    pub scope: Scope,
+    pub force: bool,
+    pub dry_run: bool,
+}
+
+/// Write the `[target.<triple>]` stanza for the target into the cargo config
+pub fn run(target: &Target, options: &InitOptions) -> Result<(), String> {
+    let linker = match options.linker {
+        Some(ref linker) => linker.clone(),
+        None => discover_linker(target)?,
+    };
+    let stanza = format!(
+        "[target.{}]\nlinker = {}\n",
+        target.triple,
+        Value::String(linker)
+    );
+
+    if options.dry_run {
+        print!("{}", stanza);
+        return Ok(());
+    }
+
+    let dir = match options.scope {
+        Scope::Global => cargo_home()
+            .ok_or_else(|| String::from("neither CARGO_HOME nor a home directory is set"))?,
+        Scope::Local => PathBuf::from(".cargo"),
+    };
+    let path = write_stanza(&dir, &target.triple, &stanza, options.force)?;
+
+    println!("Wrote to {}:\n\n{}", path.display(), stanza);
+    Ok(())
+}
+
+/// Add the `[target.<triple>]` stanza to the cargo config in the directory,
+/// replacing one already there only with `force`, and return the config's path
+pub fn write_stanza(
+    dir: &Path,
+    triple: &str,
+    stanza: &str,
+    force: bool,
+) -> Result<PathBuf, String> {
+    // Keep using a legacy `config` file if that's what already exists
+    let path = config_file(dir).unwrap_or_else(|_| dir.join("config.toml"));
+
+    let existing = if path.is_file() {
+        fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?
+    } else {
+        String::new()
+    };
+    let cfg = existing
+        .parse::<Value>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if cfg.get("target").and_then(|t| t.get(triple)).is_some() {
+        if !force {
+            return Err(format!(
+                "{} already has an entry for [target.{}], pass --force to replace it",
+                path.display(),
+                triple
+            ));
+        }
+        let remaining = remove_section(&existing, triple).ok_or_else(|| {
+            format!(
+                "could not find the [target.{}] section in {} to replace",
+                triple,
+                path.display()
+            )
+        })?;
+        write_config(&path, &remaining, stanza)?;
+    } else {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        append_config(&path, &existing, stanza)?;
+    }
+    Ok(path)
+}
+
+/// Find the cross gcc for the triple in the Kubos SDK or on PATH
+pub fn discover_linker(target: &Target) -> Result<String, String> {
+    sdk_toolchain(&target.triple)
+        .or_else(|| find_gcc(target))
+        .map(|path| path.to_string_lossy().into_owned())
+        .ok_or_else(|| {
+            format!(
+                "no cross gcc found for {} in the Kubos SDK or on PATH, pass --linker",
+                target.triple
+            )
+        })
+}
+
+/// Add the stanza to the end of the config, leaving the rest untouched
+fn append_config(path: &Path, existing: &str, stanza: &str) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let separator = match existing {
+        "" => "",
+        s if s.ends_with("\n\n") => "",
+        s if s.ends_with('\n') => "\n",
+        _ => "\n\n",
+    };
+    write!(file, "{}{}", separator, stanza).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Rewrite the config with the stanza replacing the old section
+fn write_config(path: &Path, remaining: &str, stanza: &str) -> Result<(), String> {
+    let mut contents = String::from(remaining.trim_end());
+    if !contents.is_empty() {
+        contents.push_str("\n\n");
+    }
+    contents.push_str(stanza);
+    fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Remove the `[target.<triple>]` section from the config text, returning
+/// `None` if it isn't declared with a section header
+fn remove_section(contents: &str, triple: &str) -> Option<String> {
+    let headers = [
+        format!("[target.{}]", triple),
+        format!("[target.\"{}\"]", triple),
+    ];
+    let lines: Vec<&str> = contents.lines().collect();
+    let start = lines
+        .iter()
+        .position(|line| headers.iter().any(|h| line.trim() == h))?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map(|offset| start + 1 + offset)
+        .unwrap_or(lines.len());
+
+    let mut remaining: Vec<&str> = lines[..start].to_vec();
+    remaining.extend(&lines[end..]);
+    Some(remaining.join("\n"))
+}
+
+/// Run `cargo kubos init-target`, returning the exit code
+pub fn command(matches: &Matches, targets: &TargetRegistry) -> i32 {
+    let k_targets = matches.opt_strs("t");
+    let k_target = match k_targets.as_slice() {
+        [k_target] => k_target,
+        _ => {
+            error!("init-target needs exactly one target (-t)");
+            return 2;
+        }
+    };
+    let target = match target_converter(targets, k_target) {
+        Ok(target) => target,
+        Err(e) => {
+            error!("{}", e);
+            return e.exit_code();
+        }
+    };
+    if matches.opt_present("global") && matches.opt_present("local") {
+        error!("--global and --local cannot be used together");
+        return 2;
+    }
+
+    let options = InitOptions {
+        linker: matches.opt_str("linker"),
+        scope: if matches.opt_present("global") {
+            Scope::Global
+        } else {
+            Scope::Local
+        },
+        force: matches.opt_present("force"),
+        dry_run: matches.opt_present("dry-run"),
+    };
+    match run(target, &options) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("{}", e);
+            1
+        }
+    }
+}
@@ -0,0 +1,1701 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The library behind `cargo kubos`: mapping Kubos targets to Rust target
+//! triples, finding the cross linkers for them and working out how cargo
+//! is run to build for them, for build tools to use without running
+//! cargo-kubos. [`run`] is the command line itself.
+
+// The modules with macros go first, so that the others can use them
+#[macro_use]
+mod color;
+#[macro_use]
+mod log;
+
+mod artifacts;
+mod blake2b;
+mod build;
+mod buildenv;
+mod cache;
+mod cargo_config;
+mod cbor;
+mod cli;
+mod compat;
+mod completions;
+mod config;
+mod container;
+mod defaults;
+mod deploy;
+mod doctor;
+mod error;
+mod events;
+mod file_service;
+mod help;
+mod hooks;
+mod init;
+mod json;
+mod list;
+mod man;
+mod manifest;
+mod ota;
+mod package;
+mod preflight;
+mod process;
+mod project;
+mod prompt;
+mod sdk;
+mod select;
+mod setup;
+mod sha256;
+mod size;
+mod targets;
+mod toolchain;
+mod workspace;
+mod yotta;
+
+use crate::artifacts::profile_dir;
+use crate::buildenv::BuildEnv;
+use crate::cli::Cli;
+use crate::config::{
+    config_env, config_runner, kubos_setting, kubos_target_env, kubos_target_flag,
+    kubos_target_path, resolve_rustflags, target_env_var,
+};
+use crate::deploy::DeployConfig;
+use crate::help::print_usage;
+use crate::help::print_version;
+use crate::manifest::{arg_value, find_manifest, has_flag, manifest_path_arg};
+use crate::preflight::check_linkers;
+use crate::preflight::check_rust_targets;
+use crate::preflight::check_sysroots;
+use crate::preflight::explicit_sysroot;
+use crate::preflight::preflight_linker;
+use crate::preflight::preflight_linkers;
+use crate::project::{load_project_config, ProjectConfig};
+use crate::select::select_targets;
+use crate::targets::Target;
+use crate::toolchain::find_in_path;
+use crate::toolchain::is_host_triple;
+use crate::toolchain::is_musl;
+use crate::toolchain::linker_sysroot;
+use crate::toolchain::resolve_program;
+use crate::toolchain::select_toolchain;
+use crate::toolchain::target_linker;
+use crate::toolchain::toolchain_binutils;
+use crate::toolchain::toolchain_prefix;
+use getopts::Matches;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use crate::buildenv::BuildEnv as BuildEnvironment;
+pub use crate::cache::{ConfigLoader, DiskLoader, ResolutionCache};
+pub use crate::cargo_config::{
+    BuildConfig, CargoConfig, EnvValue, LinkerValue, MergedConfig, MergedTarget, StringList,
+    TargetConfig,
+};
+pub use crate::config::{read_config, ConfigError, ConfigFile, EnvEntry, Linker, LinkerSource};
+pub use crate::error::{install_panic_hook, Error};
+pub use crate::process::{Capture, Runner as ProcessRunner, System as SystemRunner};
+pub use crate::targets::{Target as KubosTarget, TargetError, TargetRegistry, TargetSpec};
+
+/// Every Kubos target cargo-kubos knows: the built-in ones and those
+/// added by the target mapping files
+pub fn targets() -> Result<Vec<KubosTarget>, Error> {
+    TargetRegistry::load()
+        .map(Vec::from)
+        .map_err(Error::Environment)
+}
+
+/// Find a Kubos target by its name or one of its aliases
+pub fn resolve_target(name: &str) -> Result<KubosTarget, Error> {
+    TargetRegistry::load()
+        .map_err(Error::Environment)?
+        .resolve(name)
+        .cloned()
+        .map_err(Error::Target)
+}
+
+/// The linker cargo-kubos would build for the target with: the one from
+/// the target mapping or cargo config, or failing those a cross gcc found
+/// on this machine
+pub fn resolve_linker(target: &KubosTarget, cache: &ResolutionCache) -> Result<Linker, Error> {
+    target_linker(target, true, cache).map_err(Error::from)
+}
+
+/// Check that a cross target's linker exists and runs `--version`, as
+/// cargo-kubos does before building, with the runner running it. Targets
+/// built for this machine, or without a linker, have nothing to check,
+/// and a linker the cache has seen run isn't run again.
+pub fn check_linker(
+    target: &KubosTarget,
+    runner: &dyn ProcessRunner,
+    cache: &ResolutionCache,
+) -> Result<(), Error> {
+    preflight_linker(target, true, runner, cache)
+}
+
+/// A cargo command for a target, with the arguments and environment
+/// cargo-kubos would run it with
+#[derive(Debug)]
+pub struct CargoInvocation {
+    /// The target being built for
+    pub target: KubosTarget,
+    /// cargo's arguments, starting with the command
+    pub args: Vec<String>,
+    /// What's added to the environment cargo-kubos was run with
+    pub env: BuildEnvironment,
+}
+
+impl CargoInvocation {
+    /// Work out how cargo-kubos would run the command, like `["build",
+    /// "--release"]`, for the target, with none of its own options given
+    pub fn new(target: &KubosTarget, command: &[String]) -> CargoInvocation {
+        CargoInvocation::with_cache(target, command, &Arc::new(ResolutionCache::new()))
+    }
+
+    /// Like [`CargoInvocation::new`], reading the cargo configs through a
+    /// cache shared with the other targets being built
+    pub fn with_cache(
+        target: &KubosTarget,
+        command: &[String],
+        cache: &Arc<ResolutionCache>,
+    ) -> CargoInvocation {
+        let (head, rest) = command.split_at(command.len().min(1));
+        let options = BuildOptions {
+            cache: Arc::clone(cache),
+            ..BuildOptions::default()
+        };
+        let (args, env) = prepare_build(target, head.to_vec(), rest.to_vec(), &options);
+        CargoInvocation {
+            target: target.clone(),
+            args,
+            env,
+        }
+    }
+
+    /// The command which runs cargo, to be started or looked at
+    pub fn command(&self) -> Command {
+        let mut command = Command::new("cargo");
+        self.env.apply(&mut command);
+        command.args(&self.args);
+        command
+    }
+
+    /// Run cargo to completion with the runner, returning how it exited
+    pub fn run(&self, runner: &dyn ProcessRunner) -> Result<ExitStatus, Error> {
+        runner
+            .run(&mut self.command(), None, Capture::Nothing)
+            .map(|(status, _)| status)
+            .map_err(|source| Error::Spawn {
+                program: String::from("cargo"),
+                source,
+            })
+    }
+
+    /// Start cargo, with stdin, stdout and stderr inherited
+    pub fn spawn(&self) -> Result<Child, Error> {
+        self.command().spawn().map_err(|source| Error::Spawn {
+            program: String::from("cargo"),
+            source,
+        })
+    }
+}
+
+/// Environment variable which may hold the default Kubos target
+const TARGET_ENV_VAR: &str = "KUBOS_TARGET";
+
+/// Meta-target which expands to every known target
+const ALL_TARGETS: &str = "all";
+
+/// Command to run when none is given
+const DEFAULT_COMMAND: &str = "build";
+
+/// Cargo commands which may be given as the first argument instead of with -c
+const CARGO_COMMANDS: &[&str] = &[
+    "bench", "build", "check", "clean", "clippy", "doc", "fix", "run", "rustc", "rustdoc", "test",
+];
+
+/// Cargo commands which execute the binaries they build
+const RUNNING_COMMANDS: &[&str] = &["run", "test", "bench"];
+
+/// cargo-kubos commands which build and then make use of what was built
+const BUILD_COMMANDS: &[&str] = &["flash", "run-remote", "debug", "package", "size"];
+
+/// cargo-kubos commands which don't build anything themselves
+const KUBOS_COMMANDS: &[&str] = &[
+    "completions",
+    "doctor",
+    "generate-man",
+    "help",
+    "init-target",
+    "setup",
+    "sync-yotta-target",
+    "verify-manifest",
+];
+
+/// What cargo's commands, and any others forwarded to cargo,
+/// are called when working out which options they accept
+const CARGO_SCOPE: &str = "cargo";
+
+/// Every command which runs cargo
+const BUILDING: &[&str] = &[
+    CARGO_SCOPE,
+    "flash",
+    "run-remote",
+    "debug",
+    "package",
+    "size",
+];
+
+/// Cargo commands which get a qemu runner when none is configured
+const QEMU_COMMANDS: &[&str] = &["test", "bench"];
+
+/// qemu user-mode emulators, by target triplet architecture prefix.
+/// The more specific prefixes come first.
+const QEMU_EMULATORS: &[(&str, &str)] = &[
+    ("aarch64", "qemu-aarch64"),
+    ("armeb", "qemu-armeb"),
+    ("arm", "qemu-arm"),
+];
+
+/// Rust flags required to produce fully static musl binaries
+const MUSL_RUSTFLAGS: &[&str] = &["-C", "target-feature=+crt-static"];
+
+/// Append flags to those already set in an environment variable
+fn append_flags(var: &str, flags: &[String]) -> String {
+    match env::var(var) {
+        Ok(ref existing) if !existing.trim().is_empty() => {
+            format!("{} {}", existing, flags.join(" "))
+        }
+        _ => flags.join(" "),
+    }
+}
+
+/// Options which affect how each target is built
+#[derive(Clone)]
+struct BuildOptions {
+    /// How many times -v was given
+    verbose: usize,
+    /// Sysroot given with --sysroot, used for every target
+    sysroot: Option<String>,
+    /// Whether to leave --sysroot out of the C compiler flags
+    no_sysroot_flags: bool,
+    /// Whether to pass the target and sysroot to bindgen's clang
+    bindgen_args: bool,
+    /// Whether to also set the global CC and CXX, as older releases did
+    legacy_cc: bool,
+    /// Whether to leave PKG_CONFIG_ALLOW_CROSS unset
+    no_pkg_config_cross: bool,
+    /// OpenSSL install prefix given with --openssl-dir, used for every target
+    openssl_dir: Option<String>,
+    /// Whether to look for a toolchain when no linker is configured
+    discover: bool,
+    /// Whether to leave tests without a runner rather than using qemu
+    no_qemu: bool,
+    /// Whether to strip cross binaries after building them
+    strip: bool,
+    /// How long each cargo invocation may run, given with --timeout
+    timeout: Option<Duration>,
+    /// Whether to skip the post-build hooks, for --no-hooks
+    no_hooks: bool,
+    /// Whether to check that artifacts were built for the target's
+    /// architecture, unless --no-verify-arch
+    verify_arch: bool,
+    /// yotta modules in the dependency graph, built before cargo runs
+    yotta: Vec<yotta::Module>,
+    /// The yotta module the crate wraps, whose search paths are
+    /// exported to build scripts
+    yotta_module: Option<yotta::Description>,
+    /// The engine to run cargo in a toolchain image with, for --container
+    container: Option<container::Container>,
+    /// Manifests of the packages selected with -p or --workspace, whose
+    /// outputs are the only ones to deploy, package or report
+    packages: Option<Vec<PathBuf>>,
+    /// Whether the command is one cargo-kubos doesn't know, which is given
+    /// the target with `CARGO_BUILD_TARGET` since it may not take --target
+    forwarded: bool,
+    /// Arguments after `--` from the first which isn't valid UTF-8, which
+    /// cargo is given as they are, after all the others
+    raw_args: Vec<OsString>,
+    /// The configs read and linkers probed, shared by every target built
+    cache: Arc<ResolutionCache>,
+}
+
+impl Default for BuildOptions {
+    /// How a target is built when none of cargo-kubos' options are given
+    fn default() -> Self {
+        BuildOptions {
+            verbose: 0,
+            sysroot: None,
+            no_sysroot_flags: false,
+            bindgen_args: false,
+            legacy_cc: false,
+            no_pkg_config_cross: false,
+            openssl_dir: None,
+            discover: true,
+            no_qemu: false,
+            strip: false,
+            timeout: None,
+            no_hooks: false,
+            verify_arch: true,
+            yotta: vec![],
+            yotta_module: None,
+            container: None,
+            packages: None,
+            forwarded: false,
+            raw_args: vec![],
+            cache: Arc::new(ResolutionCache::new()),
+        }
+    }
+}
+
+/// Work out the cargo arguments and environment for building a target
+fn prepare_build(
+    target: &Target,
+    command: Vec<String>,
+    mut extra_params: Vec<String>,
+    options: &BuildOptions,
+) -> (Vec<String>, BuildEnv) {
+    let mut params = command;
+    if !options.forwarded {
+        params.push(String::from("--target"));
+        params.push(target.triple.clone());
+    }
+    if options.verbose > 1 {
+        params.insert(1, String::from("-v"));
+    }
+    // So that cargo itself uses the runner, rustflags and env from them
+    if !options.forwarded {
+        for path in options.cache.given_configs() {
+            params.push(String::from("--config"));
+            params.push(path.to_string_lossy().into_owned());
+        }
+    }
+    params.append(&mut extra_params);
+
+    let linker = match target_linker(target, options.discover, &options.cache) {
+        Ok(linker) => Some(linker),
+        Err(e) => {
+            log!(Debug, "no linker for {}: {}", target.triple, e);
+            None
+        }
+    };
+    let explicit_sysroot = explicit_sysroot(target, options).unwrap_or_else(|e| {
+        warning!("{}", e);
+        None
+    });
+    // Otherwise ask the cross gcc, whose own sysroot doesn't need passing to it
+    let sysroot = explicit_sysroot.clone().or_else(|| {
+        if is_host_triple(&target.triple) {
+            None
+        } else {
+            linker
+                .as_ref()
+                .and_then(|linker| linker_sysroot(linker, &options.cache))
+        }
+    });
+    let mut build_env = BuildEnv::new();
+    if options.forwarded {
+        build_env.set("CARGO_BUILD_TARGET", target.triple.clone());
+    }
+
+    // Variables from the cargo config go first so that ours don't override
+    // any which are forced. The per-target ones take precedence over [env].
+    match target_env_entries(target, linker.as_ref(), sysroot.as_deref(), &options.cache) {
+        Ok(entries) => {
+            for entry in entries {
+                if entry.force {
+                    build_env.set(&entry.key, entry.value);
+                } else {
+                    build_env.set_default(&entry.key, entry.value);
+                }
+            }
+        }
+        Err(e) => warning!("{}", e),
+    }
+
+    // What build scripts would otherwise have to work out for themselves
+    build_env.set("CARGO_KUBOS_TARGET", target.name.as_str());
+    build_env.set("CARGO_KUBOS_TRIPLE", target.triple.as_str());
+    build_env.set("CARGO_KUBOS_PROFILE", profile_dir(&params));
+    if let Some(ref linker) = linker {
+        let path = resolve_program(linker.program())
+            .and_then(|path| path.canonicalize().ok())
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from(linker.program()));
+        build_env.set("CARGO_KUBOS_LINKER", path);
+        if let Some(prefix) = toolchain_prefix(linker) {
+            build_env.set("CARGO_KUBOS_TOOLCHAIN_PREFIX", prefix);
+        }
+    }
+    if let Some(ref sysroot) = sysroot {
+        build_env.set("CARGO_KUBOS_SYSROOT", sysroot.as_str());
+    }
+    let mut target_flags = target.rustflags.clone();
+    if let Some(ref sysroot) = explicit_sysroot {
+        if !is_host_triple(&target.triple) {
+            target_flags.push(format!("-Clink-arg=--sysroot={}", sysroot));
+        }
+    }
+    if let Some(ref linker) = linker {
+        if matches!(
+            linker.source,
+            LinkerSource::Sdk | LinkerSource::Defaults(_) | LinkerSource::Path
+        ) {
+            info!(
+                "no linker is configured for {}, using {} found in {}",
+                target.triple, linker, linker.source
+            );
+        } else {
+            log!(Debug, "using linker {} from {}", linker, linker.source);
+        }
+
+        // The cc crate prefers the triple-suffixed variables, which leaves
+        // build scripts and other host code compiled with the host compiler
+        let suffix = target.triple.replace('-', "_");
+        build_env.set_default(&format!("CC_{}", suffix), linker.path.as_str());
+        build_env.set_default(&format!("CXX_{}", suffix), linker.path.as_str());
+        if options.legacy_cc {
+            build_env.set_default("CC", linker.path.as_str());
+            build_env.set_default("CXX", linker.path.as_str());
+        }
+        if !is_host_triple(&target.triple) {
+            // Unix hosts all have these, where MSVC's cl.exe is better
+            // left for the cc crate to find
+            if cfg!(unix) {
+                build_env.set_default("HOST_CC", "cc");
+                build_env.set_default("HOST_CXX", "c++");
+            }
+            pkg_config_cross(&mut build_env, options);
+            match sysroot {
+                Some(ref sysroot) => pkg_config_env(&mut build_env, &target.triple, sysroot),
+                // The image has its own, which is no concern of the host's
+                None if options.container.is_some() => {}
+                None => warning!(
+                    "no sysroot found for target {}, so pkg-config may find \
+                     host libraries which won't work on the board.\n\
+                     Pass --sysroot or set `[kubos.target.\"{}\"].sysroot` in your cargo config",
+                    target.name,
+                    target.name
+                ),
+            }
+        }
+
+        // Without these the cc crate falls back to the host's binutils,
+        // which produce archives the cross linker can't read
+        for (var, path) in toolchain_binutils(linker) {
+            let path = path.to_string_lossy();
+            build_env.set_default(var, path.as_ref());
+            build_env.set_default(&format!("{}_{}", var, suffix), path.as_ref());
+        }
+
+        target_flags.extend(linker.args.iter().map(|arg| format!("-Clink-arg={}", arg)));
+    }
+
+    // Target-specific C flags, so that bundled C code is compiled against
+    // the board's headers and processor rather than the host's. Linker
+    // arguments are needed when compiling C code as well as linking.
+    let mut cflags = target.cflags.clone();
+    if let Some(ref linker) = linker {
+        cflags.extend(linker.args.iter().cloned());
+    }
+    if let Some(ref sysroot) = sysroot {
+        if !options.no_sysroot_flags && !is_host_triple(&target.triple) {
+            cflags.insert(0, format!("--sysroot={}", sysroot));
+        }
+    }
+    if !cflags.is_empty() {
+        let suffix = target.triple.replace('-', "_");
+        for var in &["CFLAGS", "CXXFLAGS"] {
+            let var = format!("{}_{}", var, suffix);
+            let flags = append_flags(&var, &cflags);
+            build_env.set(&var, flags);
+        }
+    }
+
+    if !is_host_triple(&target.triple) {
+        let bindgen = options.bindgen_args
+            || kubos_target_flag(&options.cache, &target.name, "bindgen-args").unwrap_or_else(
+                |e| {
+                    warning!("{}", e);
+                    false
+                },
+            );
+        if bindgen {
+            bindgen_env(&mut build_env, target, sysroot.as_deref());
+        }
+    }
+
+    openssl_env(&mut build_env, target, sysroot.as_deref(), options);
+
+    // Make sure commands which execute the built binaries use the configured runner
+    if !is_host_triple(&target.triple) {
+        match config_runner(&options.cache, &target.triple) {
+            Ok(Some(runner)) => {
+                build_env.set(&target_env_var(&target.triple, "runner"), runner);
+            }
+            Ok(None) if QEMU_COMMANDS.contains(&params[0].as_str()) && !options.no_qemu => {
+                match qemu_runner(&target.triple, sysroot.as_deref()) {
+                    Ok(runner) => {
+                        log!(Debug, "using runner {}", runner);
+                        build_env.set(&target_env_var(&target.triple, "runner"), runner);
+                    }
+                    Err(e) => warning!("{}", e),
+                }
+            }
+            Ok(None) if RUNNING_COMMANDS.contains(&params[0].as_str()) => warning!(
+                "no runner configured for target {}, so `cargo {}` will \
+                 not be able to execute the built binaries on this machine.\n\
+                 Set `[target.{}].runner` in your cargo config, e.g. to a qemu user-mode \
+                 emulator",
+                target.triple,
+                params[0],
+                target.triple
+            ),
+            Ok(None) => {}
+            Err(e) => warning!("{}", e),
+        }
+    }
+
+    // Add any target-specific flags to those cargo would otherwise use.
+    // musl targets are always built as fully static binaries.
+    if is_musl(&target.triple) {
+        target_flags.extend(MUSL_RUSTFLAGS.iter().map(|flag| String::from(*flag)));
+    }
+    if !target_flags.is_empty() {
+        let mut rustflags = resolve_rustflags(&options.cache, &target.triple).unwrap_or_else(|e| {
+            warning!("{}", e);
+            vec![]
+        });
+        rustflags.extend(target_flags);
+
+        // The encoded form keeps flags containing spaces intact
+        build_env.set("CARGO_ENCODED_RUSTFLAGS", rustflags.join("\x1f"));
+    }
+
+    for module in &options.yotta {
+        for (key, value) in module.env(&target.name) {
+            build_env.set(&key, value);
+        }
+    }
+    if let Some(ref module) = options.yotta_module {
+        for (key, value) in module.search_env(&target.name) {
+            build_env.set(&key, value);
+        }
+    }
+
+    for (key, value) in build_env.vars() {
+        log!(Debug, "env {}={}", key, value);
+    }
+    log!(
+        Info,
+        "cargo {} for {} ({}) with linker {} and sysroot {}",
+        params[0],
+        target.name,
+        target.triple,
+        match linker {
+            Some(ref linker) => linker.to_string(),
+            None => String::from("(cargo's default)"),
+        },
+        sysroot.as_deref().unwrap_or("(none)")
+    );
+
+    (params, build_env)
+}
+
+/// Split a string into words like a POSIX shell, honoring single
+/// and double quotes and backslash escapes
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' || c == '$' || c == '`' => {
+                                word.push(c)
+                            }
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(String::from("unterminated double quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("unterminated double quote")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(String::from("trailing backslash")),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(word) = word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+/// A qemu user-mode runner for the target, using the sysroot's dynamic
+/// loader and libraries if a sysroot is known
+fn qemu_runner(triple: &str, sysroot: Option<&str>) -> Result<String, String> {
+    let emulator = QEMU_EMULATORS
+        .iter()
+        .find(|(arch, _)| triple.starts_with(arch))
+        .map(|(_, emulator)| *emulator)
+        .ok_or_else(|| format!("no runner configured for target {}", triple))?;
+    let path = find_in_path(emulator).ok_or_else(|| {
+        format!(
+            "{} not found on PATH, so the tests for {} can't be run on this machine.\n\
+             Install qemu's user-mode emulators (e.g. `apt install qemu-user`), \
+             set `[target.{}].runner` in your cargo config, or pass --no-qemu",
+            emulator, triple, triple
+        )
+    })?;
+
+    let mut runner = path.to_string_lossy().into_owned();
+    if let Some(sysroot) = sysroot {
+        runner.push_str(&format!(" -L {}", sysroot));
+    }
+    Ok(runner)
+}
+
+/// The arguments given to cargo for a target, other than the target itself
+fn cargo_params(command: &[String], extra_params: &[String]) -> Vec<String> {
+    command.iter().chain(extra_params).cloned().collect()
+}
+
+/// Quote a value for a POSIX shell, leaving simple words as-is
+fn shell_quote(value: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=,+@%".contains(c);
+    if !value.is_empty() && value.chars().all(plain) {
+        return String::from(value);
+    }
+    if value.chars().any(char::is_control) {
+        // Control characters, like the separator in CARGO_ENCODED_RUSTFLAGS,
+        // need bash's $'...' quoting to be written out legibly
+        let mut out = String::from("$'");
+        for c in value.chars() {
+            match c {
+                '\'' => out.push_str("\\'"),
+                '\\' => out.push_str("\\\\"),
+                c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('\'');
+        return out;
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Print the environment and cargo invocation for a
+/// target instead of running it, for --dry-run
+fn print_build(
+    target: &Target,
+    command: Vec<String>,
+    extra_params: Vec<String>,
+    options: &BuildOptions,
+) -> Result<(), Error> {
+    let (params, build_env) = prepare_build(target, command, extra_params, options);
+    println!("# {} ({})", target.name, target.triple);
+    for (key, value) in build_env.vars() {
+        println!("{}={}", key, shell_quote(value));
+    }
+    let (program, params) = match options.container {
+        Some(ref container) => (
+            container.engine(),
+            container
+                .args(target, &params, &build_env, &options.cache)
+                .map_err(Error::Environment)?,
+        ),
+        None => (String::from("cargo"), params),
+    };
+    let mut params: Vec<String> = params.iter().map(|p| shell_quote(p)).collect();
+    params.extend(
+        options
+            .raw_args
+            .iter()
+            .map(|arg| shell_quote(&arg.to_string_lossy())),
+    );
+    println!("{} {}", program, params.join(" "));
+    Ok(())
+}
+
+/// Collect the environment variables the cargo config sets for the target,
+/// with `${LINKER}`, `${TRIPLE}` and `${SYSROOT}` substituted
+fn target_env_entries(
+    target: &Target,
+    linker: Option<&Linker>,
+    sysroot: Option<&str>,
+    cache: &ResolutionCache,
+) -> Result<Vec<EnvEntry>, ConfigError> {
+    let mut entries = kubos_target_env(cache, &target.name)?;
+    for entry in config_env(cache)? {
+        if !entries.iter().any(|e| e.key == entry.key) {
+            entries.push(entry);
+        }
+    }
+
+    let vars = [
+        ("LINKER", linker.map(|l| l.path.clone())),
+        ("TRIPLE", Some(target.triple.clone())),
+        ("SYSROOT", sysroot.map(String::from)),
+    ];
+
+    for entry in entries.iter_mut() {
+        for (name, value) in &vars {
+            let pattern = format!("${{{}}}", name);
+            if !entry.value.contains(&pattern) {
+                continue;
+            }
+            match value {
+                Some(value) => entry.value = entry.value.replace(&pattern, value),
+                None => warning!(
+                    "{} uses {} but it could not be determined for target {}",
+                    entry.key,
+                    pattern,
+                    target.name
+                ),
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Allow pkg-config to be used for a cross target, unless that's been
+/// suppressed or PKG_CONFIG_ALLOW_CROSS has already been set
+fn pkg_config_cross(build_env: &mut BuildEnv, options: &BuildOptions) {
+    let decision = if options.no_pkg_config_cross {
+        String::from("not set, suppressed by --no-pkg-config-cross")
+    } else if let Ok(value) = env::var("PKG_CONFIG_ALLOW_CROSS") {
+        format!("inherited as {}", value)
+    } else if let Some(value) = build_env.get("PKG_CONFIG_ALLOW_CROSS") {
+        format!("set to {} by the cargo config", value)
+    } else {
+        build_env.set("PKG_CONFIG_ALLOW_CROSS", "1");
+        String::from("set to 1")
+    };
+
+    log!(Debug, "PKG_CONFIG_ALLOW_CROSS {}", decision);
+}
+
+/// Point openssl-sys at the target's OpenSSL, either the install prefix
+/// given for the target or the one in its sysroot
+fn openssl_env(
+    build_env: &mut BuildEnv,
+    target: &Target,
+    sysroot: Option<&str>,
+    options: &BuildOptions,
+) {
+    let configured = match options.openssl_dir {
+        Some(ref dir) => Some(dir.clone()),
+        None => {
+            kubos_target_path(&options.cache, &target.name, "openssl-dir").unwrap_or_else(|e| {
+                warning!("{}", e);
+                None
+            })
+        }
+    };
+    let prefix = match configured {
+        Some(dir) => PathBuf::from(dir),
+        None => match sysroot {
+            Some(sysroot) if Path::new(sysroot).join("usr/include/openssl").is_dir() => {
+                Path::new(sysroot).join("usr")
+            }
+            _ => return,
+        },
+    };
+
+    let var_prefix = target.triple.to_uppercase().replace('-', "_");
+    let vars = [
+        (format!("{}_OPENSSL_DIR", var_prefix), prefix.clone()),
+        (
+            format!("{}_OPENSSL_LIB_DIR", var_prefix),
+            prefix.join("lib"),
+        ),
+        (
+            format!("{}_OPENSSL_INCLUDE_DIR", var_prefix),
+            prefix.join("include"),
+        ),
+    ];
+
+    let missing: Vec<String> = vars
+        .iter()
+        .filter(|(_, path)| !path.is_dir())
+        .map(|(var, path)| format!("{} ({})", var, path.display()))
+        .collect();
+    if !missing.is_empty() {
+        warning!(
+            "OpenSSL for target {} is incomplete, these directories don't exist: {}",
+            target.name,
+            missing.join(", ")
+        );
+    }
+
+    for (var, path) in &vars {
+        build_env.set_default(var, path.to_string_lossy().as_ref());
+    }
+}
+
+/// Point pkg-config at the target sysroot rather than the host's libraries
+fn pkg_config_env(build_env: &mut BuildEnv, triple: &str, sysroot: &str) {
+    let root = Path::new(sysroot);
+    let libdirs: Vec<PathBuf> = ["usr/lib/pkgconfig", "usr/share/pkgconfig", "lib/pkgconfig"]
+        .iter()
+        .map(|dir| root.join(dir))
+        .collect();
+    let libdir = env::join_paths(&libdirs)
+        .map(|dirs| dirs.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let suffix = triple.replace('-', "_");
+
+    build_env.set_default("PKG_CONFIG_SYSROOT_DIR", sysroot);
+    build_env.set_default("PKG_CONFIG_LIBDIR", libdir.as_str());
+    build_env.set_default(&format!("PKG_CONFIG_SYSROOT_DIR_{}", suffix), sysroot);
+    build_env.set_default(&format!("PKG_CONFIG_LIBDIR_{}", suffix), libdir.as_str());
+    build_env.set_default(&format!("PKG_CONFIG_PATH_{}", suffix), libdir.as_str());
+}
+
+/// Tell bindgen's clang which target and headers to generate bindings for
+fn bindgen_env(build_env: &mut BuildEnv, target: &Target, sysroot: Option<&str>) {
+    let mut args = vec![format!("--target={}", target.triple)];
+    if let Some(sysroot) = sysroot {
+        args.push(format!("--sysroot={}", sysroot));
+    }
+    log!(
+        Debug,
+        "bindgen clang args for {} are {} ({})",
+        target.name,
+        args.join(" "),
+        match sysroot {
+            Some(_) => "from the target triple and sysroot",
+            None => "from the target triple, no sysroot was found",
+        }
+    );
+
+    let suffixed = format!(
+        "BINDGEN_EXTRA_CLANG_ARGS_{}",
+        target.triple.replace('-', "_")
+    );
+    for var in &[suffixed.as_str(), "BINDGEN_EXTRA_CLANG_ARGS"] {
+        build_env.set(var, append_flags(var, &args));
+    }
+}
+
+/// The yotta modules to build before cargo runs, unless --skip-yotta,
+/// --dry-run or the command doesn't build anything
+fn find_yotta_modules(
+    cli: &Cli,
+    command: &[String],
+    extra_params: &[String],
+) -> Vec<yotta::Module> {
+    let matches = &cli.matches;
+    // Setting up and syncing the yotta target build nothing either
+    let building = !matches!(
+        cli.subcommand.as_deref(),
+        Some("setup" | "sync-yotta-target")
+    ) && command
+        .first()
+        .is_some_and(|cmd| CARGO_COMMANDS.contains(&cmd.as_str()) && cmd != "clean");
+    if matches.opt_present("skip-yotta") || matches.opt_present("dry-run") || !building {
+        return vec![];
+    }
+    yotta::find_modules(&cargo_params(command, extra_params)).unwrap_or_else(|e| {
+        warning!("not building yotta modules: {}", e);
+        vec![]
+    })
+}
+
+/// The yotta module given with --yotta-module, or else the one
+/// in the crate's own directory, if it has one
+fn crate_yotta_module(
+    matches: &Matches,
+    params: &[String],
+) -> Result<Option<yotta::Description>, String> {
+    if let Some(path) = matches.opt_str("yotta-module") {
+        return yotta::Description::load(Path::new(&path)).map(Some);
+    }
+    let dir = match find_manifest(params).and_then(|path| path.parent().map(Path::to_path_buf)) {
+        Some(dir) => dir,
+        None => return Ok(None),
+    };
+    if !dir.join("module.json").is_file() {
+        return Ok(None);
+    }
+    yotta::Description::load(&dir).map(Some)
+}
+
+/// Say how cargo-kubos failed, unless that's been said already
+pub fn report(error: &Error) {
+    if !error.reported() {
+        error!("{}", error);
+        events::Event::new("error")
+            .string("message", &error.to_string())
+            .number("exit_code", error.exit_code())
+            .emit();
+    }
+}
+
+/// The cargo command each target is built with, less the target itself
+struct CargoArgs {
+    /// The command and the arguments cargo-kubos adds to it, which go
+    /// before --target
+    command: Vec<String>,
+    /// The project config's and the user's arguments, which go after it
+    extra_params: Vec<String>,
+    /// cargo's arguments as the user and the project config gave them
+    given: Vec<String>,
+    /// The manifests of the packages selected, if any were
+    packages: Option<Vec<PathBuf>>,
+    /// The --message-format given to cargo-kubos
+    message_format: Option<String>,
+}
+
+impl CargoArgs {
+    /// Whether cargo's been asked for JSON messages, either way
+    fn json_messages(&self) -> bool {
+        self.message_format
+            .clone()
+            .or_else(|| arg_value(&self.given, "--message-format"))
+            .map(|format| format.starts_with("json"))
+            .unwrap_or(false)
+    }
+}
+
+/// Work out the cargo command from the command line, the project config
+/// and the user's defaults
+fn cargo_args(
+    cli: &Cli,
+    project: Option<&ProjectConfig>,
+    manifest_flag: Option<PathBuf>,
+) -> Result<CargoArgs, Error> {
+    let matches = &cli.matches;
+
+    // Extra arguments from the project config go before the user's own
+    let mut extra_params = project.map(|p| p.extra_args.clone()).unwrap_or_default();
+    if let (Some(path), None) = (manifest_flag, manifest_path_arg(&cli.user_params)) {
+        extra_params.push(String::from("--manifest-path"));
+        extra_params.push(path.to_string_lossy().into_owned());
+    }
+    extra_params.extend(cli.user_params.iter().cloned());
+
+    let default_command = match cli.build_command {
+        Some(ref deploy) => {
+            if matches.opt_present("c") {
+                return Err(Error::Usage(format!(
+                    "{} always builds, so -c can't be used with it",
+                    deploy
+                )));
+            }
+            None
+        }
+        None => project.and_then(|p| p.default_command.clone()),
+    };
+    let command = match (matches.opt_str("c"), cli.positional_command.clone()) {
+        (Some(flag), Some(positional)) => {
+            if flag.split_whitespace().next() != Some(positional.as_str()) {
+                return Err(Error::Usage(format!(
+                    "conflicting commands '{}' and '{}', give the command \
+                     either with -c or as the first argument",
+                    flag, positional
+                )));
+            }
+            flag
+        }
+        (Some(command), None) | (None, Some(command)) => command,
+        (None, None) => default_command.unwrap_or_else(|| String::from(DEFAULT_COMMAND)),
+    };
+    // The command may include its own arguments, e.g. "test --lib",
+    // which go before --target while arguments after -- go last
+    let mut command = match split_words(&command) {
+        Ok(ref words) if words.is_empty() => {
+            error!("Option 'command' is empty\n");
+            print_usage(cli::options(None), None, true);
+            return Err(Error::Exit(error::USAGE_CODE));
+        }
+        Ok(words) => words,
+        Err(e) => {
+            return Err(Error::Usage(format!(
+                "invalid command '{}': {}",
+                command, e
+            )))
+        }
+    };
+    // The user's default flags for the command go before everything
+    // else, leaving out those already given
+    if let Some(defaults) = defaults::get() {
+        let name = cli.build_command.as_deref().unwrap_or(command[0].as_str());
+        let given = cargo_params(&command, &extra_params);
+        // A default --release is for when no other profile is chosen
+        let profile_chosen = matches.opt_present("profile")
+            || arg_value(&given, "--profile").is_some()
+            || has_flag(&given, "--release")
+            || has_flag(&given, "-r");
+        let flags: Vec<String> = defaults
+            .flags_for(name)
+            .iter()
+            .filter(|flag| !has_flag(&given, flag))
+            .filter(|flag| !(profile_chosen && (*flag == "--release" || *flag == "-r")))
+            .cloned()
+            .collect();
+        if !flags.is_empty() {
+            log!(
+                Debug,
+                "default flags for {} from {}: {}",
+                name,
+                defaults.path.display(),
+                flags.join(" ")
+            );
+        }
+        extra_params.splice(0..0, flags);
+    }
+
+    // Profile shortcuts go before the user's own arguments, unless
+    // they've already been given there
+    let given = cargo_params(&command, &extra_params);
+    if matches.opt_present("release") && !has_flag(&given, "--release") && !has_flag(&given, "-r") {
+        command.push(String::from("--release"));
+    }
+    // Commands cargo-kubos doesn't know may not take --quiet
+    if cli.quiet > 0 && !cli.forwarded && !has_flag(&given, "--quiet") && !has_flag(&given, "-q") {
+        command.push(String::from("--quiet"));
+    }
+    if let Some(profile) = matches.opt_str("profile") {
+        match arg_value(&given, "--profile") {
+            Some(ref existing) if *existing != profile => {
+                return Err(Error::Usage(format!(
+                    "conflicting profiles '{}' and '{}' given",
+                    profile, existing
+                )));
+            }
+            Some(_) => {}
+            None => {
+                command.push(String::from("--profile"));
+                command.push(profile);
+            }
+        }
+    }
+
+    // Feature selection goes the same way, with every --features merged
+    if matches.opt_present("all-features") && matches.opt_present("no-default-features") {
+        return Err(Error::Usage(String::from(
+            "the argument '--all-features' cannot be used with '--no-default-features'",
+        )));
+    }
+    let mut features: Vec<String> = vec![];
+    for list in matches.opt_strs("features") {
+        for feature in list.split(|c: char| c == ',' || c.is_whitespace()) {
+            if !feature.is_empty() && !features.iter().any(|f| f == feature) {
+                features.push(String::from(feature));
+            }
+        }
+    }
+    if !features.is_empty() {
+        command.push(String::from("--features"));
+        command.push(features.join(","));
+    }
+    for flag in &["all-features", "no-default-features"] {
+        let flag_arg = format!("--{}", flag);
+        if matches.opt_present(flag) && !has_flag(&given, &flag_arg) {
+            command.push(flag_arg);
+        }
+    }
+
+    let packages = select_packages(cli, &given, &mut command, &mut extra_params)?;
+
+    let message_format = matches.opt_str("message-format");
+    if let Some(ref format) = message_format {
+        if arg_value(&given, "--message-format").is_none() {
+            command.push(format!("--message-format={}", format));
+        }
+    }
+
+    Ok(CargoArgs {
+        command,
+        extra_params,
+        given,
+        packages,
+        message_format,
+    })
+}
+
+/// Select the packages to build, which may be selected either with
+/// cargo-kubos' options or in cargo's arguments, but not differently both
+/// ways, returning their manifests if any are selected
+fn select_packages(
+    cli: &Cli,
+    given: &[String],
+    command: &mut Vec<String>,
+    extra_params: &mut Vec<String>,
+) -> Result<Option<Vec<PathBuf>>, Error> {
+    let flag_selection = workspace::Selection {
+        packages: cli.matches.opt_strs("package"),
+        workspace: cli.matches.opt_present("workspace"),
+        exclude: cli.matches.opt_strs("exclude"),
+    };
+    let given_selection = workspace::Selection::from_args(given);
+    if !flag_selection.is_empty() && !given_selection.is_empty() {
+        if flag_selection != given_selection {
+            return Err(Error::Usage(String::from(
+                "packages are selected both with cargo-kubos' options and in \
+                 cargo's arguments, select them one way",
+            )));
+        }
+    } else if !flag_selection.is_empty() {
+        for package in &flag_selection.packages {
+            command.push(String::from("--package"));
+            command.push(package.clone());
+        }
+        if flag_selection.workspace {
+            command.push(String::from("--workspace"));
+        }
+        for package in &flag_selection.exclude {
+            command.push(String::from("--exclude"));
+            command.push(package.clone());
+        }
+    }
+    let selection = if flag_selection.is_empty() {
+        given_selection
+    } else {
+        flag_selection
+    };
+    if selection.is_empty() {
+        return Ok(None);
+    }
+    let members = workspace::members(given).map_err(Error::Environment)?;
+    let selected = selection.resolve(&members).map_err(Error::Usage)?;
+    let names: Vec<&str> = selected.iter().map(|m| m.name.as_str()).collect();
+    log!(Debug, "building packages {}", names.join(", "));
+    // Our own manifest lookups then find the package's settings
+    if let [member] = selected.as_slice() {
+        if !cli.forwarded && !selection.workspace && manifest_path_arg(given).is_none() {
+            extra_params.insert(0, String::from("--manifest-path"));
+            extra_params.insert(1, member.manifest_path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(Some(
+        selected.iter().map(|m| m.manifest_path.clone()).collect(),
+    ))
+}
+
+/// Work out how each target is built from the command line and the
+/// cargo configs
+fn build_options(
+    cli: &Cli,
+    args: &CargoArgs,
+    discover: bool,
+    cache: &Arc<ResolutionCache>,
+) -> Result<BuildOptions, Error> {
+    let matches = &cli.matches;
+    let timeout = match matches.opt_str("timeout") {
+        Some(secs) => match secs.parse::<f64>() {
+            Ok(secs) if secs.is_finite() && secs > 0.0 => Some(Duration::from_secs_f64(secs)),
+            _ => {
+                return Err(Error::Usage(format!(
+                    "invalid --timeout '{}', expected a number of seconds",
+                    secs
+                )));
+            }
+        },
+        None => None,
+    };
+
+    Ok(BuildOptions {
+        verbose: matches.opt_count("v"),
+        sysroot: matches.opt_str("sysroot"),
+        no_sysroot_flags: matches.opt_present("no-sysroot-flags"),
+        bindgen_args: matches.opt_present("bindgen-args"),
+        legacy_cc: matches.opt_present("legacy-cc"),
+        no_pkg_config_cross: matches.opt_present("no-pkg-config-cross"),
+        openssl_dir: matches.opt_str("openssl-dir"),
+        discover,
+        no_qemu: matches.opt_present("no-qemu"),
+        strip: matches.opt_present("strip")
+            || kubos_setting(cache, "strip").unwrap_or_else(|e| {
+                warning!("{}", e);
+                false
+            }),
+        timeout,
+        no_hooks: matches.opt_present("no-hooks"),
+        verify_arch: !matches.opt_present("no-verify-arch"),
+        yotta: find_yotta_modules(cli, &args.command, &args.extra_params),
+        yotta_module: crate_yotta_module(matches, &args.given).map_err(Error::Environment)?,
+        forwarded: cli.forwarded,
+        container: if matches.opt_present("container") {
+            Some(container::Container::new(&args.given).map_err(Error::Environment)?)
+        } else {
+            None
+        },
+        packages: args.packages.clone(),
+        raw_args: cli.raw_args.clone(),
+        cache: Arc::clone(cache),
+    })
+}
+
+/// Run cargo-kubos again in the Kubos SDK's box, when it's wanted
+/// and some target has no toolchain here, returning how that went
+fn run_in_sdk_vm(
+    cli: &Cli,
+    selected: &[Target],
+    options: &BuildOptions,
+) -> Option<Result<(), Error>> {
+    // Outside the SDK without a toolchain, the build may go into the SDK's box
+    let sdk_vm = cli.matches.opt_present("sdk-vm")
+        || kubos_setting(&options.cache, "sdk-vm").unwrap_or_else(|e| {
+            warning!("{}", e);
+            false
+        });
+    let missing_toolchain = selected.iter().any(|target| {
+        !is_host_triple(&target.triple)
+            && target_linker(target, options.discover, &options.cache).is_err()
+    });
+    if !sdk_vm || options.container.is_some() || !missing_toolchain || sdk::in_sdk() {
+        return None;
+    }
+    if !options.raw_args.is_empty() {
+        return Some(Err(Error::Usage(String::from(
+            "arguments are given to cargo-kubos in the SDK box by a shell, \
+             so must be valid UTF-8",
+        ))));
+    }
+    // It's run again as given, less the `kubos` cargo adds and --sdk-vm
+    let mut forwarded: Vec<String> = cli
+        .args
+        .iter()
+        .enumerate()
+        .filter(|(i, arg)| !(*i == 0 && *arg == "kubos") && *arg != "--sdk-vm")
+        .map(|(_, arg)| arg.clone())
+        .collect();
+    if let Some(ref passthrough) = cli.passthrough {
+        forwarded.push(String::from("--"));
+        forwarded.extend(passthrough.iter().cloned());
+    }
+    let status = sdk::Vagrant::load(&options.cache).and_then(|vagrant| vagrant.run(&forwarded));
+    Some(match status {
+        Ok(status) => error::child("vagrant", status),
+        Err(e) => Err(Error::Environment(e)),
+    })
+}
+
+/// Print what would be run for each target, with --dry-run
+fn dry_run(selected: &[Target], args: &CargoArgs, options: &BuildOptions) -> Result<(), Error> {
+    for (i, target) in selected.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_build(
+            target,
+            args.command.clone(),
+            args.extra_params.clone(),
+            options,
+        )?;
+    }
+    // Hooks don't run, but show what would follow the build
+    if args.command.first().map(String::as_str) == Some("build") && !options.no_hooks {
+        match hooks::load(&cargo_params(&args.command, &args.extra_params)) {
+            Ok(hooks) => {
+                for hook in hooks.post_build {
+                    println!("# then post-build hook: {}", hook);
+                }
+            }
+            Err(e) => warning!("{}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Check the selected targets can be built for before building any,
+/// unless they're being built in a container
+fn preflight(
+    cli: &Cli,
+    selected: &[Target],
+    given: &[String],
+    options: &BuildOptions,
+) -> Result<(), Error> {
+    let matches = &cli.matches;
+    let cache = &options.cache;
+    if options.container.is_some() {
+        // The image brings its own Rust targets and toolchains, but
+        // every target needs one
+        if let Some(e) = selected
+            .iter()
+            .find_map(|t| container::image(t, cache).err())
+        {
+            return Err(Error::Environment(e));
+        }
+        return Ok(());
+    }
+    check_rust_targets(selected, matches.opt_present("install-target"))
+        .map_err(Error::Environment)?;
+    check_linkers(
+        selected,
+        matches.opt_present("strict-linker"),
+        options.discover,
+        cache,
+    )?;
+    if !matches.opt_present("skip-preflight") {
+        preflight_linkers(selected, options.discover, &process::System, cache)?;
+    }
+    let strict = matches.opt_present("strict-versions");
+    preflight::check_versions(selected, given, strict, options.discover, cache)
+        .map_err(Error::Environment)
+}
+
+/// Run cargo-kubos with the command line it was given, the program's name
+/// first, returning how it failed for [`report`]. Output goes to stdout and
+/// stderr as it does for the command.
+pub fn run<I: IntoIterator<Item = OsString>>(args_os: I) -> Result<(), Error> {
+    let cli = Cli::parse(args_os)?;
+    let matches = &cli.matches;
+    let subcommand = cli.subcommand.as_deref();
+    let user_params = &cli.user_params;
+    color::init(cli.color);
+    color::set_quiet(cli.quiet);
+    log::init(cli.log_level, cli.verbose);
+    if let Some(format) = cli.output_format {
+        events::set_format(format);
+    }
+    if !cli.raw_args.is_empty() {
+        let lossy: Vec<String> = cli
+            .raw_args
+            .iter()
+            .map(|arg| shell_quote(&arg.to_string_lossy()))
+            .collect();
+        log!(Debug, "passing {} to cargo as given", lossy.join(" "));
+    }
+
+    // Before anything which reads config or probes the
+    // environment, so it works when those are broken
+    if matches.opt_present("version") {
+        print_version(matches.opt_present("verbose"));
+        return Ok(());
+    }
+
+    if matches.opt_present("h") {
+        print_usage(cli::options(cli.scope), cli.scope, false);
+        return Ok(());
+    }
+    if subcommand == Some("help") {
+        return help::command(&user_params[1..]);
+    }
+
+    let cache = Arc::new(ResolutionCache::with_given_configs(
+        cli.cargo_configs.clone(),
+    ));
+    // A broken config given explicitly is worth stopping for
+    for path in &cli.cargo_configs {
+        if !path.is_file() {
+            return Err(Error::Usage(format!(
+                "cargo config '{}' does not exist",
+                path.display()
+            )));
+        }
+        cache.config(path)?;
+    }
+
+    // What prints only text would get among the events
+    if events::json() {
+        let text = match subcommand {
+            Some(
+                command @ ("completions" | "generate-man" | "init-target" | "setup" | "debug"),
+            ) => Some(command),
+            _ if matches.opt_present("dry-run") => Some("--dry-run"),
+            _ => None,
+        };
+        if let Some(text) = text {
+            return Err(Error::Usage(format!(
+                "{} doesn't report in JSON, so can't be used with --output-format json",
+                text
+            )));
+        }
+    }
+
+    // Before anything which could fail or print, as the
+    // output is sourced by the shell
+    if subcommand == Some("completions") {
+        return error::status(completions::command(user_params));
+    }
+    // Nor does the man page depend on anything but this build
+    if subcommand == Some("generate-man") {
+        return man::command(matches, &user_params[1..]);
+    }
+
+    let toolchain = match (cli.plus_toolchain.clone(), matches.opt_str("toolchain")) {
+        (Some(ref plus), Some(ref flag)) if plus != flag => {
+            return Err(Error::Usage(format!(
+                "conflicting toolchains '+{}' and '--toolchain {}' given",
+                plus, flag
+            )));
+        }
+        (plus, flag) => plus.or(flag),
+    };
+    select_toolchain(toolchain).map_err(Error::Environment)?;
+
+    // Project-local files are found from the manifest cargo is pointed
+    // at, which may be outside the current directory
+    let manifest_flag = matches.opt_str("manifest-path").map(PathBuf::from);
+    let manifest_path = match (manifest_flag.clone(), manifest_path_arg(user_params)) {
+        (Some(flag), Some(arg)) if flag != arg => {
+            return Err(Error::Usage(format!(
+                "conflicting manifest paths '{}' and '{}' given",
+                flag.display(),
+                arg.display()
+            )));
+        }
+        (flag, arg) => flag.or(arg),
+    };
+    if let Some(ref path) = manifest_path {
+        if !path.is_file() {
+            return Err(Error::Usage(format!(
+                "manifest path '{}' does not exist",
+                path.display()
+            )));
+        }
+        project::set_project_dir(path.parent().unwrap_or_else(|| Path::new("")));
+    }
+
+    let targets = TargetRegistry::load().map_err(Error::Environment)?;
+
+    // Doctor reports a broken defaults file rather than failing on it
+    let user_defaults = defaults::load();
+    if subcommand != Some("doctor") {
+        user_defaults
+            .as_ref()
+            .map_err(|e| Error::Environment(e.clone()))?;
+    }
+
+    let discover = !matches.opt_present("no-toolchain-discovery");
+    if matches.opt_present("list-targets") {
+        list::run(&targets, matches.opt_present("json"), discover, &cache);
+        return Ok(());
+    }
+
+    match subcommand {
+        Some("doctor") => {
+            return error::status(doctor::run(
+                &targets,
+                &matches.opt_strs("t"),
+                discover,
+                &user_defaults,
+                &cache,
+            ))
+        }
+        Some("init-target") => return error::status(init::command(matches, &targets)),
+        Some("verify-manifest") => return error::status(ota::command(user_params)),
+        Some(name @ "setup") | Some(name @ "sync-yotta-target") if !user_params[1..].is_empty() => {
+            return Err(Error::Usage(format!("{} doesn't take any arguments", name)));
+        }
+        _ => {}
+    }
+
+    let project = load_project_config().map_err(Error::Environment)?;
+    let mut args = cargo_args(&cli, project.as_ref(), manifest_flag)?;
+
+    let deploy_profile = matches.opt_str("deploy-profile");
+    let deploy_config = match cli.build_command {
+        Some(_) => Some(
+            DeployConfig::load(&args.extra_params, deploy_profile.as_deref())
+                .map_err(Error::Environment)?,
+        ),
+        None if deploy_profile.is_some() => {
+            return Err(Error::Usage(String::from(
+                "--deploy-profile is only used by flash, run-remote, debug, package and size",
+            )));
+        }
+        None => None,
+    };
+
+    let options = build_options(&cli, &args, discover, &cache)?;
+    if subcommand == Some("setup") {
+        return error::status(setup::command(matches, &targets, &options));
+    }
+    if subcommand == Some("sync-yotta-target") {
+        return error::status(yotta::sync_command(matches, &targets, &options));
+    }
+    let interactive = !matches.opt_present("non-interactive")
+        && !matches.opt_present("dry-run")
+        && prompt::interactive();
+    let (selected, source) = select_targets(
+        matches,
+        &targets,
+        project.as_ref(),
+        deploy_config.as_ref(),
+        &args.extra_params,
+        interactive,
+    )?;
+    // Resolving the targets reads every cargo config, and cargo
+    // would refuse to build with one which can't be read anyway
+    cache.discover_configs()?;
+    for target in &selected {
+        log!(
+            Debug,
+            "using target {} ({}) from {}",
+            target.name,
+            target.triple,
+            source
+        );
+        if events::json() {
+            let linker = target_linker(target, discover, &cache)
+                .ok()
+                .map(|linker| linker.to_string());
+            events::Event::new("resolve")
+                .string("target", &target.name)
+                .string("triple", &target.triple)
+                .string("source", &source.to_string())
+                .opt_string("linker", linker.as_deref())
+                .emit();
+        }
+    }
+    if log::enabled(log::Level::Debug) {
+        for path in cache.config_paths().unwrap_or_default() {
+            log!(Debug, "consulting cargo config {}", path.display());
+        }
+    }
+
+    if let Some(result) = run_in_sdk_vm(&cli, &selected, &options) {
+        return result;
+    }
+
+    check_sysroots(&selected, &options)?;
+
+    if matches.opt_present("dry-run") {
+        return dry_run(&selected, &args, &options);
+    }
+
+    // Unavailable targets are skipped rather than checked for "all"
+    let skip_unavailable = matches.opt_strs("t").iter().any(|t| t == ALL_TARGETS);
+    if !skip_unavailable || options.container.is_some() {
+        preflight(&cli, &selected, &args.given, &options)?;
+    }
+
+    yotta::auto_sync(&selected, matches.opt_present("force-sync"), &options);
+
+    let print_artifact = matches.opt_present("print-artifact");
+    let out_dir = matches.opt_str("out-dir").map(PathBuf::from);
+    let suffix = matches.opt_str("artifact-suffix").unwrap_or_default();
+    if out_dir.is_none() && !suffix.is_empty() {
+        return Err(Error::Usage(String::from(
+            "--artifact-suffix is only used with --out-dir",
+        )));
+    }
+    if let (Some(_), Some(ref deploy)) = (&out_dir, &cli.build_command) {
+        return Err(Error::Usage(format!(
+            "--out-dir can't be used with {}",
+            deploy
+        )));
+    }
+    // Whether cargo's JSON messages are needed to know what was built
+    let collect = print_artifact || out_dir.is_some() || events::json();
+    let reader = match cli.build_command {
+        Some(ref deploy) => Some(deploy.as_str()),
+        None if print_artifact => Some("--print-artifact"),
+        None if out_dir.is_some() => Some("--out-dir"),
+        None if events::json() => Some("--output-format json"),
+        None => None,
+    };
+    if let Some(reader) = reader {
+        if args.message_format.is_some() || arg_value(&args.given, "--message-format").is_some() {
+            return Err(Error::Usage(format!(
+                "{} reads cargo's JSON messages itself, so --message-format \
+                 can't be used with it",
+                reader
+            )));
+        }
+    }
+    if collect && cli.build_command.is_none() {
+        args.command
+            .push(String::from("--message-format=json-render-diagnostics"));
+    }
+
+    if cli.build_command.as_deref() == Some("size") {
+        return error::status(size::command(
+            matches,
+            &selected,
+            &args.command,
+            &args.extra_params,
+            &options,
+        ));
+    }
+    if let Some(ref deploy) = cli.build_command {
+        let deploy = deploy::Deploy {
+            command: deploy.clone(),
+            program_args: cli.program_args.clone(),
+            config: deploy_config.unwrap_or_default(),
+        };
+        return error::status(deploy::command(
+            &deploy,
+            matches,
+            &selected,
+            args.command,
+            args.extra_params,
+            &options,
+        ));
+    }
+
+    let json_messages = args.json_messages();
+    let settings = build::Settings {
+        collect,
+        json_messages,
+        out_dir,
+        suffix,
+        print_artifact,
+        json: matches.opt_present("json"),
+        // Tagging would corrupt cargo's JSON messages
+        prefix: !matches.opt_present("no-prefix") && !json_messages,
+        fail_fast: matches.opt_present("fail-fast"),
+        skip_unavailable,
+    };
+    if let [target] = selected.as_slice() {
+        return build::one(target, args.command, args.extra_params, &options, &settings);
+    }
+    build::all(
+        &selected,
+        &args.command,
+        &args.extra_params,
+        &options,
+        &settings,
+    )
+}
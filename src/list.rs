@@ -0,0 +1,103 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos --list-targets`, which lists the known targets and
+//! whether each can be built for here.
+
+use crate::cache::ResolutionCache;
+use crate::events;
+use crate::json::json_string;
+use crate::targets::Target;
+use crate::toolchain::target_linker;
+
+/// Print every known target, its triple and the linker it would use
+pub fn run(targets: &[Target], json: bool, discover: bool, cache: &ResolutionCache) {
+    let linkers: Vec<Option<String>> = targets
+        .iter()
+        .map(|t| {
+            target_linker(t, discover, cache)
+                .ok()
+                .map(|linker| linker.to_string())
+        })
+        .collect();
+
+    if events::json() {
+        for (target, linker) in targets.iter().zip(&linkers) {
+            events::Event::new("target")
+                .string("name", &target.name)
+                .strings("aliases", &target.aliases)
+                .string("triple", &target.triple)
+                .opt_string("linker", linker.as_deref())
+                .emit();
+        }
+        return;
+    }
+    if json {
+        let entries: Vec<String> = targets
+            .iter()
+            .zip(&linkers)
+            .map(|(target, linker)| {
+                let aliases: Vec<String> = target.aliases.iter().map(|a| json_string(a)).collect();
+                format!(
+                    "{{\"name\":{},\"aliases\":[{}],\"triple\":{},\"linker\":{}}}",
+                    json_string(&target.name),
+                    aliases.join(","),
+                    json_string(&target.triple),
+                    linker
+                        .as_ref()
+                        .map(|l| json_string(l))
+                        .unwrap_or_else(|| String::from("null"))
+                )
+            })
+            .collect();
+        println!("[{}]", entries.join(","));
+        return;
+    }
+
+    let mut rows = vec![[
+        String::from("TARGET"),
+        String::from("ALIASES"),
+        String::from("TRIPLE"),
+        String::from("LINKER"),
+    ]];
+    for (target, linker) in targets.iter().zip(&linkers) {
+        rows.push([
+            target.name.clone(),
+            target.aliases.join(","),
+            target.triple.clone(),
+            linker.clone().unwrap_or_else(|| String::from("(missing)")),
+        ]);
+    }
+
+    let mut widths = [0; 3];
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(column.len());
+        }
+    }
+    for row in &rows {
+        println!(
+            "{:w0$}  {:w1$}  {:w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+}
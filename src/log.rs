@@ -0,0 +1,100 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Leveled logging of how cargo-kubos works out what to do, for debugging
+//! it remotely: `trace` for every file and program probed, `debug` for
+//! each decision made and `info` for the plan each build follows. It goes
+//! to stderr as `cargo-kubos: ` lines, and is off unless `--log-level` or
+//! `RUST_LOG` turns it on. `--verbose` turns on the debug level.
+
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How much to log, each level including those before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// The names `--log-level` takes. cargo-kubos' errors and warnings are
+/// always printed, so `error` and `warn` log nothing more than `off`.
+pub const LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+impl Level {
+    pub fn parse(name: &str) -> Result<Level, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" | "error" | "warn" => Ok(Level::Off),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!(
+                "invalid log level '{}', expected one of: {}",
+                name,
+                LEVELS.join(", ")
+            )),
+        }
+    }
+}
+
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Off as usize);
+
+/// The level `RUST_LOG` asks of cargo-kubos, in env_logger's
+/// `default,crate=level` syntax, with its own directive winning
+fn rust_log(value: &str) -> Option<Level> {
+    let mut default = None;
+    let mut own = None;
+    for directive in value.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            Some((name, level)) if name == "cargo_kubos" || name == "cargo-kubos" => {
+                own = Level::parse(level).ok()
+            }
+            Some(_) => {}
+            None => default = Level::parse(directive).ok().or(default),
+        }
+    }
+    own.or(default)
+}
+
+/// Set the level from `--log-level`, else `RUST_LOG`, at least
+/// debug with `--verbose`
+pub fn init(flag: Option<Level>, verbose: usize) {
+    let level = flag
+        .or_else(|| env::var("RUST_LOG").ok().and_then(|value| rust_log(&value)))
+        .unwrap_or(Level::Off);
+    let level = if verbose > 0 {
+        level.max(Level::Debug)
+    } else {
+        level
+    };
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Whether messages of the level are logged
+pub fn enabled(level: Level) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed) && level != Level::Off
+}
+
+/// Log a message at a level, as in `log!(Debug, "using {}", linker)`
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::$level) {
+            eprintln!("cargo-kubos: {}", format_args!($($arg)*))
+        }
+    };
+}
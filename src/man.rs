@@ -0,0 +1,377 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos generate-man`, which renders the cargo-kubos(1) man page
+//! from the same command table and getopts definitions as the help. The
+//! page has no date in it, so a given build always renders the same page.
+
+use crate::completions::{self, Opt};
+use crate::error::Error;
+use crate::help::{
+    command_line, BUILD_SCRIPT_ENV, COMMAND_HELP, EXIT_STATUS, EXIT_STATUS_NOTE, HELP_COMMAND_NOTE,
+    HELP_DEFAULTS_NOTE, HELP_EXAMPLES, HELP_INTRO,
+};
+use crate::targets::TargetRegistry;
+use crate::{cli, defaults, CARGO_SCOPE, TARGET_ENV_VAR};
+use getopts::Matches;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File the page is written to in the `--out` directory
+const PAGE_FILE: &str = "cargo-kubos.1";
+
+/// Part of a section of the page
+pub enum Part {
+    /// Text which roff fills and justifies
+    Paragraph(String),
+    /// Terms in bold, each with its text indented below it. A newline in
+    /// the text starts a new line there.
+    Rows(Vec<(String, String)>),
+    /// A heading within the section
+    Subheading(String),
+    /// Options, with their value hints and help
+    Options(Vec<Opt>),
+}
+
+/// Everything on the page
+pub struct Page {
+    /// cargo-kubos' version, for the footer
+    pub version: &'static str,
+    /// What the NAME section says cargo-kubos is
+    pub summary: &'static str,
+    /// Each section's heading and contents, in order
+    pub sections: Vec<(&'static str, Vec<Part>)>,
+}
+
+/// Escape text for roff: backslashes, minus signs, which `man` would
+/// otherwise turn into hyphens, and control characters at line starts
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.split('\n') {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        if line.starts_with('.') || line.starts_with('\'') {
+            out.push_str("\\&");
+        }
+        out.push_str(&line.replace('\\', "\\e").replace('-', "\\-"));
+    }
+    out
+}
+
+/// An option's names and value hint, as the term for its row
+fn option_term(opt: &Opt) -> String {
+    let names: Vec<String> = opt
+        .names()
+        .iter()
+        .map(|name| format!("\\fB{}\\fR", escape(name)))
+        .collect();
+    match opt.hint {
+        Some(ref hint) => format!("{} \\fI{}\\fR", names.join(", "), escape(hint)),
+        None => names.join(", "),
+    }
+}
+
+/// Render the page as roff, for the man(7) macros
+pub fn render(page: &Page) -> String {
+    let mut out = format!(
+        ".TH CARGO\\-KUBOS 1 \"\" \"cargo\\-kubos {}\" \"cargo\\-kubos Manual\"\n\
+         .SH NAME\ncargo\\-kubos \\- {}\n",
+        escape(page.version),
+        escape(page.summary)
+    );
+    for (heading, parts) in &page.sections {
+        out.push_str(&format!(".SH {}\n", escape(heading)));
+        for part in parts {
+            match part {
+                Part::Paragraph(text) => {
+                    out.push_str(&format!(".PP\n{}\n", escape(text)));
+                }
+                Part::Rows(rows) => {
+                    for (term, text) in rows {
+                        out.push_str(&format!(
+                            ".TP\n\\fB{}\\fR\n{}\n",
+                            escape(term),
+                            escape(text).replace('\n', "\n.br\n")
+                        ));
+                    }
+                }
+                Part::Subheading(text) => {
+                    out.push_str(&format!(".SS {}\n", escape(text)));
+                }
+                Part::Options(opts) => {
+                    for opt in opts {
+                        out.push_str(&format!(
+                            ".TP\n{}\n{}\n",
+                            option_term(opt),
+                            escape(&opt.description)
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Write the rendered page into the directory, creating it if need be
+pub fn write(dir: &Path, roff: &str) -> Result<PathBuf, String> {
+    let path = dir.join(PAGE_FILE);
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&path, roff))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// The environment variables cargo-kubos itself reads, for the man page
+const READ_ENV: &[(&str, &str)] = &[
+    (
+        TARGET_ENV_VAR,
+        "the target to build for when -t isn't given and the deploy config doesn't set one",
+    ),
+    (
+        defaults::CONFIG_ENV_VAR,
+        "the user defaults file, in place of ~/.config/cargo-kubos/config.toml",
+    ),
+    (
+        "XDG_CONFIG_HOME",
+        "the directory the user defaults are found in, in place of ~/.config",
+    ),
+    (
+        "CROSS_COMPILE",
+        "a Buildroot or Yocto style toolchain prefix, whose gcc is the linker when none is configured",
+    ),
+    (
+        "KUBOS_SDK",
+        "when set, cargo-kubos is taken to be running in the Kubos SDK",
+    ),
+    (
+        "KUBOS_SDK_PATH",
+        "directories to look for the Kubos SDK's toolchains in, in place of /usr/bin",
+    ),
+    (
+        "RUSTUP_TOOLCHAIN",
+        "the rustup toolchain, passed along when neither +toolchain nor --toolchain is given",
+    ),
+    (
+        "PKG_CONFIG_ALLOW_CROSS",
+        "left as it is when set, rather than set for cross targets",
+    ),
+    (
+        "CARGO_HOME",
+        "where cargo's own config is, in place of ~/.cargo",
+    ),
+    (
+        "CARGO_TARGET_DIR",
+        "where cargo builds to, for finding what it built",
+    ),
+    (
+        "SOURCE_DATE_EPOCH",
+        "the build time recorded in packages, in place of that of the last commit",
+    ),
+    (
+        "RUST_LOG",
+        "the --log-level when none is given, as cargo_kubos=LEVEL or just LEVEL",
+    ),
+    (
+        "CARGO_TERM_COLOR",
+        "the --color when none is given, as for cargo",
+    ),
+    ("NO_COLOR", "when set, leave out color unless asked for it"),
+];
+
+/// The files cargo-kubos reads, for the man page
+const CONFIG_FILES_HELP: &[(&str, &str)] = &[
+    (
+        "~/.config/cargo-kubos/config.toml",
+        "User defaults: default-target, toolchain-paths (directories to look for cross \
+         gccs in), a [default-flags] table of cargo flags for each command, and a [deploy] \
+         table with the keys of the deploy config.",
+    ),
+    (
+        ".cargo/kubos.toml",
+        "Project config, found from the crate's directory upward to the workspace root: \
+         target, default-command and extra-args, an array of cargo arguments put before \
+         the user's own.",
+    ),
+    (
+        "kubos-targets.toml, ~/.kubos/targets.toml",
+        "Target mappings, added to the built-in ones and overriding them, as \
+         [targets.\"NAME\"] tables with a triple and optionally a linker, aliases, gcc, \
+         rustflags and cflags. The project's file takes precedence.",
+    ),
+    (
+        "Cargo.toml",
+        "The crate manifest. [package.metadata.kubos.deploy] configures deploying, with \
+         named profiles as tables under it, and [package.metadata.kubos.hooks] has the \
+         post-build commands.",
+    ),
+    (
+        ".cargo/config.toml, $CARGO_HOME/config.toml",
+        "Cargo's config, where [target.TRIPLE] sets a target's linker and runner and \
+         [kubos.target.\"NAME\"] sets them for a Kubos target. init-target and setup \
+         write the linker here. Configs given with --cargo-config come first.",
+    ),
+];
+
+/// Commands which work but aren't listed in the help or completed
+pub const HIDDEN_COMMANDS: &[&str] = &["generate-man"];
+
+/// Run `cargo kubos generate-man`, printing the page or, with `--out`,
+/// writing it into that directory
+pub fn command(matches: &Matches, args: &[String]) -> Result<(), Error> {
+    if !args.is_empty() {
+        return Err(Error::Usage(String::from(
+            "generate-man doesn't take any arguments",
+        )));
+    }
+    let commands: Vec<(String, String)> = COMMAND_HELP
+        .iter()
+        .map(|(name, usage, description)| {
+            let term = if *name == CARGO_SCOPE {
+                "<cargo command>"
+            } else {
+                name
+            };
+            (
+                String::from(term),
+                format!("{}\n{}", command_line(name, usage), description),
+            )
+        })
+        .collect();
+
+    // The options are grouped by the commands which take them
+    let scopes: Vec<(&str, Vec<completions::Opt>)> = COMMAND_HELP
+        .iter()
+        .map(|(name, _, _)| (*name, completions::options(&cli::options(Some(name)))))
+        .collect();
+    let hidden: Vec<completions::Opt> = HIDDEN_COMMANDS
+        .iter()
+        .flat_map(|name| completions::options(&cli::options(Some(name))))
+        .collect();
+    let takes = |opts: &[completions::Opt], opt: &completions::Opt| {
+        opts.iter().any(|other| other.names() == opt.names())
+    };
+    let mut groups: Vec<(String, Vec<completions::Opt>)> = vec![];
+    for opt in completions::options(&cli::options(None)) {
+        let names: Vec<&str> = scopes
+            .iter()
+            .filter(|(_, opts)| takes(opts, &opt))
+            .map(|(name, _)| {
+                if *name == CARGO_SCOPE {
+                    "cargo commands"
+                } else {
+                    name
+                }
+            })
+            .collect();
+        let heading = match names.len() {
+            0 if takes(&hidden, &opt) => continue,
+            0 => String::from("Without a command"),
+            n if n == scopes.len() => String::from("Every command"),
+            _ => format!("For {}", names.join(", ")),
+        };
+        match groups.iter_mut().find(|(other, _)| *other == heading) {
+            Some((_, opts)) => opts.push(opt),
+            None => groups.push((heading, vec![opt])),
+        }
+    }
+    let options = groups
+        .into_iter()
+        .flat_map(|(heading, opts)| vec![Part::Subheading(heading), Part::Options(opts)])
+        .collect();
+
+    let rows = |rows: &[(&str, &str)]| {
+        Part::Rows(
+            rows.iter()
+                .map(|(left, right)| (String::from(*left), String::from(*right)))
+                .collect(),
+        )
+    };
+    let paragraph = |text: &str| Part::Paragraph(String::from(text));
+    let targets = TargetRegistry::builtin()
+        .iter()
+        .map(|target| {
+            let mut name = target.name.clone();
+            if !target.aliases.is_empty() {
+                name.push_str(&format!(" ({})", target.aliases.join(", ")));
+            }
+            (name, target.triple.clone())
+        })
+        .collect();
+    let page = Page {
+        version: env!("CARGO_PKG_VERSION"),
+        summary: "run Cargo commands for Kubos targets",
+        sections: vec![
+            (
+                "SYNOPSIS",
+                COMMAND_HELP
+                    .iter()
+                    .map(|(name, usage, _)| paragraph(&command_line(name, usage)))
+                    .collect(),
+            ),
+            (
+                "DESCRIPTION",
+                vec![paragraph(HELP_INTRO), paragraph(HELP_COMMAND_NOTE)],
+            ),
+            ("COMMANDS", vec![Part::Rows(commands)]),
+            ("OPTIONS", options),
+            (
+                "ENVIRONMENT",
+                vec![
+                    paragraph("cargo-kubos reads:"),
+                    rows(READ_ENV),
+                    paragraph("Build scripts can read these from the environment:"),
+                    rows(BUILD_SCRIPT_ENV),
+                ],
+            ),
+            (
+                "FILES",
+                vec![paragraph(HELP_DEFAULTS_NOTE), rows(CONFIG_FILES_HELP)],
+            ),
+            (
+                "TARGETS",
+                vec![
+                    paragraph("The built-in targets, which the target mappings can add to:"),
+                    Part::Rows(targets),
+                ],
+            ),
+            (
+                "EXIT STATUS",
+                vec![rows(EXIT_STATUS), paragraph(EXIT_STATUS_NOTE)],
+            ),
+            (
+                "EXAMPLES",
+                HELP_EXAMPLES
+                    .iter()
+                    .map(|example| paragraph(example))
+                    .collect(),
+            ),
+        ],
+    };
+    let roff = render(&page);
+
+    let dir = match matches.opt_str("out") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            print!("{}", roff);
+            return Ok(());
+        }
+    };
+    let path = write(&dir, &roff).map_err(Error::Failed)?;
+    info!("wrote {}", path.display());
+    Ok(())
+}
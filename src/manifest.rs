@@ -0,0 +1,238 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use toml::Value;
+
+const MANIFEST_FILE: &str = "Cargo.toml";
+
+/// Find the value given to a cargo option like `--manifest-path`, either
+/// as `--option value` or `--option=value`, ignoring anything after `--`.
+/// If the option is given more than once the last value wins, like cargo.
+pub fn arg_value(params: &[String], option: &str) -> Option<String> {
+    let prefix = format!("{}=", option);
+    let mut value = None;
+    let mut iter = params.iter();
+    while let Some(param) = iter.next() {
+        if param == "--" {
+            break;
+        } else if param == option {
+            value = iter.next().cloned();
+        } else if let Some(v) = param.strip_prefix(&prefix) {
+            value = Some(String::from(v));
+        }
+    }
+    value
+}
+
+/// Whether a cargo flag is given, ignoring anything after `--`
+pub fn has_flag(params: &[String], flag: &str) -> bool {
+    params
+        .iter()
+        .take_while(|param| *param != "--")
+        .any(|param| param == flag)
+}
+
+/// Find the value given to `--manifest-path` in the extra cargo parameters
+pub fn manifest_path_arg(params: &[String]) -> Option<PathBuf> {
+    arg_value(params, "--manifest-path").map(PathBuf::from)
+}
+
+/// Locate the crate manifest, either from `--manifest-path` or by
+/// searching upward from the current directory like cargo does
+pub fn find_manifest(params: &[String]) -> Option<PathBuf> {
+    if let Some(path) = manifest_path_arg(params) {
+        return Some(path);
+    }
+
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(MANIFEST_FILE))
+        .find(|path| {
+            let exists = path.is_file();
+            log!(
+                Trace,
+                "manifest {}: {}",
+                path.display(),
+                if exists { "found" } else { "not found" }
+            );
+            exists
+        })
+}
+
+pub fn read_manifest(path: &Path) -> Result<Value, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    data.parse::<Value>()
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Look up `<section>.metadata.kubos.target` in a parsed manifest
+fn metadata_target(manifest: &Value, section: &str) -> Option<String> {
+    manifest
+        .get(section)
+        .and_then(|s| s.get("metadata"))
+        .and_then(|m| m.get("kubos"))
+        .and_then(|k| k.get("target"))
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// Read the default Kubos target from `[package.metadata.kubos]` in the
+/// crate manifest, falling back to `[workspace.metadata.kubos]` in the
+/// manifest itself or in the enclosing workspace root.
+///
+/// Returns the target name along with the manifest it was read from.
+pub fn manifest_target(params: &[String]) -> Result<Option<(String, PathBuf)>, String> {
+    let manifest_path = match find_manifest(params) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let manifest = read_manifest(&manifest_path)?;
+
+    if let Some(target) = metadata_target(&manifest, "package") {
+        return Ok(Some((target, manifest_path)));
+    }
+    if let Some(target) = metadata_target(&manifest, "workspace") {
+        return Ok(Some((target, manifest_path)));
+    }
+
+    match workspace_root(&manifest_path)? {
+        Some((path, root)) => Ok(metadata_target(&root, "workspace").map(|target| (target, path))),
+        None => Ok(None),
+    }
+}
+
+/// Look up a table under `[package.metadata.kubos]` in a parsed manifest
+pub fn kubos_metadata<'a>(manifest: &'a Value, key: &str) -> Option<&'a Value> {
+    manifest
+        .get("package")
+        .and_then(|p| p.get("metadata"))
+        .and_then(|m| m.get("kubos"))
+        .and_then(|k| k.get(key))
+}
+
+/// The crate being built, from its manifest
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// Directory holding the manifest
+    pub dir: PathBuf,
+    pub manifest: Value,
+}
+
+/// Read the name and version of the crate being built, following
+/// `version.workspace = true` to the workspace root
+pub fn package(params: &[String]) -> Result<Package, String> {
+    let path = find_manifest(params).ok_or_else(|| String::from("could not find Cargo.toml"))?;
+    let manifest = read_manifest(&path)?;
+    let package = manifest
+        .get("package")
+        .ok_or_else(|| format!("{}: no [package] section", path.display()))?;
+    let name = package
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("{}: package.name must be a string", path.display()))?
+        .to_owned();
+    let version = match package.get("version") {
+        Some(Value::String(version)) => version.clone(),
+        Some(version) if version.get("workspace").and_then(Value::as_bool) == Some(true) => {
+            workspace_root(&path)?
+                .and_then(|(_, root)| {
+                    root.get("workspace")
+                        .and_then(|w| w.get("package"))
+                        .and_then(|p| p.get("version"))
+                        .and_then(Value::as_str)
+                        .map(String::from)
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "{}: version is inherited but the workspace doesn't set one",
+                        path.display()
+                    )
+                })?
+        }
+        // Cargo's default when the version is left out
+        None => String::from("0.0.0"),
+        Some(_) => {
+            return Err(format!(
+                "{}: package.version must be a string",
+                path.display()
+            ))
+        }
+    };
+
+    Ok(Package {
+        name,
+        version,
+        dir: path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from(".")),
+        manifest,
+    })
+}
+
+/// Find the manifest of the workspace root which the crate belongs to,
+/// returning its path and parsed contents
+fn workspace_root(manifest_path: &Path) -> Result<Option<(PathBuf, Value)>, String> {
+    let parents = manifest_path
+        .parent()
+        .and_then(Path::parent)
+        .map(|dir| dir.ancestors().collect::<Vec<&Path>>())
+        .unwrap_or_default();
+    for dir in parents {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.is_file() {
+            continue;
+        }
+        let root = read_manifest(&path)?;
+        if root.get("workspace").is_some() {
+            return Ok(Some((path, root)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Find the directory cargo will build into: `--target-dir`,
+/// `CARGO_TARGET_DIR`, or `target` in the workspace root
+pub fn target_dir(params: &[String]) -> Option<PathBuf> {
+    if let Some(dir) = arg_value(params, "--target-dir") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = env::var_os("CARGO_TARGET_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+
+    workspace_dir(params).map(|dir| dir.join("target"))
+}
+
+/// The directory of the workspace root the crate belongs to,
+/// or the crate's own directory when it isn't in a workspace
+pub fn workspace_dir(params: &[String]) -> Option<PathBuf> {
+    let manifest_path = find_manifest(params)?;
+    let manifest = read_manifest(&manifest_path).ok()?;
+    let root = if manifest.get("workspace").is_some() {
+        manifest_path
+    } else {
+        match workspace_root(&manifest_path) {
+            Ok(Some((path, _))) => path,
+            _ => manifest_path,
+        }
+    };
+    root.parent().map(Path::to_path_buf)
+}
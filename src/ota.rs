@@ -0,0 +1,206 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! OTA manifests, listing the size, SHA-256 and mode of each file
+//! in a deployment so the updater on board can validate uploads, and
+//! `cargo kubos verify-manifest`, which checks a directory against one
+
+use crate::sha256::file_digest;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// Name of the manifest at the root of an artifact directory
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+/// What the files in a manifest were built from
+pub struct BuildInfo<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub kubos_target: &'a str,
+}
+
+/// An entry in a manifest
+struct Entry {
+    /// Path relative to the manifest's directory
+    path: String,
+    size: u64,
+    sha256: String,
+    mode: u32,
+}
+
+impl Entry {
+    fn read(root: &Path, path: &str) -> Result<Entry, String> {
+        let file = root.join(path);
+        let meta = fs::metadata(&file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        Ok(Entry {
+            path: String::from(path),
+            size: meta.len(),
+            sha256: file_digest(&file).map_err(|e| format!("{}: {}", file.display(), e))?,
+            mode: file_mode(&meta),
+        })
+    }
+}
+
+#[cfg(unix)]
+pub fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(meta: &fs::Metadata) -> u32 {
+    if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// Write a manifest of the given files, relative to `root`, to `out`
+pub fn write(root: &Path, files: &[String], info: &BuildInfo, out: &Path) -> Result<(), String> {
+    let mut contents = format!(
+        "name = {}\nversion = {}\nkubos-target = {}\n",
+        Value::String(info.name.to_owned()),
+        Value::String(info.version.to_owned()),
+        Value::String(info.kubos_target.to_owned())
+    );
+    for path in files {
+        let entry = Entry::read(root, path)?;
+        contents.push_str(&format!(
+            "\n[[files]]\npath = {}\nsize = {}\nsha256 = {}\nmode = {}\n",
+            Value::String(entry.path),
+            entry.size,
+            Value::String(entry.sha256),
+            Value::String(format!("{:04o}", entry.mode))
+        ));
+    }
+    fs::write(out, contents).map_err(|e| format!("{}: {}", out.display(), e))
+}
+
+/// Every file under the directory, as sorted paths relative to it
+pub fn list_files(root: &Path) -> Result<Vec<String>, String> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<(), String> {
+        let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| format!("{}: {}", dir.display(), e))?
+                .path();
+            if path.is_dir() {
+                walk(root, &path, files)?;
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                files.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = vec![];
+    walk(root, root, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Check the files listed in a manifest against those beside it,
+/// returning a description of each mismatch. The path may be the
+/// manifest or an artifact directory with a `manifest.toml`.
+pub fn verify(path: &Path) -> Result<Vec<String>, String> {
+    let manifest = if path.is_dir() {
+        path.join(MANIFEST_FILE)
+    } else {
+        PathBuf::from(path)
+    };
+    let root = manifest.parent().unwrap_or_else(|| Path::new("."));
+    let data =
+        fs::read_to_string(&manifest).map_err(|e| format!("{}: {}", manifest.display(), e))?;
+    let contents = data
+        .parse::<Value>()
+        .map_err(|e| format!("{}: {}", manifest.display(), e))?;
+    let invalid = || format!("{}: not a valid OTA manifest", manifest.display());
+    let files = match contents.get("files") {
+        Some(files) => files.as_array().ok_or_else(invalid)?.as_slice(),
+        None => &[],
+    };
+
+    let mut mismatches = vec![];
+    for file in files {
+        let field = |key: &str| file.get(key).ok_or_else(invalid);
+        let path = field("path")?.as_str().ok_or_else(invalid)?;
+        let size = field("size")?.as_integer().ok_or_else(invalid)?;
+        let sha256 = field("sha256")?.as_str().ok_or_else(invalid)?;
+        let mode = field("mode")?
+            .as_str()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .ok_or_else(invalid)?;
+
+        let entry = match Entry::read(root, path) {
+            Ok(entry) => entry,
+            Err(_) => {
+                mismatches.push(format!("{}: missing", path));
+                continue;
+            }
+        };
+        if entry.size as i64 != size {
+            mismatches.push(format!(
+                "{}: size is {} bytes, expected {}",
+                path, entry.size, size
+            ));
+        }
+        if entry.sha256 != sha256 {
+            mismatches.push(format!(
+                "{}: SHA-256 is {}, expected {}",
+                path, entry.sha256, sha256
+            ));
+        }
+        if entry.mode != mode {
+            mismatches.push(format!(
+                "{}: mode is {:04o}, expected {:04o}",
+                path, entry.mode, mode
+            ));
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Run `cargo kubos verify-manifest PATH`, returning the exit code
+pub fn command(positional: &[String]) -> i32 {
+    let path = match positional {
+        [_, path] => Path::new(path),
+        _ => {
+            error!("verify-manifest needs the path of an artifact directory or manifest");
+            return 2;
+        }
+    };
+    match verify(path) {
+        Ok(ref mismatches) if mismatches.is_empty() => {
+            info!("{} matches its manifest", path.display());
+            0
+        }
+        Ok(mismatches) => {
+            error!(
+                "{} doesn't match its manifest ({} mismatches):\n{}",
+                path.display(),
+                mismatches.len(),
+                mismatches.join("\n")
+            );
+            1
+        }
+        Err(e) => {
+            error!("{}", e);
+            1
+        }
+    }
+}
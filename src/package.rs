@@ -0,0 +1,373 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos package`, which bundles a stripped binary and its
+//! support files into an archive to install on a board, configured
+//! by `[package.metadata.kubos.package]` in the crate manifest
+
+use crate::artifacts::strip;
+use crate::build::strip_program;
+use crate::manifest::{self, kubos_metadata, target_dir, Package};
+use crate::ota::{self, BuildInfo};
+use crate::targets::Target;
+use crate::BuildOptions;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use toml::Value;
+
+/// Name of the file in each package describing what it was built from
+pub const PACKAGE_INFO: &str = "kubos-package.toml";
+
+/// What kind of archive to write
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A gzipped tarball to unpack at the root of the board's filesystem
+    Tar,
+    /// An opkg package
+    Ipk,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Format, String> {
+        match name {
+            "tar" | "tar.gz" | "tgz" => Ok(Format::Tar),
+            "ipk" => Ok(Format::Ipk),
+            _ => Err(format!(
+                "unknown package format '{}', expected tar.gz or ipk",
+                name
+            )),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Tar => "tar.gz",
+            Format::Ipk => "ipk",
+        }
+    }
+}
+
+/// Settings from `[package.metadata.kubos.package]`
+pub struct PackageConfig {
+    /// Install prefix on the board, the binary going in `<prefix>/bin`
+    pub prefix: String,
+    /// Name to install the binary as, by default the crate name
+    pub service: String,
+    /// Extra files as (install path, source path relative to the crate)
+    pub files: Vec<(String, PathBuf)>,
+}
+
+impl PackageConfig {
+    pub fn load(package: &Package) -> Result<PackageConfig, String> {
+        let section = "package.metadata.kubos.package";
+        let table = kubos_metadata(&package.manifest, "package");
+        let string_key = |key: &str| -> Result<Option<String>, String> {
+            match table.and_then(|t| t.get(key)) {
+                Some(Value::String(value)) => Ok(Some(value.clone())),
+                Some(_) => Err(format!("{}.{} must be a string", section, key)),
+                None => Ok(None),
+            }
+        };
+
+        let files = match table.and_then(|t| t.get("files")) {
+            Some(Value::Table(files)) => files
+                .iter()
+                .map(|(dest, src)| match src {
+                    Value::String(src) => Ok((dest.clone(), package.dir.join(src))),
+                    _ => Err(format!(
+                        "{}.files.\"{}\" must be the path of the file to install",
+                        section, dest
+                    )),
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+            Some(_) => {
+                return Err(format!(
+                    "{}.files must map install paths to source files",
+                    section
+                ))
+            }
+            None => vec![],
+        };
+
+        Ok(PackageConfig {
+            prefix: string_key("prefix")?.unwrap_or_else(|| String::from("/usr")),
+            service: string_key("service")?.unwrap_or_else(|| package.name.clone()),
+            files,
+        })
+    }
+}
+
+/// Lay out the binary and extra files in a staging directory under
+/// `out_dir` and archive them there, returning the archive's path.
+///
+/// Everything in the archive is owned by root with the modification
+/// time of the build timestamp, so repackaging the same build gives
+/// an identical archive.
+pub fn create(
+    target: &Target,
+    package: &Package,
+    config: &PackageConfig,
+    binary: &Path,
+    strip_program: &Path,
+    format: Format,
+    out_dir: &Path,
+) -> Result<PathBuf, String> {
+    let staging = out_dir.join("staging").join(&target.name);
+    if staging.exists() {
+        fs::remove_dir_all(&staging).map_err(|e| format!("{}: {}", staging.display(), e))?;
+    }
+    let data = staging.join("data");
+    let prefix = config.prefix.trim_start_matches('/');
+
+    let installed = data.join(prefix).join("bin").join(&config.service);
+    copy_file(binary, &installed)?;
+    strip(strip_program, &installed)?;
+    for (dest, src) in &config.files {
+        copy_file(src, &data.join(dest.trim_start_matches('/')))?;
+    }
+
+    let timestamp = build_timestamp(&package.dir);
+    let info = data
+        .join(prefix)
+        .join("share")
+        .join(&config.service)
+        .join(PACKAGE_INFO);
+    let mut contents = format!(
+        "name = {}\nversion = {}\nkubos-target = {}\ntriple = {}\nbuilt = {}\n",
+        Value::String(package.name.clone()),
+        Value::String(package.version.clone()),
+        Value::String(target.name.clone()),
+        Value::String(target.triple.clone()),
+        format_timestamp(timestamp)
+    );
+    if let Some(hash) = git_hash(&package.dir) {
+        contents.push_str(&format!("git-hash = {}\n", Value::String(hash)));
+    }
+    write_file(&info, &contents)?;
+
+    // The OTA manifest goes in the package and beside it
+    let stem = format!("{}-{}-{}", package.name, package.version, target.name);
+    let build = BuildInfo {
+        name: &package.name,
+        version: &package.version,
+        kubos_target: &target.name,
+    };
+    let manifest = data.join(ota::MANIFEST_FILE);
+    ota::write(&data, &ota::list_files(&data)?, &build, &manifest)?;
+    copy_file(&manifest, &out_dir.join(format!("{}.manifest.toml", stem)))?;
+
+    let archive = out_dir.join(format!("{}.{}", stem, format.extension()));
+    check_gnu_tar()?;
+    match format {
+        Format::Tar => tar_gz(&data, &archive, timestamp)?,
+        Format::Ipk => {
+            let control = staging.join("control");
+            write_file(
+                &control.join("control"),
+                &format!(
+                    "Package: {}\nVersion: {}\nArchitecture: {}\nDescription: {} for {}\n",
+                    config.service,
+                    package.version,
+                    target.triple.split('-').next().unwrap_or("all"),
+                    package.name,
+                    target.name
+                ),
+            )?;
+            tar_gz(&control, &staging.join("control.tar.gz"), timestamp)?;
+            tar_gz(&data, &staging.join("data.tar.gz"), timestamp)?;
+            write_file(&staging.join("debian-binary"), "2.0\n")?;
+            if archive.exists() {
+                fs::remove_file(&archive).map_err(|e| format!("{}: {}", archive.display(), e))?;
+            }
+            // The D modifier zeroes the timestamps and owners in the archive
+            let mut command = Command::new("ar");
+            command
+                .arg("rcD")
+                .arg(&archive)
+                .arg("debian-binary")
+                .arg("control.tar.gz")
+                .arg("data.tar.gz")
+                .current_dir(&staging);
+            run(&mut command, "ar")?;
+        }
+    }
+    Ok(archive)
+}
+
+fn copy_file(src: &Path, dest: &Path) -> Result<(), String> {
+    if let Some(dir) = dest.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    fs::copy(src, dest)
+        .map(|_| ())
+        .map_err(|e| format!("copying {} to {}: {}", src.display(), dest.display(), e))
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Check the tar on PATH is GNU tar, as the options [`tar_gz`] makes
+/// reproducible archives with are its own
+fn check_gnu_tar() -> Result<(), String> {
+    let output = Command::new("tar")
+        .arg("--version")
+        .output()
+        .map_err(|e| crate::process::spawn_error("tar", &e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.lines().next().unwrap_or("").trim();
+    if output.status.success() && version.contains("GNU tar") {
+        return Ok(());
+    }
+    Err(format!(
+        "packaging needs GNU tar, but the tar on PATH is {}; \
+         install GNU tar and put it first on PATH as tar",
+        if version.is_empty() {
+            String::from("something else")
+        } else {
+            format!("'{}'", version)
+        }
+    ))
+}
+
+/// Write a gzipped tarball of the directory's contents
+fn tar_gz(dir: &Path, archive: &Path, timestamp: u64) -> Result<(), String> {
+    let tar = archive.with_extension("");
+    let mut command = Command::new("tar");
+    command
+        .arg("--sort=name")
+        .arg(format!("--mtime=@{}", timestamp))
+        .arg("--owner=0")
+        .arg("--group=0")
+        .arg("--numeric-owner")
+        .arg("-cf")
+        .arg(&tar)
+        .arg("-C")
+        .arg(dir)
+        .arg(".");
+    run(&mut command, "tar")?;
+
+    // gzip -n leaves the name and time out of the header
+    let mut command = Command::new("gzip");
+    command.arg("-n").arg("-f").arg("-9").arg(&tar);
+    run(&mut command, "gzip")?;
+    let gzipped = PathBuf::from(format!("{}.gz", tar.display()));
+    if gzipped != archive {
+        fs::rename(&gzipped, archive).map_err(|e| format!("{}: {}", archive.display(), e))?;
+    }
+    Ok(())
+}
+
+fn run(command: &mut Command, program: &str) -> Result<(), String> {
+    let status = command
+        .status()
+        .map_err(|e| crate::process::spawn_error(program, &e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed ({})", program, status))
+    }
+}
+
+/// The commit the crate is checked out at, if it's in a git repository
+fn git_hash(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// When the build happened, in seconds since the epoch: SOURCE_DATE_EPOCH
+/// if set, or the time of the last commit so that rebuilding a commit
+/// gives the same packages, or else now
+fn build_timestamp(dir: &Path) -> u64 {
+    if let Some(epoch) = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+    {
+        return epoch;
+    }
+    let committed = Command::new("git")
+        .args(["log", "-1", "--format=%ct"])
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse().ok());
+    committed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    })
+}
+
+/// Format seconds since the epoch as a TOML datetime in UTC
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Package the binary into `target/kubos/packages`
+pub fn command(
+    target: &Target,
+    params: &[String],
+    binary: &Path,
+    format: Format,
+    options: &BuildOptions,
+) -> Result<PathBuf, String> {
+    let package = manifest::package(params)?;
+    let config = PackageConfig::load(&package)?;
+    let out_dir = target_dir(params)
+        .ok_or_else(|| String::from("could not find the cargo target directory"))?
+        .join("kubos")
+        .join("packages");
+    let strip = strip_program(target, options)?;
+    create(target, &package, &config, binary, &strip, format, &out_dir)
+}
@@ -0,0 +1,258 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The checks made before cargo runs for the targets, so that a missing
+//! Rust target, linker or sysroot is reported up front rather than as
+//! a link error part way through the build.
+
+use crate::cache::ResolutionCache;
+use crate::config::{kubos_target_path, LinkerError};
+use crate::error::Error;
+use crate::targets::Target;
+use crate::toolchain::{
+    is_executable, is_host_triple, resolve_program, rustup_installed_targets, target_linker,
+};
+use crate::{compat, events, process, BuildOptions};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The sysroot given for a target with --sysroot or the
+/// `[kubos.target."<name>"].sysroot` config key, checked to look like one
+pub fn explicit_sysroot(target: &Target, options: &BuildOptions) -> Result<Option<String>, Error> {
+    let sysroot = match options.sysroot {
+        Some(ref sysroot) => sysroot.clone(),
+        None => match kubos_target_path(&options.cache, &target.name, "sysroot")? {
+            Some(sysroot) => sysroot,
+            None => return Ok(None),
+        },
+    };
+
+    let path = Path::new(&sysroot);
+    if !path.is_dir() {
+        return Err(Error::Environment(format!(
+            "sysroot {} for target {} does not exist",
+            sysroot, target.name
+        )));
+    }
+    if !path.join("lib").is_dir() && !path.join("usr").join("lib").is_dir() {
+        return Err(Error::Environment(format!(
+            "sysroot {} for target {} has no lib or usr/lib directory, \
+             make sure it points at the root of the unpacked sysroot",
+            sysroot, target.name
+        )));
+    }
+    Ok(Some(sysroot))
+}
+
+/// Make sure the linker for each cross target can actually be run, so a
+/// missing toolchain fails fast rather than after the host-side build
+pub fn preflight_linkers(
+    targets: &[Target],
+    discover: bool,
+    runner: &dyn process::Runner,
+    cache: &ResolutionCache,
+) -> Result<(), Error> {
+    targets
+        .iter()
+        .try_for_each(|target| preflight_linker(target, discover, runner, cache))
+}
+
+/// Make sure a cross target's linker, if it has one, can be run,
+/// running its `--version` with the runner once for every target using it
+pub fn preflight_linker(
+    target: &Target,
+    discover: bool,
+    runner: &dyn process::Runner,
+    cache: &ResolutionCache,
+) -> Result<(), Error> {
+    if is_host_triple(&target.triple) {
+        return Ok(());
+    }
+    let linker = match target_linker(target, discover, cache) {
+        Ok(linker) => linker,
+        Err(LinkerError::NotSet { .. }) => return Ok(()),
+        Err(LinkerError::Config(e)) => return Err(Error::Config(e)),
+    };
+    let hint = format!(
+        "check the linker in {}, or pass --skip-preflight if it's a wrapper \
+         which doesn't support --version",
+        linker.source
+    );
+
+    let program = resolve_program(linker.program()).ok_or_else(|| {
+        Error::Environment(format!(
+            "linker {} for target {} does not exist\n{}",
+            linker.program(),
+            target.name,
+            hint
+        ))
+    })?;
+    if !is_executable(&program) {
+        return Err(Error::Environment(format!(
+            "linker {} for target {} is not executable\n{}",
+            program.display(),
+            target.name,
+            hint
+        )));
+    }
+    let works = cache.linker_runs(&program, || {
+        runner
+            .run(
+                Command::new(&program).arg("--version"),
+                None,
+                process::Capture::Stdout,
+            )
+            .map(|(status, _)| status.success())
+            .unwrap_or(false)
+    });
+    if !works {
+        return Err(Error::Environment(format!(
+            "linker {} for target {} failed to run `--version`\n{}",
+            program.display(),
+            target.name,
+            hint
+        )));
+    }
+    Ok(())
+}
+
+/// Check the sysroots given for each target before building any of them
+pub fn check_sysroots(targets: &[Target], options: &BuildOptions) -> Result<(), Error> {
+    targets
+        .iter()
+        .try_for_each(|target| explicit_sysroot(target, options).map(|_| ()))
+}
+
+/// Check whether a target can be built on this machine,
+/// returning the reason it can't if not
+pub fn unavailable_reason(
+    target: &Target,
+    sysroot: Option<&PathBuf>,
+    host: Option<&String>,
+    discover: bool,
+    cache: &ResolutionCache,
+) -> Option<&'static str> {
+    if let Some(sysroot) = sysroot {
+        let rustlib = sysroot.join("lib").join("rustlib").join(&target.triple);
+        if !rustlib.is_dir() {
+            return Some("Rust target not installed");
+        }
+    }
+    if host != Some(&target.triple) && target_linker(target, discover, cache).is_err() {
+        return Some("linker not found");
+    }
+    None
+}
+
+/// Make sure the Rust standard library for each target is installed,
+/// optionally installing any missing ones with rustup
+pub fn check_rust_targets(targets: &[Target], install: bool) -> Result<(), String> {
+    let cross: Vec<&Target> = targets
+        .iter()
+        .filter(|target| !is_host_triple(&target.triple))
+        .collect();
+    if cross.is_empty() {
+        return Ok(());
+    }
+    let installed = match rustup_installed_targets() {
+        Some(installed) => installed,
+        None => return Ok(()),
+    };
+
+    for target in cross {
+        if installed.contains(&target.triple) {
+            continue;
+        }
+        if !install {
+            return Err(format!(
+                "Rust target '{}' is not installed, run `rustup target add {}` \
+                 or pass --install-target",
+                target.triple, target.triple
+            ));
+        }
+        let status = Command::new("rustup")
+            .args(["target", "add", &target.triple])
+            .stdout(events::child_stdout())
+            .status()
+            .map_err(|e| format!("failed to run rustup: {}", e))?;
+        if !status.success() {
+            return Err(format!("`rustup target add {}` failed", target.triple));
+        }
+    }
+
+    Ok(())
+}
+
+/// Make sure a linker can be found for each cross target, warning about
+/// (or with `strict`, refusing to build) any which have none
+pub fn check_linkers(
+    targets: &[Target],
+    strict: bool,
+    discover: bool,
+    cache: &ResolutionCache,
+) -> Result<(), Error> {
+    for target in targets {
+        if is_host_triple(&target.triple) {
+            continue;
+        }
+        match target_linker(target, discover, cache) {
+            Ok(_) => {}
+            // A config which can't be read is never worth building past
+            Err(LinkerError::Config(e)) => return Err(Error::Config(e)),
+            Err(e) if strict => return Err(Error::from(e)),
+            Err(e) => warning!("{}", Error::from(e)),
+        }
+    }
+    Ok(())
+}
+
+/// Check each cross target's toolchain against the crate's version
+/// constraints, warning about (or with `strict`, refusing to build with)
+/// any which don't meet them
+pub fn check_versions(
+    targets: &[Target],
+    params: &[String],
+    strict: bool,
+    discover: bool,
+    cache: &ResolutionCache,
+) -> Result<(), String> {
+    let constraints = compat::Constraints::load(params)?;
+    if constraints.is_empty() {
+        return Ok(());
+    }
+    for target in targets {
+        if is_host_triple(&target.triple) {
+            continue;
+        }
+        let linker = match target_linker(target, discover, cache) {
+            Ok(linker) => linker,
+            Err(_) => continue,
+        };
+        for violation in constraints.violations(&compat::probe(&linker, cache)) {
+            let message = format!(
+                "target {} linker {}: {}",
+                target.name,
+                linker.program(),
+                violation
+            );
+            if strict {
+                return Err(message);
+            }
+            warning!("{}", message);
+        }
+    }
+    Ok(())
+}
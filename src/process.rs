@@ -0,0 +1,442 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Running the cargo child process
+
+use crate::color;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Exit code used when the child couldn't be started at all,
+/// like a shell's "command not found"
+pub const SPAWN_FAILURE_CODE: i32 = 127;
+
+/// Exit code used when the child's status has neither
+/// an exit code nor a terminating signal
+pub const UNKNOWN_STATUS_CODE: i32 = 1;
+
+/// Exit code used when the child was killed for running past
+/// --timeout, like coreutils' `timeout`
+pub const TIMEOUT_CODE: i32 = 124;
+
+/// How often to check on a child which has a time limit
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Spawn the command and wait for it to finish, forwarding
+/// SIGINT and SIGTERM to it in the meantime. With a timeout, the child
+/// gets its own process group, which is killed if it runs past the limit
+/// and an error of kind `TimedOut` returned.
+pub fn run(command: &mut Command, timeout: Option<Duration>) -> io::Result<ExitStatus> {
+    let mut child = spawn(command, timeout)?;
+    let status = wait(&mut child, timeout);
+    signals::forward_to(0, false);
+    status
+}
+
+fn spawn(command: &mut Command, timeout: Option<Duration>) -> io::Result<Child> {
+    let group = timeout.is_some();
+    if group {
+        signals::own_group(command);
+    }
+    let child = command.spawn()?;
+    log!(
+        Trace,
+        "started {} as pid {}{}",
+        command.get_program().to_string_lossy(),
+        child.id(),
+        if group {
+            " in its own process group"
+        } else {
+            ""
+        }
+    );
+    signals::forward_to(child.id(), group);
+    Ok(child)
+}
+
+fn wait(child: &mut Child, timeout: Option<Duration>) -> io::Result<ExitStatus> {
+    let limit = match timeout {
+        Some(limit) => limit,
+        None => return child.wait(),
+    };
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= limit {
+            signals::kill_group(child);
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("killed after {}", format_duration(limit)),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL.min(limit - elapsed));
+    }
+}
+
+/// What [`Runner::run`] collects of a child's output while it waits
+#[derive(Clone, Copy, Debug)]
+pub enum Capture<'a> {
+    /// Nothing, it goes wherever the command sends it
+    Nothing,
+    /// Nothing, but each line is tagged like [`run_prefixed`] does
+    Prefixed {
+        /// The tag, without its brackets
+        tag: &'a str,
+        /// The ANSI color it's shown in
+        color: u8,
+    },
+    /// cargo's JSON messages on stdout, like [`run_captured`] does
+    Messages,
+    /// All of stdout, with stderr thrown away, for probing a program
+    Stdout,
+}
+
+/// Runs programs for cargo-kubos, so that something
+/// else can stand in for them
+pub trait Runner {
+    /// Run the command to completion, as [`run`] does with the timeout,
+    /// returning how it exited and what was collected of its output
+    fn run(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        capture: Capture,
+    ) -> io::Result<(ExitStatus, Vec<u8>)>;
+}
+
+/// The [`Runner`] which really runs programs
+#[derive(Clone, Copy, Debug, Default)]
+pub struct System;
+
+impl Runner for System {
+    fn run(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        capture: Capture,
+    ) -> io::Result<(ExitStatus, Vec<u8>)> {
+        match capture {
+            Capture::Nothing => run(command, timeout).map(|status| (status, vec![])),
+            Capture::Prefixed { tag, color } => {
+                run_prefixed(command, timeout, tag, color).map(|status| (status, vec![]))
+            }
+            Capture::Messages => run_captured(command, timeout),
+            Capture::Stdout => {
+                command.stdout(Stdio::piped()).stderr(Stdio::null());
+                let mut child = spawn(command, timeout)?;
+                let stdout = child.stdout.take().map(|mut out| {
+                    thread::spawn(move || -> io::Result<Vec<u8>> {
+                        let mut captured = vec![];
+                        out.read_to_end(&mut captured)?;
+                        Ok(captured)
+                    })
+                });
+                let status = wait(&mut child, timeout);
+                signals::forward_to(0, false);
+                let captured = match stdout {
+                    Some(reader) => reader
+                        .join()
+                        .unwrap_or_else(|_| Ok(vec![]))
+                        .unwrap_or_default(),
+                    None => vec![],
+                };
+                status.map(|status| (status, captured))
+            }
+        }
+    }
+}
+
+/// Format a duration in seconds, the way the timeout was given
+pub fn format_duration(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+/// Run an interactive program, like gdb, which handles the terminal's
+/// Ctrl-C itself. We ignore it meanwhile rather than dying or forwarding
+/// another, so that whatever follows the program still runs.
+pub fn run_interactive(command: &mut Command) -> io::Result<ExitStatus> {
+    signals::forward_to(0, false);
+    command.status()
+}
+
+/// Start a child in its own process group, out of reach of the
+/// terminal's Ctrl-C, to be stopped with [`stop_background`]
+pub fn spawn_background(command: &mut Command) -> io::Result<Child> {
+    signals::own_group(command);
+    command.spawn()
+}
+
+/// Kill a child started with [`spawn_background`] and wait for it
+pub fn stop_background(child: &mut Child) {
+    signals::kill_group(child);
+    let _ = child.wait();
+}
+
+/// Like [`run`], but with each line the child writes to stdout or stderr
+/// prefixed with `[tag] ` on the same stream. The tag is colored when the
+/// stream is, as [`color`] decides.
+pub fn run_prefixed(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    tag: &str,
+    color: u8,
+) -> io::Result<ExitStatus> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = spawn(command, timeout)?;
+
+    let tag = |colored: bool| {
+        if colored {
+            format!("\x1b[{}m[{}]\x1b[0m ", color, tag).into_bytes()
+        } else {
+            format!("[{}] ", tag).into_bytes()
+        }
+    };
+    let stdout_tag = tag(color::stdout());
+    let stderr_tag = tag(color::stderr());
+    let stdout = child
+        .stdout
+        .take()
+        .map(|out| thread::spawn(move || copy_prefixed(out, &mut io::stdout(), &stdout_tag)));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|err| thread::spawn(move || copy_prefixed(err, &mut io::stderr(), &stderr_tag)));
+
+    let status = wait(&mut child, timeout);
+    signals::forward_to(0, false);
+    for copier in stdout.into_iter().chain(stderr) {
+        let _ = copier.join();
+    }
+    status
+}
+
+/// Like [`run`], but collecting cargo's JSON messages from the child's
+/// stdout rather than passing them through. Any other line written there,
+/// like the output of the program `cargo run` runs, goes on to stderr as
+/// it comes, so it's neither lost nor taken for a message.
+pub fn run_captured(
+    command: &mut Command,
+    timeout: Option<Duration>,
+) -> io::Result<(ExitStatus, Vec<u8>)> {
+    command.stdout(Stdio::piped());
+    let mut child = spawn(command, timeout)?;
+    let stdout = child.stdout.take().map(|out| {
+        thread::spawn(move || -> io::Result<Vec<u8>> {
+            let mut reader = BufReader::new(out);
+            let mut captured = vec![];
+            let mut line = vec![];
+            loop {
+                line.clear();
+                if reader.read_until(b'\n', &mut line)? == 0 {
+                    return Ok(captured);
+                }
+                if line.starts_with(b"{\"reason\":") {
+                    captured.extend_from_slice(&line);
+                } else {
+                    let mut stderr = io::stderr().lock();
+                    stderr.write_all(&line)?;
+                    stderr.flush()?;
+                }
+            }
+        })
+    });
+
+    let status = wait(&mut child, timeout);
+    signals::forward_to(0, false);
+    let captured = match stdout {
+        Some(reader) => reader
+            .join()
+            .unwrap_or_else(|_| Ok(vec![]))
+            .unwrap_or_default(),
+        None => vec![],
+    };
+    status.map(|status| (status, captured))
+}
+
+/// Copy lines from the reader to the writer, each prefixed with the tag
+/// and flushed straight away so progress stays live
+fn copy_prefixed<R: Read, W: Write>(reader: R, writer: &mut W, tag: &[u8]) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        writer.write_all(tag)?;
+        writer.write_all(&line)?;
+        writer.flush()?;
+    }
+}
+
+/// The exit code to use for a child's exit status. A child killed by a
+/// signal gets the shell's conventional `128 + signal` code.
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    signals::terminating_signal(status)
+        .map(|signo| 128 + signo)
+        .unwrap_or(UNKNOWN_STATUS_CODE)
+}
+
+/// Explain how a child which didn't exit normally ended, if it didn't
+pub fn describe_abnormal(program: &str, status: &ExitStatus) -> Option<String> {
+    if status.code().is_some() {
+        return None;
+    }
+    Some(match signals::terminating_signal(status) {
+        Some(signo) => match signals::name(signo) {
+            Some(name) => format!("{} was killed by signal {} ({})", program, signo, name),
+            None => format!("{} was killed by signal {}", program, signo),
+        },
+        None => format!("{} ended without an exit code", program),
+    })
+}
+
+/// A readable error for a child which couldn't be started
+pub fn spawn_error(program: &str, err: &io::Error) -> String {
+    match err.kind() {
+        io::ErrorKind::NotFound => format!("{} not found on PATH", program),
+        _ => format!("failed to run {}: {}", program, err),
+    }
+}
+
+/// Whether the child was killed by an interrupt, after which
+/// no more targets should be built
+pub fn interrupted(status: &ExitStatus) -> bool {
+    matches!(
+        signals::terminating_signal(status),
+        Some(signals::SIGINT) | Some(signals::SIGTERM)
+    )
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::{Child, Command, ExitStatus};
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Once;
+
+    pub const SIGINT: i32 = 2;
+    pub const SIGKILL: i32 = 9;
+    pub const SIGTERM: i32 = 15;
+
+    /// Process ID of the running child, negated for its process group,
+    /// or 0 if there isn't one
+    static CHILD: AtomicI32 = AtomicI32::new(0);
+    static INSTALL: Once = Once::new();
+
+    /// The default disposition, for `signal`'s handler
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+        fn kill(pid: i32, sig: i32) -> i32;
+        fn raise(sig: i32) -> i32;
+    }
+
+    extern "C" fn forward(signo: i32) {
+        let pid = CHILD.load(Ordering::SeqCst);
+        // These are all async-signal-safe, so can be called from a handler
+        unsafe {
+            if pid != 0 {
+                kill(pid, signo);
+            } else {
+                // With nothing to outlive, die of it as if never caught
+                signal(signo, SIG_DFL);
+                raise(signo);
+            }
+        }
+    }
+
+    /// Forward signals to the given child process, or its process group,
+    /// or stop forwarding if 0, after which they kill us as usual. A
+    /// terminal's Ctrl-C already reaches a child in our process group, but
+    /// we must not die before it does.
+    pub fn forward_to(pid: u32, group: bool) {
+        let pid = pid as i32;
+        CHILD.store(if group { -pid } else { pid }, Ordering::SeqCst);
+        INSTALL.call_once(|| unsafe {
+            signal(SIGINT, forward as extern "C" fn(i32) as usize);
+            signal(SIGTERM, forward as extern "C" fn(i32) as usize);
+        });
+    }
+
+    /// Start the command in a process group of its own, so that it
+    /// can be killed along with everything it runs
+    pub fn own_group(command: &mut Command) {
+        command.process_group(0);
+    }
+
+    /// Kill the child started with [`own_group`] and its descendants
+    pub fn kill_group(child: &mut Child) {
+        unsafe {
+            kill(-(child.id() as i32), SIGKILL);
+        }
+    }
+
+    pub fn terminating_signal(status: &ExitStatus) -> Option<i32> {
+        status.signal()
+    }
+
+    /// Names of the signals a build is likely to be killed by
+    pub fn name(signo: i32) -> Option<&'static str> {
+        match signo {
+            1 => Some("SIGHUP"),
+            SIGINT => Some("SIGINT"),
+            3 => Some("SIGQUIT"),
+            6 => Some("SIGABRT"),
+            SIGKILL => Some("SIGKILL"),
+            11 => Some("SIGSEGV"),
+            SIGTERM => Some("SIGTERM"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod signals {
+    use std::process::{Child, Command, ExitStatus};
+
+    pub const SIGINT: i32 = 2;
+    pub const SIGTERM: i32 = 15;
+
+    /// Console control events already reach the child, so there's
+    /// nothing to forward
+    pub fn forward_to(_pid: u32, _group: bool) {}
+
+    pub fn own_group(_command: &mut Command) {}
+
+    /// Without process groups, only the child itself can be killed
+    pub fn kill_group(child: &mut Child) {
+        let _ = child.kill();
+    }
+
+    pub fn terminating_signal(_status: &ExitStatus) -> Option<i32> {
+        None
+    }
+
+    pub fn name(_signo: i32) -> Option<&'static str> {
+        None
+    }
+}
@@ -0,0 +1,137 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::{env, fs, io};
+use toml::Value;
+
+/// Per-project cargo-kubos settings from `.cargo/kubos.toml`
+#[derive(Debug, Default)]
+pub struct ProjectConfig {
+    /// File the settings were read from
+    pub path: PathBuf,
+    /// Default Kubos target
+    pub target: Option<String>,
+    /// Cargo command to run when `-c` isn't given
+    pub default_command: Option<String>,
+    /// Extra cargo arguments, placed before the user's own
+    pub extra_args: Vec<String>,
+}
+
+/// Directory of the manifest given with `--manifest-path`, if it was
+static PROJECT_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Look for project-local files from the directory of the manifest
+/// cargo is pointed at, rather than the current directory
+pub fn set_project_dir(dir: &Path) {
+    let _ = PROJECT_DIR.set(dir.to_path_buf());
+}
+
+/// The directory given to [`set_project_dir`], if any. Without one,
+/// project-local files are looked for from the current directory.
+pub fn project_dir() -> Option<&'static Path> {
+    PROJECT_DIR.get().map(PathBuf::as_path)
+}
+
+/// Search for `.cargo/kubos.toml` from the project directory upward,
+/// stopping at the filesystem root or the enclosing workspace root
+pub fn load_project_config() -> Result<Option<ProjectConfig>, String> {
+    // A relative manifest path's directory is made absolute to have
+    // parents to search
+    let cwd = env::current_dir().map_err(|e| format!("current directory: {}", e))?;
+    let start = match project_dir() {
+        Some(dir) => cwd.join(dir),
+        None => cwd,
+    };
+
+    for dir in start.ancestors() {
+        let path = dir.join(".cargo").join("kubos.toml");
+        if path.is_file() {
+            return read_project_config(path).map(Some);
+        }
+        if is_workspace_root(dir) {
+            break;
+        }
+    }
+
+    Ok(None)
+}
+
+/// Set the default target in the project config in `dir`, creating the
+/// file if need be. The file mustn't already set one: the setting goes
+/// first, ahead of any tables, and is otherwise left as it was.
+pub fn save_project_target(dir: &Path, target: &str) -> Result<PathBuf, String> {
+    let path = dir.join(".cargo").join("kubos.toml");
+    let existing = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let line = format!("target = {}\n", Value::String(String::from(target)));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    fs::write(&path, line + &existing).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Whether the directory holds a workspace manifest
+fn is_workspace_root(dir: &Path) -> bool {
+    fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|data| data.parse::<Value>().ok())
+        .map(|manifest| manifest.get("workspace").is_some())
+        .unwrap_or(false)
+}
+
+fn read_project_config(path: PathBuf) -> Result<ProjectConfig, String> {
+    let data = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let cfg = data
+        .parse::<Value>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let string_key = |key: &str| -> Result<Option<String>, String> {
+        match cfg.get(key) {
+            Some(value) => value
+                .as_str()
+                .map(|value| Some(String::from(value)))
+                .ok_or_else(|| format!("{}: {} must be a string", path.display(), key)),
+            None => Ok(None),
+        }
+    };
+    let target = string_key("target")?;
+    let default_command = string_key("default-command")?;
+
+    let extra_args = match cfg.get("extra-args") {
+        Some(args) => args
+            .as_array()
+            .and_then(|args| {
+                args.iter()
+                    .map(|arg| arg.as_str().map(String::from))
+                    .collect::<Option<Vec<String>>>()
+            })
+            .ok_or_else(|| format!("{}: extra-args must be an array of strings", path.display()))?,
+        None => vec![],
+    };
+
+    Ok(ProjectConfig {
+        path,
+        target,
+        default_command,
+        extra_args,
+    })
+}
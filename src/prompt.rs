@@ -0,0 +1,208 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Asking which target to build for, when nothing says and there's
+//! someone at a terminal to ask. The menu goes to stderr so that it
+//! can't get mixed up with what cargo prints.
+
+use crate::targets::Target;
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Environment variables which CI services set, any of them meaning
+/// there's nobody to answer a prompt
+const CI_VARS: &[&str] = &[
+    "CI",
+    "CONTINUOUS_INTEGRATION",
+    "BUILD_NUMBER",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "JENKINS_URL",
+    "TF_BUILD",
+];
+
+/// Whether there's someone to ask: stdin and stderr are terminals,
+/// outside CI
+pub fn interactive() -> bool {
+    io::stdin().is_terminal()
+        && io::stderr().is_terminal()
+        && !CI_VARS
+            .iter()
+            .any(|var| env::var_os(var).is_some_and(|value| !value.is_empty()))
+}
+
+/// Which of the targets an answer to the menu picks: its number, counting
+/// from 1, or its name or alias. Nothing picks the default.
+pub fn parse_choice(answer: &str, targets: &[Target], default: usize) -> Result<usize, String> {
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Ok(default);
+    }
+    // Any run of digits is a number, though too big for a usize
+    if answer.bytes().all(|b| b.is_ascii_digit()) {
+        return match answer.parse::<usize>() {
+            Ok(number) if (1..=targets.len()).contains(&number) => Ok(number - 1),
+            _ => Err(format!("pick a number from 1 to {}", targets.len())),
+        };
+    }
+    targets
+        .iter()
+        .position(|target| target.name == answer)
+        .or_else(|| {
+            targets
+                .iter()
+                .position(|target| target.aliases.iter().any(|alias| alias == answer))
+        })
+        .ok_or_else(|| format!("'{}' isn't one of the targets listed", answer))
+}
+
+/// Whether an answer to a yes/no question is yes, no being the default
+pub fn parse_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Read a line in answer to a question, or `None` at the end of input
+fn ask(question: &str) -> io::Result<Option<String>> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{}", question)?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer)? {
+        0 => {
+            writeln!(stderr)?;
+            Ok(None)
+        }
+        _ => Ok(Some(answer)),
+    }
+}
+
+/// Show the targets as a numbered menu and ask until one is picked.
+/// The end of input picks the default.
+pub fn pick_target(targets: &[Target], default: usize) -> io::Result<usize> {
+    let mut stderr = io::stderr();
+    writeln!(
+        stderr,
+        "No target was given and none is configured, which should it build for?"
+    )?;
+    let width = targets
+        .iter()
+        .map(|target| label(target).len())
+        .max()
+        .unwrap_or(0);
+    for (number, target) in targets.iter().enumerate() {
+        writeln!(
+            stderr,
+            "{:>4}) {:width$}  {}",
+            number + 1,
+            label(target),
+            target.triple,
+            width = width
+        )?;
+    }
+    loop {
+        let answer = match ask(&format!("Target [{}]: ", default + 1))? {
+            Some(answer) => answer,
+            None => return Ok(default),
+        };
+        match parse_choice(&answer, targets, default) {
+            Ok(index) => return Ok(index),
+            Err(e) => writeln!(stderr, "{}", e)?,
+        }
+    }
+}
+
+/// Ask a yes/no question, no being the answer at the end of input
+pub fn confirm(question: &str) -> io::Result<bool> {
+    Ok(ask(&format!("{} [y/N] ", question))?.is_some_and(|answer| parse_yes(&answer)))
+}
+
+fn label(target: &Target) -> String {
+    if target.aliases.is_empty() {
+        target.name.clone()
+    } else {
+        format!("{} ({})", target.name, target.aliases.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets() -> Vec<Target> {
+        vec![
+            Target::new("x86-linux-native", "x86_64-unknown-linux-gnu"),
+            Target::new("kubos-linux-beaglebone-gcc", "arm-unknown-linux-gnueabihf"),
+            Target::new("kubos-linux-rpi-cm3-gcc", "arm-unknown-linux-gnueabihf"),
+        ]
+    }
+
+    #[test]
+    fn nothing_picks_the_default() {
+        assert_eq!(parse_choice("", &targets(), 1), Ok(1));
+        assert_eq!(parse_choice(" \n", &targets(), 2), Ok(2));
+    }
+
+    #[test]
+    fn numbers_count_from_one() {
+        assert_eq!(parse_choice("1", &targets(), 1), Ok(0));
+        assert_eq!(parse_choice(" 2\n", &targets(), 0), Ok(1));
+        assert_eq!(parse_choice("3", &targets(), 0), Ok(2));
+    }
+
+    #[test]
+    fn numbers_outside_the_menu_are_refused() {
+        for answer in &["0", "4", "99999999999999999999"] {
+            assert_eq!(
+                parse_choice(answer, &targets(), 0),
+                Err(String::from("pick a number from 1 to 3")),
+                "{}",
+                answer
+            );
+        }
+    }
+
+    #[test]
+    fn names_and_aliases_pick() {
+        assert_eq!(
+            parse_choice("kubos-linux-rpi-cm3-gcc\n", &targets(), 0),
+            Ok(2)
+        );
+        assert_eq!(parse_choice("bb", &targets(), 0), Ok(1));
+        assert_eq!(parse_choice("native", &targets(), 1), Ok(0));
+    }
+
+    #[test]
+    fn anything_else_is_refused() {
+        for answer in &["-1", "1.5", "2 3", "beaglebone", "BB"] {
+            assert_eq!(
+                parse_choice(answer, &targets(), 0),
+                Err(format!("'{}' isn't one of the targets listed", answer)),
+                "{}",
+                answer
+            );
+        }
+    }
+
+    #[test]
+    fn only_yes_is_yes() {
+        for answer in &["y", "Y\n", " yes ", "YES"] {
+            assert!(parse_yes(answer), "{}", answer);
+        }
+        for answer in &["", "\n", "n", "no", "yep", "1"] {
+            assert!(!parse_yes(answer), "{}", answer);
+        }
+    }
+}
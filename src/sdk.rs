@@ -0,0 +1,172 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Noticing when cargo-kubos isn't running inside the Kubos SDK, and
+//! running the build in the SDK's Vagrant box instead, for `--sdk-vm`
+
+use crate::cache::ResolutionCache;
+use crate::toolchain::{find_in_path, sdk_dirs};
+use crate::{process, shell_quote};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Where the default Kubos SDK Vagrantfile syncs its directory to
+const DEFAULT_GUEST_DIR: &str = "/vagrant";
+
+/// Directories in the SDK's toolchain directory which only the SDK has
+const SDK_MARKERS: &[&str] = &["bbb_toolchain", "iobc_toolchain"];
+
+/// Whether cargo-kubos is running inside the Kubos SDK: `KUBOS_SDK` is
+/// set, or the SDK's toolchain directories are installed
+pub fn in_sdk() -> bool {
+    if env::var_os("KUBOS_SDK").is_some_and(|marker| !marker.is_empty()) {
+        return true;
+    }
+    sdk_dirs()
+        .iter()
+        .any(|dir| SDK_MARKERS.iter().any(|marker| dir.join(marker).is_dir()))
+}
+
+/// What to tell someone building outside the SDK without a toolchain
+pub fn guidance() -> &'static str {
+    "cargo-kubos isn't running in the Kubos SDK, which has the cross toolchains. \
+     Build inside its Vagrant box with `vagrant ssh`, or pass --sdk-vm (or set \
+     `sdk-vm = true` under [kubos] in your cargo config) to have cargo-kubos \
+     do that for you."
+}
+
+/// The SDK's Vagrant box and the folder synced into it, from
+/// `[kubos.vagrant]` in the cargo config
+#[derive(Debug)]
+pub struct Vagrant {
+    /// The directory with the Vagrantfile
+    dir: PathBuf,
+    /// The host folder which is synced into the box, `host`
+    host: PathBuf,
+    /// Where it appears in the box, `guest`
+    guest: String,
+}
+
+impl Vagrant {
+    /// Find the box: `[kubos.vagrant] dir`, `VAGRANT_CWD`, or the nearest
+    /// directory above this one with a Vagrantfile. Without `host` and
+    /// `guest`, the box's directory is synced to `/vagrant`, like the
+    /// default Vagrantfile does.
+    pub fn load(cache: &ResolutionCache) -> Result<Vagrant, String> {
+        let cwd = env::current_dir().map_err(|e| format!("current directory: {}", e))?;
+        let dir = match setting(cache, "dir")? {
+            Some(dir) => PathBuf::from(dir),
+            None => match env::var_os("VAGRANT_CWD").filter(|dir| !dir.is_empty()) {
+                Some(dir) => cwd.join(dir),
+                None => cwd
+                    .ancestors()
+                    .find(|dir| dir.join("Vagrantfile").is_file())
+                    .map(Path::to_path_buf)
+                    .ok_or_else(|| {
+                        String::from(
+                            "--sdk-vm needs the Kubos SDK's Vagrantfile, but there's none \
+                             here or above; set `dir` under [kubos.vagrant] in your cargo config",
+                        )
+                    })?,
+            },
+        };
+        // Or vagrant would seem not to be there when run from it
+        if !dir.is_dir() {
+            return Err(format!(
+                "{}, the SDK's Vagrant box directory, doesn't exist",
+                dir.display()
+            ));
+        }
+        let host = match setting(cache, "host")? {
+            Some(host) => PathBuf::from(host),
+            None => dir.clone(),
+        };
+        let guest = setting(cache, "guest")?.unwrap_or_else(|| String::from(DEFAULT_GUEST_DIR));
+        Ok(Vagrant { dir, host, guest })
+    }
+
+    /// Where a host path is in the box
+    fn guest_path(&self, path: &Path) -> Result<String, String> {
+        let host = self
+            .host
+            .canonicalize()
+            .unwrap_or_else(|_| self.host.clone());
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let relative = path.strip_prefix(&host).map_err(|_| {
+            format!(
+                "{} isn't in {}, the folder synced into the SDK box; set `host` and \
+                 `guest` under [kubos.vagrant] in your cargo config to map it",
+                path.display(),
+                host.display()
+            )
+        })?;
+        Ok(Path::new(&self.guest)
+            .join(relative)
+            .to_string_lossy()
+            .into_owned())
+    }
+
+    /// Run cargo-kubos with the arguments in the box, from where the current
+    /// directory is synced to, returning the exit status of the command.
+    /// `KUBOS_SDK` keeps it from trying to go into the box again.
+    pub fn run(&self, args: &[String]) -> Result<ExitStatus, String> {
+        let vagrant = find_in_path("vagrant")
+            .ok_or_else(|| String::from("--sdk-vm needs vagrant, which isn't on PATH"))?;
+        let cwd = env::current_dir().map_err(|e| format!("current directory: {}", e))?;
+        let args: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+        let script = format!(
+            "cd {} && KUBOS_SDK=1 cargo kubos {}",
+            shell_quote(&self.guest_path(&cwd)?),
+            args.join(" ")
+        );
+        info!("building in the Kubos SDK box at {}", self.dir.display());
+        log!(Debug, "running vagrant ssh -c {}", shell_quote(&script));
+        let mut command = Command::new(&vagrant);
+        command
+            .args(["ssh", "-c"])
+            .arg(&script)
+            .current_dir(&self.dir)
+            .stdin(Stdio::inherit());
+        process::run(&mut command, None)
+            .map_err(|e| process::spawn_error(&vagrant.to_string_lossy(), &e))
+    }
+}
+
+/// A string under `[kubos.vagrant]` from the nearest cargo config which
+/// sets it, with relative paths made relative to the config's project
+fn setting(cache: &ResolutionCache, key: &str) -> Result<Option<String>, String> {
+    let configs = cache.discover_configs().unwrap_or_default();
+    let found = configs.iter().find_map(|config| {
+        let value = config.value.get("kubos")?.get("vagrant")?.get(key)?;
+        Some((config, value))
+    });
+    match found {
+        Some((config, value)) => match value.as_str() {
+            // The guest path is in the box, so nothing to resolve
+            Some(value) if key == "guest" => Ok(Some(String::from(value))),
+            Some(value) => Ok(Some(
+                config.base_dir().join(value).to_string_lossy().into_owned(),
+            )),
+            None => Err(format!(
+                "{}: kubos.vagrant.{} must be a string",
+                config.path.display(),
+                key
+            )),
+        },
+        None => Ok(None),
+    }
+}
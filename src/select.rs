@@ -0,0 +1,190 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Choosing the Kubos targets to build for: from the command line, the
+//! environment, the project's files or the user's defaults, asking when
+//! none of them name one.
+
+use crate::deploy::DeployConfig;
+use crate::manifest::{find_manifest, manifest_target};
+use crate::project::{save_project_target, ProjectConfig};
+use crate::targets::{target_converter, Target, TargetError, TargetRegistry, X86_TARGET_STR};
+use crate::{defaults, prompt, ALL_TARGETS, TARGET_ENV_VAR};
+use getopts::Matches;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Where the selected Kubos target came from
+pub enum TargetSource {
+    Flag,
+    Env,
+    Project(PathBuf),
+    Manifest(PathBuf),
+    /// The deploy config, from the named profile if there is one
+    Deploy(Option<String>),
+    /// The user's defaults file
+    Defaults(PathBuf),
+    /// The user's answer when asked
+    Prompt,
+    Default,
+}
+
+impl fmt::Display for TargetSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetSource::Flag => write!(f, "command line flag"),
+            TargetSource::Env => write!(f, "{} environment variable", TARGET_ENV_VAR),
+            TargetSource::Project(path) => write!(f, "project config {}", path.display()),
+            TargetSource::Manifest(path) => write!(f, "manifest {}", path.display()),
+            TargetSource::Deploy(Some(profile)) => write!(f, "deploy profile {}", profile),
+            TargetSource::Deploy(None) => write!(f, "deploy config"),
+            TargetSource::Defaults(path) => write!(f, "user defaults {}", path.display()),
+            TargetSource::Prompt => write!(f, "prompt"),
+            TargetSource::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Work out which targets to build for from the command line flags,
+/// the deploy config, the KUBOS_TARGET environment variable, the
+/// project config, the crate manifest or the user's defaults. Failing
+/// those, the user is asked if they're there to ask.
+pub fn select_targets(
+    matches: &Matches,
+    targets: &TargetRegistry,
+    project: Option<&ProjectConfig>,
+    deploy: Option<&DeployConfig>,
+    extra_params: &[String],
+    interactive: bool,
+) -> Result<(Vec<Target>, TargetSource), TargetError> {
+    let k_targets = matches.opt_strs("t");
+    let triple = matches.opt_str("T");
+
+    if k_targets.iter().any(|k_target| k_target == ALL_TARGETS) {
+        // Rather than quietly dropping the others
+        let others: Vec<String> = k_targets
+            .iter()
+            .filter(|k_target| *k_target != ALL_TARGETS)
+            .map(|k_target| format!("-t {}", k_target))
+            .chain(triple.iter().map(|triple| format!("-T {}", triple)))
+            .collect();
+        if !others.is_empty() {
+            return Err(TargetError::AllWithOthers { others });
+        }
+        return Ok((targets.to_vec(), TargetSource::Flag));
+    }
+
+    if !k_targets.is_empty() {
+        let selected = k_targets
+            .iter()
+            .map(|k_target| {
+                let target = target_converter(targets, k_target)?.clone();
+                match triple {
+                    Some(ref triple) if *triple != target.triple => {
+                        Err(TargetError::TripleMismatch {
+                            name: target.name,
+                            triple: target.triple,
+                            requested: triple.clone(),
+                        })
+                    }
+                    _ => Ok(target),
+                }
+            })
+            .collect::<Result<Vec<Target>, TargetError>>()?;
+        return Ok((selected, TargetSource::Flag));
+    }
+
+    if let Some(triple) = triple {
+        let target = Target::new(&triple, &triple);
+        return Ok((vec![target], TargetSource::Flag));
+    }
+
+    if let Some(deploy) = deploy {
+        if let Some(ref k_target) = deploy.target {
+            let target = target_converter(targets, k_target)?.clone();
+            return Ok((vec![target], TargetSource::Deploy(deploy.profile.clone())));
+        }
+    }
+
+    let env_target = env::var(TARGET_ENV_VAR).ok().filter(|t| !t.is_empty());
+    let project_target = project.and_then(|p| p.target.clone().map(|t| (t, p.path.clone())));
+    let (k_target, source) = if let Some(k_target) = env_target {
+        (k_target, TargetSource::Env)
+    } else if let Some((k_target, path)) = project_target {
+        (k_target, TargetSource::Project(path))
+    } else {
+        match manifest_target(extra_params).map_err(TargetError::Config)? {
+            Some((k_target, path)) => (k_target, TargetSource::Manifest(path)),
+            None => match defaults::get().and_then(|d| d.target.clone().map(|t| (t, &d.path))) {
+                Some((k_target, path)) => (k_target, TargetSource::Defaults(path.clone())),
+                None if interactive => {
+                    let target = prompt_target(targets, project, extra_params)?;
+                    return Ok((vec![target], TargetSource::Prompt));
+                }
+                None => {
+                    info!(
+                        "no target was given or configured, building for {} \
+                         (pass -t, or set target in .cargo/kubos.toml)",
+                        X86_TARGET_STR
+                    );
+                    (String::from(X86_TARGET_STR), TargetSource::Default)
+                }
+            },
+        }
+    };
+
+    Ok((vec![target_converter(targets, &k_target)?.clone()], source))
+}
+
+/// Ask which target to build for, offering to save the answer in the
+/// project config so that it isn't asked again
+fn prompt_target(
+    targets: &[Target],
+    project: Option<&ProjectConfig>,
+    extra_params: &[String],
+) -> Result<Target, TargetError> {
+    let default = targets
+        .iter()
+        .position(|target| target.name == X86_TARGET_STR)
+        .unwrap_or(0);
+    let index = prompt::pick_target(targets, default)
+        .map_err(|e| TargetError::Config(format!("asking for a target: {}", e)))?;
+    let target = targets[index].clone();
+
+    // Next to the project config already there, or else the crate's manifest
+    let dir = project
+        .and_then(|p| p.path.parent().and_then(Path::parent))
+        .map(Path::to_path_buf)
+        .or_else(|| {
+            find_manifest(extra_params).and_then(|path| path.parent().map(Path::to_path_buf))
+        })
+        .or_else(|| env::current_dir().ok());
+    if let Some(dir) = dir {
+        let question = format!(
+            "Save {} as the target in {}?",
+            target.name,
+            dir.join(".cargo").join("kubos.toml").display()
+        );
+        if prompt::confirm(&question).unwrap_or(false) {
+            match save_project_target(&dir, &target.name) {
+                Ok(path) => info!("saved the target in {}", path.display()),
+                Err(e) => warning!("{}", e),
+            }
+        }
+    }
+    Ok(target)
+}
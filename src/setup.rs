@@ -0,0 +1,284 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos setup`, which generates the cargo config, a sourceable
+//! environment file and optionally a CI job for building a target, so
+//! projects don't copy them from one another and drift
+
+use crate::buildenv::BuildEnv;
+use crate::cache::ResolutionCache;
+use crate::init::{discover_linker, write_stanza};
+use crate::manifest::workspace_dir;
+use crate::targets::{target_converter, Target, TargetRegistry};
+use crate::toolchain::{cargo_linker, find_in_path};
+use crate::{container, prepare_build, shell_quote, BuildOptions};
+use getopts::Matches;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// Name of the environment file written to the project root
+pub const ENV_FILE: &str = "kubos-env.sh";
+
+/// The CI systems a job can be generated for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ci {
+    Github,
+    Gitlab,
+}
+
+impl Ci {
+    pub fn parse(name: &str) -> Result<Ci, String> {
+        match name {
+            "github" => Ok(Ci::Github),
+            "gitlab" => Ok(Ci::Gitlab),
+            _ => Err(format!(
+                "unknown CI system '{}', expected github or gitlab",
+                name
+            )),
+        }
+    }
+}
+
+/// Options for `setup`
+pub struct SetupOptions {
+    pub linker: Option<String>,
+    pub ci: Option<Ci>,
+    /// Print the files rather than writing them
+    pub stdout: bool,
+    pub force: bool,
+}
+
+/// The linker to write: the one given, or the discovered one. A gcc found
+/// on PATH is written by name, so the config works on other machines and
+/// in CI images which have it somewhere else.
+pub fn linker(target: &Target, options: &SetupOptions) -> Result<String, String> {
+    if let Some(ref linker) = options.linker {
+        return Ok(linker.clone());
+    }
+    let linker = discover_linker(target)?;
+    let path = Path::new(&linker);
+    Ok(match path.file_name() {
+        Some(name) if find_in_path(&name.to_string_lossy()).as_deref() == Some(path) => {
+            name.to_string_lossy().into_owned()
+        }
+        _ => linker,
+    })
+}
+
+/// The first lines of every generated file
+fn header(target: &Target) -> String {
+    format!(
+        "# Generated by cargo-kubos setup for {}. Rather than editing this,\n\
+         # rerun `cargo kubos setup -t {} --force`.\n",
+        target.name, target.name
+    )
+}
+
+/// Generate the files for the target under the project root, writing them
+/// or with `stdout` printing them, and return the cargo config written to.
+/// The environment is what cargo-kubos sets when building with the linker.
+pub fn run(
+    target: &Target,
+    linker: &str,
+    build_env: &BuildEnv,
+    root: &Path,
+    options: &SetupOptions,
+    cache: &ResolutionCache,
+) -> Result<Option<PathBuf>, String> {
+    // The comment goes after the header so it's replaced along with the section
+    let stanza = format!(
+        "[target.{}]\n{}linker = {}\n",
+        target.triple,
+        header(target),
+        Value::String(String::from(linker))
+    );
+    let mut files = vec![(root.join(ENV_FILE), env_file(target, build_env))];
+    if let Some(ci) = options.ci {
+        files.push(ci_job(target, ci, root, cache));
+    }
+
+    let config_dir = root.join(".cargo");
+    if options.stdout {
+        println!("# ==> {} <==", config_dir.join("config.toml").display());
+        print!("{}", stanza);
+        for (path, contents) in files {
+            println!("\n# ==> {} <==", path.display());
+            print!("{}", contents);
+        }
+        return Ok(None);
+    }
+
+    // Check everything first, so that nothing is written if anything would
+    // be overwritten
+    if !options.force {
+        if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+            return Err(format!(
+                "{} already exists, pass --force to replace it",
+                path.display()
+            ));
+        }
+    }
+    let config = write_stanza(&config_dir, &target.triple, &stanza, options.force)?;
+    println!("Wrote {}", config.display());
+    for (path, contents) in files {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        fs::write(&path, contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(Some(config))
+}
+
+/// A shell script exporting the variables cargo-kubos sets for the target,
+/// for builds which run cargo or make directly
+fn env_file(target: &Target, build_env: &BuildEnv) -> String {
+    let mut contents = header(target);
+    contents.push_str(&format!("# Source it with `. ./{}`\n", ENV_FILE));
+    for (key, value) in build_env.vars() {
+        contents.push_str(&format!("export {}={}\n", key, shell_quote(value)));
+    }
+    contents
+}
+
+/// A CI job building the target in release, in its container
+/// image when it has one, and where it's written
+fn ci_job(target: &Target, ci: Ci, root: &Path, cache: &ResolutionCache) -> (PathBuf, String) {
+    let image = container::image(target, cache).ok();
+    let steps = [
+        format!("rustup target add {}", target.triple),
+        String::from("cargo install cargo-kubos"),
+        format!("cargo kubos -c build -t {} -- --release", target.name),
+    ];
+    let mut job = header(target);
+    let path = match ci {
+        Ci::Github => {
+            job.push_str(&format!(
+                "name: {}\n\non: [push, pull_request]\n\njobs:\n  build:\n    \
+                 runs-on: ubuntu-latest\n",
+                target.name
+            ));
+            if let Some(image) = image {
+                job.push_str(&format!("    container: {}\n", image));
+            }
+            job.push_str("    steps:\n      - uses: actions/checkout@v4\n");
+            for step in &steps {
+                job.push_str(&format!("      - run: {}\n", step));
+            }
+            root.join(".github")
+                .join("workflows")
+                .join(format!("{}.yml", target.name))
+        }
+        Ci::Gitlab => {
+            job.push_str(&format!(
+                "# Add it to .gitlab-ci.yml with `include: .gitlab/{}.yml`\n\n{}:\n",
+                target.name, target.name
+            ));
+            if let Some(image) = image {
+                job.push_str(&format!("  image: {}\n", image));
+            }
+            job.push_str("  script:\n");
+            for step in &steps {
+                job.push_str(&format!("    - {}\n", step));
+            }
+            root.join(".gitlab").join(format!("{}.yml", target.name))
+        }
+    };
+    (path, job)
+}
+
+/// Run `cargo kubos setup`, returning the exit code
+pub fn command(matches: &Matches, targets: &TargetRegistry, options: &BuildOptions) -> i32 {
+    let target = match matches.opt_strs("t").as_slice() {
+        [k_target] => match target_converter(targets, k_target) {
+            Ok(target) => target,
+            Err(e) => {
+                error!("{}", e);
+                return e.exit_code();
+            }
+        },
+        _ => {
+            error!("setup needs exactly one target (-t)");
+            return 2;
+        }
+    };
+    let ci = match matches.opt_str("ci").map(|ci| Ci::parse(&ci)) {
+        Some(Ok(ci)) => Some(ci),
+        Some(Err(e)) => {
+            error!("{}", e);
+            return 2;
+        }
+        None => None,
+    };
+    let setup_options = SetupOptions {
+        linker: matches.opt_str("linker"),
+        ci,
+        stdout: matches.opt_present("stdout"),
+        force: matches.opt_present("force"),
+    };
+    let linker = match linker(target, &setup_options) {
+        Ok(linker) => linker,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+
+    // The environment is what building with the written linker would set
+    let written = Target {
+        linker: Some(linker.clone()),
+        ..target.clone()
+    };
+    let (_, build_env) = prepare_build(&written, vec![String::from("build")], vec![], options);
+    let root = workspace_dir(&[])
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let config = match run(
+        target,
+        &linker,
+        &build_env,
+        &root,
+        &setup_options,
+        &options.cache,
+    ) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+
+    // Something nearer, or the environment, may still take precedence.
+    // What was read before the config was written is out of date.
+    if let Some(config) = config {
+        let cache = ResolutionCache::with_given_configs(options.cache.given_configs().to_vec());
+        match cargo_linker(&target.triple, &cache) {
+            Ok(ref resolved) if resolved.path == linker => {}
+            Ok(resolved) => warning!(
+                "cargo-kubos resolves the linker for {} to {} from {}, \
+                 not the one written to {}",
+                target.triple,
+                resolved.path,
+                resolved.source,
+                config.display()
+            ),
+            Err(e) => warning!("{}", e),
+        }
+    }
+    0
+}
@@ -0,0 +1,282 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos size`, which reports the size of the binaries built for
+//! each target and how they've changed since the last report
+
+use crate::artifacts::{executables, file_size, stripped_size};
+use crate::build::{build_messages, strip_program};
+use crate::json::{self, json_string, Json};
+use crate::manifest::target_dir;
+use crate::targets::Target;
+use crate::{cargo_params, events, BuildOptions};
+use getopts::Matches;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Sizes of a binary in bytes
+#[derive(Clone, Copy)]
+pub struct Sizes {
+    pub size: u64,
+    pub stripped: u64,
+}
+
+/// The sizes recorded by the last report, keyed by target then binary
+/// name, read from and saved to `target/kubos/size-history.json`
+#[derive(Default)]
+pub struct History {
+    targets: BTreeMap<String, BTreeMap<String, Sizes>>,
+}
+
+impl History {
+    /// Read the history, which is empty if it hasn't been saved yet
+    pub fn load(path: &Path) -> Result<History, String> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(_) => return Ok(History::default()),
+        };
+        let invalid = |e: String| format!("{}: {}", path.display(), e);
+        let mut history = History::default();
+        let root = json::parse(&data).map_err(invalid)?;
+        let targets = match root {
+            Json::Object(targets) => targets,
+            _ => return Err(invalid(String::from("expected a JSON object"))),
+        };
+        for (target, binaries) in targets {
+            let binaries = match binaries {
+                Json::Object(binaries) => binaries,
+                _ => continue,
+            };
+            let entry = history.targets.entry(target).or_default();
+            for (name, sizes) in binaries {
+                let number = |key: &str| match sizes.get(key) {
+                    Some(Json::Number(n)) => Some(*n as u64),
+                    _ => None,
+                };
+                if let (Some(size), Some(stripped)) = (number("size"), number("stripped")) {
+                    entry.insert(name, Sizes { size, stripped });
+                }
+            }
+        }
+        Ok(history)
+    }
+
+    pub fn get(&self, target: &str, binary: &str) -> Option<Sizes> {
+        self.targets.get(target)?.get(binary).copied()
+    }
+
+    pub fn set(&mut self, target: &str, binary: &str, sizes: Sizes) {
+        self.targets
+            .entry(String::from(target))
+            .or_default()
+            .insert(String::from(binary), sizes);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let targets: Vec<String> = self
+            .targets
+            .iter()
+            .map(|(target, binaries)| {
+                let binaries: Vec<String> = binaries
+                    .iter()
+                    .map(|(name, sizes)| {
+                        format!(
+                            "{}:{{\"size\":{},\"stripped\":{}}}",
+                            json_string(name),
+                            sizes.size,
+                            sizes.stripped
+                        )
+                    })
+                    .collect();
+                format!("{}:{{{}}}", json_string(target), binaries.join(","))
+            })
+            .collect();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        fs::write(path, format!("{{{}}}\n", targets.join(",")))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// A binary's sizes and the change in its stripped size
+/// since the last report, if it was in that one
+pub struct Row {
+    pub binary: String,
+    pub sizes: Sizes,
+    pub delta: Option<i64>,
+}
+
+/// Print a table with a row per binary and a column per target, each
+/// cell giving the file size, the stripped size and its change
+pub fn print_table(targets: &[(String, Vec<Row>)]) {
+    let mut binaries: Vec<&str> = targets
+        .iter()
+        .flat_map(|(_, rows)| rows.iter().map(|row| row.binary.as_str()))
+        .collect();
+    binaries.sort();
+    binaries.dedup();
+
+    let cell = |target: &(String, Vec<Row>), binary: &str| match target
+        .1
+        .iter()
+        .find(|row| row.binary == binary)
+    {
+        Some(row) => {
+            let delta = match row.delta {
+                Some(delta) => format!(" ({:+})", delta),
+                None => String::from(" (new)"),
+            };
+            format!("{} / {}{}", row.sizes.size, row.sizes.stripped, delta)
+        }
+        None => String::from("-"),
+    };
+
+    let mut table: Vec<Vec<String>> = vec![];
+    let mut header = vec![String::from("binary")];
+    header.extend(targets.iter().map(|(name, _)| name.clone()));
+    table.push(header);
+    for binary in &binaries {
+        let mut line = vec![String::from(*binary)];
+        line.extend(targets.iter().map(|target| cell(target, binary)));
+        table.push(line);
+    }
+
+    let widths: Vec<usize> = (0..table[0].len())
+        .map(|column| {
+            table
+                .iter()
+                .map(|line| line[column].len())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    println!("size / stripped (change in stripped since the last report), in bytes");
+    for line in &table {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// Run `cargo kubos size`, building each target and reporting the sizes
+/// of its binaries, returning the exit code
+pub fn command(
+    matches: &Matches,
+    selected: &[Target],
+    command: &[String],
+    extra_params: &[String],
+    options: &BuildOptions,
+) -> i32 {
+    let max_size = match matches.opt_str("max-size").map(|max| max.parse::<u64>()) {
+        Some(Ok(max)) => Some(max),
+        Some(Err(_)) => {
+            error!("--max-size must be a number of bytes");
+            return 2;
+        }
+        None => None,
+    };
+    let params = cargo_params(command, extra_params);
+    let history_path = match target_dir(&params) {
+        Some(dir) => dir.join("kubos").join("size-history.json"),
+        None => {
+            error!("could not find the cargo target directory");
+            return 1;
+        }
+    };
+    let mut history = match History::load(&history_path) {
+        Ok(history) => history,
+        Err(e) => {
+            warning!("ignoring the size history: {}", e);
+            History::default()
+        }
+    };
+
+    let mut report = vec![];
+    let mut over_budget = vec![];
+    for target in selected {
+        let binaries =
+            match build_messages(target, command.to_vec(), extra_params.to_vec(), options) {
+                Ok(messages) => executables(&messages),
+                Err(code) => return code,
+            };
+        let strip = match strip_program(target, options) {
+            Ok(strip) => strip,
+            Err(e) => {
+                error!("{}", e);
+                return 1;
+            }
+        };
+
+        let mut rows = vec![];
+        for binary in binaries {
+            let name = binary
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let sizes = match file_size(&binary)
+                .and_then(|size| Ok((size, stripped_size(&strip, &binary)?)))
+            {
+                Ok((size, stripped)) => Sizes { size, stripped },
+                Err(e) => {
+                    error!("{}", e);
+                    return 1;
+                }
+            };
+            let stripped = sizes.stripped;
+            let delta = history
+                .get(&target.name, &name)
+                .map(|last| stripped as i64 - last.stripped as i64);
+            history.set(&target.name, &name, sizes);
+            events::Event::new("size")
+                .string("target", &target.name)
+                .string("binary", &name)
+                .number("size", sizes.size)
+                .number("stripped", stripped)
+                .opt_number("delta", delta)
+                .emit();
+            if max_size.is_some_and(|max| stripped > max) {
+                over_budget.push(format!("{} for {} ({} bytes)", name, target.name, stripped));
+            }
+            rows.push(Row {
+                binary: name,
+                sizes,
+                delta,
+            });
+        }
+        report.push((target.name.clone(), rows));
+    }
+
+    if !events::json() {
+        print_table(&report);
+    }
+    if let Err(e) = history.save(&history_path) {
+        warning!("could not save the size history: {}", e);
+    }
+    if !over_budget.is_empty() {
+        error!(
+            "stripped binaries over the {} byte limit: {}",
+            max_size.unwrap_or(0),
+            over_budget.join(", ")
+        );
+        return 1;
+    }
+    0
+}
@@ -0,0 +1,610 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::config::home_dir;
+use crate::error;
+use crate::project::project_dir;
+use crate::sha256::Sha256;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+use toml::Value;
+
+pub const X86_TARGET_STR: &str = "x86-linux-native";
+
+/// A built-in Kubos target
+#[derive(Clone, Copy, Debug)]
+pub struct TargetSpec {
+    /// Kubos target name
+    pub name: &'static str,
+    /// Rust/Clang target triplet
+    pub triple: &'static str,
+    /// Names its cross gcc is commonly installed under
+    pub gcc: &'static [&'static str],
+    /// Short names which can be used instead of the full target name
+    pub aliases: &'static [&'static str],
+    /// Default C compiler flags for the board's processor
+    pub cflags: &'static [&'static str],
+}
+
+impl TargetSpec {
+    /// The target as built, before any user-defined overrides
+    fn target(&self) -> Target {
+        let strings = |items: &[&str]| items.iter().map(|item| String::from(*item)).collect();
+        Target {
+            name: String::from(self.name),
+            triple: String::from(self.triple),
+            linker: None,
+            rustflags: vec![],
+            cflags: strings(self.cflags),
+            gcc: strings(self.gcc),
+            aliases: strings(self.aliases),
+        }
+    }
+}
+
+const ARMV7_CFLAGS: &[&str] = &["-march=armv7-a", "-mfpu=neon", "-mfloat-abi=hard"];
+
+/// The built-in Kubos targets, in the order they're listed
+const BUILTIN_TARGETS: &[TargetSpec] = &[
+    TargetSpec {
+        name: X86_TARGET_STR,
+        triple: "x86_64-unknown-linux-gnu",
+        gcc: &[],
+        aliases: &["native"],
+        cflags: &[],
+    },
+    TargetSpec {
+        name: "x86-linux-native-32",
+        triple: "i686-unknown-linux-gnu",
+        gcc: &["i686-linux-gnu-gcc"],
+        aliases: &[],
+        cflags: &[],
+    },
+    TargetSpec {
+        name: "kubos-linux-beaglebone-gcc",
+        triple: "arm-unknown-linux-gnueabihf",
+        gcc: &["arm-linux-gnueabihf-gcc", "arm-none-linux-gnueabihf-gcc"],
+        aliases: &["bb"],
+        cflags: ARMV7_CFLAGS,
+    },
+    TargetSpec {
+        name: "kubos-linux-pumpkin-mbm2-gcc",
+        triple: "arm-unknown-linux-gnueabihf",
+        gcc: &["arm-linux-gnueabihf-gcc", "arm-none-linux-gnueabihf-gcc"],
+        aliases: &["mbm2"],
+        cflags: ARMV7_CFLAGS,
+    },
+    TargetSpec {
+        name: "kubos-linux-isis-gcc",
+        triple: "armv5te-unknown-linux-gnueabi",
+        gcc: &[
+            "armv5te-linux-gnueabi-gcc",
+            "arm-linux-gnueabi-gcc",
+            "arm-none-linux-gnueabi-gcc",
+        ],
+        aliases: &["isis"],
+        cflags: &["-march=armv5te", "-mfloat-abi=soft"],
+    },
+    TargetSpec {
+        name: "kubos-linux-rpi-cm3-gcc",
+        triple: "aarch64-unknown-linux-gnu",
+        gcc: &["aarch64-linux-gnu-gcc", "aarch64-none-linux-gnu-gcc"],
+        aliases: &[],
+        cflags: &[],
+    },
+    TargetSpec {
+        name: "kubos-linux-rpi-zero2-gcc",
+        triple: "aarch64-unknown-linux-gnu",
+        gcc: &["aarch64-linux-gnu-gcc", "aarch64-none-linux-gnu-gcc"],
+        aliases: &[],
+        cflags: &[],
+    },
+    TargetSpec {
+        name: "kubos-linux-beaglebone-musl",
+        triple: "arm-unknown-linux-musleabihf",
+        gcc: &["arm-linux-musleabihf-gcc"],
+        aliases: &[],
+        cflags: &[],
+    },
+    TargetSpec {
+        name: "kubos-linux-isis-musl",
+        triple: "armv5te-unknown-linux-musleabi",
+        gcc: &["armv5te-linux-musleabi-gcc", "arm-linux-musleabi-gcc"],
+        aliases: &[],
+        cflags: &[],
+    },
+    TargetSpec {
+        name: "kubos-linux-riscv64-gcc",
+        triple: "riscv64gc-unknown-linux-gnu",
+        gcc: &["riscv64-linux-gnu-gcc", "riscv64-unknown-linux-gnu-gcc"],
+        aliases: &[],
+        cflags: &[],
+    },
+];
+
+/// File name of the project-local target mappings
+const LOCAL_TARGETS_FILE: &str = "kubos-targets.toml";
+
+/// A Kubos target which can be built for
+#[derive(Clone, Debug)]
+pub struct Target {
+    /// Kubos target name
+    pub name: String,
+    /// Rust/Clang target triplet
+    pub triple: String,
+    /// Linker to use instead of the one from the cargo config
+    pub linker: Option<String>,
+    /// Extra Rust flags to build this target with
+    pub rustflags: Vec<String>,
+    /// Extra flags for the C/C++ compilers used by build scripts
+    pub cflags: Vec<String>,
+    /// Names of the cross gcc to search PATH for when no linker is configured
+    pub gcc: Vec<String>,
+    /// Short names which can be used instead of the full target name
+    pub aliases: Vec<String>,
+}
+
+impl Target {
+    /// Create a target with no user-defined overrides, with the
+    /// gcc names, aliases and C flags of the built-in target of the name
+    pub fn new(name: &str, triple: &str) -> Self {
+        let mut target = match BUILTIN_TARGETS.iter().find(|spec| spec.name == name) {
+            Some(spec) => spec.target(),
+            None => Target {
+                name: String::from(name),
+                triple: String::new(),
+                linker: None,
+                rustflags: vec![],
+                cflags: vec![],
+                gcc: vec![],
+                aliases: vec![],
+            },
+        };
+        target.triple = String::from(triple);
+        target
+    }
+}
+
+/// The Kubos targets which can be built for, in a stable order: the
+/// built-in ones as listed, then those the target mapping files add in
+/// the order they're read. It derefs to the targets as a slice.
+#[derive(Clone, Debug)]
+pub struct TargetRegistry {
+    targets: Vec<Target>,
+}
+
+impl TargetRegistry {
+    /// The built-in targets, without any user-defined ones
+    pub fn builtin() -> Self {
+        TargetRegistry {
+            targets: BUILTIN_TARGETS.iter().map(TargetSpec::target).collect(),
+        }
+    }
+
+    /// The built-in targets along with any user-defined targets from
+    /// `~/.kubos/targets.toml` and `kubos-targets.toml` in the project
+    /// directory. User-defined targets take precedence over the built-in
+    /// ones, and project-local targets take precedence over the user's.
+    pub fn load() -> Result<Self, String> {
+        let mut registry = TargetRegistry::builtin();
+        for path in user_target_files() {
+            if !path.is_file() {
+                log!(Trace, "target file {}: not found", path.display());
+            } else {
+                log!(Trace, "target file {}: found", path.display());
+                let data =
+                    fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                registry.merge_toml(&data, &path)?;
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Add the `[targets."name"]` entries of a target mappings file, read
+    /// from the path, over the targets there are so far
+    pub fn merge_toml(&mut self, data: &str, path: &Path) -> Result<(), String> {
+        let targets = parse_targets(data, path)?;
+        self.merge(targets, path);
+        Ok(())
+    }
+
+    /// Add the targets, those with the name of one there is already
+    /// replacing it but keeping its aliases, C flags and gcc names
+    /// unless new ones are given
+    pub fn merge(&mut self, targets: Vec<Target>, origin: &Path) {
+        for mut target in targets {
+            match self.targets.iter_mut().find(|t| t.name == target.name) {
+                Some(existing) => {
+                    log!(
+                        Debug,
+                        "target {} is redefined by {}",
+                        target.name,
+                        origin.display()
+                    );
+                    if target.aliases.is_empty() {
+                        target.aliases = existing.aliases.clone();
+                    }
+                    if target.cflags.is_empty() {
+                        target.cflags = existing.cflags.clone();
+                    }
+                    if target.gcc.is_empty() {
+                        target.gcc = existing.gcc.clone();
+                    }
+                    *existing = target
+                }
+                None => self.targets.push(target),
+            }
+        }
+    }
+
+    /// Look a target up by its name, or failing that an alias only one
+    /// target has
+    pub fn resolve(&self, kubos_target: &str) -> Result<&Target, TargetError> {
+        if let Some(target) = self
+            .targets
+            .iter()
+            .find(|target| target.name == kubos_target)
+        {
+            log!(Debug, "target {} is {}", kubos_target, target.triple);
+            return Ok(target);
+        }
+
+        let mut matches = self
+            .targets
+            .iter()
+            .filter(|target| target.aliases.iter().any(|alias| alias == kubos_target));
+        match (matches.next(), matches.next()) {
+            (Some(target), None) => {
+                log!(
+                    Debug,
+                    "target {} is an alias of {} ({})",
+                    kubos_target,
+                    target.name,
+                    target.triple
+                );
+                Ok(target)
+            }
+            (Some(first), Some(second)) => Err(TargetError::Ambiguous {
+                name: String::from(kubos_target),
+                matches: [first, second]
+                    .iter()
+                    .copied()
+                    .chain(matches)
+                    .map(|t| t.name.clone())
+                    .collect(),
+                supported: self.supported_names(),
+            }),
+            _ => Err(TargetError::Unknown {
+                name: String::from(kubos_target),
+                suggestions: self.closest_targets(kubos_target),
+                supported: self.supported_names(),
+            }),
+        }
+    }
+
+    /// The targets' names, in order
+    pub fn names(&self) -> Vec<&str> {
+        self.targets
+            .iter()
+            .map(|target| target.name.as_str())
+            .collect()
+    }
+
+    /// Names of the targets, along with their aliases, for listing them
+    fn supported_names(&self) -> Vec<String> {
+        self.targets
+            .iter()
+            .map(|target| {
+                if target.aliases.is_empty() {
+                    target.name.clone()
+                } else {
+                    format!("{} ({})", target.name, target.aliases.join(", "))
+                }
+            })
+            .collect()
+    }
+
+    /// Find the target names closest to a mistyped one, every
+    /// one of them if several are as close, in order
+    fn closest_targets(&self, kubos_target: &str) -> Vec<String> {
+        // Only suggest names which are a plausible typo away
+        let max_distance = (kubos_target.chars().count() / 3).max(1);
+
+        let distances: Vec<(usize, &String)> = self
+            .targets
+            .iter()
+            .map(|target| (edit_distance(&target.name, kubos_target), &target.name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect();
+        let closest = match distances.iter().map(|(distance, _)| *distance).min() {
+            Some(closest) => closest,
+            None => return vec![],
+        };
+        distances
+            .into_iter()
+            .filter(|(distance, _)| *distance == closest)
+            .map(|(_, name)| name.clone())
+            .collect()
+    }
+}
+
+impl Deref for TargetRegistry {
+    type Target = [Target];
+
+    fn deref(&self) -> &[Target] {
+        &self.targets
+    }
+}
+
+impl From<TargetRegistry> for Vec<Target> {
+    fn from(registry: TargetRegistry) -> Self {
+        registry.targets
+    }
+}
+
+/// A short digest of the built-in target table, so it can be told
+/// whether two builds of cargo-kubos map targets the same way
+pub fn builtin_table_digest() -> String {
+    let mut digest = Sha256::new();
+    for target in TargetRegistry::builtin().iter() {
+        let line = format!(
+            "{} {} gcc={} aliases={} cflags={}\n",
+            target.name,
+            target.triple,
+            target.gcc.join(","),
+            target.aliases.join(","),
+            target.cflags.join(",")
+        );
+        digest.update(line.as_bytes());
+    }
+    digest.finish()[..12].to_owned()
+}
+
+/// Locations of user-defined target mappings, lowest precedence first
+fn user_target_files() -> Vec<PathBuf> {
+    let mut files = vec![];
+    if let Some(home) = home_dir() {
+        files.push(home.join(".kubos").join("targets.toml"));
+    }
+    files.push(match project_dir() {
+        Some(dir) => dir.join(LOCAL_TARGETS_FILE),
+        None => PathBuf::from(LOCAL_TARGETS_FILE),
+    });
+    files
+}
+
+/// Parse the `[targets."name"]` entries of a target mappings file
+/// read from the path
+fn parse_targets(data: &str, path: &Path) -> Result<Vec<Target>, String> {
+    let cfg = data
+        .parse::<Value>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let entries = match cfg.get("targets") {
+        Some(entries) => entries
+            .as_table()
+            .ok_or_else(|| format!("{}: targets must be a table", path.display()))?,
+        None => return Ok(vec![]),
+    };
+
+    entries
+        .iter()
+        .map(|(name, entry)| {
+            let triple = entry
+                .get("triple")
+                .and_then(Value::as_str)
+                .ok_or_else(|| format!("{}: target {} has no triple", path.display(), name))?;
+            let linker = match entry.get("linker") {
+                Some(linker) => Some(linker.as_str().map(String::from).ok_or_else(|| {
+                    format!(
+                        "{}: linker for target {} must be a string",
+                        path.display(),
+                        name
+                    )
+                })?),
+                None => None,
+            };
+            let rustflags = flags_value(path, name, entry, "rustflags")?;
+            let cflags = flags_value(path, name, entry, "cflags")?;
+            let gcc = flags_value(path, name, entry, "gcc")?;
+            let aliases = match entry.get("aliases") {
+                Some(aliases) => aliases
+                    .as_array()
+                    .and_then(|aliases| {
+                        aliases
+                            .iter()
+                            .map(|alias| alias.as_str().map(String::from))
+                            .collect::<Option<Vec<String>>>()
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "{}: aliases for target {} must be an array of strings",
+                            path.display(),
+                            name
+                        )
+                    })?,
+                None => vec![],
+            };
+
+            Ok(Target {
+                name: name.clone(),
+                triple: String::from(triple),
+                linker,
+                rustflags,
+                cflags,
+                gcc,
+                aliases,
+            })
+        })
+        .collect()
+}
+
+/// Read a list of flags from a target entry, which may be
+/// a space-separated string or an array of strings
+fn flags_value(path: &Path, name: &str, entry: &Value, key: &str) -> Result<Vec<String>, String> {
+    match entry.get(key) {
+        Some(Value::String(flags)) => Ok(flags.split_whitespace().map(String::from).collect()),
+        Some(Value::Array(flags)) => flags
+            .iter()
+            .map(|flag| flag.as_str().map(String::from))
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| {
+                format!(
+                    "{}: {} for target {} must be strings",
+                    path.display(),
+                    key,
+                    name
+                )
+            }),
+        Some(_) => Err(format!(
+            "{}: {} for target {} must be a string or array",
+            path.display(),
+            key,
+            name
+        )),
+        None => Ok(vec![]),
+    }
+}
+
+/// Errors which can occur while resolving a Kubos target
+#[derive(Debug)]
+pub enum TargetError {
+    /// The target name isn't known
+    Unknown {
+        /// The name given
+        name: String,
+        /// Known targets with similar names, closest first
+        suggestions: Vec<String>,
+        /// The names of the known targets
+        supported: Vec<String>,
+    },
+    /// An alias matches more than one target
+    Ambiguous {
+        /// The alias given
+        name: String,
+        /// The targets it matches
+        matches: Vec<String>,
+        /// The names of the known targets
+        supported: Vec<String>,
+    },
+    /// A Kubos target and raw triple were both given but don't agree
+    TripleMismatch {
+        /// The Kubos target
+        name: String,
+        /// Its triple
+        triple: String,
+        /// The triple given
+        requested: String,
+    },
+    /// `-t all` was given along with other targets
+    AllWithOthers {
+        /// The other targets given, as the flags giving them
+        others: Vec<String>,
+    },
+    /// The default target couldn't be read
+    Config(String),
+}
+
+impl std::error::Error for TargetError {}
+
+impl TargetError {
+    /// Exit code to use when this error aborts cargo-kubos
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TargetError::Config(_) => error::ENVIRONMENT_CODE,
+            _ => error::USAGE_CODE,
+        }
+    }
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetError::Unknown {
+                name,
+                suggestions,
+                supported,
+            } => {
+                write!(f, "Target '{}' not supported for cargo/yotta builds", name)?;
+                match suggestions.as_slice() {
+                    [] => {}
+                    [suggestion] => write!(f, "\nDid you mean '{}'?", suggestion)?,
+                    suggestions => {
+                        write!(f, "\nDid you mean one of '{}'?", suggestions.join("', '"))?
+                    }
+                }
+                write!(
+                    f,
+                    "\nCurrently supported targets are:\n{}",
+                    supported.join("\n")
+                )
+            }
+            TargetError::Ambiguous {
+                name,
+                matches,
+                supported,
+            } => write!(
+                f,
+                "Target alias '{}' is ambiguous, it could mean any of: {}\
+                 \nCurrently supported targets are:\n{}",
+                name,
+                matches.join(", "),
+                supported.join("\n")
+            ),
+            TargetError::TripleMismatch {
+                name,
+                triple,
+                requested,
+            } => write!(
+                f,
+                "Target '{}' maps to triple '{}', which conflicts with --target-triple '{}'",
+                name, triple, requested
+            ),
+            TargetError::AllWithOthers { others } => write!(
+                f,
+                "-t all builds every target, so can't be given along with {}",
+                others.join(", ")
+            ),
+            TargetError::Config(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Take a kubos target name or alias and convert
+/// it to a Rust/Clang target triplet
+pub fn target_converter<'a>(
+    targets: &'a TargetRegistry,
+    kubos_target: &str,
+) -> Result<&'a Target, TargetError> {
+    targets.resolve(kubos_target)
+}
+
+/// Levenshtein distance between two strings
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur.push(substitute.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
@@ -0,0 +1,440 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Finding the toolchains targets are built with: the cross gcc a target
+//! is linked with, wherever it's configured or installed, the tools next
+//! to it, and the Rust toolchain cargo runs with.
+
+use crate::cache::ResolutionCache;
+use crate::config::{config_linker, Linker, LinkerError, LinkerSource};
+use crate::targets::Target;
+use crate::{compat, defaults};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Once;
+
+/// Whether the given Rust target triplet uses the musl libc
+pub fn is_musl(target: &str) -> bool {
+    target.contains("musl")
+}
+
+/// Suffixes Windows runs a program by without them being named
+#[cfg(windows)]
+const EXE_SUFFIXES: &[&str] = &[".exe", ".bat", ".cmd"];
+
+/// The file a path to a program names. On Windows that's the path with the
+/// first suffix it runs by that exists, unless the path already has one.
+#[cfg(windows)]
+pub fn find_executable(path: &Path) -> Option<PathBuf> {
+    let name = path.to_string_lossy().to_lowercase();
+    if EXE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+        return Some(path.to_path_buf()).filter(|path| path.is_file());
+    }
+    EXE_SUFFIXES
+        .iter()
+        .map(|suffix| {
+            let mut candidate = path.as_os_str().to_owned();
+            candidate.push(suffix);
+            PathBuf::from(candidate)
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+/// The file a path to a program names
+#[cfg(not(windows))]
+pub fn find_executable(path: &Path) -> Option<PathBuf> {
+    Some(path.to_path_buf()).filter(|path| path.is_file())
+}
+
+/// Look for an executable with the given name in the directories on PATH
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths).find_map(|dir| find_executable(&dir.join(name)))
+}
+
+/// Find a program either by its path or by searching PATH for its name
+pub fn resolve_program(program: &str) -> Option<PathBuf> {
+    if program.contains(std::path::is_separator) {
+        return find_executable(Path::new(program));
+    }
+    find_in_path(program)
+}
+
+/// Whether the file at the given path can be executed
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Whether the file at the given path can be executed
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Conventional names of the cross gcc for a target triplet,
+/// e.g. arm-unknown-linux-gnueabihf -> arm-linux-gnueabihf-gcc
+fn gcc_names(target: &str) -> Vec<String> {
+    let mut parts: Vec<&str> = target.split('-').collect();
+    parts.retain(|part| *part != "unknown");
+    let mut names = vec![format!("{}-gcc", parts.join("-"))];
+
+    // Toolchains are named for the architecture family rather than the
+    // specific variant, e.g. armv5te -> arm
+    let arch = parts[0];
+    let family = if arch.starts_with("arm") {
+        "arm"
+    } else if arch.starts_with("riscv64") {
+        "riscv64"
+    } else {
+        arch
+    };
+    if family != arch {
+        parts[0] = family;
+        names.push(format!("{}-gcc", parts.join("-")));
+    }
+
+    names
+}
+
+/// Cross toolchains installed in the Kubos SDK, by target triplet,
+/// relative to the SDK toolchain directory
+const SDK_TOOLCHAINS: &[(&str, &str)] = &[
+    (
+        "arm-unknown-linux-gnueabihf",
+        "bbb_toolchain/usr/bin/arm-linux-gcc",
+    ),
+    (
+        "armv5te-unknown-linux-gnueabi",
+        "iobc_toolchain/usr/bin/arm-linux-gcc",
+    ),
+];
+
+/// Where the Kubos SDK installs its toolchains
+const SDK_TOOLCHAIN_DIR: &str = "/usr/bin";
+
+/// Directories to look for Kubos SDK toolchains in,
+/// overridable with a PATH-style `KUBOS_SDK_PATH`
+pub fn sdk_dirs() -> Vec<PathBuf> {
+    match env::var_os("KUBOS_SDK_PATH") {
+        Some(ref paths) if !paths.is_empty() => env::split_paths(paths).collect(),
+        _ => vec![PathBuf::from(SDK_TOOLCHAIN_DIR)],
+    }
+}
+
+/// Find the Kubos SDK's cross gcc for a target, if it's installed
+pub fn sdk_toolchain(target: &str) -> Option<PathBuf> {
+    let names = gcc_names(target);
+    sdk_dirs().iter().find_map(|dir| {
+        SDK_TOOLCHAINS
+            .iter()
+            .filter(|(triple, _)| *triple == target)
+            .map(|(_, path)| dir.join(path))
+            .chain(names.iter().map(|name| dir.join(name)))
+            .find_map(|path| {
+                let found = find_executable(&path);
+                log!(
+                    Trace,
+                    "SDK toolchain {}: {}",
+                    path.display(),
+                    if found.is_some() {
+                        "found"
+                    } else {
+                        "not found"
+                    }
+                );
+                found
+            })
+    })
+}
+
+/// The names the cross gcc for a target may have: those listed
+/// for the target or, failing that, the conventional ones for its triplet
+fn target_gcc_names(target: &Target) -> Vec<String> {
+    if target.gcc.is_empty() {
+        gcc_names(&target.triple)
+    } else {
+        target.gcc.clone()
+    }
+}
+
+/// Look for the cross gcc for a target in the toolchain paths
+/// of the user's defaults
+fn defaults_toolchain(target: &Target) -> Option<Linker> {
+    let defaults = defaults::get()?;
+    let names = target_gcc_names(target);
+    let gcc = defaults.toolchain_paths.iter().find_map(|dir| {
+        names.iter().map(|name| dir.join(name)).find_map(|path| {
+            let found = find_executable(&path);
+            log!(
+                Trace,
+                "toolchain {}: {}",
+                path.display(),
+                if found.is_some() {
+                    "found"
+                } else {
+                    "not found"
+                }
+            );
+            found
+        })
+    })?;
+    Some(Linker::new(
+        &gcc.to_string_lossy(),
+        LinkerSource::Defaults(defaults.path.clone()),
+    ))
+}
+
+/// Search PATH for the cross gcc for a target, trying the names listed
+/// for the target or, failing that, the conventional ones for its triplet
+pub fn find_gcc(target: &Target) -> Option<PathBuf> {
+    target_gcc_names(target).iter().find_map(|name| {
+        let found = find_in_path(name);
+        log!(
+            Trace,
+            "{} on PATH: {}",
+            name,
+            match found {
+                Some(ref path) => path.display().to_string(),
+                None => String::from("not found"),
+            }
+        );
+        found
+    })
+}
+
+/// Binutils which are exported alongside the cross compiler
+const BINUTILS: &[(&str, &str)] = &[
+    ("AR", "ar"),
+    ("RANLIB", "ranlib"),
+    ("STRIP", "strip"),
+    ("OBJCOPY", "objcopy"),
+];
+
+/// Find the binutils which sit next to a cross gcc and share its prefix,
+/// e.g. /usr/bin/arm-linux-gnueabihf-gcc -> /usr/bin/arm-linux-gnueabihf-ar
+pub fn toolchain_binutils(linker: &Linker) -> Vec<(&'static str, PathBuf)> {
+    BINUTILS
+        .iter()
+        .filter_map(|(var, tool)| toolchain_tool(linker, tool).map(|path| (*var, path)))
+        .collect()
+}
+
+/// The prefix of the tools in the linker's toolchain, e.g.
+/// `arm-linux-gnueabihf-` for `arm-linux-gnueabihf-gcc`
+pub fn toolchain_prefix(linker: &Linker) -> Option<String> {
+    let name = Path::new(linker.program())
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = name.strip_suffix(env::consts::EXE_SUFFIX).unwrap_or(&name);
+    match name.strip_suffix("gcc").or_else(|| name.strip_suffix("cc")) {
+        Some(prefix) if !prefix.is_empty() => Some(String::from(prefix)),
+        _ => None,
+    }
+}
+
+/// Find another tool from the linker's toolchain by its prefix, e.g.
+/// `arm-linux-gnueabihf-gdb` for `arm-linux-gnueabihf-gcc`
+pub fn toolchain_tool(linker: &Linker, tool: &str) -> Option<PathBuf> {
+    let program = resolve_program(linker.program())?;
+    let prefix = toolchain_prefix(linker)?;
+    let dir = program.parent().unwrap_or_else(|| Path::new(""));
+
+    // Binutils may be installed in a different PATH directory to the gcc
+    let name = format!("{}{}", prefix, tool);
+    find_executable(&dir.join(&name)).or_else(|| find_in_path(&name))
+}
+
+/// The linker named by a Buildroot/Yocto style `CROSS_COMPILE` prefix,
+/// if that gcc exists
+fn cross_compile_linker() -> Option<Linker> {
+    let prefix = env::var("CROSS_COMPILE").ok().filter(|p| !p.is_empty())?;
+    let gcc = resolve_program(&format!("{}gcc", prefix))?;
+    Some(Linker::new(
+        &gcc.to_string_lossy(),
+        LinkerSource::CrossCompile,
+    ))
+}
+
+/// Warn once if `CROSS_COMPILE` names a different gcc to the configured linker
+fn check_cross_compile(target: &str, linker: &Linker) {
+    static WARNED: Once = Once::new();
+    let cross = match cross_compile_linker() {
+        Some(cross) => cross,
+        None => return,
+    };
+    if resolve_program(linker.program()) != resolve_program(&cross.path) {
+        WARNED.call_once(|| {
+            warning!(
+                "CROSS_COMPILE names {}, but {} is configured for {} in {}, \
+                 which will be used instead",
+                cross.path,
+                linker,
+                target,
+                linker.source
+            )
+        });
+    }
+}
+
+/// Resolve the linker for a target triplet from the cargo
+/// config, falling back to `CROSS_COMPILE`
+pub fn cargo_linker(target: &str, cache: &ResolutionCache) -> Result<Linker, LinkerError> {
+    let err = match config_linker(cache, target) {
+        Ok(linker) => {
+            check_cross_compile(target, &linker);
+            return Ok(linker);
+        }
+        Err(err @ LinkerError::NotSet { .. }) => err,
+        Err(err) => return Err(err),
+    };
+    cross_compile_linker().ok_or(err)
+}
+
+/// Look for a cross gcc for a target which has no linker configured: first
+/// in the Kubos SDK, then the toolchain paths of the user's defaults, then
+/// on PATH, then a musl gcc wrapper for musl targets
+fn discover_toolchain(target: &Target) -> Option<Linker> {
+    if let Some(path) = sdk_toolchain(&target.triple) {
+        return Some(Linker::new(&path.to_string_lossy(), LinkerSource::Sdk));
+    }
+    if let Some(linker) = defaults_toolchain(target) {
+        return Some(linker);
+    }
+    find_gcc(target)
+        .or_else(|| {
+            if is_musl(&target.triple) {
+                find_in_path("musl-gcc")
+            } else {
+                None
+            }
+        })
+        .map(|path| Linker::new(&path.to_string_lossy(), LinkerSource::Path))
+}
+
+/// Ask a gcc-style linker for its sysroot
+pub fn linker_sysroot(linker: &Linker, cache: &ResolutionCache) -> Option<String> {
+    compat::probe(linker, cache).sysroot
+}
+
+/// Resolve the linker which would be used for the given target, optionally
+/// discovering an installed toolchain if none is configured.
+/// The linker may include arguments, e.g. `gcc -m32`, which are
+/// passed along as part of `CC`/`CXX`.
+pub fn target_linker(
+    target: &Target,
+    discover: bool,
+    cache: &ResolutionCache,
+) -> Result<Linker, LinkerError> {
+    if let Some(ref linker) = target.linker {
+        return Ok(Linker::new(linker, LinkerSource::TargetMapping));
+    }
+    match cargo_linker(&target.triple, cache) {
+        Err(err @ LinkerError::NotSet { .. }) if discover && !is_host_triple(&target.triple) => {
+            log!(Debug, "{}, looking for a toolchain", err);
+            discover_toolchain(target).ok_or(err)
+        }
+        result => result,
+    }
+}
+
+/// Find the sysroot of the active Rust toolchain
+pub fn rust_sysroot() -> Option<PathBuf> {
+    let output = Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let sysroot = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    Some(PathBuf::from(sysroot))
+}
+
+/// Find the host triple of the active Rust toolchain
+pub fn host_triple() -> Option<String> {
+    let output = Command::new("rustc").arg("-vV").output().ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(String::from)
+}
+
+/// Whether a target triple matches the machine cargo-kubos is running on
+pub fn is_host_triple(triple: &str) -> bool {
+    triple.starts_with(&format!("{}-", env::consts::ARCH)) && triple.contains(env::consts::OS)
+}
+
+/// Pin the rustup toolchain for cargo, rustc and rustup themselves by
+/// setting `RUSTUP_TOOLCHAIN`, which every child inherits. Without a
+/// toolchain, whatever `RUSTUP_TOOLCHAIN` we were started with (as by
+/// `cargo +nightly kubos`) is passed along as it is.
+pub fn select_toolchain(toolchain: Option<String>) -> Result<(), String> {
+    let inherited = env::var("RUSTUP_TOOLCHAIN").ok();
+    let toolchain = match toolchain {
+        Some(toolchain) => toolchain,
+        None => {
+            if let Some(inherited) = inherited {
+                log!(Debug, "using toolchain {} from RUSTUP_TOOLCHAIN", inherited);
+            }
+            return Ok(());
+        }
+    };
+    if toolchain.is_empty() {
+        return Err(String::from("no toolchain name given after +"));
+    }
+    if find_in_path("rustup").is_none() {
+        return Err(format!(
+            "toolchain '{}' was requested but rustup isn't installed",
+            toolchain
+        ));
+    }
+    match inherited {
+        Some(ref inherited) if *inherited != toolchain => log!(
+            Debug,
+            "using toolchain {} instead of {} from RUSTUP_TOOLCHAIN",
+            toolchain,
+            inherited
+        ),
+        _ => log!(Debug, "using toolchain {}", toolchain),
+    }
+    env::set_var("RUSTUP_TOOLCHAIN", toolchain);
+    Ok(())
+}
+
+/// List the Rust targets installed through rustup,
+/// or `None` if rustup isn't being used
+pub fn rustup_installed_targets() -> Option<Vec<String>> {
+    find_in_path("rustup")?;
+    let output = Command::new("rustup")
+        .args(["target", "list", "--installed"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_owned())
+            .collect(),
+    )
+}
@@ -0,0 +1,155 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Which of the workspace's packages to build, as cargo's `-p`,
+//! `--workspace` and `--exclude` select them
+
+use crate::json::{self, Json};
+use crate::manifest::{has_flag, manifest_path_arg};
+use crate::process;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// A package in the workspace
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub manifest_path: PathBuf,
+}
+
+/// The packages selected in cargo's arguments
+#[derive(Debug, Default, PartialEq)]
+pub struct Selection {
+    /// `-p`/`--package` names or pkgid specs
+    pub packages: Vec<String>,
+    /// `--workspace`, or its deprecated `--all`
+    pub workspace: bool,
+    /// `--exclude` names
+    pub exclude: Vec<String>,
+}
+
+impl Selection {
+    /// Find the selection in cargo arguments, up to any `--`
+    pub fn from_args(params: &[String]) -> Selection {
+        let mut selection = Selection::default();
+        let mut iter = params.iter().take_while(|param| *param != "--");
+        while let Some(param) = iter.next() {
+            let param = param.as_str();
+            match param {
+                "-p" | "--package" => selection.packages.extend(iter.next().cloned()),
+                "--exclude" => selection.exclude.extend(iter.next().cloned()),
+                "--workspace" | "--all" => selection.workspace = true,
+                _ => {
+                    if let Some(name) = param
+                        .strip_prefix("--package=")
+                        .or_else(|| param.strip_prefix("-p").filter(|name| !name.is_empty()))
+                    {
+                        selection.packages.push(String::from(name));
+                    } else if let Some(name) = param.strip_prefix("--exclude=") {
+                        selection.exclude.push(String::from(name));
+                    }
+                }
+            }
+        }
+        selection
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Selection::default()
+    }
+
+    /// Check the names against the workspace's members, returning the
+    /// members selected
+    pub fn resolve<'a>(&self, members: &'a [Member]) -> Result<Vec<&'a Member>, String> {
+        if !self.exclude.is_empty() && !self.workspace {
+            return Err(String::from("--exclude can only be used with --workspace"));
+        }
+        for name in self.packages.iter().chain(&self.exclude) {
+            // Patterns are left for cargo to match
+            if name.contains(['*', '?', '[']) {
+                continue;
+            }
+            if !members.iter().any(|member| member.name == spec_name(name)) {
+                let names: Vec<&str> = members.iter().map(|m| m.name.as_str()).collect();
+                return Err(format!(
+                    "package '{}' isn't in the workspace, its members are: {}",
+                    name,
+                    names.join(", ")
+                ));
+            }
+        }
+        Ok(members
+            .iter()
+            .filter(|member| {
+                if self.workspace {
+                    !self
+                        .exclude
+                        .iter()
+                        .any(|name| spec_name(name) == member.name)
+                } else {
+                    self.packages.iter().any(|name| {
+                        name.contains(['*', '?', '[']) || spec_name(name) == member.name
+                    })
+                }
+            })
+            .collect())
+    }
+}
+
+/// The package name in a pkgid spec, like `telemetry-service@0.2.0`
+fn spec_name(spec: &str) -> &str {
+    let name = spec.rsplit(['/', '#']).next().unwrap_or(spec);
+    name.split(['@', ':']).next().unwrap_or(name)
+}
+
+/// The workspace's members, from `cargo metadata`
+pub fn members(params: &[String]) -> Result<Vec<Member>, String> {
+    let mut command = Command::new("cargo");
+    command.args(["metadata", "--format-version", "1", "--no-deps"]);
+    if let Some(path) = manifest_path_arg(params) {
+        command.arg("--manifest-path").arg(path);
+    }
+    for flag in ["--offline", "--frozen", "--locked"] {
+        if has_flag(params, flag) {
+            command.arg(flag);
+        }
+    }
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| process::spawn_error("cargo", &e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "cargo metadata failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let metadata = json::parse(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|e| format!("can't read cargo metadata: {}", e))?;
+    Ok(metadata
+        .get("packages")
+        .and_then(Json::as_array)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|package| {
+            Some(Member {
+                name: String::from(package.get("name")?.as_str()?),
+                manifest_path: PathBuf::from(package.get("manifest_path")?.as_str()?),
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,493 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Building the yotta modules which crates in the dependency graph wrap,
+//! so that their C libraries exist before cargo links against them, and
+//! telling build scripts where those libraries and their headers are.
+//! The crate's own module is kept building for the Kubos target, as
+//! `cargo kubos sync-yotta-target` does.
+
+use crate::config::kubos_setting;
+use crate::json::{self, Json};
+use crate::manifest::{has_flag, manifest_path_arg};
+use crate::targets::{target_converter, Target, TargetRegistry};
+use crate::toolchain::find_in_path;
+use crate::{events, process, shell_quote, BuildOptions};
+use getopts::Matches;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The file which makes a directory a yotta module
+const MODULE_FILE: &str = "module.json";
+
+/// Where yotta keeps a module's settings, including its target
+const SETTINGS_FILE: &str = ".yotta.json";
+
+/// A yotta module wrapped by a package in the dependency graph
+#[derive(Clone, Debug)]
+pub struct Module {
+    /// The cargo package
+    pub package: String,
+    /// The yotta module's name, from its `module.json`
+    pub name: String,
+    /// The module's directory
+    pub dir: PathBuf,
+}
+
+impl Module {
+    /// Where yotta builds the module for a target
+    pub fn build_dir(&self, target: &str) -> PathBuf {
+        self.dir.join("build").join(target)
+    }
+
+    /// The environment telling the package's build script where the
+    /// module's library and headers are, like
+    /// `KUBOS_YOTTA_<PACKAGE>_LIB_DIR`
+    pub fn env(&self, target: &str) -> Vec<(String, String)> {
+        let prefix = format!(
+            "KUBOS_YOTTA_{}",
+            self.package.to_uppercase().replace('-', "_")
+        );
+        let build = self.build_dir(target);
+        vec![
+            (
+                format!("{}_BUILD_DIR", prefix),
+                build.to_string_lossy().into_owned(),
+            ),
+            (
+                format!("{}_LIB_DIR", prefix),
+                build.join("source").to_string_lossy().into_owned(),
+            ),
+            // yotta modules keep their public headers in a directory
+            // named after the module
+            (
+                format!("{}_INCLUDE_DIR", prefix),
+                self.dir.join(&self.name).to_string_lossy().into_owned(),
+            ),
+        ]
+    }
+}
+
+/// Find the packages in the dependency graph which wrap a yotta module,
+/// marked by a `module.json` beside their manifest or by a
+/// `[package.metadata.yotta]` table, optionally giving the module's
+/// `path` relative to the crate
+pub fn find_modules(params: &[String]) -> Result<Vec<Module>, String> {
+    let mut command = Command::new("cargo");
+    command.args(["metadata", "--format-version", "1"]);
+    if let Some(path) = manifest_path_arg(params) {
+        command.arg("--manifest-path").arg(path);
+    }
+    for flag in ["--offline", "--frozen", "--locked"] {
+        if has_flag(params, flag) {
+            command.arg(flag);
+        }
+    }
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| process::spawn_error("cargo", &e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "cargo metadata failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let metadata = json::parse(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|e| format!("can't read cargo metadata: {}", e))?;
+
+    let mut modules = vec![];
+    let packages = metadata
+        .get("packages")
+        .and_then(Json::as_array)
+        .unwrap_or(&[]);
+    for package in packages {
+        let (name, manifest) = match (
+            package.get("name").and_then(Json::as_str),
+            package.get("manifest_path").and_then(Json::as_str),
+        ) {
+            (Some(name), Some(manifest)) => (name, Path::new(manifest)),
+            _ => continue,
+        };
+        let crate_dir = manifest.parent().unwrap_or_else(|| Path::new("."));
+        let marker = package.get("metadata").and_then(|m| m.get("yotta"));
+        let dir = match marker
+            .and_then(|yotta| yotta.get("path"))
+            .and_then(Json::as_str)
+        {
+            Some(path) => crate_dir.join(path),
+            None => crate_dir.to_path_buf(),
+        };
+        let module_file = dir.join(MODULE_FILE);
+        if marker.is_none() && !module_file.is_file() {
+            continue;
+        }
+        modules.push(Module {
+            package: String::from(name),
+            name: Description::load(&module_file)?.name,
+            dir,
+        });
+    }
+    Ok(modules)
+}
+
+/// What a `module.json` says about a yotta module
+#[derive(Clone, Debug)]
+pub struct Description {
+    /// The module's directory
+    pub dir: PathBuf,
+    pub name: String,
+    /// Modules it always depends on
+    dependencies: Vec<String>,
+    /// Modules it depends on only for some targets, by target section
+    target_dependencies: Vec<(String, Vec<String>)>,
+    /// Further include directories, relative to the module
+    extra_includes: Vec<String>,
+}
+
+impl Description {
+    /// Read the `module.json` in a directory, or at the path itself
+    pub fn load(path: &Path) -> Result<Description, String> {
+        let (dir, file) = if path.is_dir() {
+            (path.to_path_buf(), path.join(MODULE_FILE))
+        } else {
+            (
+                path.parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| PathBuf::from(".")),
+                path.to_path_buf(),
+            )
+        };
+        let contents =
+            fs::read_to_string(&file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        let module = json::parse(&contents).map_err(|e| format!("{}: {}", file.display(), e))?;
+        let field = |name: &str, expected: &str| {
+            format!("{}: {} must be {}", file.display(), name, expected)
+        };
+
+        let name = match module.get("name") {
+            Some(Json::String(name)) if !name.is_empty() => name.clone(),
+            Some(_) => return Err(field("name", "a non-empty string")),
+            None => return Err(format!("{}: no module name", file.display())),
+        };
+        let dependency_names = |deps: &Json, path: &str| match deps {
+            Json::Object(members) => members
+                .iter()
+                .map(|(name, spec)| match spec {
+                    Json::String(_) => Ok(name.clone()),
+                    _ => Err(field(&format!("{}.{}", path, name), "a version string")),
+                })
+                .collect::<Result<Vec<String>, String>>(),
+            _ => Err(field(path, "an object of module names to versions")),
+        };
+        let dependencies = match module.get("dependencies") {
+            Some(deps) => dependency_names(deps, "dependencies")?,
+            None => vec![],
+        };
+        let target_dependencies = match module.get("targetDependencies") {
+            Some(Json::Object(sections)) => sections
+                .iter()
+                .map(|(section, deps)| {
+                    dependency_names(deps, &format!("targetDependencies.{}", section))
+                        .map(|names| (section.clone(), names))
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(field("targetDependencies", "an object of target sections")),
+            None => vec![],
+        };
+        let extra_includes = match module.get("extraIncludes") {
+            Some(Json::Array(items)) => items
+                .iter()
+                .map(|item| item.as_str().map(String::from))
+                .collect::<Option<_>>()
+                .ok_or_else(|| field("extraIncludes", "an array of paths"))?,
+            Some(_) => return Err(field("extraIncludes", "an array of paths")),
+            None => vec![],
+        };
+
+        Ok(Description {
+            dir,
+            name,
+            dependencies,
+            target_dependencies,
+            extra_includes,
+        })
+    }
+
+    /// The modules it depends on when built for a Kubos target. Like yotta's
+    /// `similarTo`, a target section applies when it's the target's name or
+    /// one of the words in it, like `linux` or `gcc`.
+    pub fn dependencies_for(&self, target: &str) -> Vec<&str> {
+        let words: Vec<&str> = target.split('-').collect();
+        let sections = self
+            .target_dependencies
+            .iter()
+            .filter(|(section, _)| section == target || words.contains(&section.as_str()))
+            .flat_map(|(_, names)| names);
+        let mut deps: Vec<&str> = vec![];
+        for dep in self.dependencies.iter().chain(sections) {
+            if !deps.contains(&dep.as_str()) {
+                deps.push(dep);
+            }
+        }
+        deps
+    }
+
+    /// The environment telling build scripts where to find the module's and
+    /// its dependencies' headers and libraries once yotta has built them:
+    /// `CARGO_KUBOS_YOTTA_INCLUDE_PATHS` and `CARGO_KUBOS_YOTTA_LIB_PATHS`
+    /// are path lists like `PATH`, and `CARGO_KUBOS_YOTTA_LIBS` the
+    /// libraries to link, separated by spaces
+    pub fn search_env(&self, target: &str) -> Vec<(String, String)> {
+        let build = self.dir.join("build").join(target);
+        let deps = self.dependencies_for(target);
+
+        // Headers are included as "module/header.h", from each module's root
+        let mut includes = vec![self.dir.clone()];
+        includes.extend(self.extra_includes.iter().map(|dir| self.dir.join(dir)));
+        includes.extend(
+            deps.iter()
+                .map(|dep| self.dir.join("yotta_modules").join(dep)),
+        );
+        let mut libs = vec![build.join("source")];
+        libs.extend(
+            deps.iter()
+                .map(|dep| build.join("ym").join(dep).join("source")),
+        );
+        let names: Vec<&str> = std::iter::once(self.name.as_str()).chain(deps).collect();
+
+        let join = |paths: Vec<PathBuf>| {
+            env::join_paths(paths)
+                .map(|joined| joined.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        vec![
+            (
+                String::from("CARGO_KUBOS_YOTTA_INCLUDE_PATHS"),
+                join(includes),
+            ),
+            (String::from("CARGO_KUBOS_YOTTA_LIB_PATHS"), join(libs)),
+            (String::from("CARGO_KUBOS_YOTTA_LIBS"), names.join(" ")),
+        ]
+    }
+}
+
+/// Build the module for a Kubos target, which is also the yotta target's
+/// name. On failure, the error includes everything yotta printed.
+pub fn build(module: &Module, target: &str) -> Result<(), String> {
+    let yotta = find_yotta().ok_or_else(|| {
+        format!(
+            "{} wraps the yotta module {}, but yotta isn't on PATH \
+                 (pass --skip-yotta if it's already built)",
+            module.package, module.name
+        )
+    })?;
+    let mut command = Command::new(&yotta);
+    command
+        .args(["--target", target, "build"])
+        .current_dir(&module.dir)
+        .stdin(Stdio::null())
+        .stdout(events::child_stdout());
+    info!("building yotta module {} for {}", module.name, target);
+    log!(
+        Debug,
+        "running {} --target {} build in {}",
+        shell_quote(&yotta.to_string_lossy()),
+        shell_quote(target),
+        module.dir.display()
+    );
+
+    let output = command
+        .output()
+        .map_err(|e| process::spawn_error(&yotta.to_string_lossy(), &e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "building yotta module {} for {} failed ({}):\n{}{}",
+            module.name,
+            target,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// The yotta CLI, as `yt` or `yotta`
+fn find_yotta() -> Option<PathBuf> {
+    find_in_path("yt").or_else(|| find_in_path("yotta"))
+}
+
+/// The target yotta builds the module in a directory for, from its
+/// `.yotta.json`, without the registry spec after any comma
+fn current_target(dir: &Path) -> Result<Option<String>, String> {
+    let path = dir.join(SETTINGS_FILE);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let settings = json::parse(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+    match settings.get("build").and_then(|build| build.get("target")) {
+        Some(Json::String(target)) => Ok(target.split(',').next().map(String::from)),
+        Some(_) => Err(format!("{}: build.target must be a string", path.display())),
+        None => Ok(None),
+    }
+}
+
+/// How syncing the yotta target went
+#[derive(Debug, PartialEq)]
+pub enum Sync {
+    /// It was already the target
+    Unchanged,
+    /// It had no target, and now has this one
+    Set,
+    /// It had another target, which was replaced
+    Changed(String),
+    /// It has another target, which was left alone
+    Differs(String),
+}
+
+/// Make the Kubos target the one yotta builds the module in the directory
+/// for, with `yt target` so yotta writes its own settings. A different
+/// target already set is only replaced with `replace`.
+pub fn sync_target(dir: &Path, target: &str, replace: bool) -> Result<Sync, String> {
+    let current = current_target(dir)?;
+    let sync = match current {
+        Some(ref current) if current == target => return Ok(Sync::Unchanged),
+        Some(current) if !replace => return Ok(Sync::Differs(current)),
+        Some(current) => Sync::Changed(current),
+        None => Sync::Set,
+    };
+
+    let yotta = find_yotta().ok_or_else(|| {
+        format!(
+            "setting the yotta target for {} needs yotta, which isn't on PATH",
+            dir.display()
+        )
+    })?;
+    log!(
+        Debug,
+        "running {} target {} in {}",
+        shell_quote(&yotta.to_string_lossy()),
+        shell_quote(target),
+        dir.display()
+    );
+    let output = Command::new(&yotta)
+        .args(["target", target])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| process::spawn_error(&yotta.to_string_lossy(), &e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "setting the yotta target for {} to {} failed ({}):\n{}{}",
+            dir.display(),
+            target,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(sync)
+}
+
+/// Describe how syncing a yotta module's target went
+fn describe_sync(module: &Description, target: &str, sync: &Sync) -> String {
+    match sync {
+        Sync::Unchanged => {
+            format!("the yotta target of {} is already {}", module.name, target)
+        }
+        Sync::Set => format!("set the yotta target of {} to {}", module.name, target),
+        Sync::Changed(from) => format!(
+            "changed the yotta target of {} from {} to {}",
+            module.name, from, target
+        ),
+        Sync::Differs(current) => format!(
+            "the yotta target of {} is {}, not {}; pass --force-sync to switch it",
+            module.name, current, target
+        ),
+    }
+}
+
+/// Run `cargo kubos sync-yotta-target`, returning the exit code
+pub fn sync_command(matches: &Matches, targets: &TargetRegistry, options: &BuildOptions) -> i32 {
+    let target = match matches.opt_strs("t").as_slice() {
+        [k_target] => match target_converter(targets, k_target) {
+            Ok(target) => target,
+            Err(e) => {
+                error!("{}", e);
+                return e.exit_code();
+            }
+        },
+        _ => {
+            error!("sync-yotta-target needs exactly one target (-t)");
+            return 2;
+        }
+    };
+    let module = match options.yotta_module {
+        Some(ref module) => module,
+        None => {
+            error!(
+                "there's no module.json beside the crate manifest; \
+                 pass --yotta-module with the yotta module to set the target of"
+            );
+            return 1;
+        }
+    };
+    // Asked for explicitly, so a different target is replaced
+    match sync_target(&module.dir, &target.name, true) {
+        Ok(sync) => {
+            info!("{}", describe_sync(module, &target.name, &sync));
+            0
+        }
+        Err(e) => {
+            error!("{}", e);
+            1
+        }
+    }
+}
+
+/// With `sync-yotta-target = true` under [kubos], make the crate's yotta
+/// module target what's being built, warning rather than switching from
+/// a different target unless --force-sync
+pub fn auto_sync(selected: &[Target], force: bool, options: &BuildOptions) {
+    let enabled = kubos_setting(&options.cache, "sync-yotta-target").unwrap_or_else(|e| {
+        warning!("{}", e);
+        false
+    });
+    let (module, target) = match (&options.yotta_module, selected) {
+        (Some(module), [target]) if enabled => (module, target),
+        _ => return,
+    };
+    match sync_target(&module.dir, &target.name, force) {
+        Ok(Sync::Unchanged) => {
+            log!(
+                Debug,
+                "{}",
+                describe_sync(module, &target.name, &Sync::Unchanged)
+            );
+        }
+        Ok(ref sync @ Sync::Differs(_)) => {
+            warning!("{}", describe_sync(module, &target.name, sync))
+        }
+        Ok(sync) => info!("{}", describe_sync(module, &target.name, &sync)),
+        Err(e) => warning!("{}", e),
+    }
+}
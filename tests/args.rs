@@ -0,0 +1,104 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Arguments which aren't valid UTF-8, as Unix allows. Those after `--`
+//! reach cargo byte for byte, and anywhere else they're a usage error
+//! rather than a panic.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+
+const TRIPLE: &str = "x86_64-unknown-linux-gnu";
+
+/// `caf\xe9`, café in Latin-1
+fn latin1() -> OsString {
+    OsString::from_vec(b"caf\xe9".to_vec())
+}
+
+#[test]
+fn passed_to_cargo_untouched() {
+    let sandbox = Sandbox::new("passed_to_cargo_untouched");
+    let output = sandbox
+        .command(&["-t", "native", "build", "--", "--features"])
+        .arg(latin1())
+        .arg("kubos")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let build = sandbox.build();
+    let expected: Vec<&[u8]> = vec![
+        b"build",
+        b"--target",
+        TRIPLE.as_bytes(),
+        b"--features",
+        b"caf\xe9",
+        b"kubos",
+    ];
+    assert_eq!(build.raw_args, expected);
+}
+
+#[test]
+fn shown_lossily_by_a_dry_run() {
+    let sandbox = Sandbox::new("shown_lossily_by_a_dry_run");
+    let output = sandbox
+        .command(&["-t", "native", "--dry-run", "build", "--"])
+        .arg(latin1())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("caf\u{fffd}"), "{}", stdout);
+    assert!(sandbox.builds().is_empty());
+}
+
+#[test]
+fn before_the_separator_is_a_usage_error() {
+    let sandbox = Sandbox::new("before_the_separator_is_a_usage_error");
+    let output = sandbox
+        .command(&["-t", "native", "-c"])
+        .arg(latin1())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("isn't valid UTF-8"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox.invocations().is_empty());
+}
+
+#[test]
+fn for_a_remote_program_is_a_usage_error() {
+    let sandbox = Sandbox::new("for_a_remote_program_is_a_usage_error");
+    let output = sandbox
+        .command(&["-t", "native", "run-remote", "--"])
+        .arg(latin1())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("must be valid UTF-8"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox.builds().is_empty());
+}
@@ -0,0 +1,118 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The clang arguments cargo-kubos gives bindgen for a cross target, so
+//! that bindings are generated against the board's headers
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::fs;
+
+const BEAGLEBONE_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+const SUFFIXED: &str = "BINDGEN_EXTRA_CLANG_ARGS_arm_unknown_linux_gnueabihf";
+
+/// A sandbox with the beaglebone's linker and a sysroot for it
+fn with_sysroot(name: &str) -> (Sandbox, String) {
+    let sandbox = Sandbox::new(name).with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let sysroot = sandbox.root.join("bbb/sysroot");
+    fs::create_dir_all(sysroot.join("usr/lib")).unwrap();
+    let sysroot = sysroot.to_string_lossy().into_owned();
+    (sandbox, sysroot)
+}
+
+#[test]
+fn target_and_sysroot_are_given_to_clang() {
+    let (sandbox, sysroot) = with_sysroot("target_and_sysroot_are_given_to_clang");
+    let output = sandbox.run(&["-t", "bb", "--sysroot", &sysroot, "--bindgen-args", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let expected = format!("--target={} --sysroot={}", BEAGLEBONE_TRIPLE, sysroot);
+    let build = sandbox.build();
+    assert_eq!(build.env(SUFFIXED), Some(expected.as_str()));
+    assert_eq!(
+        build.env("BINDGEN_EXTRA_CLANG_ARGS"),
+        Some(expected.as_str())
+    );
+}
+
+#[test]
+fn config_key_turns_them_on() {
+    let (sandbox, sysroot) = with_sysroot("config_key_turns_them_on");
+    let config = sandbox.project.join(".cargo/config.toml");
+    let sandbox = sandbox.with_config(
+        &config,
+        "[kubos.target.\"kubos-linux-beaglebone-gcc\"]\nbindgen-args = true\n",
+    );
+    let output = sandbox.run(&["-t", "bb", "--sysroot", &sysroot, "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let expected = format!("--target={} --sysroot={}", BEAGLEBONE_TRIPLE, sysroot);
+    assert_eq!(sandbox.build().env(SUFFIXED), Some(expected.as_str()));
+}
+
+#[test]
+fn users_args_come_first() {
+    let (sandbox, sysroot) = with_sysroot("users_args_come_first");
+    let sandbox = sandbox
+        .env(SUFFIXED, "-I/opt/bbb/include")
+        .env("BINDGEN_EXTRA_CLANG_ARGS", "-DKUBOS");
+    let output = sandbox.run(&["-t", "bb", "--sysroot", &sysroot, "--bindgen-args", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let derived = format!("--target={} --sysroot={}", BEAGLEBONE_TRIPLE, sysroot);
+    let build = sandbox.build();
+    assert_eq!(
+        build.env(SUFFIXED),
+        Some(format!("-I/opt/bbb/include {}", derived).as_str())
+    );
+    assert_eq!(
+        build.env("BINDGEN_EXTRA_CLANG_ARGS"),
+        Some(format!("-DKUBOS {}", derived).as_str())
+    );
+}
+
+#[test]
+fn no_sysroot_gives_just_the_target() {
+    let sandbox = Sandbox::new("no_sysroot_gives_just_the_target")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "--bindgen-args", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let expected = format!("--target={}", BEAGLEBONE_TRIPLE);
+    assert_eq!(sandbox.build().env(SUFFIXED), Some(expected.as_str()));
+}
+
+#[test]
+fn left_alone_unless_asked_for() {
+    let (sandbox, sysroot) = with_sysroot("left_alone_unless_asked_for");
+    let output = sandbox.run(&["-t", "bb", "--sysroot", &sysroot, "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(build.env(SUFFIXED), None);
+    assert_eq!(build.env("BINDGEN_EXTRA_CLANG_ARGS"), None);
+}
+
+#[test]
+fn native_builds_are_left_alone() {
+    let sandbox = Sandbox::new("native_builds_are_left_alone");
+    let output = sandbox.run(&["-t", "native", "--bindgen-args", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert_eq!(sandbox.build().env("BINDGEN_EXTRA_CLANG_ARGS"), None);
+}
@@ -0,0 +1,152 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! A crate whose build script reads the `CARGO_KUBOS_*` variables,
+//! run as cargo would with the environment cargo-kubos gave cargo
+
+mod common;
+
+use common::{exe, stderr, Sandbox};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::OnceLock;
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/build-script")
+}
+
+/// The fixture's build script, compiled once for all the tests
+fn build_script() -> &'static Path {
+    static SCRIPT: OnceLock<PathBuf> = OnceLock::new();
+    SCRIPT.get_or_init(|| {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+            .join(format!("build-script-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join(exe("build-script-build"));
+        let status = Command::new(env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()))
+            .args(["--edition", "2018", "-o"])
+            .arg(&binary)
+            .arg(fixture().join("build.rs"))
+            .status()
+            .expect("running rustc to build the build script");
+        assert!(status.success(), "building the build script failed");
+        binary
+    })
+}
+
+/// The fixture crate in a sandbox
+fn fixture_crate(name: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name);
+    for file in ["Cargo.toml", "build.rs", "src/main.rs"] {
+        fs::copy(fixture().join(file), sandbox.project.join(file)).unwrap();
+    }
+    sandbox
+}
+
+/// Build with cargo-kubos, then run the build script with what it gave
+/// cargo, adding what cargo itself tells build scripts
+fn build(sandbox: &Sandbox, args: &[&str]) -> Output {
+    let output = sandbox.run(args);
+    assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+    let build = sandbox.build();
+    let triple =
+        build.args[build.args.iter().position(|arg| arg == "--target").unwrap() + 1].clone();
+    let profile = if build.args.iter().any(|arg| arg == "--release") {
+        "release"
+    } else {
+        "debug"
+    };
+    let out_dir = sandbox.root.join("out");
+    fs::create_dir_all(&out_dir).unwrap();
+    Command::new(build_script())
+        .current_dir(&sandbox.project)
+        .env_clear()
+        .envs(build.env.iter().map(|(k, v)| (k, v)))
+        .env("TARGET", triple)
+        .env("PROFILE", profile)
+        .env("OUT_DIR", out_dir)
+        .env("CARGO_MANIFEST_DIR", &sandbox.project)
+        .output()
+        .expect("running the build script")
+}
+
+fn succeeds(output: &Output) -> String {
+    assert!(output.status.success(), "{}", stderr(output));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn cross_build_with_a_sysroot() {
+    let sandbox = fixture_crate("cross_build_with_a_sysroot")
+        .with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let sysroot = sandbox.root.join("sysroot");
+    fs::create_dir_all(sysroot.join("usr/lib")).unwrap();
+    let stdout = succeeds(&build(
+        &sandbox,
+        &["-t", "bb", "--sysroot", sysroot.to_str().unwrap(), "build"],
+    ));
+    assert!(
+        stdout.contains("cargo:rustc-cfg=kubos_target=\"kubos-linux-beaglebone-gcc\""),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("cargo:rustc-link-search=native="),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn release_cross_build() {
+    let sandbox =
+        fixture_crate("release_cross_build").with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = build(&sandbox, &["-t", "bb", "build", "--release"]);
+    let stdout = succeeds(&output);
+    assert!(!stdout.contains("rustc-link-search"), "{}", stdout);
+}
+
+#[test]
+fn native_build() {
+    let sandbox = fixture_crate("native_build");
+    let stdout = succeeds(&build(&sandbox, &["-t", "native", "build"]));
+    assert!(
+        stdout.contains("cargo:rustc-cfg=kubos_target=\"x86-linux-native\""),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn build_script_fails_without_cargo_kubos() {
+    // Cargo alone tells build scripts none of it
+    let output = Command::new(build_script())
+        .env_clear()
+        .env("TARGET", BB_TRIPLE)
+        .env("PROFILE", "debug")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("CARGO_KUBOS_TARGET isn't set"),
+        "{}",
+        stderr(&output)
+    );
+}
@@ -0,0 +1,205 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Which C compilers a crate's C code is compiled with by the cc crate,
+//! the target's or, for its build script's, the host's, and what they're
+//! run with. The fake cargo mimics the cc crate, the fake gccs recording
+//! what they're given.
+
+mod common;
+
+use common::{stderr, Invocation, Sandbox};
+use std::fs;
+
+const BEAGLEBONE_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+const ARMV7_FLAGS: &[&str] = &["-march=armv7-a", "-mfpu=neon", "-mfloat-abi=hard"];
+
+/// A crate with C and C++ files and a beaglebone gcc, and a sysroot
+fn crate_with_c(name: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name).with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    fs::write(
+        sandbox.project.join("src/hello.c"),
+        "int hello(void) { return 1; }\n",
+    )
+    .unwrap();
+    fs::write(
+        sandbox.project.join("src/world.cpp"),
+        "int world() { return 2; }\n",
+    )
+    .unwrap();
+    fs::create_dir_all(sandbox.root.join("sysroot/usr/lib")).unwrap();
+    sandbox
+}
+
+/// The compilations the gcc of the name ran, leaving out the version checks
+fn compilations(sandbox: &Sandbox, gcc: &str) -> Vec<Invocation> {
+    sandbox
+        .calls(gcc)
+        .into_iter()
+        .filter(|call| call.args.iter().any(|arg| arg == "-c"))
+        .collect()
+}
+
+fn sysroot_flag(sandbox: &Sandbox) -> String {
+    format!("--sysroot={}", sandbox.root.join("sysroot").display())
+}
+
+#[test]
+fn sysroot_and_processor_flags_reach_the_compiler() {
+    let sandbox = crate_with_c("sysroot_and_processor_flags_reach_the_compiler");
+    let sysroot = sandbox.root.join("sysroot");
+    let output = sandbox.run(&["-t", "bb", "--sysroot", sysroot.to_str().unwrap(), "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let mut expected = vec![sysroot_flag(&sandbox)];
+    expected.extend(ARMV7_FLAGS.iter().map(|flag| flag.to_string()));
+    let compilations = compilations(&sandbox, "arm-linux-gnueabihf-gcc");
+    assert_eq!(compilations.len(), 2, "{:?}", compilations);
+    for (call, source) in compilations.iter().zip(["src/hello.c", "src/world.cpp"]) {
+        let mut args = expected.clone();
+        args.extend(["-c".to_string(), source.to_string()]);
+        assert_eq!(call.args, args);
+    }
+}
+
+#[test]
+fn users_flags_come_first() {
+    let sandbox = crate_with_c("users_flags_come_first")
+        .env("CFLAGS_arm_unknown_linux_gnueabihf", "-O2 -g")
+        .env("CXXFLAGS_arm_unknown_linux_gnueabihf", "-std=c++14");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let compilations = compilations(&sandbox, "arm-linux-gnueabihf-gcc");
+    assert_eq!(compilations.len(), 2, "{:?}", compilations);
+    assert_eq!(compilations[0].args[..2], ["-O2", "-g"]);
+    assert_eq!(compilations[0].args[2..5], *ARMV7_FLAGS);
+    assert_eq!(compilations[1].args[..1], ["-std=c++14"]);
+    assert_eq!(compilations[1].args[1..4], *ARMV7_FLAGS);
+}
+
+#[test]
+fn no_sysroot_flags_leaves_the_sysroot_out() {
+    let sandbox = crate_with_c("no_sysroot_flags_leaves_the_sysroot_out");
+    let sysroot = sandbox.root.join("sysroot");
+    let output = sandbox.run(&[
+        "-t",
+        "bb",
+        "--sysroot",
+        sysroot.to_str().unwrap(),
+        "--no-sysroot-flags",
+        "build",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let compilations = compilations(&sandbox, "arm-linux-gnueabihf-gcc");
+    assert_eq!(compilations.len(), 2, "{:?}", compilations);
+    for call in &compilations {
+        assert_eq!(call.args[..3], *ARMV7_FLAGS);
+        assert!(
+            !call.args.contains(&sysroot_flag(&sandbox)),
+            "{:?}",
+            call.args
+        );
+    }
+}
+
+#[test]
+fn each_board_gets_its_processor_flags() {
+    let sandbox = Sandbox::new("each_board_gets_its_processor_flags")
+        .with_linker("armv5te-unknown-linux-gnueabi", "armv5te-linux-gnueabi-gcc");
+    fs::write(
+        sandbox.project.join("src/hello.c"),
+        "int hello(void) { return 1; }\n",
+    )
+    .unwrap();
+    let output = sandbox.run(&["-t", "isis", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let compilations = compilations(&sandbox, "armv5te-linux-gnueabi-gcc");
+    assert_eq!(compilations.len(), 1, "{:?}", compilations);
+    assert_eq!(
+        compilations[0].args,
+        ["-march=armv5te", "-mfloat-abi=soft", "-c", "src/hello.c"]
+    );
+}
+
+/// A crate whose build script compiles C for the host as well, with stub
+/// host compilers on PATH
+fn crate_with_host_c(name: &str) -> Sandbox {
+    let sandbox = crate_with_c(name);
+    fs::create_dir_all(sandbox.project.join("build")).unwrap();
+    fs::write(
+        sandbox.project.join("build/helper.c"),
+        "int helper(void) { return 3; }\n",
+    )
+    .unwrap();
+    sandbox.install_stub(&sandbox.bin, "cc");
+    sandbox.install_stub(&sandbox.bin, "c++");
+    sandbox
+}
+
+#[test]
+fn build_script_c_is_compiled_for_the_host() {
+    let sandbox = crate_with_host_c("build_script_c_is_compiled_for_the_host");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(build.env("CC"), None);
+    assert_eq!(build.env("CXX"), None);
+    assert_eq!(build.env("HOST_CC"), Some("cc"));
+    let host = sandbox.calls("cc");
+    assert_eq!(host.len(), 1, "{:?}", host);
+    assert_eq!(host[0].args, ["-c", "build/helper.c"]);
+    let target = compilations(&sandbox, "arm-linux-gnueabihf-gcc");
+    let sources: Vec<&str> = target
+        .iter()
+        .map(|call| call.args.last().unwrap().as_str())
+        .collect();
+    assert_eq!(sources, ["src/hello.c", "src/world.cpp"]);
+}
+
+#[test]
+fn legacy_cc_leaves_the_host_its_compiler() {
+    let sandbox = crate_with_host_c("legacy_cc_leaves_the_host_its_compiler");
+    let output = sandbox.run(&["-t", "bb", "--legacy-cc", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let gcc = sandbox.bin.join("arm-linux-gnueabihf-gcc");
+    let build = sandbox.build();
+    assert_eq!(build.env("CC"), gcc.to_str());
+    assert_eq!(build.env("CXX"), gcc.to_str());
+    assert_eq!(sandbox.calls("cc").len(), 1);
+    assert!(compilations(&sandbox, "arm-linux-gnueabihf-gcc")
+        .iter()
+        .all(|call| !call.args.contains(&"build/helper.c".to_string())));
+}
+
+#[test]
+fn users_host_compiler_is_kept() {
+    let sandbox = crate_with_host_c("users_host_compiler_is_kept");
+    let clang = sandbox.install_stub(&sandbox.bin, "clang");
+    let sandbox = sandbox.env("HOST_CC", clang.to_str().unwrap());
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert!(sandbox.calls("cc").is_empty());
+    let host = sandbox.calls("clang");
+    assert_eq!(host.len(), 1, "{:?}", host);
+    assert_eq!(host[0].args, ["-c", "build/helper.c"]);
+}
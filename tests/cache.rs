@@ -0,0 +1,157 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Resolving several targets against one cache: each config is read once
+//! and each linker is run once, however many targets share them. What
+//! one cache was given with `--cargo-config` is no other's concern.
+
+#![cfg(unix)]
+
+mod common;
+
+use cargo_kubos::{
+    check_linker, read_config, resolve_linker, resolve_target, CargoInvocation, ConfigError,
+    ConfigFile, ConfigLoader, ResolutionCache,
+};
+use common::runner::ScriptedRunner;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Reads the configs it was given from disk, counting the reads of each
+struct CountingLoader {
+    paths: Vec<PathBuf>,
+    loads: Arc<Mutex<HashMap<PathBuf, usize>>>,
+}
+
+impl ConfigLoader for CountingLoader {
+    fn paths(&self) -> Result<Vec<PathBuf>, Vec<PathBuf>> {
+        Ok(self.paths.clone())
+    }
+
+    fn load(&self, path: &Path) -> Result<ConfigFile, ConfigError> {
+        *self
+            .loads
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_insert(0) += 1;
+        read_config(path.to_path_buf())
+    }
+}
+
+fn fake_gcc(dir: &Path, name: &str) -> PathBuf {
+    let gcc = dir.join(name);
+    fs::write(&gcc, "").unwrap();
+    fs::set_permissions(&gcc, fs::Permissions::from_mode(0o755)).unwrap();
+    gcc
+}
+
+#[test]
+fn targets_share_configs_and_linker_probes() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("targets_share_configs");
+    fs::create_dir_all(&dir).unwrap();
+    let armv7 = fake_gcc(&dir, "arm-linux-gnueabihf-gcc");
+    let armv5 = fake_gcc(&dir, "arm-linux-gnueabi-gcc");
+
+    let project = dir.join("project.toml");
+    fs::write(
+        &project,
+        format!(
+            "[target.arm-unknown-linux-gnueabihf]\nlinker = {:?}\n\n\
+             [target.armv5te-unknown-linux-gnueabi]\nlinker = {:?}\n",
+            armv7.display().to_string(),
+            armv5.display().to_string()
+        ),
+    )
+    .unwrap();
+    let home = dir.join("home.toml");
+    fs::write(
+        &home,
+        "[build]\nrustflags = [\"-Cdebuginfo=1\"]\n\n[env]\nSATELLITE = \"kubos\"\n",
+    )
+    .unwrap();
+
+    let loads = Arc::new(Mutex::new(HashMap::new()));
+    let cache = Arc::new(ResolutionCache::with_loader(CountingLoader {
+        paths: vec![project.clone(), home.clone()],
+        loads: Arc::clone(&loads),
+    }));
+    let runner = ScriptedRunner::new()
+        .outputs(0, "arm-linux-gnueabihf-gcc 9.0.0\n")
+        .outputs(0, "arm-linux-gnueabi-gcc 7.3.0\n");
+
+    for name in &["bb", "kubos-linux-pumpkin-mbm2-gcc", "isis"] {
+        let target = resolve_target(name).unwrap();
+        check_linker(&target, &runner, &cache).unwrap();
+        let invocation = CargoInvocation::with_cache(&target, &[String::from("build")], &cache);
+        assert_eq!(invocation.env.get("SATELLITE"), Some("kubos"));
+    }
+
+    let loads = loads.lock().unwrap();
+    assert_eq!(loads.get(&project), Some(&1));
+    assert_eq!(loads.get(&home), Some(&1));
+    let probes: Vec<String> = runner
+        .calls()
+        .iter()
+        .filter(|call| call.args == ["--version"])
+        .map(|call| call.program.clone())
+        .collect();
+    assert_eq!(
+        probes,
+        [armv7.display().to_string(), armv5.display().to_string()]
+    );
+}
+
+#[test]
+fn caches_keep_their_own_given_configs() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("caches_keep_their_own_given_configs");
+    fs::create_dir_all(&dir).unwrap();
+    let given = |name: &str| {
+        let gcc = fake_gcc(&dir, name);
+        let config = dir.join(format!("{}.toml", name));
+        fs::write(
+            &config,
+            format!(
+                "[target.arm-unknown-linux-gnueabihf]\nlinker = {:?}\n",
+                gcc.display().to_string()
+            ),
+        )
+        .unwrap();
+        (
+            gcc,
+            ResolutionCache::with_given_configs(vec![config.clone()]),
+            config,
+        )
+    };
+    let (first_gcc, first, first_config) = given("first-gcc");
+    let (second_gcc, second, second_config) = given("second-gcc");
+
+    // However many are made, each resolves with the configs it was given
+    let target = resolve_target("bb").unwrap();
+    assert_eq!(first.given_configs(), [first_config]);
+    assert_eq!(second.given_configs(), [second_config]);
+    assert_eq!(
+        resolve_linker(&target, &first).unwrap().path,
+        first_gcc.display().to_string()
+    );
+    assert_eq!(
+        resolve_linker(&target, &second).unwrap().path,
+        second_gcc.display().to_string()
+    );
+}
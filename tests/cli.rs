@@ -0,0 +1,317 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! How command lines are taken, as cargo runs cargo-kubos and directly
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::process::Output;
+
+const TRIPLE: &str = "x86_64-unknown-linux-gnu";
+
+/// What a command line should come to
+enum Expect {
+    /// Running cargo once, with these arguments
+    Cargo(&'static [&'static str]),
+    /// Printing help to stdout, without running cargo
+    Help,
+    /// A usage error, exit code 2 with this on stderr and nothing on stdout
+    Usage(&'static str),
+}
+
+const CASES: &[(&[&str], Expect)] = &[
+    (
+        &["-t", "native", "build"],
+        Expect::Cargo(&["build", "--target", TRIPLE]),
+    ),
+    (
+        &["-t", "native", "-c", "build --release"],
+        Expect::Cargo(&["build", "--release", "--target", TRIPLE]),
+    ),
+    (
+        &["-t", "native", "-c", "test --lib --no-default-features"],
+        Expect::Cargo(&["test", "--lib", "--no-default-features", "--target", TRIPLE]),
+    ),
+    (
+        &["-t", "native", "-c", "build --features 'a b'", "--", "-v"],
+        Expect::Cargo(&["build", "--features", "a b", "--target", TRIPLE, "-v"]),
+    ),
+    (
+        &[
+            "-t",
+            "native",
+            "-c",
+            r#"build --features "a,b c" --bin=x\ y"#,
+        ],
+        Expect::Cargo(&[
+            "build",
+            "--features",
+            "a,b c",
+            "--bin=x y",
+            "--target",
+            TRIPLE,
+        ]),
+    ),
+    (
+        &[
+            "-t",
+            "native",
+            "-c",
+            r#"build --features '' --bin "say \"hi\"""#,
+        ],
+        Expect::Cargo(&[
+            "build",
+            "--features",
+            "",
+            "--bin",
+            "say \"hi\"",
+            "--target",
+            TRIPLE,
+        ]),
+    ),
+    (
+        &["-t", "native", "-c", "  check  "],
+        Expect::Cargo(&["check", "--target", TRIPLE]),
+    ),
+    (
+        &["-t", "native", "test", "--", "--nocapture"],
+        Expect::Cargo(&["test", "--target", TRIPLE, "--nocapture"]),
+    ),
+    (
+        &[
+            "--target",
+            "x86-linux-native",
+            "check",
+            "--",
+            "--features",
+            "a",
+        ],
+        Expect::Cargo(&["check", "--target", TRIPLE, "--features", "a"]),
+    ),
+    (
+        &["-t", "native", "-c", "run", "--", "kubos"],
+        Expect::Cargo(&["run", "--target", TRIPLE, "kubos"]),
+    ),
+    (
+        &["-t", "native", "build", "--", "--features", "kubos"],
+        Expect::Cargo(&["build", "--target", TRIPLE, "--features", "kubos"]),
+    ),
+    (
+        &["-t", "native", "-c", "run", "--", "--config", "kubos"],
+        Expect::Cargo(&["run", "--target", TRIPLE, "--config", "kubos"]),
+    ),
+    (
+        &["-t", "native", "build", "--", "-t", "other", "-v", "--help"],
+        Expect::Cargo(&["build", "--target", TRIPLE, "-t", "other", "-v", "--help"]),
+    ),
+    (
+        &["-t", "native", "-q", "build"],
+        Expect::Cargo(&["build", "--quiet", "--target", TRIPLE]),
+    ),
+    (
+        &["-t", "native", "build", "--features", "a"],
+        Expect::Cargo(&["build", "--features", "a", "--target", TRIPLE]),
+    ),
+    (
+        &[
+            "-t",
+            "native",
+            "--features",
+            "a,b",
+            "build",
+            "--features",
+            "c d",
+            "--features=b",
+        ],
+        Expect::Cargo(&["build", "--features", "a,b,c,d", "--target", TRIPLE]),
+    ),
+    (
+        &["-t", "native", "build", "--features", " a, ,b "],
+        Expect::Cargo(&["build", "--features", "a,b", "--target", TRIPLE]),
+    ),
+    (
+        &[
+            "-t",
+            "native",
+            "build",
+            "--no-default-features",
+            "--features",
+            "a",
+        ],
+        Expect::Cargo(&[
+            "build",
+            "--features",
+            "a",
+            "--no-default-features",
+            "--target",
+            TRIPLE,
+        ]),
+    ),
+    (
+        &[
+            "-t",
+            "native",
+            "build",
+            "--all-features",
+            "--",
+            "--nocapture",
+        ],
+        Expect::Cargo(&["build", "--all-features", "--target", TRIPLE, "--nocapture"]),
+    ),
+    (
+        &["-t", "native", "nextest", "run"],
+        Expect::Cargo(&["nextest", "run"]),
+    ),
+    (&["--help"], Expect::Help),
+    (&["build", "--help"], Expect::Help),
+    (
+        &["-t", "native", "-c", "build", "test"],
+        Expect::Usage("conflicting commands"),
+    ),
+    (
+        &["-t", "native", "flash", "-c", "build"],
+        Expect::Usage("for `cargo kubos flash`"),
+    ),
+    (&["-t"], Expect::Usage("Argument to option 't' missing")),
+    (
+        &["--bogus", "build"],
+        Expect::Usage("Unrecognized option: 'bogus'"),
+    ),
+    (
+        &["-t", "nope", "build"],
+        Expect::Usage("Target 'nope' not supported"),
+    ),
+    (
+        &["-t", "all", "-t", "bb", "build"],
+        Expect::Usage("-t all builds every target, so can't be given along with -t bb"),
+    ),
+    (
+        &[
+            "-t",
+            "bb",
+            "-t",
+            "all",
+            "-T",
+            "armv7-unknown-linux-gnueabihf",
+        ],
+        Expect::Usage("along with -t bb, -T armv7-unknown-linux-gnueabihf"),
+    ),
+    (
+        &["--quiet", "--verbose", "build"],
+        Expect::Usage("can't be used together"),
+    ),
+    (
+        &[
+            "-t",
+            "native",
+            "build",
+            "--all-features",
+            "--no-default-features",
+        ],
+        Expect::Usage("the argument '--all-features' cannot be used with '--no-default-features'"),
+    ),
+    (
+        &["--color", "sometimes", "build"],
+        Expect::Usage("invalid color choice"),
+    ),
+    (
+        &["--output-format", "xml", "build"],
+        Expect::Usage("invalid output format"),
+    ),
+    (
+        &["-t", "native", "-c", ""],
+        Expect::Usage("'command' is empty"),
+    ),
+    (
+        &["-t", "native", "-c", "build --features 'a b"],
+        Expect::Usage("unterminated single quote"),
+    ),
+    (
+        &["-t", "native", "-c", "build --features \"a"],
+        Expect::Usage("unterminated double quote"),
+    ),
+    (
+        &["--cargo-config", "missing.toml", "build"],
+        Expect::Usage("cargo config 'missing.toml' does not exist"),
+    ),
+];
+
+fn check(args: &[&str], expect: &Expect, sandbox: &Sandbox, output: &Output) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match expect {
+        Expect::Cargo(argv) => {
+            assert!(output.status.success(), "{:?}: {}", args, stderr(output));
+            assert_eq!(sandbox.build().args, *argv, "{:?}", args);
+        }
+        Expect::Help => {
+            assert!(output.status.success(), "{:?}: {}", args, stderr(output));
+            assert!(stdout.contains("Usage:"), "{:?}: {}", args, stdout);
+            assert!(sandbox.builds().is_empty(), "{:?} ran cargo", args);
+        }
+        Expect::Usage(message) => {
+            assert_eq!(
+                output.status.code(),
+                Some(2),
+                "{:?}: {}",
+                args,
+                stderr(output)
+            );
+            assert!(
+                stderr(output).contains(message),
+                "{:?}: {}",
+                args,
+                stderr(output)
+            );
+            assert!(stdout.is_empty(), "{:?} printed {}", args, stdout);
+            assert!(sandbox.builds().is_empty(), "{:?} ran cargo", args);
+        }
+    }
+}
+
+#[test]
+fn as_run_by_cargo() {
+    for (i, (args, expect)) in CASES.iter().enumerate() {
+        let sandbox = Sandbox::new(&format!("as_run_by_cargo_{}", i));
+        check(args, expect, &sandbox, &sandbox.run(args));
+    }
+}
+
+#[test]
+fn run_directly() {
+    for (i, (args, expect)) in CASES.iter().enumerate() {
+        let sandbox = Sandbox::new(&format!("run_directly_{}", i));
+        check(args, expect, &sandbox, &sandbox.run_direct(args));
+    }
+}
+
+#[test]
+fn version_ignores_the_cargo_configs() {
+    let sandbox = Sandbox::new("version_ignores_the_cargo_configs");
+    let broken = sandbox.project.join("broken.toml");
+    std::fs::write(&broken, "[target.x86_64-unknown-linux-gnu\n").unwrap();
+    for config in &["broken.toml", "missing.toml"] {
+        let output = sandbox.run(&["--version", "--cargo-config", config]);
+        assert!(output.status.success(), "{}: {}", config, stderr(&output));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.starts_with("cargo-kubos "), "{}", stdout);
+    }
+
+    // Which stops anything that reads them
+    let output = sandbox.run(&["--cargo-config", "broken.toml", "build"]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(sandbox.builds().is_empty());
+}
@@ -0,0 +1,320 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! A stand-in for cargo, and for the cross gccs and the other programs
+//! cargo-kubos runs, which the tests compile. As cargo, it records each
+//! invocation's arguments and environment in `$FAKE_CARGO_RECORD`,
+//! writes `$FAKE_CARGO_SAY` as a line on stdout and on stderr,
+//! compiles the crate's C code as the cc crate would, reports building
+//! the crate's binary when asked for JSON messages, runs the target's
+//! runner for the commands which run what they build, takes
+//! `$FAKE_CARGO_SLEEP` seconds over a build, and exits with
+//! `$FAKE_CARGO_EXIT`, or on Unix dies of signal `$FAKE_CARGO_SIGNAL`.
+//! Each of those can be set for one target's triple alone, as in
+//! `$FAKE_CARGO_EXIT_ARM_UNKNOWN_LINUX_GNUEABIHF`.
+//! Under any other name it records its invocations in a directory of that
+//! name there, gives a gcc's version when asked, and exits with
+//! `$FAKE_<NAME>_EXIT`.
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+use std::thread;
+use std::time::Duration;
+
+/// The bytes of an argument, as they were given where the platform allows
+#[cfg(unix)]
+fn bytes(arg: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    arg.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn bytes(arg: &OsStr) -> Vec<u8> {
+    arg.to_string_lossy().into_owned().into_bytes()
+}
+
+/// Write the fields NUL-terminated, so any argument or value survives
+fn write_fields<I: Iterator<Item = Vec<u8>>>(path: &Path, fields: I) {
+    let mut data = vec![];
+    for field in fields {
+        data.extend(field);
+        data.push(0);
+    }
+    fs::write(path, data).expect("writing the fake cargo's record");
+}
+
+/// Take the next invocation number, which works when several
+/// targets are built at once
+fn next_record(dir: &Path) -> PathBuf {
+    for n in 0.. {
+        let path = dir.join(format!("{}.args", n));
+        if OpenOptions::new().write(true).create_new(true).open(&path).is_ok() {
+            return dir.join(n.to_string());
+        }
+    }
+    unreachable!()
+}
+
+/// Record the invocation in the directory, if there's one to record in
+fn record(dir: Option<&Path>, raw_args: Vec<Vec<u8>>) {
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir).expect("creating the fake cargo's record");
+        let record = next_record(dir);
+        write_fields(&record.with_extension("args"), raw_args.into_iter());
+        write_fields(
+            &record.with_extension("env"),
+            env::vars().map(|(key, value)| format!("{}={}", key, value).into_bytes()),
+        );
+    }
+}
+
+/// The first of the variables the cc crate would read which is set, for
+/// the triple or, without one, for the host. The host's triple-suffixed
+/// variables are left out, the fake not knowing the host.
+fn cc_var(name: &str, triple: Option<&str>) -> Option<String> {
+    let vars = match triple {
+        Some(triple) => vec![
+            format!("{}_{}", name, triple),
+            format!("{}_{}", name, triple.replace('-', "_")),
+            format!("TARGET_{}", name),
+            String::from(name),
+        ],
+        None => vec![format!("HOST_{}", name), String::from(name)],
+    };
+    vars.iter().find_map(|var| env::var(var).ok())
+}
+
+/// Compile each C and C++ file in the directory, as a build script using
+/// the cc crate would, for the triple or, without one, for the host
+fn compile_dir(dir: &str, triple: Option<&str>) {
+    let mut sources: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => return,
+    };
+    sources.sort();
+    for source in sources {
+        let (compiler, flags) = match Path::new(&source).extension().and_then(|ext| ext.to_str()) {
+            Some("c") => ("CC", "CFLAGS"),
+            Some("cpp") => ("CXX", "CXXFLAGS"),
+            _ => continue,
+        };
+        let compiler = match cc_var(compiler, triple) {
+            Some(compiler) => compiler,
+            None => continue,
+        };
+        let flags = cc_var(flags, triple).unwrap_or_default();
+        let status = Command::new(compiler)
+            .args(flags.split_whitespace())
+            .arg("-c")
+            .arg(format!("{}/{}", dir, source))
+            .status()
+            .expect("running the C compiler");
+        if !status.success() {
+            exit(101);
+        }
+    }
+}
+
+/// Compile the crate's C code: that in `build`, the build script's own,
+/// for the host, then that in `src` for the target
+fn compile_c(args: &[String]) {
+    if let Some(triple) = target(args) {
+        compile_dir("build", None);
+        compile_dir("src", Some(triple));
+    }
+}
+
+/// The triple cargo was given with `--target`
+fn target(args: &[String]) -> Option<&str> {
+    let i = args.iter().position(|arg| arg == "--target")?;
+    args.get(i + 1).map(String::as_str)
+}
+
+/// The crate's directory, that of `--manifest-path` if it's given
+fn package_dir(args: &[String]) -> PathBuf {
+    let cwd = env::current_dir().unwrap();
+    match args.iter().position(|arg| arg == "--manifest-path") {
+        Some(i) => cwd.join(Path::new(&args[i + 1]).parent().unwrap()),
+        None => cwd,
+    }
+}
+
+/// The directory the profile builds into, as cargo names it
+fn profile_dir(args: &[String]) -> &str {
+    let profile = match args.iter().position(|arg| arg == "--profile") {
+        Some(i) => args[i + 1].as_str(),
+        None if args.iter().any(|arg| arg == "--release" || arg == "-r") => "release",
+        None => "dev",
+    };
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    }
+}
+
+/// A string as JSON
+fn json_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Build the crate's binary, an empty file, if it has a `src/main.rs`,
+/// and report it as cargo does in its JSON messages if they were asked for
+fn report_artifact(args: &[String]) {
+    let json = args.iter().any(|arg| arg.starts_with("--message-format=json"));
+    let dir = package_dir(args);
+    let triple = match target(args) {
+        Some(triple) if json && dir.join("src/main.rs").exists() => triple,
+        _ => return,
+    };
+    let binary = dir
+        .join("target")
+        .join(triple)
+        .join(profile_dir(args))
+        .join("sandbox");
+    fs::create_dir_all(binary.parent().unwrap()).unwrap();
+    fs::write(&binary, b"").unwrap();
+    let binary = json_string(&binary.to_string_lossy());
+    println!(
+        "{{\"reason\":\"compiler-artifact\",\"package_id\":\"sandbox 0.1.0\",\
+         \"manifest_path\":{},\"target\":{{\"kind\":[\"bin\"],\"name\":\"sandbox\"}},\
+         \"filenames\":[{}],\"executable\":{}}}",
+        json_string(&dir.join("Cargo.toml").to_string_lossy()),
+        binary,
+        binary
+    );
+}
+
+/// Run the binary under `CARGO_TARGET_<TRIPLE>_RUNNER` as cargo would,
+/// for run, test and bench, exiting with the runner's status
+fn run_under_runner(args: &[String]) {
+    if !matches!(args.first().map(String::as_str), Some("run" | "test" | "bench")) {
+        return;
+    }
+    let triple = match target(args) {
+        Some(triple) => triple,
+        None => return,
+    };
+    let var = format!(
+        "CARGO_TARGET_{}_RUNNER",
+        triple.to_uppercase().replace(['-', '.'], "_")
+    );
+    let runner = match env::var(var) {
+        Ok(runner) if !runner.is_empty() => runner,
+        _ => return,
+    };
+    let mut words = runner.split_whitespace();
+    let program = words.next().unwrap();
+    let program_args = match args.iter().position(|arg| arg == "--") {
+        Some(i) => &args[i + 1..],
+        None => &[],
+    };
+    let status = Command::new(program)
+        .args(words)
+        .arg(Path::new("target").join(triple).join("debug").join("sandbox"))
+        .args(program_args)
+        .status()
+        .expect("running the runner");
+    exit(status.code().unwrap_or(101));
+}
+
+/// Die of the signal, as a build killed by it would
+#[cfg(unix)]
+fn die_of(signo: i32) {
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+        fn raise(sig: i32) -> i32;
+    }
+    // Rust's runtime handles SIGSEGV itself, so restore the default
+    const SIG_DFL: usize = 0;
+    unsafe {
+        signal(signo, SIG_DFL);
+        raise(signo);
+    }
+}
+
+#[cfg(not(unix))]
+fn die_of(_signo: i32) {}
+
+/// A setting of the fake cargo, `$FAKE_CARGO_<NAME>`, or that for the
+/// triple it's building for if that's set
+fn cargo_setting<T: std::str::FromStr>(name: &str, args: &[String]) -> Option<T> {
+    let var = format!("FAKE_CARGO_{}", name);
+    target(args)
+        .and_then(|triple| {
+            env::var(format!("{}_{}", var, triple.to_uppercase().replace(['-', '.'], "_"))).ok()
+        })
+        .or_else(|| env::var(var).ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// The code to exit with as the program of the name, from `$FAKE_<NAME>_EXIT`
+fn exit_code(name: &str) -> i32 {
+    let var = format!("FAKE_{}_EXIT", name.to_uppercase().replace(['-', '.'], "_"));
+    env::var(var)
+        .ok()
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0)
+}
+
+fn main() {
+    let program = env::args_os().next().unwrap_or_default();
+    let raw_args: Vec<Vec<u8>> = env::args_os().skip(1).map(|arg| bytes(&arg)).collect();
+    let args: Vec<String> = raw_args
+        .iter()
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    let records = env::var_os("FAKE_CARGO_RECORD").map(PathBuf::from);
+
+    // Anything else, a gcc say, just has a version to give
+    let name = Path::new(&program)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if name != "cargo" {
+        record(records.map(|dir| dir.join(&name)).as_deref(), raw_args);
+        if args.iter().any(|arg| arg == "--version") {
+            println!("gcc (fake) 9.0.0");
+        }
+        exit(exit_code(&name));
+    }
+
+    record(records.as_deref(), raw_args);
+    if args.first().map(String::as_str) == Some("metadata") {
+        println!("{{\"packages\":[],\"workspace_members\":[]}}");
+    } else {
+        if let Some(line) = cargo_setting::<String>("SAY", &args) {
+            println!("{}", line);
+            eprintln!("{}", line);
+        }
+        compile_c(&args);
+        report_artifact(&args);
+        run_under_runner(&args);
+        // Long enough for a test to interrupt
+        if let Some(secs) = cargo_setting("SLEEP", &args) {
+            thread::sleep(Duration::from_secs(secs));
+        }
+        if let Some(signo) = cargo_setting("SIGNAL", &args) {
+            die_of(signo);
+        }
+    }
+    exit(cargo_setting("EXIT", &args).unwrap_or(0));
+}
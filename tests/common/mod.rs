@@ -0,0 +1,302 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! A sandbox to run cargo-kubos in, with a fake cargo on PATH recording
+//! how cargo-kubos runs it
+
+#![allow(dead_code)]
+
+#[cfg(unix)]
+pub mod runner;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::OnceLock;
+
+/// Where the tests' files go, which cargo keeps between runs
+const TMP_DIR: &str = env!("CARGO_TARGET_TMPDIR");
+
+/// What `x86-linux-native` builds for, which only
+/// needs no cross linker on an x86-64 Linux host
+const NATIVE_TRIPLE: &str = "x86_64-unknown-linux-gnu";
+
+/// The file name of a program of the name on this host
+pub fn exe(name: &str) -> String {
+    format!("{}{}", name, env::consts::EXE_SUFFIX)
+}
+
+/// The fake cargo, compiled once for all the tests in a test binary
+fn fake_cargo() -> &'static Path {
+    static FAKE: OnceLock<PathBuf> = OnceLock::new();
+    FAKE.get_or_init(|| {
+        let dir = Path::new(TMP_DIR).join(format!("fake-cargo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join(exe("cargo"));
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/common/fake_cargo.rs");
+        let status = Command::new(env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()))
+            .args(["--edition", "2018", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .status()
+            .expect("running rustc to build the fake cargo");
+        assert!(status.success(), "building the fake cargo failed");
+        binary
+    })
+}
+
+/// One time cargo-kubos ran the fake cargo
+#[derive(Debug)]
+pub struct Invocation {
+    pub args: Vec<String>,
+    /// The arguments' bytes, for those which aren't UTF-8
+    pub raw_args: Vec<Vec<u8>>,
+    pub env: Vec<(String, String)>,
+}
+
+impl Invocation {
+    /// The value cargo was given for an environment variable
+    pub fn env(&self, key: &str) -> Option<&str> {
+        self.env
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_raw_fields(path: &Path) -> Vec<Vec<u8>> {
+    let data = fs::read(path).unwrap_or_default();
+    match data.strip_suffix(&[0]) {
+        Some(fields) => fields.split(|b| *b == 0).map(<[u8]>::to_vec).collect(),
+        None => vec![],
+    }
+}
+
+fn read_fields(path: &Path) -> Vec<String> {
+    read_raw_fields(path)
+        .iter()
+        .map(|field| String::from_utf8_lossy(field).into_owned())
+        .collect()
+}
+
+/// The invocations recorded in the directory, in order
+fn read_invocations(dir: &Path) -> Vec<Invocation> {
+    let mut invocations = vec![];
+    for n in 0.. {
+        let record = dir.join(n.to_string());
+        if !record.with_extension("args").exists() {
+            break;
+        }
+        let env = read_fields(&record.with_extension("env"))
+            .into_iter()
+            .filter_map(|var| {
+                let (key, value) = var.split_once('=')?;
+                Some((key.to_string(), value.to_string()))
+            })
+            .collect();
+        let raw_args = read_raw_fields(&record.with_extension("args"));
+        invocations.push(Invocation {
+            args: raw_args
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect(),
+            raw_args,
+            env,
+        });
+    }
+    invocations
+}
+
+/// A crate to build, a home directory of its own, and a PATH with nothing
+/// on it but the fake cargo and what a test adds
+pub struct Sandbox {
+    pub root: PathBuf,
+    pub bin: PathBuf,
+    pub project: PathBuf,
+    pub home: PathBuf,
+    records: PathBuf,
+    vars: Vec<(String, String)>,
+}
+
+impl Sandbox {
+    /// A fresh sandbox, named for the test using it
+    pub fn new(name: &str) -> Sandbox {
+        let root = Path::new(TMP_DIR).join(name);
+        let _ = fs::remove_dir_all(&root);
+        let sandbox = Sandbox {
+            bin: root.join("bin"),
+            project: root.join("project"),
+            home: root.join("home"),
+            records: root.join("records"),
+            root,
+            vars: vec![],
+        };
+        for dir in [&sandbox.bin, &sandbox.home, &sandbox.records] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::create_dir_all(sandbox.project.join("src")).unwrap();
+        fs::write(
+            sandbox.project.join("Cargo.toml"),
+            "[package]\nname = \"sandbox\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        fs::write(sandbox.project.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::copy(fake_cargo(), sandbox.bin.join(exe("cargo"))).unwrap();
+        if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+            sandbox
+        } else {
+            sandbox.with_linker(NATIVE_TRIPLE, "x86_64-linux-gnu-gcc")
+        }
+    }
+
+    /// Take cargo off PATH, to see cargo-kubos fail to run it
+    pub fn without_cargo(self) -> Sandbox {
+        fs::remove_file(self.bin.join(exe("cargo"))).unwrap();
+        self
+    }
+
+    /// Put a fake gcc of the name on PATH and configure
+    /// it as the triple's linker in `$CARGO_HOME`
+    pub fn with_linker(self, triple: &str, gcc: &str) -> Sandbox {
+        let bin = self.bin.clone();
+        self.with_linker_in(triple, &bin, gcc)
+    }
+
+    /// Install a fake gcc of the name in the directory, which needn't be on
+    /// PATH, and configure its path, less any `.exe`, as the triple's linker
+    pub fn with_linker_in(self, triple: &str, dir: &Path, gcc: &str) -> Sandbox {
+        let gcc = self.install_gcc(dir, gcc);
+        let config = self.home_config();
+        self.with_config(
+            &config,
+            &format!("[target.{}]\nlinker = {:?}\n", triple, gcc),
+        )
+    }
+
+    /// Install a fake gcc of the name in the directory, which needn't be on
+    /// PATH, returning its path less any `.exe`
+    pub fn install_gcc(&self, dir: &Path, gcc: &str) -> PathBuf {
+        self.install_stub(dir, gcc)
+    }
+
+    /// Install a stub program of the name in the directory, recording how
+    /// it's run for [`Sandbox::calls`], returning its path less any `.exe`
+    pub fn install_stub(&self, dir: &Path, name: &str) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        fs::copy(fake_cargo(), dir.join(exe(name))).unwrap();
+        dir.join(name)
+    }
+
+    /// The cargo config in `$CARGO_HOME`
+    pub fn home_config(&self) -> PathBuf {
+        self.home.join(".cargo").join("config.toml")
+    }
+
+    /// Add the contents to the cargo config at the path, creating it
+    /// and its directory if they don't exist
+    pub fn with_config(self, path: &Path, contents: &str) -> Sandbox {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        self
+    }
+
+    /// Set a variable for cargo-kubos, and so for the fake cargo
+    pub fn env(mut self, key: &str, value: &str) -> Sandbox {
+        self.vars.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// `cargo kubos` with the arguments, as cargo runs it, to run in the project
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = self.direct_command(&[]);
+        command.arg("kubos").args(args);
+        command
+    }
+
+    /// `cargo-kubos` run directly with the arguments, to run in the project
+    pub fn direct_command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_cargo-kubos"));
+        command
+            .args(args)
+            .current_dir(&self.project)
+            .env_clear()
+            .env("PATH", &self.bin)
+            .env("HOME", &self.home)
+            .env("USERPROFILE", &self.home)
+            .env("CARGO_HOME", self.home.join(".cargo"))
+            .env("FAKE_CARGO_RECORD", &self.records)
+            .envs(self.vars.iter().map(|(k, v)| (k, v)));
+        // Windows programs can't start without knowing where Windows is
+        if let Some(root) = env::var_os("SYSTEMROOT") {
+            command.env("SYSTEMROOT", root);
+        }
+        command
+    }
+
+    /// Run `cargo kubos` with the arguments to completion
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().expect("running cargo-kubos")
+    }
+
+    /// Run `cargo-kubos` directly with the arguments to completion
+    pub fn run_direct(&self, args: &[&str]) -> Output {
+        self.direct_command(args)
+            .output()
+            .expect("running cargo-kubos")
+    }
+
+    /// Every time the fake cargo was run, in order
+    pub fn invocations(&self) -> Vec<Invocation> {
+        read_invocations(&self.records)
+    }
+
+    /// Every time the stub or fake gcc of the name was run, in order
+    pub fn calls(&self, name: &str) -> Vec<Invocation> {
+        read_invocations(&self.records.join(name))
+    }
+
+    /// The invocations building something, leaving out `cargo metadata`
+    pub fn builds(&self) -> Vec<Invocation> {
+        self.invocations()
+            .into_iter()
+            .filter(|invocation| invocation.args.first().map(String::as_str) != Some("metadata"))
+            .collect()
+    }
+
+    /// The one build cargo-kubos ran, failing the test if there wasn't exactly one
+    pub fn build(&self) -> Invocation {
+        let mut builds = self.builds();
+        assert_eq!(
+            builds.len(),
+            1,
+            "expected one cargo build, got {:?}",
+            builds
+        );
+        builds.remove(0)
+    }
+}
+
+/// What cargo-kubos wrote to stderr, for assertion messages
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
@@ -0,0 +1,112 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! A runner which runs nothing, recording what it was asked to run
+//! and answering with what the test scripted
+
+use cargo_kubos::{Capture, ProcessRunner};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus};
+use std::time::Duration;
+
+/// What a run comes to: how the program exited and its output
+type RunResult = io::Result<(ExitStatus, Vec<u8>)>;
+
+/// A program the runner was asked to run
+#[derive(Debug)]
+pub struct Call {
+    pub program: String,
+    pub args: Vec<String>,
+    /// The variables set, or with `None` removed, for the program
+    pub env: Vec<(String, Option<String>)>,
+}
+
+impl Call {
+    /// What the program was given for a variable
+    pub fn env(&self, key: &str) -> Option<&str> {
+        self.env
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, value)| value.as_deref())
+    }
+}
+
+/// Answers each run with the next scripted result, and once
+/// those run out, with success and no output
+#[derive(Default)]
+pub struct ScriptedRunner {
+    results: RefCell<VecDeque<RunResult>>,
+    calls: RefCell<Vec<Call>>,
+}
+
+impl ScriptedRunner {
+    pub fn new() -> ScriptedRunner {
+        ScriptedRunner::default()
+    }
+
+    /// Have the next program exit with the code
+    pub fn exits(self, code: i32) -> ScriptedRunner {
+        self.outputs(code, "")
+    }
+
+    /// Have the next program exit with the code, having written the output
+    pub fn outputs(self, code: i32, stdout: &str) -> ScriptedRunner {
+        let status = ExitStatus::from_raw((code & 0xff) << 8);
+        self.results
+            .borrow_mut()
+            .push_back(Ok((status, stdout.as_bytes().to_vec())));
+        self
+    }
+
+    /// Have the next program fail to start
+    pub fn fails(self, kind: io::ErrorKind) -> ScriptedRunner {
+        self.results
+            .borrow_mut()
+            .push_back(Err(io::Error::from(kind)));
+        self
+    }
+
+    /// What was run, in order
+    pub fn calls(&self) -> std::cell::Ref<'_, Vec<Call>> {
+        self.calls.borrow()
+    }
+}
+
+impl ProcessRunner for ScriptedRunner {
+    fn run(
+        &self,
+        command: &mut Command,
+        _timeout: Option<Duration>,
+        _capture: Capture,
+    ) -> RunResult {
+        let lossy = |s: &std::ffi::OsStr| s.to_string_lossy().into_owned();
+        self.calls.borrow_mut().push(Call {
+            program: lossy(command.get_program()),
+            args: command.get_args().map(lossy).collect(),
+            env: command
+                .get_envs()
+                .map(|(key, value)| (lossy(key), value.map(lossy)))
+                .collect(),
+        });
+        self.results
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or_else(|| Ok((ExitStatus::from_raw(0), vec![])))
+    }
+}
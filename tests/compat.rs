@@ -0,0 +1,259 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Every documented way of invoking cargo-kubos, as a subcommand and in
+//! the older `-c COMMAND` form, comes to the same cargo command line.
+//! Dry runs show it without building, so the deploying commands are
+//! covered without a board.
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+const BB: &str = "arm-unknown-linux-gnueabihf";
+const NATIVE: &str = "x86_64-unknown-linux-gnu";
+
+/// The forms of a command line, and the cargo command each should run
+const MATRIX: &[(&[&[&str]], &str)] = &[
+    // The cargo scope, defaulting to build
+    (
+        &[
+            &["-t", "bb"],
+            &["build", "-t", "bb"],
+            &["-c", "build", "-t", "bb"],
+            &["-t", "bb", "-c", "build"],
+        ],
+        "cargo build --target arm-unknown-linux-gnueabihf",
+    ),
+    // The examples in --help
+    (
+        &[
+            &["build", "-t", "x86-linux-native", "--", "-vv"],
+            &["-c", "build", "-t", "x86-linux-native", "--", "-vv"],
+        ],
+        "cargo build --target x86_64-unknown-linux-gnu -vv",
+    ),
+    (
+        &[
+            &["test", "-t", "bb", "--", "--lib", "--features", "foo"],
+            &["-c", "test", "-t", "bb", "--", "--lib", "--features", "foo"],
+        ],
+        "cargo test --target arm-unknown-linux-gnueabihf --lib --features foo",
+    ),
+    // Arguments in the command go before --target
+    (
+        &[&["-c", "test --lib", "-t", "bb", "--", "--features", "foo"]],
+        "cargo test --lib --target arm-unknown-linux-gnueabihf --features foo",
+    ),
+    (
+        &[
+            &["clippy", "-T", "armv7-unknown-linux-gnueabihf"],
+            &["-c", "clippy", "-T", "armv7-unknown-linux-gnueabihf"],
+        ],
+        "cargo clippy --target armv7-unknown-linux-gnueabihf",
+    ),
+    // Options of the cargo scope, before or after the command
+    (
+        &[
+            &["build", "-t", "isis", "--release"],
+            &["--release", "build", "-t", "isis"],
+            &["-c", "build", "-t", "isis", "--release"],
+            &["-c", "build --release", "-t", "isis"],
+        ],
+        "cargo build --release --target armv5te-unknown-linux-gnueabi",
+    ),
+    (
+        &[
+            &["check", "-t", "bb", "--features", "a,b", "--features", "c"],
+            &[
+                "-c",
+                "check",
+                "-t",
+                "bb",
+                "--features",
+                "a",
+                "--features",
+                "b c",
+            ],
+        ],
+        "cargo check --features a,b,c --target arm-unknown-linux-gnueabihf",
+    ),
+    // Commands cargo-kubos doesn't know are given the target another way
+    (
+        &[
+            &["nextest", "run", "-t", "bb"],
+            &["-c", "nextest run", "-t", "bb"],
+        ],
+        "cargo nextest run",
+    ),
+    // The commands of its own, which build first
+    (
+        &[
+            &["flash", "-t", "bb", "--host", "me@board"],
+            &[
+                "flash",
+                "-t",
+                "bb",
+                "--host",
+                "me@board",
+                "--dest",
+                "/home/system",
+            ],
+            &["run-remote", "-t", "bb", "--env", "A=1", "--", "x", "y"],
+            &["debug", "-t", "bb", "--", "x"],
+            &["package", "-t", "bb", "--format", "ipk"],
+            &["size", "-t", "bb", "--max-size", "100"],
+        ],
+        "cargo build --target arm-unknown-linux-gnueabihf",
+    ),
+    (
+        &[
+            &["flash", "-t", "bb", "--host", "me@board", "--release"],
+            &["package", "-t", "bb", "--release"],
+        ],
+        "cargo build --release --target arm-unknown-linux-gnueabihf",
+    ),
+];
+
+/// The cargo command a dry run of the arguments shows
+fn dry_run(sandbox: &Sandbox, args: &[&str]) -> String {
+    let mut argv = vec!["--dry-run"];
+    argv.extend(args);
+    let output = sandbox.run(&argv);
+    assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+    assert!(!stderr(&output).contains("deprecated"), "{:?}", args);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let command = stdout
+        .lines()
+        .filter(|line| line.starts_with("cargo "))
+        .collect::<Vec<_>>();
+    assert_eq!(command.len(), 1, "{:?}: {}", args, stdout);
+    String::from(command[0])
+}
+
+#[test]
+fn every_form_runs_the_same_cargo_command() {
+    let sandbox = Sandbox::new("every_form_runs_the_same_cargo_command")
+        .with_linker(BB, "arm-linux-gnueabihf-gcc")
+        .with_linker("armv5te-unknown-linux-gnueabi", "arm-linux-gcc")
+        .with_linker("armv7-unknown-linux-gnueabihf", "armv7-linux-gnueabihf-gcc");
+    for (forms, expected) in MATRIX {
+        for args in forms.iter() {
+            assert_eq!(dry_run(&sandbox, args), *expected, "{:?}", args);
+        }
+    }
+    assert!(sandbox.builds().is_empty(), "a dry run ran cargo");
+}
+
+/// The toolchain is given to cargo in its environment, which a dry run
+/// doesn't show
+#[test]
+fn toolchain_goes_to_cargo_in_every_form() {
+    let forms: &[&[&str]] = &[
+        &["+nightly", "build", "-t", "native", "--", "-Z", "build-std"],
+        &[
+            "+nightly",
+            "-c",
+            "build",
+            "-t",
+            "native",
+            "--",
+            "-Z",
+            "build-std",
+        ],
+        &[
+            "build",
+            "--toolchain",
+            "nightly",
+            "-t",
+            "native",
+            "--",
+            "-Z",
+            "build-std",
+        ],
+    ];
+    for (i, args) in forms.iter().enumerate() {
+        let sandbox = Sandbox::new(&format!("toolchain_goes_to_cargo_in_every_form_{}", i));
+        sandbox.install_stub(&sandbox.bin, "rustup");
+        let output = sandbox.run(args);
+        assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+        let build = sandbox.build();
+        assert_eq!(
+            build.args,
+            ["build", "--target", NATIVE, "-Z", "build-std"],
+            "{:?}",
+            args
+        );
+        assert_eq!(build.env("RUSTUP_TOOLCHAIN"), Some("nightly"), "{:?}", args);
+    }
+}
+
+#[test]
+fn every_command_has_its_own_help() {
+    let sandbox = Sandbox::new("every_command_has_its_own_help");
+    for command in [
+        "flash",
+        "run-remote",
+        "debug",
+        "package",
+        "size",
+        "doctor",
+        "setup",
+    ] {
+        for args in [vec!["help", command], vec![command, "--help"]] {
+            let output = sandbox.run(&args);
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            assert!(output.status.success(), "{:?}", args);
+            assert!(
+                stdout.contains(&format!("cargo kubos {}", command)),
+                "{:?}: {}",
+                args,
+                stdout
+            );
+        }
+    }
+}
+
+#[test]
+fn only_the_help_says_c_is_deprecated() {
+    let sandbox = Sandbox::new("only_the_help_says_c_is_deprecated");
+    let output = sandbox.run(&["--help"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("is deprecated"), "{}", stdout);
+
+    let output = sandbox.run(&["-t", "native", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        !stderr(&output).contains("deprecated"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn deploying_commands_reject_c() {
+    let sandbox = Sandbox::new("deploying_commands_reject_c");
+    for command in ["flash", "run-remote", "debug", "package", "size"] {
+        let output = sandbox.run(&["-t", "native", command, "-c", "build"]);
+        assert_eq!(output.status.code(), Some(2), "{}", command);
+        assert!(
+            stderr(&output).contains(&format!("for `cargo kubos {}`", command)),
+            "{}",
+            stderr(&output)
+        );
+    }
+    assert!(sandbox.builds().is_empty());
+}
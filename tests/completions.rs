@@ -0,0 +1,113 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The targets the completion scripts `cargo kubos completions` prints
+//! offer for `-t`, against the registry
+
+mod common;
+
+use cargo_kubos::TargetRegistry;
+use common::{stderr, Sandbox};
+use std::fs;
+
+/// Every name `-t` takes: `all`, then each built-in target's name and
+/// aliases in the registry's order
+fn registry_names() -> Vec<String> {
+    let mut names = vec![String::from("all")];
+    for target in Vec::from(TargetRegistry::builtin()) {
+        for name in std::iter::once(target.name).chain(target.aliases) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// Print the script for the shell, checking it succeeded
+fn script(sandbox: &Sandbox, shell: &str) -> String {
+    let output = sandbox.run(&["completions", shell]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The words of the array the bash or zsh script assigns, which are all
+/// plain names so are left unquoted
+fn array<'a>(script: &'a str, assignment: &str) -> Vec<&'a str> {
+    let start = script.find(assignment).unwrap() + assignment.len();
+    let end = start + script[start..].find(')').unwrap();
+    script[start..end].split_whitespace().collect()
+}
+
+/// The names the fish script's function echoes, one per line
+fn echoed<'a>(script: &'a str, function: &str) -> Vec<&'a str> {
+    let start = script.find(&format!("function {}\n", function)).unwrap();
+    script[start..]
+        .lines()
+        .skip(1)
+        .take_while(|line| *line != "end")
+        .map(|line| {
+            line.trim()
+                .strip_prefix("echo '")
+                .and_then(|name| name.strip_suffix('\''))
+                .unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn bash_offers_every_target() {
+    let sandbox = Sandbox::new("bash_offers_every_target");
+    let script = script(&sandbox, "bash");
+    assert_eq!(array(&script, "local targets=("), registry_names());
+}
+
+#[test]
+fn zsh_offers_every_target() {
+    let sandbox = Sandbox::new("zsh_offers_every_target");
+    let script = script(&sandbox, "zsh");
+    assert_eq!(array(&script, "    targets=("), registry_names());
+}
+
+#[test]
+fn fish_offers_every_target() {
+    let sandbox = Sandbox::new("fish_offers_every_target");
+    let script = script(&sandbox, "fish");
+    assert_eq!(echoed(&script, "__cargo_kubos_targets"), registry_names());
+}
+
+#[test]
+fn targets_added_by_the_project_are_offered() {
+    let sandbox = Sandbox::new("targets_added_by_the_project_are_offered");
+    fs::write(
+        sandbox.project.join("kubos-targets.toml"),
+        "[targets.kubos-linux-obc-gcc]\ntriple = \"armv7-unknown-linux-gnueabihf\"\naliases = [\"obc\"]\n",
+    )
+    .unwrap();
+    let mut expected = registry_names();
+    expected.extend([String::from("kubos-linux-obc-gcc"), String::from("obc")]);
+    for shell in &["bash", "zsh"] {
+        let script = script(&sandbox, shell);
+        let assignment = if *shell == "bash" {
+            "local targets=("
+        } else {
+            "    targets=("
+        };
+        assert_eq!(array(&script, assignment), expected, "{}", shell);
+    }
+    let script = script(&sandbox, "fish");
+    assert_eq!(echoed(&script, "__cargo_kubos_targets"), expected);
+}
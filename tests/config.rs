@@ -0,0 +1,264 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Reading the parts of cargo configs cargo-kubos cares about, and
+//! combining them across files
+
+use cargo_kubos::{
+    read_config, ConfigFile, EnvEntry, EnvValue, Linker, LinkerSource, LinkerValue, MergedConfig,
+    StringList,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn fixture(project: &str, name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/cargo-config")
+        .join(project)
+        .join(".cargo")
+        .join(name)
+}
+
+fn project_dir(project: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/cargo-config")
+        .join(project)
+}
+
+fn read(project: &str, name: &str) -> ConfigFile {
+    read_config(fixture(project, name)).unwrap()
+}
+
+/// Read a config written out for the test, returning why it was rejected
+fn rejected(name: &str, data: &str) -> String {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("config-{}.toml", name));
+    fs::write(&path, data).unwrap();
+    let error = read_config(path.clone()).unwrap_err().to_string();
+    let prefix = format!("{}: ", path.display());
+    assert!(error.starts_with(&prefix), "{}", error);
+    error[prefix.len()..].to_string()
+}
+
+fn strings(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+}
+
+#[test]
+fn project_config() {
+    let config = read("project", "config.toml").cargo;
+    assert_eq!(
+        config.build.rustflags,
+        Some(StringList::List(strings(&["-C", "target-cpu=cortex-a8"])))
+    );
+
+    let bbb = &config.target["arm-unknown-linux-gnueabihf"];
+    assert_eq!(bbb.linker, Some(LinkerValue::Path("tools/bbb-gcc".into())));
+    assert_eq!(
+        bbb.runner,
+        Some(StringList::List(strings(&[
+            "qemu-arm",
+            "-L",
+            "/usr/arm-linux-gnueabihf"
+        ])))
+    );
+    assert_eq!(
+        bbb.rustflags.as_ref().map(StringList::words),
+        Some(strings(&["-C", "link-arg=-Wl,--gc-sections"]))
+    );
+
+    assert_eq!(
+        config.target["armv5te-unknown-linux-gnueabi"].linker,
+        Some(LinkerValue::Table {
+            path: "/opt/iobc/bin/arm-linux-gcc".into(),
+            args: strings(&["-mcpu=arm926ej-s"]),
+        })
+    );
+
+    assert_eq!(
+        config.env["KUBOS_CONFIG"],
+        EnvValue::Table {
+            value: "config/flight.toml".into(),
+            force: false,
+            relative: true,
+        }
+    );
+    assert_eq!(config.env["RUST_LOG"], EnvValue::Plain("info".into()));
+}
+
+#[test]
+fn sections_cargo_kubos_doesnt_read_are_ignored() {
+    let config = read("home", "config.toml").cargo;
+    assert_eq!(
+        config.target.keys().collect::<Vec<_>>(),
+        ["arm-unknown-linux-gnueabihf", "x86_64-unknown-linux-gnu"]
+    );
+    assert_eq!(
+        config.build.rustflags,
+        Some(StringList::String("-C debuginfo=1".into()))
+    );
+    assert_eq!(config.env.len(), 2);
+}
+
+#[test]
+fn legacy_config_with_cfg_target_and_link_overrides() {
+    let config = read("embedded", "config").cargo;
+    let cfg = &config.target[r#"cfg(all(target_arch = "arm", target_os = "none"))"#];
+    assert_eq!(
+        cfg.runner.as_ref().map(StringList::words),
+        Some(strings(&["probe-rs", "run", "--chip", "STM32F407VGTx"]))
+    );
+    assert_eq!(cfg.rustflags.as_ref().map(|f| f.words().len()), Some(4));
+
+    // `[target.<triple>.<links>]` overrides are only cargo's business
+    let thumb = &config.target["thumbv7em-none-eabihf"];
+    assert_eq!(thumb.linker, None);
+    assert_eq!(thumb.runner, None);
+    assert_eq!(config.build.rustflags, None);
+}
+
+#[test]
+fn nearer_configs_take_precedence() {
+    let project = read("project", "config.toml");
+    let home = read("home", "config.toml");
+    let merged = MergedConfig::new(&[project, home]);
+
+    assert_eq!(
+        merged.paths,
+        [
+            fixture("project", "config.toml"),
+            fixture("home", "config.toml")
+        ]
+    );
+    assert_eq!(
+        merged.build_rustflags,
+        Some(strings(&[
+            "-C",
+            "debuginfo=1",
+            "-C",
+            "target-cpu=cortex-a8"
+        ]))
+    );
+
+    let bbb = merged.target("arm-unknown-linux-gnueabihf").unwrap();
+    assert_eq!(
+        bbb.linker,
+        Some(Linker::new(
+            &project_dir("project")
+                .join("tools/bbb-gcc")
+                .to_string_lossy(),
+            LinkerSource::Config(fixture("project", "config.toml")),
+        ))
+    );
+    assert_eq!(
+        bbb.runner.as_deref(),
+        Some("qemu-arm -L /usr/arm-linux-gnueabihf")
+    );
+    // Target flags are joined, farthest first
+    assert_eq!(
+        bbb.rustflags,
+        Some(strings(&[
+            "-C",
+            "link-arg=-s",
+            "-C",
+            "link-arg=-Wl,--gc-sections"
+        ]))
+    );
+
+    let x86 = merged.target("x86_64-unknown-linux-gnu").unwrap();
+    assert_eq!(x86.linker.as_ref().map(|l| l.path.as_str()), Some("clang"));
+    assert_eq!(
+        x86.linker.as_ref().map(|l| &l.source),
+        Some(&LinkerSource::Config(fixture("home", "config.toml")))
+    );
+
+    let iobc = merged.target("armv5te-unknown-linux-gnueabi").unwrap();
+    assert_eq!(
+        iobc.linker.as_ref().map(ToString::to_string).as_deref(),
+        Some("/opt/iobc/bin/arm-linux-gcc -mcpu=arm926ej-s")
+    );
+}
+
+#[test]
+fn env_is_merged_per_variable() {
+    let merged = MergedConfig::new(&[read("project", "config.toml"), read("home", "config.toml")]);
+    let mut env = merged.env.clone();
+    env.sort_by(|a, b| a.key.cmp(&b.key));
+    assert_eq!(
+        env,
+        [
+            EnvEntry {
+                key: "CC_armv7_unknown_linux_gnueabihf".into(),
+                value: "arm-linux-gnueabihf-gcc".into(),
+                force: true,
+            },
+            EnvEntry {
+                key: "KUBOS_CONFIG".into(),
+                value: project_dir("project")
+                    .join("config/flight.toml")
+                    .to_string_lossy()
+                    .into_owned(),
+                force: false,
+            },
+            EnvEntry {
+                key: "RUST_LOG".into(),
+                value: "info".into(),
+                force: false,
+            },
+        ]
+    );
+}
+
+#[test]
+fn wrong_types_cite_the_key() {
+    assert_eq!(
+        rejected(
+            "linker-type",
+            "[target.arm-unknown-linux-gnueabihf]\nlinker = 5\n"
+        ),
+        "invalid type: integer `5`, expected a linker path or a table with a path \
+         for key `target.arm-unknown-linux-gnueabihf.linker`"
+    );
+    assert_eq!(
+        rejected(
+            "linker-path",
+            "[target.arm-unknown-linux-gnueabihf.linker]\nargs = [\"-m32\"]\n"
+        ),
+        "missing field `path` for key `target.arm-unknown-linux-gnueabihf.linker`"
+    );
+    assert_eq!(
+        rejected(
+            "runner",
+            "[target.x86_64-unknown-linux-gnu]\nrunner = true\n"
+        ),
+        "invalid type: boolean `true`, expected a string or array of strings \
+         for key `target.x86_64-unknown-linux-gnu.runner`"
+    );
+    assert_eq!(
+        rejected("env", "[env]\nSYSROOT = { relative = true }\n"),
+        "missing field `value` for key `env.SYSROOT`"
+    );
+}
+
+#[test]
+fn syntax_errors_cite_the_line() {
+    assert_eq!(
+        rejected(
+            "syntax",
+            "[build]\njobs = 4\n\n[target.arm\nlinker = \"gcc\"\n"
+        ),
+        "expected a right bracket, found a newline at line 4"
+    );
+}
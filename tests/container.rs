@@ -0,0 +1,157 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `--container`: what docker or podman is told to run, with stubs of
+//! them standing in for the engines
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+extern "C" {
+    fn getuid() -> u32;
+    fn getgid() -> u32;
+}
+
+/// The arguments expected before the image, for a sandbox whose project
+/// is the workspace and the working directory
+fn mounts(sandbox: &Sandbox, user: &[&str]) -> Vec<String> {
+    let project = sandbox.project.canonicalize().unwrap();
+    let project = project.to_str().unwrap();
+    let cargo_home = sandbox.home.join(".cargo");
+    let mut args: Vec<String> = ["run", "--rm", "-i", "--init"]
+        .iter()
+        .chain(user)
+        .map(|arg| String::from(*arg))
+        .collect();
+    args.extend(vec![
+        String::from("-v"),
+        format!("{}:{}", project, project),
+        String::from("-v"),
+        format!("{}:/cargo/registry", cargo_home.join("registry").display()),
+        String::from("-v"),
+        format!("{}:/cargo/git", cargo_home.join("git").display()),
+        String::from("-e"),
+        String::from("CARGO_HOME=/cargo"),
+        String::from("-e"),
+        String::from("CARGO_KUBOS_TARGET=kubos-linux-beaglebone-gcc"),
+        String::from("-e"),
+        format!("CARGO_KUBOS_TRIPLE={}", BB_TRIPLE),
+        String::from("-e"),
+        String::from("CARGO_KUBOS_PROFILE=debug"),
+        String::from("-e"),
+        String::from("CARGO_TERM_COLOR=never"),
+        String::from("-w"),
+        String::from(project),
+    ]);
+    args
+}
+
+/// The variables the sandbox itself runs cargo-kubos with
+const SANDBOX_VARS: &[&str] = &[
+    "CARGO_HOME",
+    "FAKE_CARGO_RECORD",
+    "HOME",
+    "PATH",
+    "USERPROFILE",
+];
+
+#[test]
+fn docker_runs_cargo_in_the_targets_image() {
+    let sandbox = Sandbox::new("docker_runs_cargo_in_the_targets_image");
+    sandbox.install_stub(&sandbox.bin, "docker");
+    let output = sandbox.run(&["-t", "bb", "--container", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let calls = sandbox.calls("docker");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    let user = format!("{}:{}", unsafe { getuid() }, unsafe { getgid() });
+    let mut expected = mounts(&sandbox, &["--user", &user]);
+    expected.extend(
+        [
+            "kubos/kubos-dev:latest",
+            "cargo",
+            "build",
+            "--target",
+            BB_TRIPLE,
+        ]
+        .iter()
+        .map(|arg| String::from(*arg)),
+    );
+    assert_eq!(calls[0].args, expected);
+    // Nothing naming the host's toolchains reaches the engine
+    let vars: Vec<&str> = calls[0].env.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(vars, SANDBOX_VARS);
+    // Nor is cargo run here
+    assert!(sandbox.builds().is_empty(), "{:?}", sandbox.builds());
+}
+
+#[test]
+fn podman_keeps_the_users_id() {
+    let sandbox = Sandbox::new("podman_keeps_the_users_id");
+    sandbox.install_stub(&sandbox.bin, "podman");
+    let output = sandbox.run(&["-t", "bb", "--container", "-c", "test --lib"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let calls = sandbox.calls("podman");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    let mut expected = mounts(&sandbox, &["--userns=keep-id"]);
+    expected.extend(
+        [
+            "kubos/kubos-dev:latest",
+            "cargo",
+            "test",
+            "--lib",
+            "--target",
+            BB_TRIPLE,
+        ]
+        .iter()
+        .map(|arg| String::from(*arg)),
+    );
+    assert_eq!(calls[0].args, expected);
+}
+
+#[test]
+fn configured_image_is_used() {
+    let sandbox = Sandbox::new("configured_image_is_used");
+    let config = sandbox.home_config();
+    let sandbox = sandbox.with_config(
+        &config,
+        "[kubos.target.\"kubos-linux-beaglebone-gcc\"]\nimage = \"ghcr.io/cube-os/bbb:1.2\"\n",
+    );
+    sandbox.install_stub(&sandbox.bin, "docker");
+    let output = sandbox.run(&["-t", "bb", "--container", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let args = &sandbox.calls("docker")[0].args;
+    let cargo = args.iter().position(|arg| arg == "cargo").unwrap();
+    assert_eq!(args[cargo - 1], "ghcr.io/cube-os/bbb:1.2");
+}
+
+#[test]
+fn no_engine_is_an_environment_error() {
+    let sandbox = Sandbox::new("no_engine_is_an_environment_error");
+    let output = sandbox.run(&["-t", "bb", "--container", "build"]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("--container needs docker or podman, and neither is on PATH"),
+        "{}",
+        stderr(&output)
+    );
+}
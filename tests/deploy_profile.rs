@@ -0,0 +1,151 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Where `cargo kubos flash` copies to with a named deploy profile, whose
+//! settings override those at the top of `[package.metadata.kubos.deploy]`
+//! and the user's defaults
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::fs;
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+const ISIS_TRIPLE: &str = "armv5te-unknown-linux-gnueabi";
+
+/// A sandbox whose crate deploys to a board by default and to the
+/// flatsat with the profile of that name, with scp stubbed out
+fn with_profiles(name: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name)
+        .with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc")
+        .with_linker(ISIS_TRIPLE, "arm-linux-gcc");
+    fs::write(
+        sandbox.project.join("Cargo.toml"),
+        "[package]\nname = \"sandbox\"\nversion = \"0.1.0\"\n\n\
+         [package.metadata.kubos.deploy]\n\
+         host = \"192.0.2.1\"\nuser = \"kubos\"\ndest = \"/home/system/usr/bin\"\n\
+         target = \"bb\"\n\n\
+         [package.metadata.kubos.deploy.flatsat]\n\
+         host = \"192.0.2.50\"\nport = 2222\ndest = \"/home/kubos/${CARGO_PKG_NAME}\"\n\
+         target = \"isis\"\n",
+    )
+    .unwrap();
+    sandbox.install_stub(&sandbox.bin, "scp");
+    sandbox
+}
+
+/// What scp was run with, less the binary it copied
+fn copied_to(sandbox: &Sandbox) -> Vec<String> {
+    let calls = sandbox.calls("scp");
+    assert_eq!(calls.len(), 1);
+    let mut args = calls[0].args.clone();
+    let binary = args.len() - 2;
+    assert!(args[binary].ends_with("sandbox"), "{:?}", args);
+    args.remove(binary);
+    args
+}
+
+/// The triple the one build was for
+fn built_for(sandbox: &Sandbox) -> String {
+    let build = sandbox.build();
+    let target = build.args.iter().position(|arg| arg == "--target").unwrap();
+    build.args[target + 1].clone()
+}
+
+#[test]
+fn top_level_settings_without_a_profile() {
+    let sandbox = with_profiles("top_level_settings_without_a_profile");
+    let output = sandbox.run(&["flash"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(built_for(&sandbox), BB_TRIPLE);
+    assert_eq!(
+        copied_to(&sandbox),
+        ["-p", "kubos@192.0.2.1:/home/system/usr/bin/sandbox"]
+    );
+}
+
+#[test]
+fn profile_overrides_the_top_level() {
+    let sandbox = with_profiles("profile_overrides_the_top_level");
+    let output = sandbox.run(&["flash", "--deploy-profile", "flatsat"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(built_for(&sandbox), ISIS_TRIPLE);
+    // The user isn't set by the profile, so is still the top level's
+    assert_eq!(
+        copied_to(&sandbox),
+        [
+            "-p",
+            "-P",
+            "2222",
+            "kubos@192.0.2.50:/home/kubos/sandbox/sandbox"
+        ]
+    );
+}
+
+#[test]
+fn profile_overrides_the_users_defaults() {
+    let sandbox = with_profiles("profile_overrides_the_users_defaults");
+    let defaults = sandbox.home.join(".config/cargo-kubos/config.toml");
+    fs::create_dir_all(defaults.parent().unwrap()).unwrap();
+    fs::write(&defaults, "[deploy]\nuser = \"admin\"\nport = 22\n").unwrap();
+    let output = sandbox.run(&["flash", "--deploy-profile", "flatsat"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        copied_to(&sandbox),
+        [
+            "-p",
+            "-P",
+            "2222",
+            "kubos@192.0.2.50:/home/kubos/sandbox/sandbox"
+        ]
+    );
+}
+
+#[test]
+fn flags_override_the_profile() {
+    let sandbox = with_profiles("flags_override_the_profile");
+    let output = sandbox.run(&[
+        "flash",
+        "--deploy-profile",
+        "flatsat",
+        "-t",
+        "bb",
+        "--host",
+        "root@192.0.2.99",
+        "--dest",
+        "/tmp",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(built_for(&sandbox), BB_TRIPLE);
+    assert_eq!(
+        copied_to(&sandbox),
+        ["-p", "-P", "2222", "root@192.0.2.99:/tmp/sandbox"]
+    );
+}
+
+#[test]
+fn unknown_profile_names_those_there_are() {
+    let sandbox = with_profiles("unknown_profile_names_those_there_are");
+    let output = sandbox.run(&["flash", "--deploy-profile", "orbit"]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("unknown deploy profile 'orbit', available profiles: flatsat"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox.builds().is_empty());
+    assert!(sandbox.calls("scp").is_empty());
+}
@@ -0,0 +1,140 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Finding a cross gcc on PATH for a target when nothing configures its
+//! linker, with a directory of stubs put at the front of PATH
+
+mod common;
+
+use common::{exe, stderr, Sandbox};
+use std::env;
+use std::path::PathBuf;
+
+/// A sandbox with a directory of stub gccs of the names ahead of its
+/// own bin on PATH
+fn with_tools(name: &str, gccs: &[&str]) -> (Sandbox, PathBuf) {
+    let sandbox = Sandbox::new(name);
+    let tools = sandbox.root.join("tools");
+    for gcc in gccs {
+        sandbox.install_stub(&tools, gcc);
+    }
+    let path = env::join_paths([&tools, &sandbox.bin]).unwrap();
+    let sandbox = sandbox.env("PATH", path.to_str().unwrap());
+    (sandbox, tools)
+}
+
+/// The beaglebone compiler cargo was given
+fn compiler(sandbox: &Sandbox) -> Option<String> {
+    sandbox
+        .build()
+        .env("CC_arm_unknown_linux_gnueabihf")
+        .map(String::from)
+}
+
+#[test]
+fn gcc_on_path_is_used_and_said_so() {
+    let (sandbox, tools) = with_tools(
+        "gcc_on_path_is_used_and_said_so",
+        &["arm-none-linux-gnueabihf-gcc"],
+    );
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let gcc = tools.join(exe("arm-none-linux-gnueabihf-gcc"));
+    assert_eq!(compiler(&sandbox).as_deref(), gcc.to_str());
+    let said = format!(
+        "no linker is configured for arm-unknown-linux-gnueabihf, using {} found in PATH",
+        gcc.display()
+    );
+    assert!(stderr(&output).contains(&said), "{}", stderr(&output));
+}
+
+#[test]
+fn earlier_names_beat_earlier_directories() {
+    let (sandbox, _) = with_tools(
+        "earlier_names_beat_earlier_directories",
+        &["arm-none-linux-gnueabihf-gcc"],
+    );
+    sandbox.install_stub(&sandbox.bin, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let gcc = sandbox.bin.join(exe("arm-linux-gnueabihf-gcc"));
+    assert_eq!(compiler(&sandbox).as_deref(), gcc.to_str());
+}
+
+#[test]
+fn earlier_directories_beat_later_ones() {
+    let (sandbox, tools) = with_tools(
+        "earlier_directories_beat_later_ones",
+        &["arm-linux-gnueabihf-gcc"],
+    );
+    sandbox.install_stub(&sandbox.bin, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let gcc = tools.join(exe("arm-linux-gnueabihf-gcc"));
+    assert_eq!(compiler(&sandbox).as_deref(), gcc.to_str());
+}
+
+#[test]
+fn each_target_has_its_own_names() {
+    let (sandbox, tools) = with_tools(
+        "each_target_has_its_own_names",
+        &["arm-linux-gnueabihf-gcc", "arm-linux-gnueabi-gcc"],
+    );
+    let output = sandbox.run(&["-t", "isis", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let gcc = tools.join(exe("arm-linux-gnueabi-gcc"));
+    assert_eq!(
+        sandbox.build().env("CC_armv5te_unknown_linux_gnueabi"),
+        gcc.to_str()
+    );
+}
+
+#[test]
+fn configured_linker_beats_path() {
+    let (sandbox, _) = with_tools("configured_linker_beats_path", &["arm-linux-gnueabihf-gcc"]);
+    let off_path = sandbox.root.join("bbb/bin");
+    let sandbox = sandbox.with_linker_in(
+        "arm-unknown-linux-gnueabihf",
+        &off_path,
+        "arm-linux-gnueabihf-gcc",
+    );
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let gcc = off_path.join("arm-linux-gnueabihf-gcc");
+    assert_eq!(compiler(&sandbox).as_deref(), gcc.to_str());
+}
+
+#[test]
+fn no_toolchain_discovery_leaves_path_alone() {
+    let (sandbox, _) = with_tools(
+        "no_toolchain_discovery_leaves_path_alone",
+        &["arm-linux-gnueabihf-gcc"],
+    );
+    let output = sandbox.run(&["-t", "bb", "--no-toolchain-discovery", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert_eq!(compiler(&sandbox), None);
+    assert!(
+        stderr(&output).contains("no linker found for target arm-unknown-linux-gnueabihf"),
+        "{}",
+        stderr(&output)
+    );
+}
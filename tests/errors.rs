@@ -0,0 +1,557 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! What each error says and the code cargo-kubos exits with for it,
+//! so neither changes without the change showing up here, and which
+//! error resolving a target's linker fails with
+
+mod common;
+
+use cargo_kubos::{
+    check_linker, read_config, resolve_linker, resolve_target, ConfigError, ConfigFile,
+    ConfigLoader, Error, KubosTarget, ResolutionCache, SystemRunner, TargetError,
+};
+use common::{stderr, Sandbox};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn supported() -> Vec<String> {
+    vec![
+        String::from("x86-linux-native"),
+        String::from("kubos-linux-bbb-gcc"),
+    ]
+}
+
+#[test]
+fn usage() {
+    let error = Error::Usage(String::from("--quiet and --verbose can't be used together"));
+    assert_eq!(
+        error.to_string(),
+        "--quiet and --verbose can't be used together"
+    );
+    assert_eq!(error.exit_code(), 2);
+}
+
+#[test]
+fn unknown_target() {
+    let error = Error::from(TargetError::Unknown {
+        name: String::from("x86-linux-nativ"),
+        suggestions: vec![String::from("x86-linux-native")],
+        supported: supported(),
+    });
+    assert_eq!(
+        error.to_string(),
+        "Target 'x86-linux-nativ' not supported for cargo/yotta builds\n\
+         Did you mean 'x86-linux-native'?\n\
+         Currently supported targets are:\n\
+         x86-linux-native\n\
+         kubos-linux-bbb-gcc"
+    );
+    assert_eq!(error.exit_code(), 2);
+}
+
+#[test]
+fn unknown_target_between_two() {
+    let error = Error::from(TargetError::Unknown {
+        name: String::from("kubos-linux-bbb"),
+        suggestions: vec![
+            String::from("kubos-linux-bbb-gcc"),
+            String::from("kubos-linux-bbb-musl"),
+        ],
+        supported: supported(),
+    });
+    assert_eq!(
+        error.to_string().lines().nth(1),
+        Some("Did you mean one of 'kubos-linux-bbb-gcc', 'kubos-linux-bbb-musl'?")
+    );
+}
+
+#[test]
+fn ambiguous_target() {
+    let error = Error::from(TargetError::Ambiguous {
+        name: String::from("linux"),
+        matches: supported(),
+        supported: supported(),
+    });
+    assert_eq!(
+        error.to_string(),
+        "Target alias 'linux' is ambiguous, it could mean any of: \
+         x86-linux-native, kubos-linux-bbb-gcc\n\
+         Currently supported targets are:\n\
+         x86-linux-native\n\
+         kubos-linux-bbb-gcc"
+    );
+    assert_eq!(error.exit_code(), 2);
+}
+
+#[test]
+fn triple_mismatch() {
+    let error = Error::from(TargetError::TripleMismatch {
+        name: String::from("x86-linux-native"),
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        requested: String::from("aarch64-unknown-linux-gnu"),
+    });
+    assert_eq!(
+        error.to_string(),
+        "Target 'x86-linux-native' maps to triple 'x86_64-unknown-linux-gnu', \
+         which conflicts with --target-triple 'aarch64-unknown-linux-gnu'"
+    );
+    assert_eq!(error.exit_code(), 2);
+}
+
+#[test]
+fn config_parse() {
+    let source = "[target\nlinker = 1".parse::<toml::Value>().unwrap_err();
+    let error = Error::from(ConfigError::Parse {
+        path: PathBuf::from("/home/kubos/.cargo/config.toml"),
+        source,
+    });
+    assert_eq!(
+        error.to_string(),
+        "/home/kubos/.cargo/config.toml: expected a right bracket, found a newline at line 1"
+    );
+    assert_eq!(error.exit_code(), 5);
+    assert!(std::error::Error::source(&error).is_some());
+}
+
+#[test]
+fn config_read() {
+    let error = Error::from(ConfigError::Read {
+        path: PathBuf::from("/ci/cargo.toml"),
+        source: io::Error::new(io::ErrorKind::PermissionDenied, "Permission denied"),
+    });
+    assert_eq!(error.to_string(), "/ci/cargo.toml: Permission denied");
+    assert_eq!(error.exit_code(), 5);
+}
+
+/// Whether there's guidance on building in the SDK depends on the
+/// environment, so this runs cargo-kubos with the variables it needs
+/// rather than setting them for every test in the binary
+#[test]
+fn linker_not_found() {
+    let sandbox = Sandbox::new("linker_not_found");
+    let config = sandbox.home.join(".cargo/config.toml");
+    fs::create_dir_all(config.parent().unwrap()).unwrap();
+    fs::write(&config, "[build]\n").unwrap();
+    let expected = format!(
+        "no linker found for target arm-unknown-linux-gnueabihf: \
+         `[target.arm-unknown-linux-gnueabihf].linker` is not set in {}\n\
+         Add one to your cargo config, e.g.:\n\
+         \n    [target.arm-unknown-linux-gnueabihf]\n    \
+         linker = \"/path/to/gcc\"\n",
+        config.display()
+    );
+    let args = [
+        "-t",
+        "bb",
+        "--strict-linker",
+        "--no-toolchain-discovery",
+        "-c",
+        "build",
+    ];
+
+    let in_sdk = sandbox
+        .command(&args)
+        .env("KUBOS_SDK", "1")
+        .output()
+        .unwrap();
+    assert_eq!(in_sdk.status.code(), Some(5), "{}", stderr(&in_sdk));
+    assert!(stderr(&in_sdk).contains(&expected), "{}", stderr(&in_sdk));
+    assert!(!stderr(&in_sdk).contains("Vagrant"), "{}", stderr(&in_sdk));
+
+    // Only outside the SDK is there guidance on building in it too
+    let outside = sandbox
+        .command(&args)
+        .env("KUBOS_SDK_PATH", &sandbox.root)
+        .output()
+        .unwrap();
+    assert_eq!(outside.status.code(), Some(5), "{}", stderr(&outside));
+    assert!(stderr(&outside).contains(&expected), "{}", stderr(&outside));
+    assert!(stderr(&outside).contains("Vagrant"), "{}", stderr(&outside));
+    assert!(sandbox.builds().is_empty());
+}
+
+#[test]
+fn spawn_failed() {
+    let missing = Error::Spawn {
+        program: String::from("cargo"),
+        source: io::Error::from(io::ErrorKind::NotFound),
+    };
+    assert_eq!(missing.to_string(), "cargo not found on PATH");
+    assert_eq!(missing.exit_code(), 127);
+
+    let denied = Error::Spawn {
+        program: String::from("yt"),
+        source: io::Error::new(io::ErrorKind::PermissionDenied, "Permission denied"),
+    };
+    assert_eq!(denied.to_string(), "failed to run yt: Permission denied");
+    assert_eq!(denied.exit_code(), 127);
+}
+
+#[cfg(unix)]
+#[test]
+fn child_failed() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let error = Error::Child {
+        program: String::from("cargo"),
+        status: std::process::ExitStatus::from_raw(101 << 8),
+    };
+    assert_eq!(error.to_string(), "cargo failed (exit status: 101)");
+    assert_eq!(error.exit_code(), 101);
+    assert!(error.reported());
+}
+
+#[test]
+fn failed_and_exit() {
+    let failed = Error::Failed(String::from("post-build hook `strip` failed"));
+    assert_eq!(failed.to_string(), "post-build hook `strip` failed");
+    assert_eq!(failed.exit_code(), 1);
+    assert!(!failed.reported());
+
+    let exit = Error::Exit(4);
+    assert_eq!(exit.exit_code(), 4);
+    assert!(exit.reported());
+}
+
+/// Reads only the configs it's given, or finds none, having tried some paths
+struct Configs(Result<Vec<PathBuf>, Vec<PathBuf>>);
+
+impl ConfigLoader for Configs {
+    fn paths(&self) -> Result<Vec<PathBuf>, Vec<PathBuf>> {
+        self.0.clone()
+    }
+
+    fn load(&self, path: &Path) -> Result<ConfigFile, ConfigError> {
+        read_config(path.to_path_buf())
+    }
+}
+
+/// Write a config for a test, returning its path
+fn config(name: &str, contents: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("errors");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// A cross target no toolchain on this machine could be found for
+fn unfindable() -> KubosTarget {
+    KubosTarget::new("kubos-sparc", "sparc-unknown-kubos-gnu")
+}
+
+#[test]
+fn malformed_config_fails_resolution_as_config_error() {
+    let path = config("malformed.toml", "[target.arm-unknown-linux-gnueabihf\n");
+    let cache = ResolutionCache::with_loader(Configs(Ok(vec![path.clone()])));
+    let target = resolve_target("kubos-linux-beaglebone-gcc").unwrap();
+
+    match resolve_linker(&target, &cache) {
+        Err(Error::Config(ConfigError::Parse { path: bad, .. })) => assert_eq!(bad, path),
+        other => panic!("expected a config parse error, got {:?}", other),
+    }
+    match check_linker(&target, &SystemRunner, &cache) {
+        Err(Error::Config(ConfigError::Parse { path: bad, .. })) => assert_eq!(bad, path),
+        other => panic!("expected a config parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn mistyped_linker_fails_resolution_as_config_error() {
+    let path = config(
+        "mistyped.toml",
+        "[target.arm-unknown-linux-gnueabihf]\nlinker = 1\n",
+    );
+    let cache = ResolutionCache::with_loader(Configs(Ok(vec![path.clone()])));
+    let target = resolve_target("kubos-linux-beaglebone-gcc").unwrap();
+
+    match resolve_linker(&target, &cache) {
+        Err(Error::Config(ConfigError::Parse { path: bad, .. })) => assert_eq!(bad, path),
+        other => panic!("expected a config parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn unreadable_config_fails_resolution_as_config_error() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("errors/missing.toml");
+    let cache = ResolutionCache::with_loader(Configs(Ok(vec![path.clone()])));
+
+    match resolve_linker(&unfindable(), &cache) {
+        Err(Error::Config(ConfigError::Read { path: bad, source })) => {
+            assert_eq!(bad, path);
+            assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        }
+        other => panic!("expected a config read error, got {:?}", other),
+    }
+}
+
+#[test]
+fn missing_linker_lists_the_configs_searched() {
+    let project = config("project.toml", "[build]\nrustflags = []\n");
+    let home = config(
+        "home.toml",
+        "[target.sparc-unknown-kubos-gnux]\nlinker = \"sparc-gcc\"\n",
+    );
+    let cache = ResolutionCache::with_loader(Configs(Ok(vec![project.clone(), home.clone()])));
+
+    match resolve_linker(&unfindable(), &cache) {
+        Err(Error::LinkerNotFound {
+            triple,
+            searched,
+            found_config,
+            closest,
+        }) => {
+            assert_eq!(triple, "sparc-unknown-kubos-gnu");
+            assert_eq!(searched, [project, home]);
+            assert!(found_config);
+            assert_eq!(closest.as_deref(), Some("sparc-unknown-kubos-gnux"));
+        }
+        other => panic!("expected no linker to be found, got {:?}", other),
+    }
+}
+
+#[test]
+fn missing_linker_without_configs_lists_the_paths_tried() {
+    let tried = vec![
+        PathBuf::from("/work/.cargo/config.toml"),
+        PathBuf::from("/work/.cargo/config"),
+    ];
+    let cache = ResolutionCache::with_loader(Configs(Err(tried.clone())));
+
+    match resolve_linker(&unfindable(), &cache) {
+        Err(Error::LinkerNotFound {
+            searched,
+            found_config,
+            closest,
+            ..
+        }) => {
+            assert_eq!(searched, tried);
+            assert!(!found_config);
+            assert_eq!(closest, None);
+        }
+        other => panic!("expected no linker to be found, got {:?}", other),
+    }
+}
+
+/// Run cargo-kubos in the sandbox, checking that it exits with the code
+/// and says why in a single error, without a backtrace, returning stderr
+fn exits_with(sandbox: &Sandbox, args: &[&str], code: i32, says: &str) -> String {
+    let output = sandbox.run(args);
+    let stderr = stderr(&output);
+    assert_eq!(output.status.code(), Some(code), "{:?}: {}", args, stderr);
+    // Said once, as the error
+    let errors: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.starts_with("Error - "))
+        .collect();
+    assert_eq!(errors.len(), 1, "{}", stderr);
+    assert!(errors[0].contains(says), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(!stderr.contains("backtrace"), "{}", stderr);
+    stderr
+}
+
+#[test]
+fn usage_errors_exit_2() {
+    let sandbox = Sandbox::new("usage_errors_exit_2");
+    exits_with(&sandbox, &["--bogus", "build"], 2, "bogus");
+    exits_with(&sandbox, &["-t", "nope", "build"], 2, "nope");
+    let stderr = exits_with(
+        &sandbox,
+        &["-t", "kubos-linux-beagelbone-gcc", "build"],
+        2,
+        "Target 'kubos-linux-beagelbone-gcc' not supported",
+    );
+    assert!(
+        stderr.contains("\nDid you mean 'kubos-linux-beaglebone-gcc'?\n"),
+        "{}",
+        stderr
+    );
+    exits_with(
+        &sandbox,
+        &["-t", "native", "flash"],
+        2,
+        "no board to deploy to",
+    );
+    assert!(sandbox.builds().is_empty());
+}
+
+/// A sandbox whose crate has the post-build hook
+fn with_hook(name: &str, hook: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name);
+    fs::write(
+        sandbox.project.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"sandbox\"\nversion = \"0.1.0\"\n\n\
+             [package.metadata.kubos.hooks]\npost-build = [\"{}\"]\n",
+            hook
+        ),
+    )
+    .unwrap();
+    sandbox
+}
+
+#[test]
+fn broken_hooks_exit_1() {
+    let sandbox = Sandbox::new("broken_hooks_exit_1");
+    fs::write(
+        sandbox.project.join("Cargo.toml"),
+        "[package]\nname = \"sandbox\"\nversion = \"0.1.0\"\n\n\
+         [package.metadata.kubos.hooks]\npost-build = \"not-a-list\"\n",
+    )
+    .unwrap();
+    exits_with(
+        &sandbox,
+        &["-t", "native", "build"],
+        1,
+        "post-build must be an array of strings",
+    );
+    assert_eq!(sandbox.builds().len(), 1);
+}
+
+#[test]
+fn failed_hook_exits_with_its_code() {
+    let sandbox = with_hook("failed_hook_exits_with_its_code", "failing-hook")
+        .env("FAKE_FAILING_HOOK_EXIT", "3");
+    sandbox.install_stub(&sandbox.bin, "failing-hook");
+    exits_with(
+        &sandbox,
+        &["-t", "native", "build"],
+        3,
+        "post-build hook 'failing-hook' failed",
+    );
+    assert_eq!(sandbox.builds().len(), 1);
+    assert_eq!(sandbox.calls("failing-hook").len(), 1);
+}
+
+#[test]
+fn missing_program_exits_127() {
+    let sandbox = with_hook("missing_program_exits_127", "missing-hook");
+    exits_with(
+        &sandbox,
+        &["-t", "native", "build"],
+        127,
+        "missing-hook not found on PATH",
+    );
+
+    let sandbox = Sandbox::new("missing_program_exits_127_cargo").without_cargo();
+    exits_with(
+        &sandbox,
+        &["-t", "native", "build"],
+        127,
+        "cargo not found on PATH",
+    );
+}
+
+#[test]
+fn broken_environment_exits_5() {
+    let sandbox = Sandbox::new("broken_environment_exits_5");
+    exits_with(
+        &sandbox,
+        &["-t", "bb", "--strict-linker", "build"],
+        5,
+        "no linker found",
+    );
+    let config = sandbox.project.join(".cargo/config.toml");
+    let sandbox = sandbox.with_config(&config, "[target\n");
+    exits_with(&sandbox, &["-t", "bb", "build"], 5, "config.toml");
+    assert!(sandbox.builds().is_empty());
+}
+
+#[test]
+fn timing_out_exits_124() {
+    let sandbox = Sandbox::new("timing_out_exits_124").env("FAKE_CARGO_SLEEP", "60");
+    exits_with(
+        &sandbox,
+        &["-t", "native", "--timeout", "1", "build"],
+        124,
+        "timed out",
+    );
+}
+
+#[test]
+fn failed_build_exits_with_cargos_code() {
+    let sandbox = Sandbox::new("failed_build_exits_with_cargos_code").env("FAKE_CARGO_EXIT", "101");
+    let output = sandbox.run(&["-t", "native", "build"]);
+    assert_eq!(output.status.code(), Some(101), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
+
+#[test]
+fn nothing_to_deploy_exits_3() {
+    let sandbox = Sandbox::new("nothing_to_deploy_exits_3");
+    fs::rename(
+        sandbox.project.join("src/main.rs"),
+        sandbox.project.join("src/lib.rs"),
+    )
+    .unwrap();
+    exits_with(
+        &sandbox,
+        &["-t", "native", "flash", "--host", "192.0.2.1"],
+        3,
+        "cargo didn't build an executable to deploy",
+    );
+}
+
+#[test]
+fn failed_copy_exits_4() {
+    let sandbox = Sandbox::new("failed_copy_exits_4").env("FAKE_SCP_EXIT", "1");
+    sandbox.install_stub(&sandbox.bin, "scp");
+    exits_with(
+        &sandbox,
+        &["-t", "native", "flash", "--host", "192.0.2.1"],
+        4,
+        "to 192.0.2.1:/home/system/usr/bin/sandbox failed",
+    );
+    assert_eq!(sandbox.calls("scp").len(), 1);
+}
+
+/// A sandbox with linkers for the beaglebone and the iOBC, for
+/// building both at once
+fn with_two_targets(name: &str) -> Sandbox {
+    Sandbox::new(name)
+        .with_linker("arm-unknown-linux-gnueabihf", "arm-linux-gnueabihf-gcc")
+        .with_linker("armv5te-unknown-linux-gnueabi", "arm-linux-gcc")
+}
+
+#[test]
+fn timing_out_beats_a_larger_exit_code() {
+    let sandbox = with_two_targets("timing_out_beats_a_larger_exit_code")
+        .env("FAKE_CARGO_EXIT_ARM_UNKNOWN_LINUX_GNUEABIHF", "125")
+        .env("FAKE_CARGO_SLEEP_ARMV5TE_UNKNOWN_LINUX_GNUEABI", "60");
+    let output = sandbox.run(&["-t", "bb", "-t", "isis", "--timeout", "1", "build"]);
+    assert_eq!(output.status.code(), Some(124), "{}", stderr(&output));
+    assert_eq!(sandbox.builds().len(), 2);
+}
+
+#[test]
+fn equally_bad_failures_exit_with_the_first() {
+    let sandbox = with_two_targets("equally_bad_failures_exit_with_the_first")
+        .env("FAKE_CARGO_EXIT_ARM_UNKNOWN_LINUX_GNUEABIHF", "1")
+        .env("FAKE_CARGO_EXIT_ARMV5TE_UNKNOWN_LINUX_GNUEABI", "101");
+    let output = sandbox.run(&["-t", "bb", "-t", "isis", "build"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+}
+
+#[test]
+fn one_failure_fails_them_all() {
+    let sandbox = with_two_targets("one_failure_fails_them_all")
+        .env("FAKE_CARGO_EXIT_ARMV5TE_UNKNOWN_LINUX_GNUEABI", "101");
+    let output = sandbox.run(&["-t", "bb", "-t", "isis", "build"]);
+    assert_eq!(output.status.code(), Some(101), "{}", stderr(&output));
+}
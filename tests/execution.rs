@@ -0,0 +1,217 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Running what's built for a cross target: cargo-kubos hands cargo the
+//! runner configured for it, or for tests qemu, which the fake cargo runs
+//! as cargo would
+
+mod common;
+
+use common::{exe, stderr, Sandbox};
+use std::fs;
+
+const BEAGLEBONE_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+const RUNNER_VAR: &str = "CARGO_TARGET_ARM_UNKNOWN_LINUX_GNUEABIHF_RUNNER";
+
+/// The binary cargo hands the runner, as the fake cargo names it
+const BINARY: &str = "target/arm-unknown-linux-gnueabihf/debug/sandbox";
+
+/// A sandbox with the beaglebone's linker in `$CARGO_HOME`'s config and a
+/// stub runner, configured with the runner's entry in the crate's
+fn with_runner(name: &str, runner: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name).with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let stub = sandbox.install_stub(&sandbox.root.join("qemu"), "stub-runner");
+    let config = sandbox.project.join(".cargo/config.toml");
+    let runner = runner.replace("STUB", stub.to_str().unwrap());
+    sandbox.with_config(
+        &config,
+        &format!("[target.{}]\nrunner = {}\n", BEAGLEBONE_TRIPLE, runner),
+    )
+}
+
+#[test]
+fn configured_runner_runs_the_binary() {
+    let sandbox = with_runner(
+        "configured_runner_runs_the_binary",
+        "\"STUB -L /opt/bbb/sysroot\"",
+    );
+    let output = sandbox.run(&["-t", "bb", "-c", "run", "--", "--", "hello"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let stub = sandbox.root.join("qemu/stub-runner");
+    let runner = format!("{} -L /opt/bbb/sysroot", stub.display());
+    assert_eq!(sandbox.build().env(RUNNER_VAR), Some(runner.as_str()));
+    let calls = sandbox.calls("stub-runner");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    assert_eq!(calls[0].args, ["-L", "/opt/bbb/sysroot", BINARY, "hello"]);
+}
+
+#[test]
+fn array_runner_runs_the_tests() {
+    let sandbox = with_runner(
+        "array_runner_runs_the_tests",
+        "[\"STUB\", \"-cpu\", \"cortex-a8\"]",
+    );
+    let output = sandbox.run(&["-t", "bb", "-c", "test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let calls = sandbox.calls("stub-runner");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    assert_eq!(calls[0].args, ["-cpu", "cortex-a8", BINARY]);
+}
+
+#[test]
+fn runner_variable_beats_the_config() {
+    let sandbox = with_runner("runner_variable_beats_the_config", "\"STUB\"");
+    let other = sandbox.install_stub(&sandbox.root.join("other"), "other-runner");
+    let sandbox = sandbox.env(RUNNER_VAR, other.to_str().unwrap());
+    let output = sandbox.run(&["-t", "bb", "-c", "run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert!(sandbox.calls("stub-runner").is_empty());
+    assert_eq!(sandbox.calls("other-runner").len(), 1);
+}
+
+#[test]
+fn no_runner_is_warned_of() {
+    let sandbox = Sandbox::new("no_runner_is_warned_of")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "-c", "run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert!(
+        stderr(&output).contains("no runner configured for target arm-unknown-linux-gnueabihf"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.build().env(RUNNER_VAR), None);
+}
+
+#[test]
+fn native_builds_need_no_runner() {
+    let sandbox = Sandbox::new("native_builds_need_no_runner");
+    let output = sandbox.run(&["-t", "native", "-c", "run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert!(!stderr(&output).contains("runner"), "{}", stderr(&output));
+    assert_eq!(
+        sandbox
+            .build()
+            .env("CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_RUNNER"),
+        None
+    );
+}
+
+/// A sandbox with the beaglebone's linker, a stub qemu-arm on PATH and a
+/// sysroot, returned with it
+fn with_qemu(name: &str) -> (Sandbox, String) {
+    let sandbox = Sandbox::new(name).with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    sandbox.install_stub(&sandbox.bin, "qemu-arm");
+    let sysroot = sandbox.root.join("bbb/sysroot");
+    fs::create_dir_all(sysroot.join("usr/lib")).unwrap();
+    let sysroot = sysroot.to_string_lossy().into_owned();
+    (sandbox, sysroot)
+}
+
+#[test]
+fn qemu_runs_the_tests() {
+    let (sandbox, sysroot) = with_qemu("qemu_runs_the_tests");
+    let output = sandbox.run(&["-t", "bb", "--sysroot", &sysroot, "-c", "test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let qemu = sandbox.bin.join(exe("qemu-arm"));
+    let runner = format!("{} -L {}", qemu.display(), sysroot);
+    assert_eq!(sandbox.build().env(RUNNER_VAR), Some(runner.as_str()));
+    let calls = sandbox.calls("qemu-arm");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    assert_eq!(calls[0].args, ["-L", sysroot.as_str(), BINARY]);
+}
+
+#[test]
+fn qemu_runs_the_benchmarks_without_a_sysroot() {
+    let (sandbox, _) = with_qemu("qemu_runs_the_benchmarks_without_a_sysroot");
+    let output = sandbox.run(&["-t", "bb", "-c", "bench"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let calls = sandbox.calls("qemu-arm");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    assert_eq!(calls[0].args, [BINARY]);
+}
+
+#[test]
+fn qemu_runs_the_older_boards_tests() {
+    let sandbox = Sandbox::new("qemu_runs_the_older_boards_tests")
+        .with_linker("armv5te-unknown-linux-gnueabi", "armv5te-linux-gnueabi-gcc");
+    sandbox.install_stub(&sandbox.bin, "qemu-arm");
+    let output = sandbox.run(&["-t", "isis", "-c", "test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let calls = sandbox.calls("qemu-arm");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    assert_eq!(
+        calls[0].args,
+        ["target/armv5te-unknown-linux-gnueabi/debug/sandbox"]
+    );
+}
+
+#[test]
+fn qemu_is_only_for_tests() {
+    let (sandbox, _) = with_qemu("qemu_is_only_for_tests");
+    let output = sandbox.run(&["-t", "bb", "-c", "run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert!(sandbox.calls("qemu-arm").is_empty());
+    assert_eq!(sandbox.build().env(RUNNER_VAR), None);
+}
+
+#[test]
+fn configured_runner_beats_qemu() {
+    let sandbox = with_runner("configured_runner_beats_qemu", "\"STUB\"");
+    sandbox.install_stub(&sandbox.bin, "qemu-arm");
+    let output = sandbox.run(&["-t", "bb", "-c", "test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert!(sandbox.calls("qemu-arm").is_empty());
+    assert_eq!(sandbox.calls("stub-runner").len(), 1);
+}
+
+#[test]
+fn no_qemu_leaves_the_tests_unrun() {
+    let (sandbox, _) = with_qemu("no_qemu_leaves_the_tests_unrun");
+    let output = sandbox.run(&["-t", "bb", "--no-qemu", "-c", "test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert!(sandbox.calls("qemu-arm").is_empty());
+    assert_eq!(sandbox.build().env(RUNNER_VAR), None);
+}
+
+#[test]
+fn missing_qemu_says_how_to_get_it() {
+    let sandbox = Sandbox::new("missing_qemu_says_how_to_get_it")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "-c", "test"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    for expected in &[
+        "qemu-arm not found on PATH",
+        "apt install qemu-user",
+        "--no-qemu",
+    ] {
+        assert!(stderr(&output).contains(expected), "{}", stderr(&output));
+    }
+    assert_eq!(sandbox.build().env(RUNNER_VAR), None);
+}
@@ -0,0 +1,53 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! A build script leaning on what cargo-kubos tells it about the build,
+//! which fails unless that's all there and agrees with what cargo says
+
+use std::env;
+use std::path::Path;
+
+fn var(key: &str) -> String {
+    env::var(key).unwrap_or_else(|_| panic!("{} isn't set", key))
+}
+
+fn main() {
+    let target = var("CARGO_KUBOS_TARGET");
+    let triple = var("CARGO_KUBOS_TRIPLE");
+    assert_eq!(triple, var("TARGET"), "{} is for another triple", target);
+    assert_eq!(var("CARGO_KUBOS_PROFILE"), var("PROFILE"));
+
+    // A cross gcc is found, its prefix naming the rest of its toolchain
+    if let Ok(linker) = env::var("CARGO_KUBOS_LINKER") {
+        let path = Path::new(&linker);
+        assert!(path.is_absolute(), "linker {} isn't absolute", linker);
+        assert!(path.is_file(), "linker {} doesn't exist", linker);
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        assert_eq!(name, format!("{}gcc", var("CARGO_KUBOS_TOOLCHAIN_PREFIX")));
+    } else {
+        assert!(env::var_os("CARGO_KUBOS_TOOLCHAIN_PREFIX").is_none());
+    }
+    if let Ok(sysroot) = env::var("CARGO_KUBOS_SYSROOT") {
+        assert!(
+            Path::new(&sysroot).is_dir(),
+            "sysroot {} isn't a directory",
+            sysroot
+        );
+        println!("cargo:rustc-link-search=native={}/usr/lib", sysroot);
+    }
+
+    println!("cargo:rustc-cfg=kubos_target=\"{}\"", target);
+}
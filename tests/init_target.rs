@@ -0,0 +1,145 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos init-target`: the cargo config it writes for each target
+//! in the registry, which the builds that follow then use
+
+mod common;
+
+use cargo_kubos::TargetRegistry;
+use common::{stderr, Sandbox};
+use std::fs;
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+#[test]
+fn stanza_names_each_targets_triple() {
+    let sandbox = Sandbox::new("stanza_names_each_targets_triple");
+    for target in Vec::from(TargetRegistry::builtin()) {
+        for name in std::iter::once(&target.name).chain(&target.aliases) {
+            let output = sandbox.run(&[
+                "init-target",
+                "-t",
+                name,
+                "--linker",
+                "/opt/toolchain/bin/gcc",
+                "--dry-run",
+            ]);
+            assert!(output.status.success(), "{}: {}", name, stderr(&output));
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                format!(
+                    "[target.{}]\nlinker = \"/opt/toolchain/bin/gcc\"\n",
+                    target.triple
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+    assert!(!sandbox.project.join(".cargo").exists());
+}
+
+#[test]
+fn written_linker_is_built_with() {
+    let sandbox = Sandbox::new("written_linker_is_built_with");
+    let gcc = sandbox.install_gcc(&sandbox.root.join("toolchain"), "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["init-target", "-t", "bb", "--linker", gcc.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stanza = format!(
+        "[target.{}]\nlinker = {:?}\n",
+        BB_TRIPLE,
+        gcc.to_str().unwrap()
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("Wrote to .cargo/config.toml:\n\n{}\n", stanza)
+    );
+    assert_eq!(
+        fs::read_to_string(sandbox.project.join(".cargo/config.toml")).unwrap(),
+        stanza
+    );
+
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        sandbox.build().env("CC_arm_unknown_linux_gnueabihf"),
+        gcc.to_str()
+    );
+}
+
+#[test]
+fn global_goes_to_cargo_home() {
+    let sandbox = Sandbox::new("global_goes_to_cargo_home");
+    let output = sandbox.run(&[
+        "init-target",
+        "-t",
+        "isis",
+        "--linker",
+        "arm-linux-gcc",
+        "--global",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(sandbox.home_config()).unwrap(),
+        "[target.armv5te-unknown-linux-gnueabi]\nlinker = \"arm-linux-gcc\"\n"
+    );
+    assert!(!sandbox.project.join(".cargo").exists());
+}
+
+#[test]
+fn existing_entry_is_only_replaced_with_force() {
+    let sandbox = Sandbox::new("existing_entry_is_only_replaced_with_force");
+    let config = sandbox.project.join(".cargo/config.toml");
+    let sandbox = sandbox.with_config(
+        &config,
+        &format!(
+            "[build]\njobs = 2\n\n[target.{}]\nlinker = \"old-gcc\"\n\n[env]\nSATELLITE = \"kubos\"\n",
+            BB_TRIPLE
+        ),
+    );
+    let output = sandbox.run(&["init-target", "-t", "bb", "--linker", "new-gcc"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(&format!(
+            ".cargo/config.toml already has an entry for [target.{}], pass --force to replace it",
+            BB_TRIPLE
+        )),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = sandbox.run(&["init-target", "-t", "bb", "--linker", "new-gcc", "--force"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let contents = fs::read_to_string(&config).unwrap();
+    assert!(!contents.contains("old-gcc"), "{}", contents);
+    assert!(contents.contains("new-gcc"), "{}", contents);
+    // The rest of the config is left as it was
+    assert!(contents.starts_with("[build]\njobs = 2\n"), "{}", contents);
+    assert!(
+        contents.contains("[env]\nSATELLITE = \"kubos\"\n"),
+        "{}",
+        contents
+    );
+}
+
+#[test]
+fn unknown_target_is_a_usage_error() {
+    let sandbox = Sandbox::new("unknown_target_is_a_usage_error");
+    let output = sandbox.run(&["init-target", "-t", "beaglebone", "--linker", "gcc"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(!sandbox.project.join(".cargo").exists());
+}
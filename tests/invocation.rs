@@ -0,0 +1,198 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! How cargo-kubos runs cargo: its arguments, its environment,
+//! and what cargo-kubos exits with
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+const BEAGLEBONE_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+#[test]
+fn target_follows_command() {
+    let sandbox = Sandbox::new("target_follows_command");
+    let output = sandbox.run(&["-t", "x86-linux-native", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(
+        build.args,
+        ["build", "--target", "x86_64-unknown-linux-gnu"]
+    );
+    assert_eq!(build.env("CARGO_KUBOS_TARGET"), Some("x86-linux-native"));
+}
+
+#[test]
+fn extra_args_follow_target_in_order() {
+    let sandbox = Sandbox::new("extra_args_follow_target_in_order");
+    let output = sandbox.run(&[
+        "-t",
+        "x86-linux-native",
+        "-c",
+        "build",
+        "--",
+        "--release",
+        "--features",
+        "a b",
+        "",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(
+        build.args,
+        [
+            "build",
+            "--target",
+            "x86_64-unknown-linux-gnu",
+            "--release",
+            "--features",
+            "a b",
+            "",
+        ]
+    );
+    assert_eq!(build.env("CARGO_KUBOS_PROFILE"), Some("release"));
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn native_build_is_not_cross() {
+    let sandbox = Sandbox::new("native_build_is_not_cross");
+    let output = sandbox.run(&["-t", "x86-linux-native", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(build.env("PKG_CONFIG_ALLOW_CROSS"), None);
+    assert_eq!(build.env("CC_x86_64_unknown_linux_gnu"), None);
+}
+
+#[test]
+fn cross_build_gets_compilers() {
+    let sandbox = Sandbox::new("cross_build_gets_compilers")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    let gcc = sandbox.bin.join("arm-linux-gnueabihf-gcc");
+    assert_eq!(build.args, ["build", "--target", BEAGLEBONE_TRIPLE]);
+    assert_eq!(
+        build.env("CARGO_KUBOS_TARGET"),
+        Some("kubos-linux-beaglebone-gcc")
+    );
+    assert_eq!(build.env("CC_arm_unknown_linux_gnueabihf"), gcc.to_str());
+    assert!(build.env("CXX_arm_unknown_linux_gnueabihf").is_some());
+    assert_eq!(build.env("PKG_CONFIG_ALLOW_CROSS"), Some("1"));
+}
+
+#[test]
+fn cross_build_with_linker_installed_off_path() {
+    // Like a Linaro toolchain installed under `C:\Program Files` on Windows
+    let sandbox = Sandbox::new("cross_build_with_linker_installed_off_path");
+    let toolchain = sandbox.root.join("Linaro ARM").join("bin");
+    let sandbox = sandbox.with_linker_in(BEAGLEBONE_TRIPLE, &toolchain, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    let gcc = toolchain.join("arm-linux-gnueabihf-gcc");
+    assert_eq!(build.args, ["build", "--target", BEAGLEBONE_TRIPLE]);
+    assert_eq!(build.env("CC_arm_unknown_linux_gnueabihf"), gcc.to_str());
+    assert_eq!(build.env("CXX_arm_unknown_linux_gnueabihf"), gcc.to_str());
+}
+
+#[test]
+fn pkg_config_allow_cross_set_to_0_is_kept() {
+    let sandbox = Sandbox::new("pkg_config_allow_cross_set_to_0_is_kept")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc")
+        .env("PKG_CONFIG_ALLOW_CROSS", "0");
+    let output = sandbox.run(&["-t", "bb", "-v", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert_eq!(sandbox.build().env("PKG_CONFIG_ALLOW_CROSS"), Some("0"));
+    assert!(
+        stderr(&output).contains("PKG_CONFIG_ALLOW_CROSS inherited as 0"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn pkg_config_allow_cross_from_the_config_is_kept() {
+    let sandbox = Sandbox::new("pkg_config_allow_cross_from_the_config_is_kept")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let config = sandbox.project.join(".cargo/config.toml");
+    let sandbox = sandbox.with_config(&config, "[env]\nPKG_CONFIG_ALLOW_CROSS = \"0\"\n");
+    let output = sandbox.run(&["-t", "bb", "-v", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert_eq!(sandbox.build().env("PKG_CONFIG_ALLOW_CROSS"), Some("0"));
+    assert!(
+        stderr(&output).contains("PKG_CONFIG_ALLOW_CROSS set to 0 by the cargo config"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn no_pkg_config_cross_leaves_it_unset() {
+    let sandbox = Sandbox::new("no_pkg_config_cross_leaves_it_unset")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "-v", "--no-pkg-config-cross", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert_eq!(sandbox.build().env("PKG_CONFIG_ALLOW_CROSS"), None);
+    assert!(
+        stderr(&output).contains("suppressed by --no-pkg-config-cross"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn native_build_with_a_linker_is_not_cross() {
+    let sandbox = Sandbox::new("native_build_with_a_linker_is_not_cross")
+        .with_linker("x86_64-unknown-linux-gnu", "x86_64-linux-gnu-gcc");
+    let output = sandbox.run(&["-t", "x86-linux-native", "-c", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert_eq!(sandbox.build().env("PKG_CONFIG_ALLOW_CROSS"), None);
+}
+
+#[test]
+fn cargo_exit_code_is_passed_on() {
+    for code in [0, 1, 3, 101] {
+        let sandbox = Sandbox::new(&format!("cargo_exit_code_is_passed_on_{}", code))
+            .env("FAKE_CARGO_EXIT", &code.to_string());
+        let output = sandbox.run(&["-t", "x86-linux-native", "-c", "build"]);
+        assert_eq!(output.status.code(), Some(code), "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn missing_cargo_exits_127() {
+    let sandbox = Sandbox::new("missing_cargo_exits_127").without_cargo();
+    let output = sandbox.run(&["-t", "x86-linux-native", "-c", "build"]);
+    assert_eq!(output.status.code(), Some(127), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("cargo not found"),
+        "{}",
+        stderr(&output)
+    );
+}
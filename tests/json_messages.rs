@@ -0,0 +1,120 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! What the tools reading cargo's JSON messages get on stdout
+//! when cargo-kubos runs the build for them
+
+mod common;
+
+// The crate's own parser, to read stdout as a tool would
+#[allow(dead_code)]
+#[path = "../src/json.rs"]
+mod json;
+
+use common::{stderr, Sandbox};
+use json::Json;
+use std::process::Output;
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+/// Each line of stdout, parsed, failing on any which isn't JSON
+fn messages(output: &Output) -> Vec<Json> {
+    String::from_utf8(output.stdout.clone())
+        .unwrap()
+        .lines()
+        .map(|line| match json::parse(line) {
+            Ok(message) => message,
+            Err(e) => panic!("stdout line {:?} isn't JSON: {}", line, e),
+        })
+        .collect()
+}
+
+fn reasons(messages: &[Json]) -> Vec<&str> {
+    messages
+        .iter()
+        .map(|message| message.get("reason").and_then(Json::as_str).unwrap())
+        .collect()
+}
+
+#[test]
+fn one_target_prints_only_cargos_messages() {
+    let sandbox = Sandbox::new("one_target_prints_only_cargos_messages")
+        .with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&[
+        "-t",
+        "bb",
+        "--message-format",
+        "json-diagnostic-rendered-ansi",
+        "build",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(reasons(&messages(&output)), ["compiler-artifact"]);
+    assert!(sandbox.build().args.contains(&String::from(
+        "--message-format=json-diagnostic-rendered-ansi"
+    )));
+}
+
+#[test]
+fn several_targets_summarize_as_json() {
+    // The beaglebone's gcc can't be found, so there's a warning too
+    let sandbox = Sandbox::new("several_targets_summarize_as_json");
+    let output = sandbox.run(&[
+        "-t",
+        "native",
+        "-t",
+        "bb",
+        "build",
+        "--",
+        "--message-format=json",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("warning: "), "{}", stderr(&output));
+
+    let messages = messages(&output);
+    assert_eq!(
+        reasons(&messages),
+        [
+            "compiler-artifact",
+            "compiler-artifact",
+            "kubos-build-summary"
+        ]
+    );
+    let targets = messages[2].get("targets").and_then(Json::as_array).unwrap();
+    let built: Vec<_> = targets
+        .iter()
+        .map(|t| t.get("target").and_then(Json::as_str).unwrap())
+        .collect();
+    assert_eq!(built, ["x86-linux-native", "kubos-linux-beaglebone-gcc"]);
+}
+
+#[test]
+fn failed_target_still_summarizes_as_json() {
+    let sandbox = Sandbox::new("failed_target_still_summarizes_as_json")
+        .with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc")
+        .env("FAKE_CARGO_EXIT", "101");
+    let output = sandbox.run(&[
+        "-t",
+        "native",
+        "-t",
+        "bb",
+        "--message-format",
+        "json",
+        "build",
+    ]);
+    assert_eq!(output.status.code(), Some(101), "{}", stderr(&output));
+    let messages = messages(&output);
+    assert_eq!(reasons(&messages).last(), Some(&"kubos-build-summary"));
+}
@@ -0,0 +1,168 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! How cargo is run for a target, worked out through the library as a
+//! build tool would, without running cargo-kubos
+
+mod common;
+
+use cargo_kubos::{
+    read_config, resolve_linker, resolve_target, CargoInvocation, ConfigError, ConfigFile,
+    ConfigLoader, LinkerSource, ResolutionCache,
+};
+use common::Sandbox;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const BEAGLEBONE_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+/// Cargo configs at the given paths, rather than those cargo would find
+struct Configs(Vec<PathBuf>);
+
+impl ConfigLoader for Configs {
+    fn paths(&self) -> Result<Vec<PathBuf>, Vec<PathBuf>> {
+        Ok(self.0.clone())
+    }
+
+    fn load(&self, path: &Path) -> Result<ConfigFile, ConfigError> {
+        read_config(path.to_path_buf())
+    }
+}
+
+/// A cache reading only a config which sets the BeagleBone's linker to
+/// a gcc installed in the sandbox, returning the gcc's path too
+fn beaglebone_config(sandbox: &Sandbox) -> (Arc<ResolutionCache>, PathBuf) {
+    let gcc = sandbox.install_gcc(&sandbox.root.join("toolchain"), "arm-linux-gnueabihf-gcc");
+    let config = sandbox.root.join("config.toml");
+    let linker = gcc.to_string_lossy().replace('\\', "\\\\");
+    fs::write(
+        &config,
+        format!("[target.{}]\nlinker = \"{}\"\n", BEAGLEBONE_TRIPLE, linker),
+    )
+    .unwrap();
+    let cache = ResolutionCache::with_loader(Configs(vec![config]));
+    (Arc::new(cache), gcc)
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| String::from(*arg)).collect()
+}
+
+#[test]
+fn target_follows_command() {
+    let target = resolve_target("x86-linux-native").unwrap();
+    let invocation = CargoInvocation::new(&target, &args(&["build"]));
+    assert_eq!(
+        invocation.args,
+        ["build", "--target", "x86_64-unknown-linux-gnu"]
+    );
+    assert_eq!(
+        invocation.env.get("CARGO_KUBOS_TARGET"),
+        Some("x86-linux-native")
+    );
+}
+
+#[test]
+fn extra_args_follow_target_in_order() {
+    let target = resolve_target("x86-linux-native").unwrap();
+    let command = args(&["build", "--release", "--features", "a b", ""]);
+    let invocation = CargoInvocation::new(&target, &command);
+    assert_eq!(
+        invocation.args,
+        [
+            "build",
+            "--target",
+            "x86_64-unknown-linux-gnu",
+            "--release",
+            "--features",
+            "a b",
+            "",
+        ]
+    );
+    assert_eq!(invocation.env.get("CARGO_KUBOS_PROFILE"), Some("release"));
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn native_build_is_not_cross() {
+    let target = resolve_target("x86-linux-native").unwrap();
+    let invocation = CargoInvocation::new(&target, &args(&["build"]));
+    assert_eq!(invocation.env.get("PKG_CONFIG_ALLOW_CROSS"), None);
+    assert_eq!(invocation.env.get("CC_x86_64_unknown_linux_gnu"), None);
+}
+
+#[test]
+fn configured_linker_is_resolved() {
+    let sandbox = Sandbox::new("library_configured_linker_is_resolved");
+    let (cache, gcc) = beaglebone_config(&sandbox);
+    let target = resolve_target("bb").unwrap();
+
+    let linker = resolve_linker(&target, &cache).unwrap();
+    assert_eq!(linker.program(), gcc.to_string_lossy());
+    assert!(
+        matches!(linker.source, LinkerSource::Config(_)),
+        "{:?}",
+        linker.source
+    );
+}
+
+#[test]
+fn cross_build_gets_compilers() {
+    let sandbox = Sandbox::new("library_cross_build_gets_compilers");
+    let (cache, gcc) = beaglebone_config(&sandbox);
+    let target = resolve_target("bb").unwrap();
+
+    let invocation = CargoInvocation::with_cache(&target, &args(&["build"]), &cache);
+    assert_eq!(invocation.args, ["build", "--target", BEAGLEBONE_TRIPLE]);
+    assert_eq!(
+        invocation.env.get("CARGO_KUBOS_TARGET"),
+        Some("kubos-linux-beaglebone-gcc")
+    );
+    assert_eq!(
+        invocation.env.get("CC_arm_unknown_linux_gnueabihf"),
+        gcc.to_str()
+    );
+    assert_eq!(
+        invocation.env.get("CXX_arm_unknown_linux_gnueabihf"),
+        gcc.to_str()
+    );
+    assert_eq!(invocation.env.get("PKG_CONFIG_ALLOW_CROSS"), Some("1"));
+}
+
+#[test]
+fn command_runs_cargo_with_the_environment() {
+    let sandbox = Sandbox::new("library_command_runs_cargo_with_the_environment");
+    let (cache, gcc) = beaglebone_config(&sandbox);
+    let target = resolve_target("bb").unwrap();
+    let invocation = CargoInvocation::with_cache(&target, &args(&["build"]), &cache);
+
+    let command = invocation.command();
+    assert_eq!(command.get_program(), "cargo");
+    let command_args: Vec<_> = command.get_args().map(|arg| arg.to_str()).collect();
+    let expected: Vec<_> = invocation
+        .args
+        .iter()
+        .map(|arg| Some(arg.as_str()))
+        .collect();
+    assert_eq!(command_args, expected);
+    let cc = command
+        .get_envs()
+        .find(|(key, _)| *key == "CC_arm_unknown_linux_gnueabihf")
+        .and_then(|(_, value)| value);
+    assert_eq!(cc, Some(gcc.as_os_str()));
+    assert_eq!(command.get_envs().count(), invocation.env.vars().len());
+}
@@ -0,0 +1,455 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Where cargo-kubos finds a cross target's linker, and what cargo and
+//! the C compilers it runs are given because of it
+
+mod common;
+
+use common::{exe, stderr, Sandbox};
+
+const BEAGLEBONE_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+/// The beaglebone's C flags variable, which the cc crate reads for it alone
+const BEAGLEBONE_CFLAGS: &str = "CFLAGS_arm_unknown_linux_gnueabihf";
+
+#[test]
+fn string_linker_is_the_compiler() {
+    let sandbox = Sandbox::new("string_linker_is_the_compiler")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    let gcc = sandbox.bin.join("arm-linux-gnueabihf-gcc");
+    assert_eq!(build.env("CC_arm_unknown_linux_gnueabihf"), gcc.to_str());
+    assert_eq!(build.env("CXX_arm_unknown_linux_gnueabihf"), gcc.to_str());
+    assert_eq!(
+        build.env(BEAGLEBONE_CFLAGS),
+        Some("-march=armv7-a -mfpu=neon -mfloat-abi=hard")
+    );
+}
+
+#[test]
+fn table_linker_args_go_to_the_target_alone() {
+    let sandbox = Sandbox::new("table_linker_args_go_to_the_target_alone");
+    let gcc = sandbox.install_gcc(&sandbox.root.join("bbb/bin"), "arm-linux-gnueabihf-gcc");
+    let config = sandbox.home_config();
+    let linker = format!(
+        "{{ path = {:?}, args = [\"--sysroot=/opt/bbb/sysroot\", \"-mcpu=cortex-a8\"] }}",
+        gcc
+    );
+    let sandbox = sandbox
+        .with_config(
+            &config,
+            &format!("[target.{}]\nlinker = {}\n", BEAGLEBONE_TRIPLE, linker),
+        )
+        .env("CFLAGS", "-O2");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(build.env("CC_arm_unknown_linux_gnueabihf"), gcc.to_str());
+    let cflags = build.env(BEAGLEBONE_CFLAGS).unwrap_or_default();
+    assert!(
+        cflags.ends_with("--sysroot=/opt/bbb/sysroot -mcpu=cortex-a8"),
+        "{}",
+        cflags
+    );
+    assert_eq!(
+        build.env("CXXFLAGS_arm_unknown_linux_gnueabihf"),
+        Some(cflags)
+    );
+    // Build scripts and host code are compiled with the host's flags
+    assert_eq!(build.env("CFLAGS"), Some("-O2"));
+    assert_eq!(build.env("CXXFLAGS"), None);
+    // The link still gets them
+    let rustflags: Vec<&str> = build
+        .env("CARGO_ENCODED_RUSTFLAGS")
+        .unwrap_or_default()
+        .split('\x1f')
+        .collect();
+    assert!(
+        rustflags.contains(&"-Clink-arg=--sysroot=/opt/bbb/sysroot"),
+        "{:?}",
+        rustflags
+    );
+    assert!(
+        rustflags.contains(&"-Clink-arg=-mcpu=cortex-a8"),
+        "{:?}",
+        rustflags
+    );
+}
+
+#[test]
+fn malformed_table_linker_is_a_config_error() {
+    let sandbox = Sandbox::new("malformed_table_linker_is_a_config_error");
+    let config = sandbox.home_config();
+    let sandbox = sandbox.with_config(
+        &config,
+        &format!(
+            "[target.{}]\nlinker = {{ args = [\"-mcpu=cortex-a8\"] }}\n",
+            BEAGLEBONE_TRIPLE
+        ),
+    );
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(&format!("{}: missing field `path`", config.display())),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox.builds().is_empty());
+}
+
+/// A sandbox whose crate sits in a workspace directory, with a gcc of its
+/// own name configured as the beaglebone's linker in each of the configs
+/// named, among the crate's, the workspace's and `$CARGO_HOME`'s
+fn layered_linkers(name: &str, layers: &[&str]) -> Sandbox {
+    let mut sandbox = Sandbox::new(name);
+    for layer in layers {
+        let dir = match *layer {
+            "crate" => sandbox.project.join(".cargo"),
+            "workspace" => sandbox.root.join(".cargo"),
+            "home" => sandbox.home.join(".cargo"),
+            other => panic!("no config layer {}", other),
+        };
+        let gcc = sandbox.install_gcc(&sandbox.bin, &format!("{}-gcc", layer));
+        let config = dir.join("config.toml");
+        sandbox = sandbox.with_config(
+            &config,
+            &format!("[target.{}]\nlinker = {:?}\n", BEAGLEBONE_TRIPLE, gcc),
+        );
+    }
+    sandbox
+}
+
+/// The linker the one build was given as the beaglebone's C compiler
+fn built_with(sandbox: &Sandbox) -> String {
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let build = sandbox.build();
+    let cc = build.env("CC_arm_unknown_linux_gnueabihf").unwrap();
+    String::from(cc)
+}
+
+#[test]
+fn crate_config_beats_those_further_up() {
+    let sandbox = layered_linkers(
+        "crate_config_beats_those_further_up",
+        &["crate", "workspace", "home"],
+    );
+    assert_eq!(
+        built_with(&sandbox),
+        sandbox.bin.join("crate-gcc").to_str().unwrap()
+    );
+}
+
+#[test]
+fn workspace_config_beats_cargo_home() {
+    let sandbox = layered_linkers("workspace_config_beats_cargo_home", &["workspace", "home"]);
+    let config = sandbox.project.join(".cargo").join("config.toml");
+    // A nearer config without a linker doesn't hide those further up
+    let sandbox = sandbox.with_config(&config, "[build]\njobs = 2\n");
+    assert_eq!(
+        built_with(&sandbox),
+        sandbox.bin.join("workspace-gcc").to_str().unwrap()
+    );
+}
+
+#[test]
+fn cargo_home_config_is_the_fallback() {
+    let sandbox = layered_linkers("cargo_home_config_is_the_fallback", &["home"]);
+    assert_eq!(
+        built_with(&sandbox),
+        sandbox.bin.join("home-gcc").to_str().unwrap()
+    );
+}
+
+#[test]
+fn given_config_beats_the_crates() {
+    let sandbox = layered_linkers("given_config_beats_the_crates", &["crate", "home"]);
+    let gcc = sandbox.install_gcc(&sandbox.bin, "given-gcc");
+    let given = sandbox.root.join("given.toml");
+    let sandbox = sandbox.with_config(
+        &given,
+        &format!("[target.{}]\nlinker = {:?}\n", BEAGLEBONE_TRIPLE, gcc),
+    );
+    let output = sandbox.run(&[
+        "-t",
+        "bb",
+        "--cargo-config",
+        given.to_str().unwrap(),
+        "build",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let build = sandbox.build();
+    assert_eq!(build.env("CC_arm_unknown_linux_gnueabihf"), gcc.to_str());
+}
+
+#[test]
+fn linker_variable_beats_the_configs() {
+    let sandbox = layered_linkers("linker_variable_beats_the_configs", &["crate", "home"]);
+    let gcc = sandbox.install_gcc(&sandbox.bin, "variable-gcc");
+    let sandbox = sandbox.env(
+        "CARGO_TARGET_ARM_UNKNOWN_LINUX_GNUEABIHF_LINKER",
+        gcc.to_str().unwrap(),
+    );
+    assert_eq!(built_with(&sandbox), gcc.to_str().unwrap());
+}
+
+#[test]
+fn empty_linker_variable_is_ignored() {
+    let sandbox = layered_linkers("empty_linker_variable_is_ignored", &["home"])
+        .env("CARGO_TARGET_ARM_UNKNOWN_LINUX_GNUEABIHF_LINKER", "");
+    assert_eq!(
+        built_with(&sandbox),
+        sandbox.bin.join("home-gcc").to_str().unwrap()
+    );
+}
+
+#[test]
+fn cargo_home_variable_names_the_config_dir() {
+    let sandbox = layered_linkers("cargo_home_variable_names_the_config_dir", &["home"]);
+    let elsewhere = sandbox.root.join("elsewhere");
+    let gcc = sandbox.install_gcc(&sandbox.bin, "elsewhere-gcc");
+    let sandbox = sandbox
+        .with_config(
+            &elsewhere.join("config.toml"),
+            &format!("[target.{}]\nlinker = {:?}\n", BEAGLEBONE_TRIPLE, gcc),
+        )
+        .env("CARGO_HOME", elsewhere.to_str().unwrap());
+    assert_eq!(built_with(&sandbox), gcc.to_str().unwrap());
+}
+
+#[test]
+fn cargo_home_defaults_to_the_home_directory() {
+    let sandbox = layered_linkers("cargo_home_defaults_to_the_home_directory", &["home"]);
+    let output = sandbox
+        .command(&["-t", "bb", "build"])
+        .env_remove("CARGO_HOME")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let build = sandbox.build();
+    let gcc = sandbox.bin.join("home-gcc");
+    assert_eq!(build.env("CC_arm_unknown_linux_gnueabihf"), gcc.to_str());
+}
+
+#[test]
+fn no_cargo_home_or_home_directory() {
+    let sandbox = layered_linkers("no_cargo_home_or_home_directory", &["home"]);
+    let output = sandbox
+        .command(&[
+            "-t",
+            "bb",
+            "--strict-linker",
+            "--no-toolchain-discovery",
+            "build",
+        ])
+        .env_remove("CARGO_HOME")
+        .env_remove("HOME")
+        .env_remove("USERPROFILE")
+        .output()
+        .unwrap();
+    // Only the crate's directory and its parents are left to look in
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    let tried = sandbox.project.join(".cargo").join("config.toml");
+    assert!(
+        stderr(&output).contains(&format!("no cargo config found, tried {}", tried.display())),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        !stderr(&output).contains(&sandbox.home.display().to_string()),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox.builds().is_empty());
+}
+
+/// A beaglebone toolchain directory, off PATH, with a gcc and the binutils
+/// of the names, the gcc configured as the linker
+fn toolchain(name: &str, binutils: &[&str]) -> Sandbox {
+    let sandbox = Sandbox::new(name);
+    let dir = sandbox.root.join("bbb/bin");
+    for tool in binutils {
+        sandbox.install_stub(&dir, &format!("arm-linux-gnueabihf-{}", tool));
+    }
+    sandbox.with_linker_in(BEAGLEBONE_TRIPLE, &dir, "arm-linux-gnueabihf-gcc")
+}
+
+/// A tool of the toolchain's, as it's exported
+fn tool(sandbox: &Sandbox, tool: &str) -> String {
+    let path = sandbox
+        .root
+        .join("bbb/bin")
+        .join(exe(&format!("arm-linux-gnueabihf-{}", tool)));
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn binutils_next_to_the_gcc_are_exported() {
+    let sandbox = toolchain(
+        "binutils_next_to_the_gcc_are_exported",
+        &["ar", "ranlib", "strip", "objcopy"],
+    );
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    for (var, name) in &[
+        ("AR", "ar"),
+        ("RANLIB", "ranlib"),
+        ("STRIP", "strip"),
+        ("OBJCOPY", "objcopy"),
+    ] {
+        let expected = tool(&sandbox, name);
+        assert_eq!(build.env(var), Some(expected.as_str()), "{}", var);
+        let var = format!("{}_arm_unknown_linux_gnueabihf", var);
+        assert_eq!(build.env(&var), Some(expected.as_str()), "{}", var);
+    }
+}
+
+#[test]
+fn missing_binutils_are_left_unset() {
+    let sandbox = toolchain("missing_binutils_are_left_unset", &["ar", "strip"]);
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(build.env("AR"), Some(tool(&sandbox, "ar").as_str()));
+    assert_eq!(build.env("STRIP"), Some(tool(&sandbox, "strip").as_str()));
+    for var in &["RANLIB", "OBJCOPY"] {
+        assert_eq!(build.env(var), None, "{}", var);
+        let var = format!("{}_arm_unknown_linux_gnueabihf", var);
+        assert_eq!(build.env(&var), None, "{}", var);
+    }
+}
+
+#[test]
+fn binutils_already_set_are_kept() {
+    let sandbox = toolchain("binutils_already_set_are_kept", &["ar", "ranlib"])
+        .env("AR", "/opt/host/ar")
+        .env("RANLIB_arm_unknown_linux_gnueabihf", "/opt/bbb/ranlib");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(build.env("AR"), Some("/opt/host/ar"));
+    assert_eq!(
+        build.env("AR_arm_unknown_linux_gnueabihf"),
+        Some(tool(&sandbox, "ar").as_str())
+    );
+    assert_eq!(build.env("RANLIB"), Some(tool(&sandbox, "ranlib").as_str()));
+    assert_eq!(
+        build.env("RANLIB_arm_unknown_linux_gnueabihf"),
+        Some("/opt/bbb/ranlib")
+    );
+}
+
+#[test]
+fn binutils_elsewhere_on_path_are_found() {
+    let sandbox = toolchain("binutils_elsewhere_on_path_are_found", &[]);
+    sandbox.install_stub(&sandbox.bin, "arm-linux-gnueabihf-ar");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let ar = sandbox.bin.join(exe("arm-linux-gnueabihf-ar"));
+    assert_eq!(sandbox.build().env("AR"), ar.to_str());
+}
+
+/// A Buildroot style prefix, which none of the beaglebone's default gcc
+/// names have, so only `CROSS_COMPILE` can find its gcc
+const BUILDROOT_PREFIX: &str = "arm-buildroot-linux-gnueabihf-";
+
+#[test]
+fn cross_compile_prefix_finds_the_toolchain_on_path() {
+    let sandbox = Sandbox::new("cross_compile_prefix_finds_the_toolchain_on_path")
+        .env("CROSS_COMPILE", BUILDROOT_PREFIX);
+    for tool in &["gcc", "ar", "strip"] {
+        sandbox.install_stub(&sandbox.bin, &format!("{}{}", BUILDROOT_PREFIX, tool));
+    }
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let on_path = |tool: &str| {
+        let path = sandbox
+            .bin
+            .join(exe(&format!("{}{}", BUILDROOT_PREFIX, tool)));
+        path.to_string_lossy().into_owned()
+    };
+    let build = sandbox.build();
+    assert_eq!(
+        build.env("CC_arm_unknown_linux_gnueabihf"),
+        Some(on_path("gcc").as_str())
+    );
+    assert_eq!(build.env("AR"), Some(on_path("ar").as_str()));
+    assert_eq!(build.env("STRIP"), Some(on_path("strip").as_str()));
+    assert_eq!(build.env("RANLIB"), None);
+}
+
+#[test]
+fn cross_compile_prefix_without_a_gcc_is_passed_over() {
+    let sandbox = Sandbox::new("cross_compile_prefix_without_a_gcc_is_passed_over")
+        .env("CROSS_COMPILE", BUILDROOT_PREFIX);
+    sandbox.install_stub(&sandbox.bin, &format!("{}ar", BUILDROOT_PREFIX));
+    let output = sandbox.run(&["-t", "bb", "--strict-linker", "build"]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("no linker found for target arm-unknown-linux-gnueabihf"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox.builds().is_empty());
+}
+
+#[test]
+fn configured_linker_beats_cross_compile() {
+    let sandbox = Sandbox::new("configured_linker_beats_cross_compile")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc")
+        .env("CROSS_COMPILE", BUILDROOT_PREFIX);
+    sandbox.install_stub(&sandbox.bin, &format!("{}gcc", BUILDROOT_PREFIX));
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let gcc = sandbox.bin.join("arm-linux-gnueabihf-gcc");
+    assert_eq!(
+        sandbox.build().env("CC_arm_unknown_linux_gnueabihf"),
+        gcc.to_str()
+    );
+    let cross = sandbox.bin.join(format!("{}gcc", BUILDROOT_PREFIX));
+    let warning = format!(
+        "CROSS_COMPILE names {}, but {} is configured",
+        cross.display(),
+        gcc.display()
+    );
+    assert!(stderr(&output).contains(&warning), "{}", stderr(&output));
+}
+
+#[test]
+fn configured_linker_agreeing_with_cross_compile() {
+    let sandbox = Sandbox::new("configured_linker_agreeing_with_cross_compile")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc")
+        .env("CROSS_COMPILE", "arm-linux-gnueabihf-");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        !stderr(&output).contains("CROSS_COMPILE"),
+        "{}",
+        stderr(&output)
+    );
+}
@@ -0,0 +1,126 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The table and JSON `cargo kubos --list-targets` prints, against the
+//! registry
+
+mod common;
+
+// The crate's own parser, to read the listing as a tool would
+#[allow(dead_code)]
+#[path = "../src/json.rs"]
+mod json;
+
+use cargo_kubos::{KubosTarget, TargetRegistry};
+use common::{stderr, Sandbox};
+use json::Json;
+use std::path::{Path, PathBuf};
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+/// A sandbox with a linker configured for the beaglebone's triple only,
+/// returning the linker's path
+fn with_bb_linker(name: &str) -> (Sandbox, PathBuf) {
+    let sandbox = Sandbox::new(name);
+    let gcc = sandbox.install_gcc(&sandbox.root.join("toolchain"), "arm-linux-gnueabihf-gcc");
+    let config = sandbox.home_config();
+    let sandbox = sandbox.with_config(
+        &config,
+        &format!("[target.{}]\nlinker = {:?}\n", BB_TRIPLE, gcc),
+    );
+    (sandbox, gcc)
+}
+
+/// The linker each target is listed with, only those sharing the
+/// beaglebone's triple having one
+fn expected_linker(target: &KubosTarget, gcc: &Path) -> Option<String> {
+    if target.triple == BB_TRIPLE {
+        Some(gcc.to_str().unwrap().to_owned())
+    } else {
+        None
+    }
+}
+
+/// The listing, checking it succeeded
+fn list(sandbox: &Sandbox, args: &[&str]) -> String {
+    let mut all = vec!["--list-targets", "--no-toolchain-discovery"];
+    all.extend(args);
+    let output = sandbox.run(&all);
+    assert!(output.status.success(), "{}", stderr(&output));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn table_lists_every_target() {
+    let (sandbox, gcc) = with_bb_linker("table_lists_every_target");
+    let stdout = list(&sandbox, &[]);
+    let mut lines = stdout.lines();
+
+    // The columns start where their headings do
+    let header = lines.next().unwrap();
+    let columns: Vec<usize> = ["TARGET", "ALIASES", "TRIPLE", "LINKER"]
+        .iter()
+        .map(|heading| header.find(heading).unwrap())
+        .collect();
+    assert_eq!(columns[0], 0, "{}", header);
+
+    let targets = Vec::from(TargetRegistry::builtin());
+    let rows: Vec<&str> = lines.collect();
+    assert_eq!(rows.len(), targets.len(), "{}", stdout);
+    for (row, target) in rows.iter().zip(&targets) {
+        let cell = |n: usize| match columns.get(n + 1) {
+            Some(&end) => row[columns[n]..end].trim_end(),
+            None => &row[columns[n]..],
+        };
+        assert_eq!(cell(0), target.name, "{}", row);
+        assert_eq!(cell(1), target.aliases.join(","), "{}", row);
+        assert_eq!(cell(2), target.triple, "{}", row);
+        assert_eq!(
+            cell(3),
+            expected_linker(target, &gcc).unwrap_or_else(|| String::from("(missing)")),
+            "{}",
+            row
+        );
+    }
+}
+
+#[test]
+fn json_lists_every_target() {
+    let (sandbox, gcc) = with_bb_linker("json_lists_every_target");
+    let stdout = list(&sandbox, &["--json"]);
+    assert_eq!(stdout.lines().count(), 1, "{}", stdout);
+    let listing = json::parse(stdout.trim()).unwrap();
+    let entries = listing.as_array().unwrap();
+
+    let targets = Vec::from(TargetRegistry::builtin());
+    assert_eq!(entries.len(), targets.len(), "{}", stdout);
+    for (entry, target) in entries.iter().zip(&targets) {
+        let field = |key: &str| entry.get(key).unwrap();
+        assert_eq!(field("name").as_str(), Some(&target.name[..]));
+        let aliases: Vec<&str> = field("aliases")
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|alias| alias.as_str().unwrap())
+            .collect();
+        assert_eq!(aliases, target.aliases);
+        assert_eq!(field("triple").as_str(), Some(&target.triple[..]));
+        match expected_linker(target, &gcc) {
+            Some(linker) => assert_eq!(field("linker").as_str(), Some(&linker[..])),
+            None => assert_eq!(field("linker"), &Json::Null),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The man page `cargo kubos generate-man` renders, against the copy in
+//! the fixtures. After changing the help, the options or the version,
+//! update the copy with `cargo run -- kubos generate-man --out tests/fixtures`.
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::fs;
+use std::path::Path;
+
+fn golden() -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cargo-kubos.1");
+    fs::read_to_string(path).unwrap()
+}
+
+/// Compare line by line, so a failure shows where the pages differ
+fn assert_page(page: &str) {
+    let golden = golden();
+    for (n, (line, expected)) in page.lines().zip(golden.lines()).enumerate() {
+        assert_eq!(line, expected, "line {} of the man page differs", n + 1);
+    }
+    assert_eq!(
+        page.lines().count(),
+        golden.lines().count(),
+        "the man page is a different length"
+    );
+    assert_eq!(page, golden);
+}
+
+#[test]
+fn page_matches_the_fixture() {
+    let sandbox = Sandbox::new("page_matches_the_fixture");
+    let output = sandbox.run(&["generate-man"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_page(&String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn page_written_to_a_directory_matches_the_fixture() {
+    let sandbox = Sandbox::new("page_written_to_a_directory_matches_the_fixture");
+    let out = sandbox.root.join("man");
+    let output = sandbox.run(&["generate-man", "--out", out.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    assert_page(&fs::read_to_string(out.join("cargo-kubos.1")).unwrap());
+}
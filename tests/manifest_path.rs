@@ -0,0 +1,125 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Building a crate from another directory with only `--manifest-path`,
+//! as CI does from the repository root
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+/// Copy the directory's contents into another, recursively
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let dest = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &dest);
+        } else {
+            fs::copy(entry.path(), dest).unwrap();
+        }
+    }
+}
+
+/// A sandbox with the fixture's services/telemetry crate beside its own
+/// project, returning where the crate is
+fn with_service(name: &str) -> (Sandbox, PathBuf) {
+    let sandbox = Sandbox::new(name).with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let service = sandbox.root.join("services/telemetry");
+    copy_dir(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/services/telemetry"),
+        &service,
+    );
+    sandbox.install_stub(&service.join("tools"), "notify");
+    // The directory it's run from has settings of its own, to be ignored
+    fs::create_dir_all(sandbox.project.join(".cargo")).unwrap();
+    fs::write(
+        sandbox.project.join(".cargo/kubos.toml"),
+        "target = \"kubos-linux-isis-gcc\"\ndefault-command = \"check\"\n",
+    )
+    .unwrap();
+    (sandbox, service)
+}
+
+#[test]
+fn sibling_crate_builds_with_its_own_settings() {
+    let (sandbox, service) = with_service("sibling_crate_builds_with_its_own_settings");
+    let output = sandbox.run(&[
+        "--manifest-path",
+        "../services/telemetry/Cargo.toml",
+        "--print-artifact",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // The crate's default target, command and extra arguments
+    let build = sandbox.build();
+    assert_eq!(build.args[..2], ["build", "--release"]);
+    let target = build.args.iter().position(|arg| arg == "--target").unwrap();
+    assert_eq!(build.args[target + 1], BB_TRIPLE);
+    assert!(
+        build.args.contains(&String::from("--locked")),
+        "{:?}",
+        build.args
+    );
+    let manifest = build
+        .args
+        .iter()
+        .position(|arg| arg == "--manifest-path")
+        .unwrap();
+    assert_eq!(build.args[manifest + 1], "../services/telemetry/Cargo.toml");
+    assert_eq!(
+        build.env("CARGO_KUBOS_TARGET"),
+        Some("kubos-linux-beaglebone-gcc")
+    );
+
+    // The hook is named relative to the crate
+    let calls = sandbox.calls("notify");
+    assert_eq!(calls.len(), 1, "{}", stderr(&output));
+    assert_eq!(calls[0].args, ["--built"]);
+
+    // What was built is in the crate's target directory
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let artifact = PathBuf::from(stdout.trim()).canonicalize().unwrap();
+    assert!(
+        artifact.starts_with(
+            service
+                .join("target")
+                .join(BB_TRIPLE)
+                .canonicalize()
+                .unwrap()
+        ),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn missing_manifest_is_a_usage_error() {
+    let (sandbox, _) = with_service("missing_manifest_is_a_usage_error");
+    let output = sandbox.run(&["--manifest-path", "../services/missing/Cargo.toml"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("manifest path '../services/missing/Cargo.toml' does not exist"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox.builds().is_empty());
+}
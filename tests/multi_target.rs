@@ -0,0 +1,156 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Building for several targets at once: what reaches the terminal of
+//! cargo's output, and which targets `--fail-fast` leaves unbuilt
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+/// A sandbox with linkers for the beaglebone and the iOBC, whose cargo
+/// says which it's building for
+fn with_two_targets(name: &str) -> Sandbox {
+    Sandbox::new(name)
+        .with_linker("arm-unknown-linux-gnueabihf", "arm-linux-gnueabihf-gcc")
+        .with_linker("armv5te-unknown-linux-gnueabi", "arm-linux-gcc")
+        .env("FAKE_CARGO_SAY_ARM_UNKNOWN_LINUX_GNUEABIHF", "building bb")
+        .env(
+            "FAKE_CARGO_SAY_ARMV5TE_UNKNOWN_LINUX_GNUEABI",
+            "building isis",
+        )
+}
+
+#[test]
+fn each_line_is_tagged_with_its_target() {
+    let sandbox = with_two_targets("each_line_is_tagged_with_its_target");
+    let output = sandbox.run(&["-t", "bb", "-t", "isis", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "[bb] building bb\n[isis] building isis\n"
+    );
+    let stderr = stderr(&output);
+    assert!(stderr.contains("[bb] building bb\n"), "{}", stderr);
+    assert!(stderr.contains("[isis] building isis\n"), "{}", stderr);
+}
+
+#[test]
+fn no_prefix_passes_output_through() {
+    let sandbox = with_two_targets("no_prefix_passes_output_through");
+    let output = sandbox.run(&["-t", "bb", "-t", "isis", "--no-prefix", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "building bb\nbuilding isis\n"
+    );
+    let stderr = stderr(&output);
+    assert!(stderr.contains("\nbuilding bb\n"), "{}", stderr);
+    assert!(stderr.contains("\nbuilding isis\n"), "{}", stderr);
+    assert!(!stderr.contains("[bb]"), "{}", stderr);
+    assert!(!stderr.contains("[isis]"), "{}", stderr);
+}
+
+#[test]
+fn one_target_is_never_tagged() {
+    let sandbox = with_two_targets("one_target_is_never_tagged");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "building bb\n");
+    assert!(!stderr(&output).contains("[bb]"), "{}", stderr(&output));
+}
+
+/// What each build was for, in the order they ran
+fn built_for(sandbox: &Sandbox) -> Vec<String> {
+    sandbox
+        .builds()
+        .iter()
+        .map(|build| {
+            let target = build.args.iter().position(|arg| arg == "--target").unwrap();
+            build.args[target + 1].clone()
+        })
+        .collect()
+}
+
+#[test]
+fn fail_fast_stops_after_the_first_failure() {
+    let sandbox = with_two_targets("fail_fast_stops_after_the_first_failure")
+        .with_linker("aarch64-unknown-linux-gnu", "aarch64-linux-gnu-gcc")
+        .env("FAKE_CARGO_EXIT_ARM_UNKNOWN_LINUX_GNUEABIHF", "101");
+    let args = [
+        "-t",
+        "bb",
+        "-t",
+        "isis",
+        "-t",
+        "kubos-linux-rpi-cm3-gcc",
+        "build",
+    ];
+
+    let output = sandbox.run(&[&["--fail-fast"], &args[..]].concat());
+    assert_eq!(output.status.code(), Some(101), "{}", stderr(&output));
+    assert_eq!(built_for(&sandbox), ["arm-unknown-linux-gnueabihf"]);
+    assert!(
+        stderr(&output)
+            .contains("not building the remaining targets after kubos-linux-beaglebone-gcc failed"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn without_fail_fast_every_target_is_built() {
+    let sandbox = with_two_targets("without_fail_fast_every_target_is_built")
+        .with_linker("aarch64-unknown-linux-gnu", "aarch64-linux-gnu-gcc")
+        .env("FAKE_CARGO_EXIT_ARM_UNKNOWN_LINUX_GNUEABIHF", "101");
+    let output = sandbox.run(&[
+        "-t",
+        "bb",
+        "-t",
+        "isis",
+        "-t",
+        "kubos-linux-rpi-cm3-gcc",
+        "build",
+    ]);
+    assert_eq!(output.status.code(), Some(101), "{}", stderr(&output));
+    assert_eq!(
+        built_for(&sandbox),
+        [
+            "arm-unknown-linux-gnueabihf",
+            "armv5te-unknown-linux-gnueabi",
+            "aarch64-unknown-linux-gnu"
+        ]
+    );
+    assert!(
+        !stderr(&output).contains("not building the remaining targets"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn fail_fast_has_nothing_to_stop_after_the_last_target() {
+    let sandbox = with_two_targets("fail_fast_has_nothing_to_stop_after_the_last_target")
+        .env("FAKE_CARGO_EXIT_ARMV5TE_UNKNOWN_LINUX_GNUEABI", "101");
+    let output = sandbox.run(&["--fail-fast", "-t", "bb", "-t", "isis", "build"]);
+    assert_eq!(output.status.code(), Some(101), "{}", stderr(&output));
+    assert_eq!(built_for(&sandbox).len(), 2);
+    assert!(
+        !stderr(&output).contains("not building the remaining targets"),
+        "{}",
+        stderr(&output)
+    );
+}
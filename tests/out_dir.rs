@@ -0,0 +1,100 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Where --out-dir stages what's built: a directory for each target,
+//! copied from the output directory of the profile cargo built with
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::fs;
+
+const NATIVE_TRIPLE: &str = "x86_64-unknown-linux-gnu";
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+/// Build with the cargo command, staging into `staged`, and return the
+/// profile's output directory it was built into
+fn staged_from(sandbox: &Sandbox, command: &str) -> String {
+    let output = sandbox.run(&["-t", "native", "--out-dir", "staged", "-c", command]);
+    assert!(output.status.success(), "{}: {}", command, stderr(&output));
+    let staged = sandbox.project.join("staged/x86-linux-native/sandbox");
+    assert!(staged.is_file(), "{}: {}", command, stderr(&output));
+    let builds = sandbox.builds();
+    let build = builds.last().unwrap();
+    String::from(build.env("CARGO_KUBOS_PROFILE").unwrap())
+}
+
+#[test]
+fn profiles_build_into_their_directories() {
+    let sandbox = Sandbox::new("profiles_build_into_their_directories");
+    for (command, dir) in &[
+        ("build", "debug"),
+        ("build --release", "release"),
+        ("build -r", "release"),
+        ("build --profile test", "debug"),
+        ("build --profile bench", "release"),
+        ("build --profile flight", "flight"),
+    ] {
+        assert_eq!(staged_from(&sandbox, command), *dir, "{}", command);
+        assert!(
+            sandbox
+                .project
+                .join("target")
+                .join(NATIVE_TRIPLE)
+                .join(dir)
+                .join("sandbox")
+                .is_file(),
+            "{}",
+            command
+        );
+    }
+}
+
+#[test]
+fn each_target_is_staged_apart() {
+    let sandbox = Sandbox::new("each_target_is_staged_apart")
+        .with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc");
+    let output = sandbox.run(&[
+        "-t",
+        "native",
+        "-t",
+        "bb",
+        "--out-dir",
+        "staged",
+        "--artifact-suffix",
+        "-{version}",
+        "-c",
+        "build --release",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let staged = sandbox.project.join("staged");
+    let mut dirs: Vec<String> = fs::read_dir(&staged)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    dirs.sort();
+    assert_eq!(dirs, ["kubos-linux-beaglebone-gcc", "x86-linux-native"]);
+    for dir in &dirs {
+        let names: Vec<String> = fs::read_dir(staged.join(dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["sandbox-0.1.0"], "{}", dir);
+        let line = format!("    staged/{}/sandbox-0.1.0 (0 bytes)\n", dir);
+        assert!(stderr(&output).contains(&line), "{}", stderr(&output));
+    }
+}
@@ -0,0 +1,95 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The archives `cargo kubos package` writes with the system's tar and
+//! gzip: the same each time for the same build, and refused without
+//! GNU tar's options for that
+#![cfg(unix)]
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+/// Where the beaglebone's tarball is written
+const ARCHIVE: &str = "target/kubos/packages/sandbox-0.1.0-kubos-linux-beaglebone-gcc.tar.gz";
+
+/// The program of the name on the PATH the tests were run with
+fn host_program(name: &str) -> PathBuf {
+    env::split_paths(&env::var_os("PATH").unwrap())
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| panic!("{} isn't on PATH", name))
+}
+
+/// A sandbox which can package for the beaglebone with the system's tar
+/// and gzip, and a stub for its strip, at a fixed build time
+fn with_packaging(name: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name)
+        .with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc")
+        .env("SOURCE_DATE_EPOCH", "1500000000");
+    sandbox.install_stub(&sandbox.bin, "arm-linux-gnueabihf-strip");
+    for program in &["tar", "gzip"] {
+        std::os::unix::fs::symlink(host_program(program), sandbox.bin.join(program)).unwrap();
+    }
+    sandbox
+}
+
+#[test]
+fn repackaging_gives_the_same_archive() {
+    let sandbox = with_packaging("repackaging_gives_the_same_archive");
+    let archive = sandbox.project.join(ARCHIVE);
+
+    let output = sandbox.run(&["package", "-t", "bb"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let first = fs::read(&archive).unwrap();
+
+    // Written again later, from files written again
+    thread::sleep(Duration::from_secs(1));
+    let output = sandbox.run(&["package", "-t", "bb"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(first == fs::read(&archive).unwrap(), "the archives differ");
+}
+
+#[test]
+fn other_tars_are_refused() {
+    let sandbox = with_packaging("other_tars_are_refused");
+    fs::remove_file(sandbox.bin.join("tar")).unwrap();
+    // A stub, whose version isn't GNU tar's
+    sandbox.install_stub(&sandbox.bin, "tar");
+
+    let output = sandbox.run(&["package", "-t", "bb"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(
+            "packaging needs GNU tar, but the tar on PATH is 'gcc (fake) 9.0.0'; \
+             install GNU tar and put it first on PATH as tar"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    // Only asked its version, not to archive anything
+    let calls = sandbox.calls("tar");
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].args, ["--version"]);
+    assert!(!sandbox.project.join(ARCHIVE).exists());
+}
@@ -0,0 +1,138 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Running cargo and probing linkers through a runner standing in for them
+
+#![cfg(unix)]
+
+mod common;
+
+use cargo_kubos::{check_linker, resolve_target, CargoInvocation, Error, ResolutionCache};
+use common::runner::ScriptedRunner;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+fn build() -> CargoInvocation {
+    let target = resolve_target("x86-linux-native").unwrap();
+    CargoInvocation::new(&target, &[String::from("build"), String::from("--release")])
+}
+
+#[test]
+fn invocation_runs_cargo_with_its_args_and_env() {
+    let invocation = build();
+    let runner = ScriptedRunner::new();
+    let status = invocation.run(&runner).unwrap();
+    assert!(status.success());
+
+    let calls = runner.calls();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].program, "cargo");
+    assert_eq!(calls[0].args, invocation.args);
+    assert_eq!(
+        calls[0].args,
+        ["build", "--target", "x86_64-unknown-linux-gnu", "--release"]
+    );
+    assert_eq!(calls[0].env("CARGO_KUBOS_TARGET"), Some("x86-linux-native"));
+    assert_eq!(calls[0].env("CARGO_KUBOS_PROFILE"), Some("release"));
+}
+
+#[test]
+fn invocation_returns_cargo_status() {
+    let runner = ScriptedRunner::new().exits(101);
+    let status = build().run(&runner).unwrap();
+    assert_eq!(status.code(), Some(101));
+}
+
+#[test]
+fn invocation_reports_cargo_not_starting() {
+    let runner = ScriptedRunner::new().fails(io::ErrorKind::NotFound);
+    match build().run(&runner) {
+        Err(error @ Error::Spawn { .. }) => {
+            assert_eq!(error.to_string(), "cargo not found on PATH");
+            assert_eq!(error.exit_code(), 127);
+        }
+        other => panic!("expected a spawn error, got {:?}", other),
+    }
+}
+
+/// A cache reading only the config giving the beaglebone's linker, as
+/// `--cargo-config` would, rather than setting the linker in the
+/// environment the other tests run in at the same time
+fn linker_config(dir: &Path, linker: &Path) -> ResolutionCache {
+    let config = dir.join(format!(
+        "{}.toml",
+        linker.file_name().unwrap().to_string_lossy()
+    ));
+    fs::write(
+        &config,
+        format!(
+            "[target.arm-unknown-linux-gnueabihf]\nlinker = {:?}\n",
+            linker
+        ),
+    )
+    .unwrap();
+    ResolutionCache::with_given_configs(vec![config])
+}
+
+#[test]
+fn preflight_probes_linker_version() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("preflight_probes_linker_version");
+    fs::create_dir_all(&dir).unwrap();
+    let gcc = dir.join("arm-linux-gnueabihf-gcc");
+    fs::write(&gcc, "").unwrap();
+    fs::set_permissions(&gcc, fs::Permissions::from_mode(0o755)).unwrap();
+    let target = resolve_target("kubos-linux-beaglebone-gcc").unwrap();
+
+    let runner = ScriptedRunner::new().outputs(0, "arm-linux-gnueabihf-gcc 9.0.0\n");
+    check_linker(&target, &runner, &linker_config(&dir, &gcc)).unwrap();
+    let calls = runner.calls();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(Path::new(&calls[0].program), gcc);
+    assert_eq!(calls[0].args, ["--version"]);
+
+    // A wrapper which doesn't take --version
+    let runner = ScriptedRunner::new().exits(1);
+    let error = check_linker(&target, &runner, &linker_config(&dir, &gcc)).unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("for target kubos-linux-beaglebone-gcc failed to run `--version`"),
+        "{}",
+        error
+    );
+    assert_eq!(error.exit_code(), 5);
+
+    // Which can't start is no better
+    let runner = ScriptedRunner::new().fails(io::ErrorKind::PermissionDenied);
+    assert!(check_linker(&target, &runner, &linker_config(&dir, &gcc)).is_err());
+
+    // A linker which isn't there isn't run at all
+    let runner = ScriptedRunner::new();
+    let missing = linker_config(&dir, &dir.join("missing-gcc"));
+    let error = check_linker(&target, &runner, &missing).unwrap_err();
+    assert!(error.to_string().contains("does not exist"), "{}", error);
+    assert!(runner.calls().is_empty());
+}
+
+#[test]
+fn preflight_leaves_native_targets_alone() {
+    let target = resolve_target("x86-linux-native").unwrap();
+    let runner = ScriptedRunner::new().exits(1);
+    check_linker(&target, &runner, &ResolutionCache::new()).unwrap();
+    assert!(runner.calls().is_empty());
+}
@@ -0,0 +1,124 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! How the rust flags cargo-kubos adds for a target join those cargo
+//! would have used, rather than replacing them
+
+mod common;
+
+use common::{stderr, Sandbox};
+
+const BEAGLEBONE_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+/// The flag cargo-kubos adds for the beaglebone's table-form linker
+const LINK_ARG: &str = "-Clink-arg=-mcpu=cortex-a8";
+
+/// A sandbox whose beaglebone linker has an argument, so that cargo-kubos
+/// has a flag of its own to add, with more of `$CARGO_HOME`'s config
+fn with_link_arg(name: &str, config: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name);
+    let gcc = sandbox.install_gcc(&sandbox.bin, "arm-linux-gnueabihf-gcc");
+    let path = sandbox.home_config();
+    sandbox.with_config(
+        &path,
+        &format!(
+            "[target.{}]\nlinker = {{ path = {:?}, args = [\"-mcpu=cortex-a8\"] }}\n{}",
+            BEAGLEBONE_TRIPLE, gcc, config
+        ),
+    )
+}
+
+/// The flags the one build was given in `CARGO_ENCODED_RUSTFLAGS`
+fn encoded_rustflags(sandbox: &Sandbox) -> Vec<String> {
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let build = sandbox.build();
+    let flags = build
+        .env("CARGO_ENCODED_RUSTFLAGS")
+        .expect("CARGO_ENCODED_RUSTFLAGS is set");
+    flags.split('\x1f').map(String::from).collect()
+}
+
+#[test]
+fn target_rustflags_are_kept() {
+    let sandbox = with_link_arg(
+        "target_rustflags_are_kept",
+        "rustflags = [\"-C\", \"link-arg=-Wl,--gc-sections\"]\n\
+         [build]\nrustflags = [\"--cfg\", \"build_flags\"]\n",
+    );
+    // Like cargo, the target's flags are used instead of the build's
+    assert_eq!(
+        encoded_rustflags(&sandbox),
+        ["-C", "link-arg=-Wl,--gc-sections", LINK_ARG]
+    );
+}
+
+#[test]
+fn build_rustflags_are_kept() {
+    let sandbox = with_link_arg(
+        "build_rustflags_are_kept",
+        "[build]\nrustflags = [\"--cfg\", \"build_flags\"]\n",
+    );
+    assert_eq!(
+        encoded_rustflags(&sandbox),
+        ["--cfg", "build_flags", LINK_ARG]
+    );
+}
+
+#[test]
+fn build_rustflags_are_joined_across_configs() {
+    let sandbox = with_link_arg(
+        "build_rustflags_are_joined_across_configs",
+        "[build]\nrustflags = [\"-Chome\"]\n",
+    );
+    let project = sandbox.project.join(".cargo").join("config.toml");
+    let sandbox = sandbox.with_config(&project, "[build]\nrustflags = [\"-Cproj\"]\n");
+    // As cargo does, with the farther config's flags first
+    assert_eq!(encoded_rustflags(&sandbox), ["-Chome", "-Cproj", LINK_ARG]);
+}
+
+#[test]
+fn rustflags_variable_beats_the_config() {
+    let sandbox = with_link_arg(
+        "rustflags_variable_beats_the_config",
+        "rustflags = [\"-C\", \"link-arg=-Wl,--gc-sections\"]\n",
+    )
+    .env("RUSTFLAGS", "-C opt-level=2");
+    assert_eq!(encoded_rustflags(&sandbox), ["-C", "opt-level=2", LINK_ARG]);
+}
+
+#[test]
+fn encoded_rustflags_keep_their_spaces() {
+    let sandbox = with_link_arg("encoded_rustflags_keep_their_spaces", "")
+        .env("RUSTFLAGS", "-C opt-level=2")
+        .env("CARGO_ENCODED_RUSTFLAGS", "--cfg\x1ffeature=\"a b\"");
+    assert_eq!(
+        encoded_rustflags(&sandbox),
+        ["--cfg", "feature=\"a b\"", LINK_ARG]
+    );
+}
+
+#[test]
+fn rustflags_are_left_alone_without_flags_to_add() {
+    let sandbox = Sandbox::new("rustflags_are_left_alone_without_flags_to_add")
+        .with_linker(BEAGLEBONE_TRIPLE, "arm-linux-gnueabihf-gcc")
+        .env("RUSTFLAGS", "-C opt-level=2");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let build = sandbox.build();
+    assert_eq!(build.env("RUSTFLAGS"), Some("-C opt-level=2"));
+    assert_eq!(build.env("CARGO_ENCODED_RUSTFLAGS"), None);
+}
@@ -0,0 +1,128 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `--sdk-vm`: running cargo-kubos again in the Kubos SDK's Vagrant box
+//! when a target has no toolchain here, with a stub of vagrant
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::fs;
+
+/// The variables the sandbox itself runs cargo-kubos with
+const SANDBOX_VARS: &[&str] = &[
+    "CARGO_HOME",
+    "FAKE_CARGO_RECORD",
+    "HOME",
+    "PATH",
+    "USERPROFILE",
+];
+
+/// A sandbox with no beaglebone toolchain, whose Vagrantfile is in the
+/// directory above the project, as it is in the SDK's checkout
+fn with_vagrant(name: &str) -> Sandbox {
+    let sandbox = Sandbox::new(name);
+    fs::write(sandbox.root.join("Vagrantfile"), "").unwrap();
+    sandbox.install_stub(&sandbox.bin, "vagrant");
+    sandbox
+}
+
+#[test]
+fn command_is_run_again_in_the_box() {
+    let sandbox = with_vagrant("command_is_run_again_in_the_box");
+    let output = sandbox.run(&[
+        "-t",
+        "bb",
+        "--sdk-vm",
+        "-c",
+        "build --features 'a b'",
+        "--",
+        "-v",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let calls = sandbox.calls("vagrant");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    assert_eq!(
+        calls[0].args,
+        [
+            "ssh",
+            "-c",
+            "cd /vagrant/project && KUBOS_SDK=1 cargo kubos -t bb \
+             -c 'build --features '\\''a b'\\''' -- -v"
+        ]
+    );
+    let vars: Vec<&str> = calls[0].env.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(vars, SANDBOX_VARS);
+    assert!(sandbox.builds().is_empty(), "{:?}", sandbox.builds());
+}
+
+#[test]
+fn synced_folder_is_mapped_as_configured() {
+    let sandbox = with_vagrant("synced_folder_is_mapped_as_configured");
+    let config = sandbox.project.join(".cargo/config.toml");
+    let sandbox = sandbox.with_config(
+        &config,
+        "[kubos.vagrant]\ndir = \"../sdk\"\nhost = \"..\"\nguest = \"/home/vagrant/work\"\n",
+    );
+    let output = sandbox.run(&["-t", "bb", "--sdk-vm", "build"]);
+    let sdk = sandbox.project.join("../sdk");
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(&format!(
+            "Error - {}, the SDK's Vagrant box directory, doesn't exist",
+            sdk.display()
+        )),
+        "{}",
+        stderr(&output)
+    );
+
+    fs::create_dir(sandbox.root.join("sdk")).unwrap();
+    let output = sandbox.run(&["-t", "bb", "--sdk-vm", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(&format!(
+            "building in the Kubos SDK box at {}",
+            sdk.display()
+        )),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        sandbox.calls("vagrant")[0].args,
+        [
+            "ssh",
+            "-c",
+            "cd /home/vagrant/work/project && KUBOS_SDK=1 cargo kubos -t bb build"
+        ]
+    );
+}
+
+#[test]
+fn box_exits_as_the_command_did() {
+    let sandbox = with_vagrant("box_exits_as_the_command_did").env("FAKE_VAGRANT_EXIT", "101");
+    let output = sandbox.run(&["-t", "bb", "--sdk-vm", "build"]);
+    assert_eq!(output.status.code(), Some(101), "{}", stderr(&output));
+}
+
+#[test]
+fn inside_the_sdk_builds_here() {
+    let sandbox = with_vagrant("inside_the_sdk_builds_here").env("KUBOS_SDK", "1");
+    sandbox.run(&["-t", "bb", "--sdk-vm", "build"]);
+    assert!(sandbox.calls("vagrant").is_empty());
+}
@@ -0,0 +1,253 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Signals and cargo: an interrupt sent to cargo-kubos is passed on to
+//! the cargo it's running, which cargo-kubos outlives to exit with the
+//! shell's `128 + signal` code, as it does whatever signal kills cargo
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::net::UdpSocket;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SIGINT: i32 = 2;
+const SIGKILL: i32 = 9;
+/// Linux's, other Unixes numbering it differently
+#[cfg(target_os = "linux")]
+const SIGUSR1: i32 = 10;
+const SIGSEGV: i32 = 11;
+const SIGTERM: i32 = 15;
+
+/// Longer than any test should take, so that only a signal ends the build
+const SLEEP: &str = "60";
+
+extern "C" {
+    fn kill(pid: i32, sig: i32) -> i32;
+}
+
+/// Run cargo-kubos with a fake cargo which takes a long time over the
+/// build, and once cargo has started send the signal to cargo-kubos alone,
+/// not its process group as a terminal's Ctrl-C would, so that cargo only
+/// gets it if it's forwarded
+fn interrupt(sandbox: &Sandbox, args: &[&str], signal: i32) -> Output {
+    let child = sandbox
+        .command(args)
+        .env("FAKE_CARGO_SLEEP", SLEEP)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while sandbox.builds().is_empty() {
+        assert!(
+            start.elapsed() < Duration::from_secs(20),
+            "cargo never started"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    // So cargo-kubos has had time to note whom to forward to
+    thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    unsafe {
+        kill(child.id() as i32, signal);
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        start.elapsed() < Duration::from_secs(30),
+        "cargo-kubos waited for cargo to finish"
+    );
+    output
+}
+
+#[test]
+fn interrupt_is_forwarded_to_cargo() {
+    let sandbox = Sandbox::new("interrupt_is_forwarded_to_cargo");
+    let output = interrupt(&sandbox, &["-t", "native", "build"], SIGINT);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + SIGINT),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
+
+#[test]
+fn terminate_is_forwarded_to_cargo() {
+    let sandbox = Sandbox::new("terminate_is_forwarded_to_cargo");
+    let output = interrupt(&sandbox, &["-t", "native", "build"], SIGTERM);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + SIGTERM),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
+
+#[test]
+fn interrupt_stops_the_other_targets() {
+    let sandbox = Sandbox::new("interrupt_stops_the_other_targets")
+        .with_linker("arm-unknown-linux-gnueabihf", "arm-linux-gnueabihf-gcc");
+    let output = interrupt(&sandbox, &["-t", "native", "-t", "bb", "build"], SIGINT);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + SIGINT),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.builds().len(), 1, "{:?}", sandbox.builds());
+}
+
+/// Build with a fake cargo which dies of the signal
+fn killed_by(name: &str, signal: i32) -> (Sandbox, Output) {
+    let sandbox = Sandbox::new(name).env("FAKE_CARGO_SIGNAL", &signal.to_string());
+    let output = sandbox.run(&["-t", "native", "build"]);
+    (sandbox, output)
+}
+
+#[test]
+fn cargo_killed_by_a_signal() {
+    let (sandbox, output) = killed_by("cargo_killed_by_a_signal", SIGKILL);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + SIGKILL),
+        "{}",
+        stderr(&output)
+    );
+    let stderr = stderr(&output);
+    let lines: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.contains("signal"))
+        .collect();
+    assert_eq!(
+        lines,
+        ["Error - cargo was killed by signal 9 (SIGKILL)"],
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert_eq!(sandbox.builds().len(), 1);
+}
+
+#[test]
+fn cargo_crashing() {
+    let (_, output) = killed_by("cargo_crashing", SIGSEGV);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + SIGSEGV),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        stderr(&output).contains("Error - cargo was killed by signal 11 (SIGSEGV)\n"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cargo_killed_by_a_signal_without_a_name() {
+    let (_, output) = killed_by("cargo_killed_by_a_signal_without_a_name", SIGUSR1);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + SIGUSR1),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        stderr(&output).contains("Error - cargo was killed by signal 10\n"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn killed_target_fails_the_others_too() {
+    let sandbox = Sandbox::new("killed_target_fails_the_others_too")
+        .with_linker("arm-unknown-linux-gnueabihf", "arm-linux-gnueabihf-gcc")
+        .env("FAKE_CARGO_SIGNAL", &SIGKILL.to_string());
+    let output = sandbox.run(&["-t", "native", "-t", "bb", "build"]);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + SIGKILL),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.builds().len(), 2);
+}
+
+#[test]
+fn killed_target_beats_a_larger_exit_code() {
+    // 200 is more than the 128 + 9 a SIGKILL exits with
+    let sandbox = Sandbox::new("killed_target_beats_a_larger_exit_code")
+        .with_linker("arm-unknown-linux-gnueabihf", "arm-linux-gnueabihf-gcc")
+        .with_linker("armv5te-unknown-linux-gnueabi", "arm-linux-gcc")
+        .env("FAKE_CARGO_EXIT_ARM_UNKNOWN_LINUX_GNUEABIHF", "200")
+        .env(
+            "FAKE_CARGO_SIGNAL_ARMV5TE_UNKNOWN_LINUX_GNUEABI",
+            &SIGKILL.to_string(),
+        );
+    let output = sandbox.run(&["-t", "bb", "-t", "isis", "build"]);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + SIGKILL),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.builds().len(), 2);
+}
+
+#[test]
+fn interrupt_between_children_still_stops_us() {
+    let sandbox = Sandbox::new("interrupt_between_children_still_stops_us");
+    // A file service which never answers, so the upload waits on it
+    // long after cargo has come and gone
+    let service = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let endpoint = service.local_addr().unwrap().to_string();
+    let child = sandbox
+        .command(&["-t", "native", "flash", "--file-service", &endpoint])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    service
+        .set_read_timeout(Some(Duration::from_secs(20)))
+        .unwrap();
+    let mut buffer = [0u8; 65536];
+    service.recv(&mut buffer).expect("the upload never started");
+    assert_eq!(sandbox.builds().len(), 1);
+
+    let start = Instant::now();
+    unsafe {
+        kill(child.id() as i32, SIGINT);
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        start.elapsed() < Duration::from_secs(4),
+        "cargo-kubos ignored the interrupt"
+    );
+    // Dying of it, which a shell reports as 130
+    assert_eq!(output.status.signal(), Some(SIGINT), "{}", stderr(&output));
+}
@@ -0,0 +1,246 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Looking targets up in the registry, and what the target
+//! mapping files do to it
+
+use cargo_kubos::{TargetError, TargetRegistry};
+use std::path::Path;
+
+const BUILTIN: &[&str] = &[
+    "x86-linux-native",
+    "x86-linux-native-32",
+    "kubos-linux-beaglebone-gcc",
+    "kubos-linux-pumpkin-mbm2-gcc",
+    "kubos-linux-isis-gcc",
+    "kubos-linux-rpi-cm3-gcc",
+    "kubos-linux-rpi-zero2-gcc",
+    "kubos-linux-beaglebone-musl",
+    "kubos-linux-isis-musl",
+    "kubos-linux-riscv64-gcc",
+];
+
+fn merged(files: &[&str]) -> TargetRegistry {
+    let mut registry = TargetRegistry::builtin();
+    for (i, data) in files.iter().enumerate() {
+        registry
+            .merge_toml(data, Path::new(&format!("targets-{}.toml", i)))
+            .unwrap();
+    }
+    registry
+}
+
+#[test]
+fn builtin_order_is_stable() {
+    assert_eq!(TargetRegistry::builtin().names(), BUILTIN);
+}
+
+#[test]
+fn added_targets_follow_in_order_read() {
+    let registry = merged(&[
+        "[targets.zeta]\ntriple = \"thumbv7em-none-eabihf\"\n",
+        "[targets.alpha]\ntriple = \"riscv32imc-unknown-none-elf\"\n",
+    ]);
+    let mut expected = BUILTIN.to_vec();
+    expected.extend(["zeta", "alpha"]);
+    assert_eq!(registry.names(), expected);
+}
+
+#[test]
+fn names_and_aliases_resolve() {
+    let registry = TargetRegistry::builtin();
+    let bb = registry.resolve("bb").unwrap();
+    assert_eq!(bb.name, "kubos-linux-beaglebone-gcc");
+    assert_eq!(bb.triple, "arm-unknown-linux-gnueabihf");
+    assert_eq!(bb.gcc[0], "arm-linux-gnueabihf-gcc");
+    assert_eq!(
+        bb.cflags,
+        ["-march=armv7-a", "-mfpu=neon", "-mfloat-abi=hard"]
+    );
+    assert_eq!(
+        registry.resolve("x86-linux-native").unwrap().aliases,
+        ["native"]
+    );
+}
+
+/// What each built-in target builds for, in the order of `BUILTIN`
+const BUILTIN_TRIPLES: &[&str] = &[
+    "x86_64-unknown-linux-gnu",
+    "i686-unknown-linux-gnu",
+    "arm-unknown-linux-gnueabihf",
+    "arm-unknown-linux-gnueabihf",
+    "armv5te-unknown-linux-gnueabi",
+    "aarch64-unknown-linux-gnu",
+    "aarch64-unknown-linux-gnu",
+    "arm-unknown-linux-musleabihf",
+    "armv5te-unknown-linux-musleabi",
+    "riscv64gc-unknown-linux-gnu",
+];
+
+#[test]
+fn every_target_round_trips() {
+    let registry = TargetRegistry::builtin();
+    for (name, triple) in BUILTIN.iter().zip(BUILTIN_TRIPLES) {
+        let target = registry.resolve(name).unwrap();
+        assert_eq!(target.name, *name);
+        assert_eq!(target.triple, *triple, "{}", name);
+        // Only the native build needs no cross gcc to look for
+        assert_eq!(
+            target.gcc.is_empty(),
+            *name == "x86-linux-native",
+            "{}: {:?}",
+            name,
+            target.gcc
+        );
+        for alias in &target.aliases {
+            assert_eq!(registry.resolve(alias).unwrap().name, *name, "{}", alias);
+        }
+    }
+}
+
+#[test]
+fn every_target_is_listed_as_supported() {
+    let message = match TargetRegistry::builtin().resolve("kubos-linux-unknown-gcc") {
+        Err(error @ TargetError::Unknown { .. }) => error.to_string(),
+        other => panic!("expected an unknown target, got {:?}", other),
+    };
+    let listed: Vec<&str> = message
+        .lines()
+        .skip_while(|line| *line != "Currently supported targets are:")
+        .skip(1)
+        .map(|line| line.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(listed, BUILTIN);
+}
+
+#[test]
+fn later_files_override_earlier_and_builtin() {
+    let registry = merged(&[
+        "[targets.\"kubos-linux-beaglebone-gcc\"]\n\
+         triple = \"armv7-unknown-linux-gnueabihf\"\n\
+         linker = \"/opt/user/gcc\"\n",
+        "[targets.\"kubos-linux-beaglebone-gcc\"]\n\
+         triple = \"armv7-unknown-linux-gnueabihf\"\n\
+         linker = \"/opt/project/gcc\"\n",
+    ]);
+    let bb = registry.resolve("kubos-linux-beaglebone-gcc").unwrap();
+    assert_eq!(bb.triple, "armv7-unknown-linux-gnueabihf");
+    assert_eq!(bb.linker.as_deref(), Some("/opt/project/gcc"));
+    // What wasn't redefined is kept, and so is the target's place
+    assert_eq!(bb.aliases, ["bb"]);
+    assert_eq!(bb.gcc[0], "arm-linux-gnueabihf-gcc");
+    assert_eq!(registry.resolve("bb").unwrap().linker, bb.linker);
+    assert_eq!(registry.names(), BUILTIN);
+}
+
+#[test]
+fn redefined_aliases_replace_builtin_ones() {
+    let registry = merged(&["[targets.\"kubos-linux-isis-gcc\"]\n\
+         triple = \"armv5te-unknown-linux-gnueabi\"\n\
+         aliases = [\"iobc\"]\n"]);
+    assert_eq!(
+        registry.resolve("iobc").unwrap().name,
+        "kubos-linux-isis-gcc"
+    );
+    assert!(matches!(
+        registry.resolve("isis"),
+        Err(TargetError::Unknown { .. })
+    ));
+}
+
+#[test]
+fn shared_alias_is_ambiguous() {
+    let registry = merged(&[
+        "[targets.\"flight-a\"]\ntriple = \"arm-unknown-linux-gnueabihf\"\naliases = [\"bb\"]\n",
+    ]);
+    match registry.resolve("bb") {
+        Err(TargetError::Ambiguous { name, matches, .. }) => {
+            assert_eq!(name, "bb");
+            assert_eq!(matches, ["kubos-linux-beaglebone-gcc", "flight-a"]);
+        }
+        other => panic!("expected an ambiguous alias, got {:?}", other),
+    }
+}
+
+#[test]
+fn name_beats_alias() {
+    let registry = merged(&["[targets.native]\ntriple = \"aarch64-unknown-linux-gnu\"\n"]);
+    assert_eq!(
+        registry.resolve("native").unwrap().triple,
+        "aarch64-unknown-linux-gnu"
+    );
+}
+
+#[test]
+fn unknown_target_suggests_closest() {
+    match TargetRegistry::builtin().resolve("kubos-linux-isis-gc") {
+        Err(TargetError::Unknown {
+            suggestions,
+            supported,
+            ..
+        }) => {
+            assert_eq!(suggestions, ["kubos-linux-isis-gcc"]);
+            assert_eq!(supported.len(), BUILTIN.len());
+            assert_eq!(supported[0], "x86-linux-native (native)");
+        }
+        other => panic!("expected an unknown target, got {:?}", other),
+    }
+}
+
+#[test]
+fn typos_suggest_the_intended_target() {
+    let cases: &[(&str, &[&str])] = &[
+        (
+            "kubos-linux-beagelbone-gcc",
+            &["kubos-linux-beaglebone-gcc"],
+        ),
+        (
+            "kubos-linux-beaglebone-mus",
+            &["kubos-linux-beaglebone-musl"],
+        ),
+        (
+            "kubos-linux-pumpkin-mbm-gcc",
+            &["kubos-linux-pumpkin-mbm2-gcc"],
+        ),
+        ("kubos-linux-rpi-zero-gcc", &["kubos-linux-rpi-zero2-gcc"]),
+        ("x86-linux-natve", &["x86-linux-native"]),
+        ("kubos-linux-isis-mcc", &["kubos-linux-isis-gcc"]),
+        // Too far from anything to be a typo
+        ("stm32f4", &[]),
+    ];
+    let registry = TargetRegistry::builtin();
+    for (typo, expected) in cases {
+        match registry.resolve(typo) {
+            Err(TargetError::Unknown { suggestions, .. }) => {
+                assert_eq!(suggestions, *expected, "{}", typo)
+            }
+            other => panic!("expected {} to be unknown, got {:?}", typo, other),
+        }
+    }
+}
+
+#[test]
+fn malformed_file_is_rejected() {
+    let mut registry = TargetRegistry::builtin();
+    let error = registry
+        .merge_toml(
+            "[targets.broken]\nlinker = \"gcc\"\n",
+            Path::new("kubos-targets.toml"),
+        )
+        .unwrap_err();
+    assert_eq!(error, "kubos-targets.toml: target broken has no triple");
+    assert_eq!(registry.names(), BUILTIN);
+}
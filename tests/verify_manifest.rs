@@ -0,0 +1,132 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! `cargo kubos verify-manifest`, checking an artifact directory against
+//! a manifest written out by hand
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+const SERVICE: &[u8] = b"#!/bin/sh\necho telemetry\n";
+const SERVICE_SHA256: &str = "47e894a90cfdbe480ae7b2f376e93b5f7dfcbdc15e7c85ee1adcab77d1921118";
+const TAMPERED_SHA256: &str = "7e40d73947def685849713b029b6c69910b8c1f18b0496f268e5042300232eee";
+const CONFIG: &[u8] = b"log_level = \"info\"\n";
+const CONFIG_SHA256: &str = "0d8cf25343b1d0b3aef9afc35b6d1cc69fa6d3e8b7719c576cea232eee042e5f";
+
+/// An artifact directory with a service and its config, and a manifest
+/// of them as they should be
+fn artifacts(sandbox: &Sandbox) -> PathBuf {
+    let dir = sandbox.project.join("artifacts");
+    fs::create_dir_all(dir.join("etc")).unwrap();
+    let service = dir.join("telemetry-service");
+    fs::write(&service, SERVICE).unwrap();
+    fs::set_permissions(&service, fs::Permissions::from_mode(0o755)).unwrap();
+    let config = dir.join("etc/telemetry.toml");
+    fs::write(&config, CONFIG).unwrap();
+    fs::set_permissions(&config, fs::Permissions::from_mode(0o644)).unwrap();
+    fs::write(
+        dir.join("manifest.toml"),
+        format!(
+            "name = \"telemetry-service\"\nversion = \"0.1.0\"\n\
+             kubos-target = \"kubos-linux-beaglebone-gcc\"\n\n\
+             [[files]]\npath = \"telemetry-service\"\nsize = {}\nsha256 = \"{}\"\nmode = \"0755\"\n\n\
+             [[files]]\npath = \"etc/telemetry.toml\"\nsize = {}\nsha256 = \"{}\"\nmode = \"0644\"\n",
+            SERVICE.len(),
+            SERVICE_SHA256,
+            CONFIG.len(),
+            CONFIG_SHA256
+        ),
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn matching_artifacts_pass() {
+    let sandbox = Sandbox::new("matching_artifacts_pass");
+    let dir = artifacts(&sandbox);
+    for path in &[dir.clone(), dir.join("manifest.toml")] {
+        let output = sandbox.run(&["verify-manifest", path.to_str().unwrap()]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert!(
+            stderr(&output).contains("matches its manifest"),
+            "{}",
+            stderr(&output)
+        );
+    }
+    assert!(sandbox.invocations().is_empty());
+}
+
+#[test]
+fn every_mismatch_is_listed() {
+    let sandbox = Sandbox::new("every_mismatch_is_listed");
+    let dir = artifacts(&sandbox);
+    let service = dir.join("telemetry-service");
+    fs::write(&service, b"#!/bin/sh\necho tampered\n").unwrap();
+    fs::set_permissions(&service, fs::Permissions::from_mode(0o700)).unwrap();
+    fs::remove_file(dir.join("etc/telemetry.toml")).unwrap();
+
+    let output = sandbox.run(&["verify-manifest", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains(&format!(
+            "Error - {} doesn't match its manifest (4 mismatches):\n",
+            dir.display()
+        )),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("telemetry-service: size is 24 bytes, expected 25\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "telemetry-service: SHA-256 is {}, expected {}\n",
+            TAMPERED_SHA256, SERVICE_SHA256
+        )),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("telemetry-service: mode is 0700, expected 0755\n"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("etc/telemetry.toml: missing"), "{}", stderr);
+}
+
+#[test]
+fn no_manifest_fails() {
+    let sandbox = Sandbox::new("no_manifest_fails");
+    let dir = artifacts(&sandbox);
+    fs::remove_file(dir.join("manifest.toml")).unwrap();
+    let output = sandbox.run(&["verify-manifest", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("manifest.toml: No such file"),
+        "{}",
+        stderr(&output)
+    );
+}
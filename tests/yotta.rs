@@ -0,0 +1,291 @@
+//
+// Copyright (C) 2019 Kubos Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License")
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! What build scripts are told about the crate's yotta module, and how
+//! its yotta target is synced, read from the `module.json` fixtures
+
+mod common;
+
+use common::{stderr, Sandbox};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BB_TRIPLE: &str = "arm-unknown-linux-gnueabihf";
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/yotta")
+        .join(name)
+}
+
+fn paths(list: &str) -> Vec<PathBuf> {
+    env::split_paths(list).collect()
+}
+
+fn with_bb(name: &str) -> Sandbox {
+    Sandbox::new(name).with_linker(BB_TRIPLE, "arm-linux-gnueabihf-gcc")
+}
+
+#[test]
+fn module_and_target_dependencies_are_exported() {
+    let sandbox = with_bb("module_and_target_dependencies_are_exported");
+    let module = fixture("telemetry");
+    let output = sandbox.run(&[
+        "-t",
+        "bb",
+        "--yotta-module",
+        module.to_str().unwrap(),
+        "build",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    let modules = module.join("yotta_modules");
+    assert_eq!(
+        paths(build.env("CARGO_KUBOS_YOTTA_INCLUDE_PATHS").unwrap()),
+        [
+            module.clone(),
+            module.join("include"),
+            modules.join("csp"),
+            modules.join("kubos-core"),
+            modules.join("kubos-hal"),
+        ]
+    );
+    let out = module.join("build/kubos-linux-beaglebone-gcc");
+    assert_eq!(
+        paths(build.env("CARGO_KUBOS_YOTTA_LIB_PATHS").unwrap()),
+        [
+            out.join("source"),
+            out.join("ym/csp/source"),
+            out.join("ym/kubos-core/source"),
+            out.join("ym/kubos-hal/source"),
+        ]
+    );
+    // Not freertos, which is only for stm32f4 targets
+    assert_eq!(
+        build.env("CARGO_KUBOS_YOTTA_LIBS"),
+        Some("telemetry-service csp kubos-core kubos-hal")
+    );
+}
+
+#[test]
+fn crates_own_module_is_found() {
+    let sandbox = with_bb("crates_own_module_is_found");
+    fs::copy(
+        fixture("ipc").join("module.json"),
+        sandbox.project.join("module.json"),
+    )
+    .unwrap();
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let build = sandbox.build();
+    assert_eq!(build.env("CARGO_KUBOS_YOTTA_LIBS"), Some("ipc"));
+    assert_eq!(
+        paths(build.env("CARGO_KUBOS_YOTTA_LIB_PATHS").unwrap()),
+        [sandbox
+            .project
+            .join("build/kubos-linux-beaglebone-gcc/source")]
+    );
+}
+
+#[test]
+fn no_module_exports_nothing() {
+    let sandbox = with_bb("no_module_exports_nothing");
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(sandbox.build().env("CARGO_KUBOS_YOTTA_LIBS"), None);
+}
+
+#[test]
+fn malformed_module_names_the_field() {
+    for (name, says) in &[
+        (
+            "bad-dependency",
+            "dependencies.csp must be a version string",
+        ),
+        ("bad-includes", "extraIncludes must be an array of paths"),
+    ] {
+        let sandbox = with_bb(&format!("malformed_module_names_the_field_{}", name));
+        let module = fixture(name);
+        let output = sandbox.run(&[
+            "-t",
+            "bb",
+            "--yotta-module",
+            module.to_str().unwrap(),
+            "build",
+        ]);
+        let stderr = stderr(&output);
+        assert_eq!(output.status.code(), Some(5), "{}", stderr);
+        assert!(
+            stderr.contains(&format!("module.json: {}", says)),
+            "{}",
+            stderr
+        );
+        assert!(sandbox.builds().is_empty());
+    }
+}
+
+#[test]
+fn only_building_looks_for_modules() {
+    let sandbox = with_bb("only_building_looks_for_modules");
+    let output = sandbox.run(&["-t", "bb", "--dry-run", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // Not even `cargo metadata` to find the modules
+    assert!(
+        sandbox.invocations().is_empty(),
+        "{:?}",
+        sandbox.invocations()
+    );
+
+    let output = sandbox.run(&["-t", "bb", "sync-yotta-target"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(sandbox.invocations().is_empty());
+
+    let output = sandbox.run(&["-t", "bb", "--skip-yotta", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let invocations = sandbox.invocations();
+    assert_eq!(invocations.len(), 1, "{:?}", invocations);
+    assert_eq!(invocations[0].args[0], "build");
+}
+
+/// The variables the sandbox itself runs cargo-kubos with
+const SANDBOX_VARS: &[&str] = &[
+    "CARGO_HOME",
+    "FAKE_CARGO_RECORD",
+    "HOME",
+    "PATH",
+    "USERPROFILE",
+];
+
+/// A sandbox whose crate wraps the ipc module, yotta's settings for
+/// which have the given target, with a stub of `yt`
+fn with_module_target(name: &str, target: Option<&str>) -> Sandbox {
+    let sandbox = with_bb(name);
+    fs::copy(
+        fixture("ipc").join("module.json"),
+        sandbox.project.join("module.json"),
+    )
+    .unwrap();
+    if let Some(target) = target {
+        fs::write(
+            sandbox.project.join(".yotta.json"),
+            format!("{{\"build\": {{\"target\": \"{},*\"}}}}\n", target),
+        )
+        .unwrap();
+    }
+    sandbox.install_stub(&sandbox.bin, "yt");
+    sandbox
+}
+
+#[test]
+fn sync_sets_the_modules_target() {
+    let sandbox = with_module_target("sync_sets_the_modules_target", None);
+    let output = sandbox.run(&["-t", "bb", "sync-yotta-target"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("set the yotta target of ipc to kubos-linux-beaglebone-gcc\n"),
+        "{}",
+        stderr(&output)
+    );
+
+    let calls = sandbox.calls("yt");
+    assert_eq!(calls.len(), 1, "{:?}", calls);
+    assert_eq!(calls[0].args, ["target", "kubos-linux-beaglebone-gcc"]);
+    let vars: Vec<&str> = calls[0].env.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(vars, SANDBOX_VARS);
+    assert!(sandbox.invocations().is_empty());
+}
+
+#[test]
+fn sync_replaces_another_target() {
+    let sandbox = with_module_target("sync_replaces_another_target", Some("kubos-linux-isis-gcc"));
+    let output = sandbox.run(&["-t", "bb", "sync-yotta-target"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(
+            "changed the yotta target of ipc from kubos-linux-isis-gcc \
+             to kubos-linux-beaglebone-gcc\n"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        sandbox.calls("yt")[0].args,
+        ["target", "kubos-linux-beaglebone-gcc"]
+    );
+}
+
+#[test]
+fn sync_leaves_the_same_target_alone() {
+    let sandbox = with_module_target(
+        "sync_leaves_the_same_target_alone",
+        Some("kubos-linux-beaglebone-gcc"),
+    );
+    let output = sandbox.run(&["-t", "bb", "sync-yotta-target"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(sandbox.calls("yt").is_empty());
+}
+
+#[test]
+fn failed_sync_says_what_yotta_said() {
+    let sandbox =
+        with_module_target("failed_sync_says_what_yotta_said", None).env("FAKE_YT_EXIT", "1");
+    let output = sandbox.run(&["-t", "bb", "sync-yotta-target"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(&format!(
+            "Error - setting the yotta target for {} to kubos-linux-beaglebone-gcc failed",
+            sandbox.project.display()
+        )),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn building_syncs_only_when_configured() {
+    let sandbox = with_module_target(
+        "building_syncs_only_when_configured",
+        Some("kubos-linux-isis-gcc"),
+    );
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(sandbox.calls("yt").is_empty());
+
+    let config = sandbox.project.join(".cargo/config.toml");
+    let sandbox = sandbox.with_config(&config, "[kubos]\nsync-yotta-target = true\n");
+    // A different target is only warned of
+    let output = sandbox.run(&["-t", "bb", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(
+            "warning: the yotta target of ipc is kubos-linux-isis-gcc, \
+             not kubos-linux-beaglebone-gcc; pass --force-sync to switch it\n"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox.calls("yt").is_empty());
+
+    let output = sandbox.run(&["-t", "bb", "--force-sync", "build"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        sandbox.calls("yt")[0].args,
+        ["target", "kubos-linux-beaglebone-gcc"]
+    );
+}